@@ -0,0 +1,132 @@
+# the library
+error-filename-not-found = Filename not found in path: { $path }
+error-claim-failed = Failed to claim filename: { $path }: { $error }
+error-checksum-mismatch = Checksum mismatch after copy: { $src } -> { $dst }
+error-not-reversible = Filename can't be shortened reversibly: { $name }
+error-reversible-name-taken = Reversibly shortened filename is already taken: { $path }
+error-unsupported-format-version = Unsupported format version: { $version } (up to { $supported } is supported)
+error-invalid-format = Invalid format: { $line }
+error-insufficient-space = Not enough free space on the filesystem of { $path }: { $needed } bytes needed, { $available } bytes available
+error-existence-check-failed = Failed to check existence: { $path }: { $error }
+error-git-failed = git failed: { $path }: { $message }
+error-aws-failed = aws failed: { $message }
+error-curl-failed = curl failed: { $message }
+error-download-checksum-mismatch = Downloaded file doesn't match the published checksum: { $url }
+error-budget-impossible = The limit of { $available } bytes is too small, { $needed } bytes are needed at least
+error-empty-component = Empty component in filename: { $name }
+error-non-utf8-name = Non UTF-8 name can't be shortened: { $path }
+error-destination-exists = Destination already exists: { $path }
+config-conversion-with-separator = Conversion { $from } -> { $to } contains a path separator
+config-conversion-with-nul = Conversion { $from } -> { $to } contains NUL
+config-conversion-with-delimiter = Conversion { $from } -> { $to } contains a delimiter, the result would be split into other tags next time
+config-conversion-longer-than-key = Conversion { $from } -> { $to } makes the tag longer
+config-conversion-cycle = Conversions form a cycle: { $cycle }
+config-conversion-chain-too-long = Conversion chain from { $from } is longer than { $n } steps
+config-unknown-key = Unknown key { $key } (ignored)
+config-unknown-key-similar-to = Unknown key { $key }, did you mean { $similar }? (ignored)
+config-migrated = Config migrated from version { $from_version } to { $version }: { $path } (the old one is kept as { $backup_path })
+config-not-migrated = Config not migrated: { $path }: { $error }
+
+# the journal
+undo-destination-missing = Renamed file no longer exists: { $path }
+undo-source-taken = Original path is taken: { $path }
+undo-moved-later = Renamed again by run { $run_id }: { $path }
+journal-destination-missing = Renamed file no longer exists: { $path } (run { $run_id })
+journal-moved-later = Renamed file was renamed again: { $path } (run { $run_id }, again by run { $later_run_id })
+journal-checksum-mismatch = Renamed file has changed since: { $path } (run { $run_id })
+journal-unreadable = Failed to read renamed file: { $path } (run { $run_id }): { $error }
+
+# the command line
+error-rename = Rename error: { $src } -> { $dst }: { $error }
+error-claim = Claim error: { $path }: { $error }
+error-existence-check = Existence check error: { $path }: { $error }
+error-batch = Failed to rename { $n } files
+error-protected-path = Protected path: { $path } (use --force to rename it anyway)
+error-too-many-changes = Too many changes: { $n } files would be renamed, but --max-changes is { $max }
+error-invalid-config = Invalid config: { $n } errors
+error-integration-path-unknown = Where to install unknown, HOME isn't set
+error-rule-profile-not-found = Rule profile not found in the config: { $name }
+error-journal-path-unknown = Journal path unknown, HOME isn't set (use --journal)
+error-run-not-found = Run not found in the journal: { $run_id }
+error-entry-not-found = Entry not found in the journal: { $entry } ({ $n } entries)
+error-undo-conflicts = Can't undo { $n } renames (use --force to undo the rest anyway)
+error-invalid-s3-uri = Not an S3 URI: { $uri } (s3://bucket/prefix)
+error-dialog-not-found = Neither zenity nor kdialog is found
+error-journal-issues = Found { $n } problems in the journal
+error-lint-violations = Found { $n } lint violations
+error-hook-exists = Pre-commit hook already exists: { $path } (use --force to replace it)
+error-release-binary-not-found = No binary for this machine in release { $version }
+error-release-checksum-not-found = No checksum of the binary in release { $version }
+error-test-names-rejected = { $n } test names were rejected by the filesystem
+error-tui-conflict = --{ $option } can't be used with tui
+error-tui-subcommand = tui takes the arguments of a run, not a subcommand
+error-shim-library-not-found = Preload library not found: { $path } (build shim/, or use --library)
+error-watch-state-path-unknown = Watch state path unknown, HOME isn't set (use --state)
+error-nothing-to-watch = No directory to watch, give one or set `watches` in the config
+error-watched-twice = Directory watched twice: { $path }
+error-io = IO error: { $error }
+error-unknown = Unknown error: { $error }
+error = Error: { $error }
+config-issue = Config: { $issue }
+retention-nothing = Information retained: nothing would be renamed
+retention-summary = Information retained in { $n } renamed names: mean { $mean }%, min { $min }%
+loss-stats-previews-only = --loss-stats is only reported in the previews (-s, --dry-run, --check, --emit-script, --clusters)
+would-rename = { $n } files would be renamed
+retained = { $src } -> { $dst }: { $percent }% retained
+dialog-nothing-to-rename = Nothing to rename.
+and-more = ... and { $n } more
+dialog-rename = Rename { $n } files?
+run-id = Run ID: { $run_id }
+references-updated = Updated references: { $path }
+references-update-failed = Failed to update references in { $dir }: { $error }
+explain = { $src } -> { $dst }: dropped { $dropped }, distinctiveness { $distinctiveness }
+eta-unknown = unknown
+heartbeat = { $n_done }/{ $n_files } files, { $files_per_sec } files/s, ETA { $eta }
+mounting = Mounting { $src } at { $mountpoint }
+archive-renamed = Renamed { $n } members: { $dst } (manifest: { $manifest })
+config-changed-since-run = The config has changed since run { $run_id }
+s3-renaming = Renaming { $n } keys (run ID: { $run_id })
+renamed = Renamed: { $src } -> { $dst }
+journal-record-failed = Failed to record in the journal: { $path }: { $error }
+installed = Installed: { $path }
+replay-config-changed = The config has changed since the run, replaying with the recorded one
+replay-config-not-saved = The config of the run isn't saved, replaying with the current one
+replay-config-not-read = The recorded config isn't read without the json feature, replaying with the current one
+replay-recorded = Recorded: { $src } -> { $dst } (run { $run_id })
+replay-replayed = Replayed: { $src } -> { $dst }
+replay-differs = The name differs from the recorded one, the options of the run, the config or the directory differ
+history-imported = Imported { $n } renames from the journal
+lint-fix-failed = Can't fix: { $path }: { $error }
+lint-fix-taken = Can't fix, the name is taken: { $src } -> { $dst }
+update-up-to-date = Up to date: { $version }
+update-available = { $version } is available (current: { $current })
+updated = Updated: { $current } -> { $version }: { $path }
+config-validate-error = error: { $issue }
+config-validate-warning = warning: { $issue }
+skipped-nfs-temp-file = Skipped NFS temporary file: { $path }
+skipped-empty-file = Skipped empty file: { $path }
+skipped-protected-path = Skipped protected path: { $path }
+undoing = Undoing run { $run_id } (run ID: { $undo_run_id })
+watch-state-reset = Watch state reset: { $path }
+watching = Watching { $dir }
+watch-state-save-failed = Failed to save the watch state: { $path }: { $error }
+batch-summary = { $dir }: { $n_arrived } files arrived, { $n_renamed } renamed
+batch-summary-conflicts = {" "}({ $n } with a counter)
+batch-summary-failed = , { $n } failed
+notify-send-failed = notify-send failed: { $error }
+notify-send-not-found = notify-send not found, install libnotify for the notifications
+notification-renamed-one = Renamed a file
+notification-renamed = Renamed { $n } files
+history-open-failed = Failed to open the history: { $error }
+copy-owner-failed = Failed to copy the owner of { $src }: { $dst }: { $error }
+created-directory = Created directory: { $path }
+deleted-duplicate = Deleted duplicate: { $src } (same as { $dst })
+skipped-duplicate = Skipped duplicate: { $src } (same as { $dst })
+already-short = Filename is already short enough: { $path }
+setgid-group-mismatch = Moved into a setgid directory of group { $dir_gid } keeping group { $gid }, members of the group may lose access: { $path }
+config-not-found = No config file: { $path }
+config-up-to-date = Config is of this version already: { $path } (version { $version })
+copied = Copied: { $src } -> { $dst }
+renamed-git = Renamed with git mv: { $src } -> { $dst }
+test-name-rejected = Rejected: { $path }: { $error }
+checksum-failed = Failed to compute checksum: { $path }: { $error }
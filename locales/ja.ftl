@@ -0,0 +1,132 @@
+# the library
+error-filename-not-found = パスにファイル名がありません: { $path }
+error-claim-failed = ファイル名を確保できませんでした: { $path }: { $error }
+error-checksum-mismatch = コピー後のチェックサムが一致しません: { $src } -> { $dst }
+error-not-reversible = ファイル名を可逆に短縮できません: { $name }
+error-reversible-name-taken = 可逆に短縮したファイル名は既に使われています: { $path }
+error-unsupported-format-version = 対応していない形式のバージョンです: { $version }（{ $supported } まで対応）
+error-invalid-format = 形式が不正です: { $line }
+error-insufficient-space = { $path } のファイルシステムの空き容量が足りません: { $needed } バイト必要ですが、空きは { $available } バイトです
+error-existence-check-failed = 存在を確認できませんでした: { $path }: { $error }
+error-git-failed = git が失敗しました: { $path }: { $message }
+error-aws-failed = aws が失敗しました: { $message }
+error-curl-failed = curl が失敗しました: { $message }
+error-download-checksum-mismatch = ダウンロードしたファイルが公開されたチェックサムと一致しません: { $url }
+error-budget-impossible = 上限の { $available } バイトでは小さすぎます。少なくとも { $needed } バイト必要です
+error-empty-component = ファイル名に空の要素があります: { $name }
+error-non-utf8-name = UTF-8 でない名前は短縮できません: { $path }
+error-destination-exists = 移動先が既に存在します: { $path }
+config-conversion-with-separator = 変換 { $from } -> { $to } にパスの区切り文字が含まれています
+config-conversion-with-nul = 変換 { $from } -> { $to } に NUL が含まれています
+config-conversion-with-delimiter = 変換 { $from } -> { $to } に区切り文字が含まれています。次回、変換結果が別のタグに分かれてしまいます
+config-conversion-longer-than-key = 変換 { $from } -> { $to } でタグが長くなります
+config-conversion-cycle = 変換が循環しています: { $cycle }
+config-conversion-chain-too-long = { $from } からの変換の連鎖が { $n } 段を超えています
+config-unknown-key = 不明なキー { $key }（無視します）
+config-unknown-key-similar-to = 不明なキー { $key } です。{ $similar } のことですか？（無視します）
+config-migrated = 設定をバージョン { $from_version } から { $version } に移行しました: { $path }（元のファイルは { $backup_path } に残してあります）
+config-not-migrated = 設定を移行できませんでした: { $path }: { $error }
+
+# the journal
+undo-destination-missing = 名前を変更したファイルがもうありません: { $path }
+undo-source-taken = 元のパスは既に使われています: { $path }
+undo-moved-later = 実行 { $run_id } で再び名前が変更されています: { $path }
+journal-destination-missing = 名前を変更したファイルがもうありません: { $path }（実行 { $run_id }）
+journal-moved-later = 名前を変更したファイルが再び変更されています: { $path }（実行 { $run_id }、再変更は実行 { $later_run_id }）
+journal-checksum-mismatch = 名前を変更したファイルがその後変更されています: { $path }（実行 { $run_id }）
+journal-unreadable = 名前を変更したファイルを読めませんでした: { $path }（実行 { $run_id }）: { $error }
+
+# the command line
+error-rename = 名前の変更に失敗しました: { $src } -> { $dst }: { $error }
+error-claim = 名前を確保できませんでした: { $path }: { $error }
+error-existence-check = 存在を確認できませんでした: { $path }: { $error }
+error-batch = { $n } 個のファイルの名前を変更できませんでした
+error-protected-path = 保護されたパスです: { $path }（それでも変更するには --force）
+error-too-many-changes = 変更が多すぎます: { $n } 個のファイルの名前が変わりますが、--max-changes は { $max } です
+error-invalid-config = 設定が不正です: エラー { $n } 件
+error-integration-path-unknown = インストール先がわかりません。HOME が設定されていません
+error-rule-profile-not-found = 設定にルールのプロファイルがありません: { $name }
+error-journal-path-unknown = ジャーナルのパスがわかりません。HOME が設定されていません（--journal で指定できます）
+error-run-not-found = ジャーナルに実行がありません: { $run_id }
+error-entry-not-found = ジャーナルにエントリーがありません: { $entry }（全 { $n } 件）
+error-undo-conflicts = { $n } 件の名前の変更を元に戻せません（残りだけでも戻すには --force）
+error-invalid-s3-uri = S3 の URI ではありません: { $uri }（s3://bucket/prefix）
+error-dialog-not-found = zenity も kdialog も見つかりません
+error-journal-issues = ジャーナルに { $n } 件の問題が見つかりました
+error-lint-violations = { $n } 件の規約違反が見つかりました
+error-hook-exists = pre-commit フックが既にあります: { $path }（置き換えるには --force）
+error-release-binary-not-found = リリース { $version } にこのマシン用のバイナリがありません
+error-release-checksum-not-found = リリース { $version } にバイナリのチェックサムがありません
+error-test-names-rejected = { $n } 個のテスト用の名前がファイルシステムに拒否されました
+error-tui-conflict = --{ $option } は tui と一緒に使えません
+error-tui-subcommand = tui が受け取るのは実行の引数で、サブコマンドではありません
+error-shim-library-not-found = プリロードするライブラリが見つかりません: { $path }（shim/ をビルドするか、--library で指定してください）
+error-watch-state-path-unknown = 監視の状態のパスがわかりません。HOME が設定されていません（--state で指定できます）
+error-nothing-to-watch = 監視するディレクトリがありません。指定するか、設定の `watches` に書いてください
+error-watched-twice = ディレクトリが二重に監視されています: { $path }
+error-io = 入出力エラー: { $error }
+error-unknown = 不明なエラー: { $error }
+error = エラー: { $error }
+config-issue = 設定: { $issue }
+retention-nothing = 残った情報: 名前が変わるファイルはありません
+retention-summary = 名前が変わる { $n } 個の名前に残った情報: 平均 { $mean }%、最小 { $min }%
+loss-stats-previews-only = --loss-stats はプレビュー（-s、--dry-run、--check、--emit-script、--clusters）でだけ表示されます
+would-rename = { $n } 個のファイルの名前が変わります
+retained = { $src } -> { $dst }: { $percent }% が残ります
+dialog-nothing-to-rename = 名前を変更するファイルはありません。
+and-more = ……ほか { $n } 件
+dialog-rename = { $n } 個のファイルの名前を変更しますか？
+run-id = 実行 ID: { $run_id }
+references-updated = 参照を更新しました: { $path }
+references-update-failed = { $dir } の参照を更新できませんでした: { $error }
+explain = { $src } -> { $dst }: 削ったもの { $dropped }、識別しやすさ { $distinctiveness }
+eta-unknown = 不明
+heartbeat = { $n_done }/{ $n_files } 個、毎秒 { $files_per_sec } 個、残り { $eta }
+mounting = { $src } を { $mountpoint } にマウントします
+archive-renamed = { $n } 個のメンバーの名前を変更しました: { $dst }（対応表: { $manifest }）
+config-changed-since-run = 実行 { $run_id } の後に設定が変わっています
+s3-renaming = { $n } 個のキーの名前を変更します（実行 ID: { $run_id }）
+renamed = 名前を変更しました: { $src } -> { $dst }
+journal-record-failed = ジャーナルに記録できませんでした: { $path }: { $error }
+installed = インストールしました: { $path }
+replay-config-changed = 実行の後に設定が変わっています。記録された設定で再現します
+replay-config-not-saved = 実行の設定が保存されていません。今の設定で再現します
+replay-config-not-read = json 機能なしでは記録された設定を読めません。今の設定で再現します
+replay-recorded = 記録: { $src } -> { $dst }（実行 { $run_id }）
+replay-replayed = 再現: { $src } -> { $dst }
+replay-differs = 記録された名前と違います。実行のオプション、設定、ディレクトリのどれかが違います
+history-imported = ジャーナルから { $n } 件の名前の変更を取り込みました
+lint-fix-failed = 修正できません: { $path }: { $error }
+lint-fix-taken = 修正できません。名前が既に使われています: { $src } -> { $dst }
+update-up-to-date = 最新です: { $version }
+update-available = { $version } が利用できます（現在: { $current }）
+updated = 更新しました: { $current } -> { $version }: { $path }
+config-validate-error = エラー: { $issue }
+config-validate-warning = 警告: { $issue }
+skipped-nfs-temp-file = NFS の一時ファイルを飛ばしました: { $path }
+skipped-empty-file = 空のファイルを飛ばしました: { $path }
+skipped-protected-path = 保護されたパスを飛ばしました: { $path }
+undoing = 実行 { $run_id } を元に戻します（実行 ID: { $undo_run_id }）
+watch-state-reset = 監視の状態を消去しました: { $path }
+watching = { $dir } を監視しています
+watch-state-save-failed = 監視の状態を保存できませんでした: { $path }: { $error }
+batch-summary = { $dir }: { $n_arrived } 個のファイルが届き、{ $n_renamed } 個の名前を変更
+batch-summary-conflicts = （うち { $n } 個は番号付き）
+batch-summary-failed = 、{ $n } 個は失敗
+notify-send-failed = notify-send が失敗しました: { $error }
+notify-send-not-found = notify-send が見つかりません。通知には libnotify をインストールしてください
+notification-renamed-one = ファイルの名前を変更しました
+notification-renamed = { $n } 個のファイルの名前を変更しました
+history-open-failed = 履歴を開けませんでした: { $error }
+copy-owner-failed = { $src } の所有者をコピーできませんでした: { $dst }: { $error }
+created-directory = ディレクトリを作成しました: { $path }
+deleted-duplicate = 重複を削除しました: { $src }（{ $dst } と同じ）
+skipped-duplicate = 重複を飛ばしました: { $src }（{ $dst } と同じ）
+already-short = ファイル名は既に十分短いです: { $path }
+setgid-group-mismatch = グループ { $dir_gid } の setgid ディレクトリにグループ { $gid } のまま移動しました。グループのメンバーがアクセスできなくなるかもしれません: { $path }
+config-not-found = 設定ファイルがありません: { $path }
+config-up-to-date = 設定は既にこのバージョンのものです: { $path }（バージョン { $version }）
+copied = コピーしました: { $src } -> { $dst }
+renamed-git = git mv で名前を変更しました: { $src } -> { $dst }
+test-name-rejected = 拒否されました: { $path }: { $error }
+checksum-failed = チェックサムを計算できませんでした: { $path }: { $error }
@@ -0,0 +1,101 @@
+// the functions stand in for the ones of libc of the same names, whose contracts they keep
+#![allow(clippy::missing_safety_doc)]
+
+use std::{path::Path, fs, cell::Cell, sync::{Mutex, OnceLock}, ffi::{CStr, CString, OsStr}, os::unix::ffi::{OsStrExt, OsStringExt}};
+use libc::{c_char, c_int, c_uint, mode_t};
+use rename_for_linux_limit::Shim;
+
+type OpenFn = unsafe extern "C" fn(*const c_char, c_int, mode_t) -> c_int;
+type OpenatFn = unsafe extern "C" fn(c_int, *const c_char, c_int, mode_t) -> c_int;
+type CreatFn = unsafe extern "C" fn(*const c_char, mode_t) -> c_int;
+type RenameFn = unsafe extern "C" fn(*const c_char, *const c_char) -> c_int;
+type RenameatFn = unsafe extern "C" fn(c_int, *const c_char, c_int, *const c_char) -> c_int;
+type Renameat2Fn = unsafe extern "C" fn(c_int, *const c_char, c_int, *const c_char, c_uint) -> c_int;
+
+static SHIM: OnceLock<Mutex<Shim>> = OnceLock::new();
+
+thread_local! {
+    // shortening a name opens and stats files too (the config), those go to libc as they are
+    static IN_SHIM: Cell<bool> = const { Cell::new(false) };
+}
+
+// the function of libc this one stands in for
+macro_rules! next {
+    ($name:literal, $type:ty) => {{
+        static NEXT: OnceLock<usize> = OnceLock::new();
+        let next = *NEXT.get_or_init(|| unsafe { libc::dlsym(libc::RTLD_NEXT, concat!($name, "\0").as_ptr().cast()) } as usize);
+        assert_ne!(next, 0, concat!($name, " not found in libc"));
+        unsafe { std::mem::transmute::<usize, $type>(next) }
+    }};
+}
+
+// the path to pass on instead, none to pass the given one. a relative path of *at is of the directory of dirfd
+fn shimmed(dirfd: Option<c_int>, path: *const c_char, create: bool) -> Option<CString> {
+    if path.is_null() || IN_SHIM.get() {
+        return None;
+    }
+    IN_SHIM.set(true);
+    let shimmed = (|| {
+        let path = Path::new(OsStr::from_bytes(unsafe { CStr::from_ptr(path) }.to_bytes()));
+        let path = match dirfd {
+            Some(dirfd) if dirfd != libc::AT_FDCWD && path.is_relative() => fs::read_link(format!("/proc/self/fd/{}", dirfd)).ok()?.join(path),
+            _ => path.to_path_buf(),
+        };
+        let shimmed = SHIM.get_or_init(|| Mutex::new(Shim::new())).lock().ok()?.path(&path, create)?;
+        CString::new(shimmed.into_os_string().into_vec()).ok()
+    })();
+    IN_SHIM.set(false);
+    shimmed
+}
+
+fn or_given(shimmed: &Option<CString>, path: *const c_char) -> *const c_char {
+    shimmed.as_ref().map_or(path, |shimmed| shimmed.as_ptr())
+}
+
+// open(2) is variadic, the mode is read as a fixed argument, which is where the calling convention of x86_64 and
+// aarch64 puts it too
+#[no_mangle]
+pub unsafe extern "C" fn open(path: *const c_char, flags: c_int, mode: mode_t) -> c_int {
+    let shimmed = shimmed(None, path, flags & libc::O_CREAT != 0);
+    next!("open", OpenFn)(or_given(&shimmed, path), flags, mode)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn open64(path: *const c_char, flags: c_int, mode: mode_t) -> c_int {
+    let shimmed = shimmed(None, path, flags & libc::O_CREAT != 0);
+    next!("open64", OpenFn)(or_given(&shimmed, path), flags, mode)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn openat(dirfd: c_int, path: *const c_char, flags: c_int, mode: mode_t) -> c_int {
+    let shimmed = shimmed(Some(dirfd), path, flags & libc::O_CREAT != 0);
+    next!("openat", OpenatFn)(dirfd, or_given(&shimmed, path), flags, mode)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn creat(path: *const c_char, mode: mode_t) -> c_int {
+    let shimmed = shimmed(None, path, true);
+    next!("creat", CreatFn)(or_given(&shimmed, path), mode)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rename(old_path: *const c_char, new_path: *const c_char) -> c_int {
+    let old_shimmed = shimmed(None, old_path, false);
+    let new_shimmed = shimmed(None, new_path, true);
+    next!("rename", RenameFn)(or_given(&old_shimmed, old_path), or_given(&new_shimmed, new_path))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn renameat(old_dirfd: c_int, old_path: *const c_char, new_dirfd: c_int, new_path: *const c_char) -> c_int {
+    let old_shimmed = shimmed(Some(old_dirfd), old_path, false);
+    let new_shimmed = shimmed(Some(new_dirfd), new_path, true);
+    next!("renameat", RenameatFn)(old_dirfd, or_given(&old_shimmed, old_path), new_dirfd, or_given(&new_shimmed, new_path))
+}
+
+// mv of coreutils renames with this one
+#[no_mangle]
+pub unsafe extern "C" fn renameat2(old_dirfd: c_int, old_path: *const c_char, new_dirfd: c_int, new_path: *const c_char, flags: c_uint) -> c_int {
+    let old_shimmed = shimmed(Some(old_dirfd), old_path, false);
+    let new_shimmed = shimmed(Some(new_dirfd), new_path, true);
+    next!("renameat2", Renameat2Fn)(old_dirfd, or_given(&old_shimmed, old_path), new_dirfd, or_given(&new_shimmed, new_path), flags)
+}
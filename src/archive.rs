@@ -0,0 +1,199 @@
+use std::{path::{Path, PathBuf}, fs, io::{self, Read}, collections::{HashMap, HashSet}};
+use anyhow::Result;
+
+use crate::{Error, Rules, shorten_filename_among};
+
+// the local file headers, or the end of the central directory of an empty archive
+const ZIP_SIGNATURES: [&[u8; 4]; 2] = [b"PK\x03\x04", b"PK\x05\x06"];
+
+// writes a copy of the tar or zip archive whose member names fit the limit in every path component,
+// because extracting an archive with too long member names fails before there is any file to rename.
+// returns the renamed members as (original, new) in the archive order.
+pub fn shorten_archive(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let src = src.as_ref();
+    let rules = Rules::load();
+    if is_zip(src)? {
+        shorten_zip(src, dst.as_ref(), &rules)
+    } else {
+        shorten_tar(src, dst.as_ref(), &rules)
+    }
+}
+
+// by the signature at the start, whatever the extension is
+fn is_zip(path: &Path) -> io::Result<bool> {
+    let mut signature = [0; 4];
+    match fs::File::open(path)?.read_exact(&mut signature) {
+        Ok(()) => Ok(ZIP_SIGNATURES.contains(&&signature)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn shorten_tar(src: &Path, dst: &Path, rules: &Rules) -> Result<Vec<(PathBuf, PathBuf)>> {
+    // the whole member list is needed first, so that a shortened name never takes a name appearing later
+    let mut member_paths = Vec::new();
+    let mut archive = tar::Archive::new(fs::File::open(src)?);
+    for entry in archive.entries()? {
+        member_paths.push(entry?.path()?.into_owned());
+    }
+    let member_map = shorten_member_paths(&member_paths, rules)?;
+
+    let mut renamed_members = Vec::new();
+    let mut archive = tar::Archive::new(fs::File::open(src)?);
+    let mut builder = tar::Builder::new(fs::File::create(dst)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let new_path = match member_map.get(&path) {
+            Some(new_path) => {
+                log::info!("Renamed member: {} -> {}", path.display(), new_path.display());
+                renamed_members.push((path, new_path.clone()));
+                new_path.clone()
+            },
+            None => path,
+        };
+
+        let mut header = entry.header().clone();
+        let link_name = entry.link_name()?.map(|p| p.into_owned());
+        match link_name {
+            Some(target) => {
+                // hard links refer to other members by their names, symlink targets are left as they are
+                let target = if header.entry_type().is_hard_link() {
+                    member_map.get(&target).cloned().unwrap_or(target)
+                } else {
+                    target
+                };
+                builder.append_link(&mut header, &new_path, &target)?;
+            },
+            None => {
+                builder.append_data(&mut header, &new_path, &mut entry)?;
+            },
+        }
+    }
+    builder.into_inner()?;
+
+    Ok(renamed_members)
+}
+
+// the members are copied as they are compressed, only the names in the headers change
+fn shorten_zip(src: &Path, dst: &Path, rules: &Rules) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(src)?)?;
+    let mut member_paths = Vec::new();
+    for i in 0..archive.len() {
+        member_paths.push(PathBuf::from(archive.by_index_raw(i)?.name()));
+    }
+    let member_map = shorten_member_paths(&member_paths, rules)?;
+
+    let mut renamed_members = Vec::new();
+    let mut writer = zip::ZipWriter::new(fs::File::create(dst)?);
+    for (i, path) in member_paths.into_iter().enumerate() {
+        let member = archive.by_index_raw(i)?;
+        let new_name = match member_map.get(&path) {
+            Some(new_path) => {
+                log::info!("Renamed member: {} -> {}", path.display(), new_path.display());
+                // zip names are utf-8, and so are the shortened ones
+                let mut new_name = new_path.to_str().expect("a shortened zip name is utf-8").to_string();
+                if member.is_dir() {
+                    new_name.push('/');
+                }
+                renamed_members.push((path, new_path.clone()));
+                new_name
+            },
+            None => member.name().to_string(),
+        };
+        writer.raw_copy_file_rename(member, new_name)?;
+    }
+    writer.finish()?;
+
+    Ok(renamed_members)
+}
+
+// returns only the members whose paths change
+fn shorten_member_paths(member_paths: &[PathBuf], rules: &Rules) -> Result<HashMap<PathBuf, PathBuf>> {
+    // all the original paths (and their parent directories) are taken from the beginning
+    let mut taken = HashSet::new();
+    for path in member_paths {
+        let mut prefix = PathBuf::new();
+        for component in path.components() {
+            prefix.push(component);
+            taken.insert(prefix.clone());
+        }
+    }
+
+    // a directory is shortened once, and all the members under it follow
+    let mut prefix_map: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut member_map = HashMap::new();
+    for path in member_paths {
+        let mut prefix = PathBuf::new();
+        let mut new_prefix = PathBuf::new();
+        for component in path.components() {
+            prefix.push(component);
+            if let Some(mapped_prefix) = prefix_map.get(&prefix) {
+                new_prefix = mapped_prefix.clone();
+                continue;
+            }
+
+            let name = component.as_os_str();
+            if name.len() <= rules.n_filename_bytes {
+                new_prefix.push(name);
+            } else {
+                // a lossy name would be a different name, not a shorter one
+                let name = name.to_str().ok_or_else(|| Error::NonUtf8Name(prefix.clone()))?;
+                let new_name = shorten_filename_among(name, rules, |candidate| taken.contains(&new_prefix.join(candidate)))?;
+                new_prefix.push(new_name);
+                taken.insert(new_prefix.clone());
+            }
+            prefix_map.insert(prefix.clone(), new_prefix.clone());
+        }
+
+        if &new_prefix != path {
+            member_map.insert(path.clone(), new_prefix);
+        }
+    }
+    Ok(member_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    use env_logger;
+
+    #[test]
+    fn test_shorten_member_paths() {
+        let _ = env_logger::try_init();
+
+        let long_dir = "あ".repeat(100);
+        let long_file = format!("{}.txt", "い".repeat(100));
+        let member_paths = vec![
+            PathBuf::from("a"),
+            PathBuf::from(format!("a/{}", long_dir)),
+            PathBuf::from(format!("a/{}/{}", long_dir, long_file)),
+            PathBuf::from(format!("a/{}", "あ".repeat(85))),
+            PathBuf::from("b.txt"),
+        ];
+
+        let member_map = shorten_member_paths(&member_paths, &Rules::default()).unwrap();
+        assert_eq!(member_map.len(), 2);
+        assert_eq!(member_map[&member_paths[1]], PathBuf::from(format!("a/{}.1", "あ".repeat(84))));
+        assert_eq!(member_map[&member_paths[2]], PathBuf::from(format!("a/{}.1/{}.txt", "あ".repeat(84), "い".repeat(83))));
+
+        let mut long_name = "あ".repeat(100).into_bytes();
+        long_name.push(0xff);
+        assert!(shorten_member_paths(&[PathBuf::from(OsStr::from_bytes(&long_name))], &Rules::default()).is_err());
+    }
+
+    #[test]
+    fn test_is_zip() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("a.zip"), b"PK\x05\x06").unwrap();
+        fs::write(dir.join("a.tar"), b"a.txt").unwrap();
+        fs::write(dir.join("empty"), b"").unwrap();
+        assert!(is_zip(&dir.join("a.zip")).unwrap());
+        assert!(!is_zip(&dir.join("a.tar")).unwrap());
+        assert!(!is_zip(&dir.join("empty")).unwrap());
+    }
+}
@@ -0,0 +1,75 @@
+use std::{path::{Path, PathBuf}, io::{self, BufRead}, collections::HashSet};
+
+// where the existence of the destination candidates is checked, for when the real destination isn't the local directory
+// the names are shortened for, e.g. a remote share or an object store the files are uploaded to afterwards.
+// the remote ones are behind features, `SftpBackend` (sftp) and `S3Bucket` (s3)
+pub trait ExistenceBackend: std::fmt::Debug {
+    fn exists(&self, path: &Path) -> io::Result<bool>;
+}
+
+#[derive(Debug, Default)]
+pub struct LocalBackend;
+
+impl ExistenceBackend for LocalBackend {
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        // a dangling symlink is there too
+        Ok(path.symlink_metadata().is_ok())
+    }
+}
+
+// a snapshot of the paths of a remote store, as listed by e.g. `rclone lsf -R remote:dir`. the listed directory and
+// the directories of the listed paths exist too. the snapshot isn't updated, so the store shouldn't change until the plan is applied
+#[derive(Debug, Default)]
+pub struct ListingBackend {
+    paths: HashSet<PathBuf>,
+}
+
+impl ListingBackend {
+    // one path per line, relative to `root`
+    pub fn read(reader: impl BufRead, root: impl AsRef<Path>) -> io::Result<Self> {
+        let root = root.as_ref();
+        let mut paths = HashSet::from([root.to_path_buf()]);
+        for line in reader.lines() {
+            let line = line?;
+            // rclone lists directories with a trailing slash
+            let line = line.trim_end_matches('/');
+            if line.is_empty() {
+                continue;
+            }
+            let mut path = root.join(line);
+            while paths.insert(path.clone()) {
+                match path.parent() {
+                    Some(parent) if parent != root && !parent.as_os_str().is_empty() => path = parent.to_path_buf(),
+                    _ => break,
+                }
+            }
+        }
+        Ok(Self { paths })
+    }
+}
+
+impl ExistenceBackend for ListingBackend {
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        Ok(self.paths.contains(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_listing_backend() {
+        let _ = env_logger::try_init();
+
+        let listing = "a.txt\nb/\nb/c/d.txt\n";
+        let backend = ListingBackend::read(listing.as_bytes(), "/dst").unwrap();
+        assert!(backend.exists(Path::new("/dst/a.txt")).unwrap());
+        assert!(backend.exists(Path::new("/dst/b/c")).unwrap());
+        assert!(backend.exists(Path::new("/dst/b/c/d.txt")).unwrap());
+        assert!(!backend.exists(Path::new("/dst/d.txt")).unwrap());
+        assert!(backend.exists(Path::new("/dst")).unwrap());
+        assert!(!backend.exists(Path::new("/")).unwrap());
+    }
+}
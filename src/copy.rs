@@ -0,0 +1,387 @@
+use std::{path::{Path, PathBuf}, fs, io::{self, Read, BufReader}, os::{fd::AsRawFd, unix::{fs::{FileExt, MetadataExt}, ffi::OsStrExt}}, collections::HashMap, ffi::CString};
+use anyhow::Result;
+#[cfg(feature = "sha256")]
+use sha2::{Sha256, Digest};
+
+use crate::{Error, PlanEntry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ChecksumAlgorithm {
+    Crc32,
+    #[cfg(feature = "sha256")]
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    // as written in the journal
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32 => "crc32",
+            #[cfg(feature = "sha256")]
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "crc32" => Some(ChecksumAlgorithm::Crc32),
+            #[cfg(feature = "sha256")]
+            "sha256" => Some(ChecksumAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn checksum(&self, path: impl AsRef<Path>) -> io::Result<String> {
+        let mut reader = BufReader::new(fs::File::open(path)?);
+        let mut buf = vec![0; 64 * 1024];
+        match self {
+            ChecksumAlgorithm::Crc32 => {
+                let mut hasher = crate::crc32::Hasher::new();
+                loop {
+                    let n = reader.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    hasher.update(&buf[..n]);
+                }
+                Ok(format!("{:08x}", hasher.finalize()))
+            },
+            #[cfg(feature = "sha256")]
+            ChecksumAlgorithm::Sha256 => {
+                let mut hasher = Sha256::new();
+                loop {
+                    let n = reader.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    hasher.update(&buf[..n]);
+                }
+                Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CopyOptions {
+    // compare the copy with the source by checksum before the source is removed
+    pub verify: Option<ChecksumAlgorithm>,
+    // keep holes of sparse files (e.g. disk images) as holes
+    pub sparse: bool,
+    // give the copy the owner and the group of the source, which only root can do. otherwise the copy is owned by
+    // whoever runs this and gets the group of the directory when it's setgid
+    pub preserve_owner: bool,
+    // dst is the empty placeholder left by --claim, which is written over. otherwise an existing dst is an error
+    pub claimed: bool,
+}
+
+impl CopyOptions {
+    // nothing but the copy itself, which a plain rename falls back to as well
+    pub fn is_plain(&self) -> bool {
+        self.verify.is_none() && !self.sparse && !self.preserve_owner
+    }
+}
+
+// moves the file, falling back to copy and remove when src and dst are on different filesystems.
+// on checksum mismatch the copy is removed instead of the source. the checksum of a verified copy, see `copy_file`
+pub fn move_file(src: impl AsRef<Path>, dst: impl AsRef<Path>, options: &CopyOptions) -> Result<Option<(ChecksumAlgorithm, String)>> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+
+    match fs::rename(src, dst) {
+        Ok(()) => return Ok(None),
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            log::debug!("Different filesystems, copying: {} -> {}", src.display(), dst.display());
+        },
+        Err(e) => return Err(e.into()),
+    }
+
+    let checksum = copy_file(src, dst, options)?;
+    fs::remove_file(src)?;
+    Ok(checksum)
+}
+
+// clones the file when the filesystem supports reflinks (btrfs, xfs, ...), which is instant and shares the data blocks,
+// otherwise copies it. on checksum mismatch the copy is removed. the checksum of the copy when verified, for the journal
+pub fn copy_file(src: impl AsRef<Path>, dst: impl AsRef<Path>, options: &CopyOptions) -> Result<Option<(ChecksumAlgorithm, String)>> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+
+    let src_file = fs::File::open(src)?;
+    let dst_file = create_dst(dst, options.claimed)?;
+    let result = match reflink(&src_file, &dst_file) {
+        Ok(()) => {
+            log::debug!("Cloned: {} -> {}", src.display(), dst.display());
+            Ok(())
+        },
+        // different filesystems, or the filesystem doesn't support reflinks
+        Err(e) if matches!(e.raw_os_error(), Some(libc::EXDEV | libc::EOPNOTSUPP | libc::EINVAL | libc::ENOTTY | libc::ENOSYS)) => {
+            if options.sparse {
+                copy_sparse(&src_file, &dst_file)
+            } else {
+                io::copy(&mut &src_file, &mut &dst_file).map(|_| ())
+            }
+        },
+        Err(e) => Err(e),
+    }.and_then(|()| dst_file.set_permissions(src_file.metadata()?.permissions()));
+    if let Err(e) = result {
+        drop(dst_file);
+        // the placeholder is released by whoever claimed it
+        if !options.claimed {
+            let _ = fs::remove_file(dst);
+        }
+        return Err(e.into());
+    }
+
+    if options.preserve_owner {
+        if let Err(e) = copy_owner(src, dst) {
+            let _ = fs::remove_file(dst);
+            return Err(e.into());
+        }
+    }
+
+    let Some(algorithm) = options.verify else {
+        return Ok(None);
+    };
+    let src_checksum = algorithm.checksum(src)?;
+    let dst_checksum = algorithm.checksum(dst)?;
+    if src_checksum != dst_checksum {
+        let _ = fs::remove_file(dst);
+        return Err(Error::ChecksumMismatch(src.to_path_buf(), dst.to_path_buf()).into());
+    }
+    log::info!("Verified copy ({:?} {}): {}", algorithm, dst_checksum, dst.display());
+    Ok(Some((algorithm, dst_checksum)))
+}
+
+// checks that every destination filesystem has room for the data which will be written there, before anything is
+// moved, rather than failing halfway with ENOSPC. that's the files copied with `copy`, or moved from other
+// filesystems. reflinks would need no room, so this may refuse a copy which would fit.
+pub fn check_free_space(plan: &[PlanEntry], copy: bool) -> Result<()> {
+    check_free_space_impl(plan, copy, available_space)
+}
+
+// dependency injection for testing
+fn check_free_space_impl(plan: &[PlanEntry], copy: bool, mut available_space: impl FnMut(&Path) -> io::Result<u64>) -> Result<()> {
+    // device -> (a directory on it, needed bytes)
+    let mut needed: HashMap<u64, (PathBuf, u64)> = HashMap::new();
+    for entry in plan {
+        if entry.duplicate || entry.src == entry.dst {
+            continue;
+        }
+        let src_metadata = fs::symlink_metadata(&entry.src)?;
+        if !src_metadata.is_file() {
+            continue;
+        }
+        let dst_dir = existing_ancestor(&entry.dst);
+        let dst_dev = fs::metadata(&dst_dir)?.dev();
+        if !copy && src_metadata.dev() == dst_dev {
+            continue;
+        }
+        needed.entry(dst_dev).or_insert((dst_dir, 0)).1 += src_metadata.len();
+    }
+
+    let mut needed = needed.into_values().collect::<Vec<_>>();
+    needed.sort();
+    let mut shortage = None;
+    for (dir, n_needed_bytes) in needed {
+        let n_available_bytes = available_space(&dir)?;
+        log::debug!("Free space of {}: {} bytes needed, {} bytes available", dir.display(), n_needed_bytes, n_available_bytes);
+        if n_available_bytes < n_needed_bytes {
+            log::error!("Not enough free space on the filesystem of {}: {} bytes needed, {} bytes available", dir.display(), n_needed_bytes, n_available_bytes);
+            shortage.get_or_insert(Error::InsufficientSpace(dir, n_needed_bytes, n_available_bytes));
+        }
+    }
+    match shortage {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+// the destination directory may not be created yet
+fn existing_ancestor(path: &Path) -> PathBuf {
+    let mut path = path.parent().unwrap_or(Path::new("."));
+    while !path.exists() {
+        match path.parent() {
+            Some(parent) if parent != Path::new("") => path = parent,
+            _ => return PathBuf::from("."),
+        }
+    }
+    path.to_path_buf()
+}
+
+// bytes available to unprivileged users
+#[allow(clippy::unnecessary_cast)] // the field types differ by platform
+fn available_space(path: &Path) -> io::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+fn copy_owner(src: &Path, dst: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(src)?;
+    std::os::unix::fs::chown(dst, Some(metadata.uid()), Some(metadata.gid()))?;
+    // chown clears the setuid and setgid bits
+    fs::set_permissions(dst, metadata.permissions())
+}
+
+// a file moved into a setgid directory keeps its group, unlike the files created there which get the group of
+// the directory, so the members of that group may lose the access they have to the rest of the directory.
+// returns (the group of the file, the group of the directory) when that happens.
+pub fn setgid_group_mismatch(src: impl AsRef<Path>, dst_dir: impl AsRef<Path>) -> io::Result<Option<(u32, u32)>> {
+    let dir_metadata = fs::metadata(dst_dir)?;
+    // S_ISGID
+    if dir_metadata.mode() & 0o2000 == 0 {
+        return Ok(None);
+    }
+    let gid = fs::symlink_metadata(src)?.gid();
+    Ok(if gid != dir_metadata.gid() { Some((gid, dir_metadata.gid())) } else { None })
+}
+
+// a new file, or the placeholder claimed for it. an existing file is never truncated, it may have been created since
+// the plan
+fn create_dst(dst: &Path, claimed: bool) -> io::Result<fs::File> {
+    if claimed {
+        fs::OpenOptions::new().write(true).truncate(true).open(dst)
+    } else {
+        fs::OpenOptions::new().write(true).create_new(true).open(dst)
+    }
+}
+
+fn reflink(src_file: &fs::File, dst_file: &fs::File) -> io::Result<()> {
+    if unsafe { libc::ioctl(dst_file.as_raw_fd(), libc::FICLONE, src_file.as_raw_fd()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// copies only the data segments found by SEEK_DATA / SEEK_HOLE, so holes stay holes.
+// falls back to a plain copy when the filesystem can't tell where the holes are.
+fn copy_sparse(src_file: &fs::File, dst_file: &fs::File) -> io::Result<()> {
+    let len = src_file.metadata()?.len() as libc::off_t;
+
+    let fd = src_file.as_raw_fd();
+    let mut buf = vec![0; 64 * 1024];
+    let mut offset = 0;
+    while offset < len {
+        let data_start = unsafe { libc::lseek(fd, offset, libc::SEEK_DATA) };
+        if data_start < 0 {
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                // no data after the offset, the rest is a hole
+                Some(libc::ENXIO) => break,
+                Some(libc::EINVAL) if offset == 0 => {
+                    log::debug!("SEEK_DATA not supported, copying as is");
+                    io::copy(&mut &*src_file, &mut &*dst_file)?;
+                    return Ok(());
+                },
+                _ => return Err(e),
+            }
+        }
+        let data_end = unsafe { libc::lseek(fd, data_start, libc::SEEK_HOLE) };
+        if data_end < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut position = data_start as u64;
+        while position < data_end as u64 {
+            let n_bytes = buf.len().min((data_end as u64 - position) as usize);
+            let n = src_file.read_at(&mut buf[..n_bytes], position)?;
+            if n == 0 {
+                break;
+            }
+            dst_file.write_all_at(&buf[..n], position)?;
+            position += n as u64;
+        }
+        offset = data_end;
+    }
+
+    // the trailing hole, if any
+    dst_file.set_len(len as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlanKind;
+    use env_logger;
+
+    #[test]
+    fn test_move_file() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("a"), "abc").unwrap();
+
+        let checksum = ChecksumAlgorithm::Crc32.checksum(dir.join("a")).unwrap();
+        assert_eq!(checksum, "352441c2");
+        move_file(dir.join("a"), dir.join("b"), &CopyOptions { verify: Some(ChecksumAlgorithm::Crc32), ..Default::default() }).unwrap();
+        assert!(!dir.join("a").exists());
+        assert_eq!(ChecksumAlgorithm::Crc32.checksum(dir.join("b")).unwrap(), checksum);
+        assert!(move_file(dir.join("a"), dir.join("c"), &CopyOptions::default()).is_err());
+
+        let checksum = copy_file(dir.join("b"), dir.join("c"), &CopyOptions { verify: Some(ChecksumAlgorithm::Crc32), ..Default::default() }).unwrap();
+        assert_eq!(fs::read(dir.join("b")).unwrap(), fs::read(dir.join("c")).unwrap());
+        assert_eq!(checksum, Some((ChecksumAlgorithm::Crc32, ChecksumAlgorithm::Crc32.checksum(dir.join("c")).unwrap())));
+
+        // giving the copy its own owner is allowed without root
+        assert_eq!(copy_file(dir.join("b"), dir.join("d"), &CopyOptions { preserve_owner: true, ..Default::default() }).unwrap(), None);
+        assert_eq!(fs::metadata(dir.join("d")).unwrap().uid(), fs::metadata(dir.join("b")).unwrap().uid());
+        assert_eq!(setgid_group_mismatch(dir.join("d"), dir).unwrap(), None);
+
+        // an existing file is neither written over nor removed
+        fs::write(dir.join("e"), "x").unwrap();
+        assert!(copy_file(dir.join("b"), dir.join("e"), &CopyOptions::default()).is_err());
+        assert_eq!(fs::read(dir.join("e")).unwrap(), b"x");
+        // unless it's the placeholder claimed for the copy
+        fs::write(dir.join("f"), "").unwrap();
+        copy_file(dir.join("b"), dir.join("f"), &CopyOptions { claimed: true, ..Default::default() }).unwrap();
+        assert_eq!(fs::read(dir.join("f")).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn test_copy_sparse() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let file = fs::File::create(dir.join("a")).unwrap();
+        file.write_all_at(b"head", 0).unwrap();
+        file.write_all_at(b"tail", 1024 * 1024).unwrap();
+        file.set_len(2 * 1024 * 1024).unwrap();
+        drop(file);
+
+        copy_sparse(&fs::File::open(dir.join("a")).unwrap(), &fs::File::create(dir.join("b")).unwrap()).unwrap();
+        assert_eq!(fs::read(dir.join("a")).unwrap(), fs::read(dir.join("b")).unwrap());
+        // the holes are kept, the copy has fewer blocks allocated than its length
+        let metadata = fs::metadata(dir.join("b")).unwrap();
+        assert!(metadata.blocks() * 512 < metadata.len());
+    }
+
+    #[test]
+    fn test_check_free_space() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("a"), "abc").unwrap();
+        fs::write(dir.join("b"), "de").unwrap();
+
+        let plan = [
+            PlanEntry::create_dir(dir.join("x"), dir),
+            PlanEntry { kind: PlanKind::Rename, src: dir.join("a"), dst: dir.join("x/y/a"), duplicate: false, conflict: false },
+            PlanEntry { kind: PlanKind::Rename, src: dir.join("b"), dst: dir.join("b.1"), duplicate: false, conflict: true },
+            PlanEntry::unchanged(dir.join("b")),
+        ];
+        // moves within the filesystem need no room
+        check_free_space_impl(&plan, false, |_| Ok(0)).unwrap();
+        check_free_space_impl(&plan, true, |_| Ok(5)).unwrap();
+        let e = check_free_space_impl(&plan, true, |_| Ok(4)).unwrap_err();
+        assert!(matches!(e.downcast_ref::<Error>(), Some(Error::InsufficientSpace(p, 5, 4)) if p == dir));
+    }
+}
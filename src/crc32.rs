@@ -0,0 +1,63 @@
+// CRC-32 (IEEE), the same as zlib and crc32fast, for the short hashes in names and in the journal and for --verify.
+// a byte at a time through a table, fast enough for the checksums of files
+const TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            crc = if crc & 1 == 1 { 0xedb88320 ^ (crc >> 1) } else { crc >> 1 };
+            k += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Hasher {
+    crc: u32,
+}
+
+impl Hasher {
+    pub(crate) fn new() -> Self {
+        Self { crc: !0 }
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.crc = TABLE[((self.crc ^ *b as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub(crate) fn finalize(self) -> u32 {
+        !self.crc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_crc32() {
+        let _ = env_logger::try_init();
+
+        let crc32 = |bytes: &[u8]| {
+            let mut hasher = Hasher::new();
+            hasher.update(bytes);
+            hasher.finalize()
+        };
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        let mut hasher = Hasher::new();
+        hasher.update(b"1234");
+        hasher.update(b"56789");
+        assert_eq!(hasher.finalize(), 0xcbf43926);
+    }
+}
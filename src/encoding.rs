@@ -0,0 +1,118 @@
+use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+// legacy encodings of the names on the target filesystem, e.g. old NAS shares mounted without iocharset=utf8
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TargetEncoding {
+    // Shift_JIS with the windows extensions (CP932)
+    #[cfg(feature = "shift-jis")]
+    ShiftJis,
+    // ISO-8859-1
+    Latin1,
+}
+
+// what happens to the characters which the target encoding doesn't have
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Unmappable {
+    Drop,
+    // with `_`
+    #[default]
+    Replace,
+    // with similar characters when there are (`é` to `e`), otherwise with `_`
+    Translit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputEncoding {
+    pub target: TargetEncoding,
+    pub unmappable: Unmappable,
+}
+
+impl OutputEncoding {
+    // the number of bytes of the character in the target encoding, None when it isn't there
+    pub(crate) fn char_len(&self, c: char) -> Option<usize> {
+        match self.target {
+            TargetEncoding::Latin1 => if (c as u32) < 0x100 { Some(1) } else { None },
+            #[cfg(feature = "shift-jis")]
+            TargetEncoding::ShiftJis => {
+                let mut buf = [0; 4];
+                let (bytes, _, had_errors) = encoding_rs::SHIFT_JIS.encode(c.encode_utf8(&mut buf));
+                if had_errors { None } else { Some(bytes.len()) }
+            },
+        }
+    }
+
+    // lengths are counted in the target encoding after the unmappable characters are mapped, as written by `encode`
+    pub(crate) fn str_len(&self, s: &str) -> usize {
+        self.map_unmappable(s).chars().map(|c| self.char_len(c).unwrap_or(1)).sum()
+    }
+
+    // leaves only the characters the target encoding has
+    pub(crate) fn map_unmappable(&self, s: &str) -> String {
+        let mut mapped = String::new();
+        for c in s.chars() {
+            if self.char_len(c).is_some() {
+                mapped.push(c);
+                continue;
+            }
+            match self.unmappable {
+                Unmappable::Drop => (),
+                Unmappable::Replace => mapped.push('_'),
+                Unmappable::Translit => {
+                    let folded = c.to_string().nfkc().collect::<String>();
+                    if folded.chars().all(|c| self.char_len(c).is_some()) {
+                        mapped.push_str(&folded);
+                        continue;
+                    }
+                    // the base letter without the accents
+                    let stripped = c.to_string().nfkd().filter(|c| !is_combining_mark(*c)).collect::<String>();
+                    if !stripped.is_empty() && stripped.chars().all(|c| self.char_len(c).is_some()) {
+                        mapped.push_str(&stripped);
+                    } else {
+                        mapped.push('_');
+                    }
+                },
+            }
+        }
+        mapped
+    }
+
+    pub(crate) fn encode(&self, s: &str) -> OsString {
+        let s = self.map_unmappable(s);
+        let bytes = match self.target {
+            TargetEncoding::Latin1 => s.chars().map(|c| c as u8).collect(),
+            #[cfg(feature = "shift-jis")]
+            TargetEncoding::ShiftJis => encoding_rs::SHIFT_JIS.encode(&s).0.into_owned(),
+        };
+        OsString::from_vec(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_output_encoding() {
+        let _ = env_logger::try_init();
+
+        let latin1 = OutputEncoding { target: TargetEncoding::Latin1, unmappable: Unmappable::Translit };
+        assert_eq!(latin1.str_len("café"), 4);
+        assert_eq!(latin1.str_len("ﬁ✓"), 3);
+        assert_eq!(latin1.encode("café"), OsString::from_vec(b"caf\xe9".to_vec()));
+        assert_eq!(latin1.map_unmappable("Ａ✓あ"), "A__");
+    }
+
+    #[cfg(feature = "shift-jis")]
+    #[test]
+    fn test_shift_jis() {
+        let _ = env_logger::try_init();
+
+        let shift_jis = OutputEncoding { target: TargetEncoding::ShiftJis, unmappable: Unmappable::Translit };
+        assert_eq!(shift_jis.str_len("aあ"), 3);
+        assert_eq!(shift_jis.map_unmappable("café"), "cafe");
+        assert_eq!(OutputEncoding { unmappable: Unmappable::Drop, ..shift_jis }.map_unmappable("café"), "caf");
+        assert_eq!(OutputEncoding { unmappable: Unmappable::Replace, ..shift_jis }.map_unmappable("café"), "caf_");
+    }
+}
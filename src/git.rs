@@ -0,0 +1,134 @@
+use std::{path::{Path, PathBuf}, process::{Command, Stdio}, io::{self, Write}, ffi::OsStr, os::unix::ffi::OsStrExt};
+use anyhow::Result;
+use clap::crate_name;
+
+use crate::{Error, script::bash_quote};
+
+// the directory git is run in, so that the work tree is found from the file and not from the current directory
+fn git_dir_of(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    }
+}
+
+// whether the file is in the index of a git work tree, untracked and ignored files are not
+pub fn is_git_tracked(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    let Some(filename) = path.file_name() else {
+        return false;
+    };
+    Command::new("git").arg("-C").arg(git_dir_of(path)).args(["ls-files", "--error-unmatch", "--"]).arg(filename)
+        .stdout(Stdio::null()).stderr(Stdio::null())
+        .status().is_ok_and(|status| status.success())
+}
+
+// renames a tracked file with `git mv`, so that the index follows. git refuses to overwrite an existing destination
+pub fn git_move_file(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    // relative paths would be taken from the directory git runs in
+    let (src_abs, dst_abs) = (std::path::absolute(src)?, std::path::absolute(dst)?);
+    let output = Command::new("git").arg("-C").arg(git_dir_of(&src_abs)).args(["mv", "--"]).arg(&src_abs).arg(&dst_abs)
+        .stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(Error::GitFailed(src.to_path_buf(), String::from_utf8_lossy(&output.stderr).trim().to_string()).into());
+    }
+    Ok(())
+}
+
+// runs git in the directory and returns its stdout
+fn git_output(dir: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(Error::GitFailed(dir.to_path_buf(), String::from_utf8_lossy(&output.stderr).trim().to_string()).into());
+    }
+    Ok(output.stdout)
+}
+
+// the top directory of the work tree the directory is in
+fn git_toplevel(dir: &Path) -> Result<PathBuf> {
+    let stdout = git_output(dir, &["rev-parse", "--show-toplevel"])?;
+    Ok(PathBuf::from(OsStr::from_bytes(stdout.strip_suffix(b"\n").unwrap_or(&stdout))))
+}
+
+// the files added or renamed in the index of the work tree the directory is in, the names a commit would bring in.
+// modified and deleted files are not, their names are in the last commit already
+pub fn staged_paths(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    let toplevel = git_toplevel(dir)?;
+    // the paths are relative to the top directory whatever the directory git runs in
+    let stdout = git_output(dir, &["diff", "--cached", "--name-only", "-z", "--diff-filter=AR"])?;
+    Ok(stdout.split(|b| *b == b'\0').filter(|path| !path.is_empty()).map(|path| toplevel.join(OsStr::from_bytes(path))).collect())
+}
+
+// where the pre-commit hook of the repository the directory is in goes, core.hooksPath if it's set
+pub fn pre_commit_hook_path(dir: impl AsRef<Path>) -> Result<PathBuf> {
+    let dir = dir.as_ref();
+    let stdout = git_output(dir, &["rev-parse", "--git-path", "hooks"])?;
+    // relative to the directory git runs in
+    Ok(dir.join(OsStr::from_bytes(stdout.strip_suffix(b"\n").unwrap_or(&stdout))).join("pre-commit"))
+}
+
+// the pre-commit hook, failing the commit when a staged name would be renamed
+pub fn write_pre_commit_hook(mut writer: impl Write, exe: &Path) -> io::Result<()> {
+    writeln!(writer, "#!/bin/sh")?;
+    writeln!(writer, "# installed by {} hook install", crate_name!())?;
+    writeln!(writer, "exec {} --check --staged", bash_quote(exe))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+    use std::fs;
+
+    #[test]
+    fn test_git_move_file() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        // no git in the environment, nothing to test
+        if !Command::new("git").arg("-C").arg(dir).args(["init", "-q"]).status().is_ok_and(|s| s.success()) {
+            return;
+        }
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("untracked.txt"), "u").unwrap();
+        assert!(Command::new("git").arg("-C").arg(dir).args(["add", "a.txt"]).status().unwrap().success());
+
+        assert!(is_git_tracked(dir.join("a.txt")));
+        assert!(!is_git_tracked(dir.join("untracked.txt")));
+        assert!(!is_git_tracked(dir.join("missing.txt")));
+
+        git_move_file(dir.join("a.txt"), dir.join("b.txt")).unwrap();
+        assert!(!dir.join("a.txt").exists());
+        assert!(is_git_tracked(dir.join("b.txt")));
+        assert!(git_move_file(dir.join("b.txt"), dir.join("untracked.txt")).is_err());
+    }
+
+    #[test]
+    fn test_staged_paths() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        // no git in the environment, nothing to test
+        if !Command::new("git").arg("-C").arg(dir).args(["init", "-q"]).status().is_ok_and(|s| s.success()) {
+            return;
+        }
+        fs::write(dir.join("sub/a b.txt"), "a").unwrap();
+        fs::write(dir.join("unstaged.txt"), "u").unwrap();
+        assert!(Command::new("git").arg("-C").arg(dir).args(["add", "sub"]).status().unwrap().success());
+
+        let toplevel = git_toplevel(dir).unwrap();
+        assert_eq!(staged_paths(dir.join("sub")).unwrap(), vec![toplevel.join("sub/a b.txt")]);
+        assert_eq!(pre_commit_hook_path(dir.join("sub")).unwrap().file_name(), Some(OsStr::new("pre-commit")));
+        assert!(staged_paths(dir.join("missing")).is_err());
+
+        let mut hook = Vec::new();
+        write_pre_commit_hook(&mut hook, Path::new("/usr/bin/it's")).unwrap();
+        assert!(String::from_utf8(hook).unwrap().ends_with(" --check --staged\n"));
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::{Path, Component};
+
+// minimal glob matching on path components:
+// `**` matches any number of components, `*` and `?` match within a component.
+// a leading `~/` is expanded to the home directory. patterns are meant to be matched against absolute paths,
+// so a leading `/` has no meaning other than readability.
+pub(crate) fn glob_match(pattern: &str, path: impl AsRef<Path>) -> bool {
+    let home = std::env::var("HOME").ok();
+    glob_match_impl(pattern, path, home.as_deref())
+}
+
+// dependency injection for testing
+fn glob_match_impl(pattern: &str, path: impl AsRef<Path>, home: Option<&str>) -> bool {
+    let pattern = match (pattern.strip_prefix("~/"), home) {
+        (Some(rest), Some(home)) => format!("{}/{}", home, rest),
+        _ => pattern.to_string(),
+    };
+    let pattern = pattern.split('/').filter(|s| !s.is_empty()).collect::<Vec<_>>();
+    let components = path.as_ref().components().filter_map(|c| match c {
+        Component::Normal(s) => Some(s.to_string_lossy().chars().collect::<Vec<_>>()),
+        _ => None,
+    }).collect::<Vec<_>>();
+    match_components(&pattern, &components)
+}
+
+fn match_components(pattern: &[&str], components: &[Vec<char>]) -> bool {
+    match pattern.split_first() {
+        None => components.is_empty(),
+        Some((&"**", rest)) => (0..=components.len()).any(|i| match_components(rest, &components[i..])),
+        Some((p, rest)) => {
+            let Some((component, remaining_components)) = components.split_first() else {
+                return false;
+            };
+            match_wildcard(&p.chars().collect::<Vec<_>>(), component) && match_components(rest, remaining_components)
+        },
+    }
+}
+
+fn match_wildcard(pattern: &[char], s: &[char]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some(('*', rest)) => (0..=s.len()).any(|i| match_wildcard(rest, &s[i..])),
+        Some(('?', rest)) => !s.is_empty() && match_wildcard(rest, &s[1..]),
+        Some((c, rest)) => s.first() == Some(c) && match_wildcard(rest, &s[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match_impl("/etc/**", "/etc/passwd", None));
+        assert!(glob_match_impl("/etc/**", "/etc/ssh/sshd_config", None));
+        assert!(!glob_match_impl("/etc/**", "/home/etc/passwd", None));
+        assert!(glob_match_impl("~/.ssh/**", "/home/user/.ssh/id_ed25519", Some("/home/user")));
+        assert!(!glob_match_impl("~/.ssh/**", "/home/other/.ssh/id_ed25519", Some("/home/user")));
+        assert!(glob_match_impl("**/.git/**", "/src/repo/.git/objects/ab/cdef", None));
+        assert!(!glob_match_impl("**/.git/**", "/src/repo/.gitignore", None));
+        assert!(glob_match_impl("**/*.sw?", "/tmp/a.txt.swp", None));
+        assert!(!glob_match_impl("**/*.sw?", "/tmp/a.txt.sw", None));
+        assert!(glob_match_impl("/mnt/*/backup/**", "/mnt/disk1/backup/a", None));
+    }
+}
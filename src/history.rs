@@ -0,0 +1,225 @@
+use std::{path::{Path, PathBuf}, fs, ffi::OsStr, os::unix::ffi::OsStrExt};
+use anyhow::Result;
+use rusqlite::{Connection, params};
+
+use crate::JournalEntry;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// the renames of the journal in a sqlite database next to it, with the host they were done on, for finding them by
+// name or time. the journal stays what undo reads, the history is written along with it
+#[derive(Debug)]
+pub struct History {
+    conn: Connection,
+    // of this machine, recorded with the renames
+    hostname: Option<String>,
+}
+
+// substrings of the paths and the range of the time, all of them have to match
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryQuery {
+    pub src: Option<String>,
+    pub dst: Option<String>,
+    // unix time in seconds, from `since` and before `until`
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub run_id: String,
+    pub time: u64,
+    // unknown for the renames imported from the journal
+    pub hostname: Option<String>,
+    pub src: PathBuf,
+    pub dst: PathBuf,
+}
+
+impl History {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        // the paths are blobs, they may not be UTF-8. a rename is there once, whether recorded or imported
+        conn.execute_batch("
+            CREATE TABLE IF NOT EXISTS renames (
+                run_id TEXT NOT NULL,
+                time INTEGER NOT NULL,
+                hostname TEXT,
+                src BLOB NOT NULL,
+                dst BLOB NOT NULL,
+                UNIQUE (run_id, time, src, dst)
+            );
+            CREATE INDEX IF NOT EXISTS renames_time ON renames (time);
+        ")?;
+        Ok(Self { conn, hostname: hostname() })
+    }
+
+    // `journal.sqlite` for `journal.tsv`
+    pub fn path_for(journal_path: &Path) -> PathBuf {
+        journal_path.with_extension("sqlite")
+    }
+
+    // the renames of a run are inserted in one transaction, begun by the first of them and ended by `commit` or when
+    // the history is dropped. the renames of a run which didn't get there are imported from the journal later
+    pub fn record(&self, run_id: &str, time: u64, src: &Path, dst: &Path) -> Result<()> {
+        if self.conn.is_autocommit() {
+            self.conn.execute_batch("BEGIN")?;
+        }
+        self.conn.execute(
+            "INSERT OR IGNORE INTO renames (run_id, time, hostname, src, dst) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![run_id, time as i64, self.hostname, src.as_os_str().as_bytes(), dst.as_os_str().as_bytes()],
+        )?;
+        Ok(())
+    }
+
+    pub fn commit(&self) -> Result<()> {
+        if !self.conn.is_autocommit() {
+            self.conn.execute_batch("COMMIT")?;
+        }
+        Ok(())
+    }
+
+    // adds the renames of the journal which aren't in the history yet, the ones recorded before it was enabled or by a
+    // build without it. the number of the added ones
+    pub fn import(&mut self, entries: &[JournalEntry]) -> Result<usize> {
+        self.commit()?;
+        let tx = self.conn.transaction()?;
+        let mut n_imported = 0;
+        {
+            let mut stmt = tx.prepare("INSERT OR IGNORE INTO renames (run_id, time, src, dst) VALUES (?1, ?2, ?3, ?4)")?;
+            for entry in entries {
+                n_imported += stmt.execute(params![entry.run_id, entry.time as i64, entry.src.as_os_str().as_bytes(), entry.dst.as_os_str().as_bytes()])?;
+            }
+        }
+        tx.commit()?;
+        Ok(n_imported)
+    }
+
+    // in the order of the time
+    pub fn query(&self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>> {
+        let mut stmt = self.conn.prepare("
+            SELECT run_id, time, hostname, src, dst FROM renames
+            WHERE (?1 IS NULL OR instr(src, ?1) > 0) AND (?2 IS NULL OR instr(dst, ?2) > 0)
+                AND (?3 IS NULL OR ?3 <= time) AND (?4 IS NULL OR time < ?4)
+            ORDER BY time, rowid
+        ")?;
+        let src = query.src.as_deref().map(str::as_bytes);
+        let dst = query.dst.as_deref().map(str::as_bytes);
+        let entries = stmt.query_map(params![src, dst, query.since.map(|t| t as i64), query.until.map(|t| t as i64)], |row| {
+            Ok(HistoryEntry {
+                run_id: row.get(0)?,
+                time: row.get::<_, i64>(1)? as u64,
+                hostname: row.get(2)?,
+                src: PathBuf::from(OsStr::from_bytes(&row.get::<_, Vec<u8>>(3)?)),
+                dst: PathBuf::from(OsStr::from_bytes(&row.get::<_, Vec<u8>>(4)?)),
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+}
+
+impl Drop for History {
+    fn drop(&mut self) {
+        if let Err(e) = self.commit() {
+            log::warn!("Failed to commit the history: {}", e);
+        }
+    }
+}
+
+fn hostname() -> Option<String> {
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname").ok()?;
+    Some(hostname.trim().to_string()).filter(|hostname| !hostname.is_empty())
+}
+
+// the unix time of the start of the day (UTC) of `2024-05-01`
+pub fn parse_date(s: &str) -> Option<u64> {
+    let mut fields = s.splitn(3, '-').map(|field| field.parse::<i64>().ok());
+    let (year, month, day) = (fields.next()??, fields.next()??, fields.next()??);
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    // days from 1970-01-01, counted in eras of 400 years from march, which puts the leap day last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if 2 < month { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    u64::try_from(days).ok().map(|days| days * SECONDS_PER_DAY)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// `2024-05-01 12:34:56` in UTC, the inverse of `parse_date` for the day
+pub fn format_time(time: u64) -> String {
+    let days = (time / SECONDS_PER_DAY) as i64 + 719468;
+    let seconds = time % SECONDS_PER_DAY;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_dates() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2024-03-01"), Some(1709251200));
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("2024-02-31"), None);
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("2000-02-29"), Some(951782400));
+        assert_eq!(parse_date("1900-02-29"), None);
+        assert_eq!(parse_date("2024-04-31"), None);
+        assert_eq!(parse_date("1969-12-31"), None);
+        assert_eq!(parse_date("yesterday"), None);
+        assert_eq!(format_time(1709251200 - 1), "2024-02-29 23:59:59");
+        assert_eq!(format_time(parse_date("2000-12-31").unwrap() + 3661), "2000-12-31 01:01:01");
+    }
+
+    #[test]
+    fn test_history() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let mut history = History::open(History::path_for(&dir.join("journal.tsv"))).unwrap();
+        let src = PathBuf::from(OsStr::from_bytes(b"/a/long\xff.txt"));
+        history.record("1", 100, &src, Path::new("/a/l.txt")).unwrap();
+        history.commit().unwrap();
+
+        // the recorded one isn't imported again
+        let entry = |run_id: &str, time: u64, src: &Path, dst: &str| JournalEntry { run_id: run_id.to_string(), time, src: src.to_path_buf(), dst: PathBuf::from(dst), checksum: None, config_hash: None };
+        assert_eq!(history.import(&[entry("1", 100, &src, "/a/l.txt"), entry("2", 200, Path::new("/b/x.txt"), "/b/y.txt")]).unwrap(), 1);
+
+        let found = history.query(&HistoryQuery { src: Some("long".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].src, src);
+        assert_eq!(found[0].hostname, hostname());
+        let found = history.query(&HistoryQuery { dst: Some("y.txt".to_string()), since: Some(150), ..Default::default() }).unwrap();
+        assert_eq!(found, vec![HistoryEntry { run_id: "2".to_string(), time: 200, hostname: None, src: PathBuf::from("/b/x.txt"), dst: PathBuf::from("/b/y.txt") }]);
+        assert_eq!(history.query(&HistoryQuery { until: Some(100), ..Default::default() }).unwrap(), vec![]);
+        assert_eq!(history.query(&HistoryQuery::default()).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt::Display;
+#[cfg(feature = "i18n")]
+use fluent_bundle::{FluentArgs, FluentBundle, FluentResource, FluentValue};
+#[cfg(feature = "i18n")]
+use unic_langid::LanguageIdentifier;
+
+// by language. the english one has the messages written in the code, so that a message missing there still reads the same
+#[cfg(feature = "i18n")]
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("ja", include_str!("../locales/ja.ftl")),
+];
+
+#[cfg(feature = "i18n")]
+thread_local! {
+    // of the language of the locale, none for a language without a catalog
+    static BUNDLE: Option<FluentBundle<FluentResource>> = message_language().and_then(|language| bundle(&language));
+}
+
+// the message `id` of the catalog of the language of the locale, or the english one written here without a catalog for
+// the language or without the `i18n` feature. the arguments are `{name}` in the english one and `{ $name }` in the catalogs.
+// only for the terminal: the errors in --json, the journal and the other files read by programs stay in english
+#[macro_export]
+macro_rules! tr {
+    // a match rather than lets, for the temporaries of the values (`dir(args).display()`) to live through the message
+    ($id:literal, $english:literal $(, $name:ident = $value:expr)* $(,)?) => {
+        match ($(&$value,)*) {
+            ($($name,)*) => $crate::localize($id, &[$((stringify!($name), $name as &dyn std::fmt::Display)),*]).unwrap_or_else(|| format!($english)),
+        }
+    };
+}
+
+// see `tr!`
+pub fn localize(id: &str, args: &[(&str, &dyn Display)]) -> Option<String> {
+    #[cfg(feature = "i18n")]
+    return BUNDLE.with(|bundle| format_message(bundle.as_ref()?, id, args));
+    #[cfg(not(feature = "i18n"))]
+    {
+        let _ = (id, args);
+        None
+    }
+}
+
+// as gettext picks it: LC_ALL, LC_MESSAGES, then LANG. `ja_JP.UTF-8` is `ja`, none for the C locale
+pub fn message_language() -> Option<String> {
+    language_of(|name| std::env::var(name).ok())
+}
+
+fn language_of(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"].into_iter().filter_map(var).find(|locale| !locale.is_empty())?;
+    let language = locale.split(['_', '.', '@']).next().unwrap_or_default().to_lowercase();
+    if language.is_empty() || language == "c" || language == "posix" {
+        return None;
+    }
+    Some(language)
+}
+
+#[cfg(feature = "i18n")]
+fn bundle(language: &str) -> Option<FluentBundle<FluentResource>> {
+    let (_, catalog) = CATALOGS.iter().find(|(catalog_language, _)| *catalog_language == language)?;
+    let language_id = language.parse::<LanguageIdentifier>().ok()?;
+    let resource = FluentResource::try_new(catalog.to_string()).inspect_err(|(_, errors)| log::warn!("Catalog {} has errors: {:?}", language, errors)).ok()?;
+    let mut bundle = FluentBundle::new(vec![language_id]);
+    // the isolation marks around the arguments show up as they are in terminals
+    bundle.set_use_isolating(false);
+    bundle.add_resource(resource).inspect_err(|errors| log::warn!("Catalog {} has errors: {:?}", language, errors)).ok()?;
+    Some(bundle)
+}
+
+#[cfg(feature = "i18n")]
+fn format_message(bundle: &FluentBundle<FluentResource>, id: &str, args: &[(&str, &dyn Display)]) -> Option<String> {
+    let pattern = bundle.get_message(id)?.value()?;
+    let mut fluent_args = FluentArgs::new();
+    for (name, value) in args {
+        fluent_args.set(*name, FluentValue::from(value.to_string()));
+    }
+    let mut errors = Vec::new();
+    let message = bundle.format_pattern(pattern, Some(&fluent_args), &mut errors);
+    // an argument missing in the catalog, the english one has them all
+    if !errors.is_empty() {
+        log::debug!("Message {} has errors: {:?}", id, errors);
+        return None;
+    }
+    Some(message.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_language_of() {
+        let _ = env_logger::try_init();
+
+        let locales = |lc_all: &'static str, lang: &'static str| move |name: &str| match name {
+            "LC_ALL" => Some(lc_all.to_string()),
+            "LANG" => Some(lang.to_string()),
+            _ => None,
+        };
+        assert_eq!(language_of(locales("", "ja_JP.UTF-8")), Some("ja".to_string()));
+        assert_eq!(language_of(locales("en_US.UTF-8", "ja_JP.UTF-8")), Some("en".to_string()));
+        assert_eq!(language_of(locales("C", "ja_JP.UTF-8")), None);
+        assert_eq!(language_of(locales("", "")), None);
+        assert_eq!(language_of(|_| None), None);
+    }
+
+    #[test]
+    fn test_tr() {
+        let _ = env_logger::try_init();
+
+        // no catalog has it
+        assert_eq!(crate::tr!("test-missing", "{n} files in {dir}", n = 3, dir = "a"), "3 files in a");
+    }
+
+    #[cfg(feature = "i18n")]
+    #[test]
+    fn test_catalogs() {
+        let _ = env_logger::try_init();
+
+        let ids = |catalog: &str| {
+            let mut ids = catalog.lines().filter_map(|line| line.split_once(" =")).map(|(id, _)| id.to_string()).filter(|id| !id.starts_with(['#', ' '])).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        let (_, english) = CATALOGS[0];
+        for (language, catalog) in CATALOGS {
+            assert_eq!(ids(catalog), ids(english), "{}", language);
+            let bundle = bundle(language).unwrap();
+            assert!(format_message(&bundle, "error-batch", &[("n", &3)]).unwrap().contains('3'));
+        }
+        let bundle = bundle("ja").unwrap();
+        assert_eq!(format_message(&bundle, "error-batch", &[("n", &3)]), Some("3 個のファイルの名前を変更できませんでした".to_string()));
+        // an argument missing
+        assert_eq!(format_message(&bundle, "error-batch", &[]), None);
+    }
+}
@@ -0,0 +1,100 @@
+use std::{path::{Path, PathBuf}, io::{self, Write}};
+use clap::crate_name;
+
+use crate::script::bash_quote;
+
+const ACTION_NAME: &str = "Shorten long filenames";
+
+// file managers whose context menu can run the tool on the selected files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FileManager {
+    // a script in ~/.local/share/nautilus/scripts, under Scripts in the menu
+    Nautilus,
+    // a service menu of dolphin (kde 5.85 or later)
+    Dolphin,
+}
+
+impl FileManager {
+    pub fn all() -> &'static [Self] {
+        &[Self::Nautilus, Self::Dolphin]
+    }
+
+    // where the entry is installed for the current user, $XDG_DATA_HOME or ~/.local/share
+    pub fn integration_path(&self) -> Option<PathBuf> {
+        let data_dir = match std::env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/share"),
+        };
+        Some(match self {
+            Self::Nautilus => data_dir.join("nautilus/scripts").join(ACTION_NAME),
+            Self::Dolphin => data_dir.join("kio/servicemenus").join(format!("{}.desktop", crate_name!())),
+        })
+    }
+
+    // writes the entry running `exe --gui-confirm` for each selected file. both have to be executable to be picked up
+    pub fn write_integration(&self, mut writer: impl Write, exe: &Path) -> io::Result<()> {
+        match self {
+            Self::Nautilus => {
+                writeln!(writer, "#!/bin/sh")?;
+                writeln!(writer, "# installed by {} install-integration", crate_name!())?;
+                writeln!(writer, "for path in \"$@\"; do")?;
+                writeln!(writer, "    {} --gui-confirm -- \"$path\"", bash_quote(exe))?;
+                writeln!(writer, "done")?;
+            },
+            Self::Dolphin => {
+                writeln!(writer, "[Desktop Entry]")?;
+                writeln!(writer, "Type=Service")?;
+                writeln!(writer, "MimeType=all/allfiles;inode/directory;")?;
+                writeln!(writer, "Actions=shorten;")?;
+                writeln!(writer, "X-KDE-ServiceTypes=KonqPopupMenu/Plugin")?;
+                writeln!(writer)?;
+                writeln!(writer, "[Desktop Action shorten]")?;
+                writeln!(writer, "Name={}", ACTION_NAME)?;
+                writeln!(writer, "Icon=edit-rename")?;
+                // %f runs one process per selected file
+                writeln!(writer, "Exec={} --gui-confirm -- %f", desktop_exec_quote(exe))?;
+            },
+        }
+        Ok(())
+    }
+}
+
+// the quoting of the Exec key of desktop entries: `"`, `` ` ``, `$` and `\` are escaped in double quotes, then every
+// `\` is escaped again as in any string value. `%` is for the field codes
+fn desktop_exec_quote(path: &Path) -> String {
+    let mut quoted = String::from("\"");
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '"' | '`' | '$' => {
+                quoted.push_str(r"\\");
+                quoted.push(c);
+            },
+            '\\' => quoted.push_str(r"\\\\"),
+            '%' => quoted.push_str("%%"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_write_integration() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(desktop_exec_quote(Path::new("/opt/a b/$x%")), r#""/opt/a b/\\$x%%""#);
+
+        let mut script = Vec::new();
+        FileManager::Nautilus.write_integration(&mut script, Path::new("/opt/it's/tool")).unwrap();
+        assert!(String::from_utf8(script).unwrap().contains(r#"    '/opt/it'\''s/tool' --gui-confirm -- "$path""#));
+
+        let mut desktop = Vec::new();
+        FileManager::Dolphin.write_integration(&mut desktop, Path::new("/usr/bin/tool")).unwrap();
+        assert!(String::from_utf8(desktop).unwrap().contains("Exec=\"/usr/bin/tool\" --gui-confirm -- %f\n"));
+    }
+}
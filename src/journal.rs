@@ -0,0 +1,531 @@
+use std::{path::{Path, PathBuf}, fs, io::{self, Write, BufRead, BufReader}, ffi::OsStr, os::unix::ffi::OsStrExt, time::{SystemTime, UNIX_EPOCH}};
+#[cfg(feature = "history-db")]
+use std::rc::Rc;
+use anyhow::Result;
+use clap::crate_name;
+
+use crate::{Error, ChecksumAlgorithm, ExistenceBackend, Planner, PlanEntry};
+#[cfg(feature = "json")]
+use crate::ConfigSnapshot;
+#[cfg(feature = "history-db")]
+use crate::History;
+
+// same policy as the manifest: this and older versions are read, newer ones are refused.
+// 2 added the checksum column, 3 the hash of the config.
+pub const JOURNAL_VERSION: u32 = 3;
+const JOURNAL_VERSION_PREFIX: &str = "# journal version ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub run_id: String,
+    // unix time in seconds
+    pub time: u64,
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    // of the renamed file, if it was asked for
+    pub checksum: Option<(ChecksumAlgorithm, String)>,
+    // of the config of the run, see `ConfigSnapshot::hash`. the snapshot itself is in the directory of `Journal::config_snapshot`
+    pub config_hash: Option<String>,
+}
+
+// appends every rename to a tab separated file, so that a whole run can be undone later
+#[derive(Debug, Clone)]
+pub struct Journal {
+    path: PathBuf,
+    // recorded with the renames
+    config_hash: Option<String>,
+    // the renames are recorded there too
+    #[cfg(feature = "history-db")]
+    history: Option<Rc<History>>,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum UndoConflict {
+    #[error("Renamed file no longer exists: {0}")]
+    DestinationMissing(PathBuf),
+    #[error("Original path is taken: {0}")]
+    SourceTaken(PathBuf),
+    #[error("Renamed again by run {1}: {0}")]
+    MovedLater(PathBuf, String),
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum JournalIssue {
+    #[error("Renamed file no longer exists: {0} (run {1})")]
+    DestinationMissing(PathBuf, String),
+    #[error("Renamed file was renamed again: {0} (run {1}, again by run {2})")]
+    MovedLater(PathBuf, String, String),
+    #[error("Renamed file has changed since: {0} (run {1})")]
+    ChecksumMismatch(PathBuf, String),
+    #[error("Failed to read renamed file: {0} (run {1}): {2}")]
+    Unreadable(PathBuf, String, String),
+}
+
+impl UndoConflict {
+    // the message in the language of the locale, see `tr!`
+    pub fn localized(&self) -> String {
+        match self {
+            Self::DestinationMissing(path) => crate::tr!("undo-destination-missing", "Renamed file no longer exists: {path}", path = path.display()),
+            Self::SourceTaken(path) => crate::tr!("undo-source-taken", "Original path is taken: {path}", path = path.display()),
+            Self::MovedLater(path, run_id) => crate::tr!("undo-moved-later", "Renamed again by run {run_id}: {path}", run_id = run_id, path = path.display()),
+        }
+    }
+}
+
+impl JournalIssue {
+    // the message in the language of the locale, see `tr!`
+    pub fn localized(&self) -> String {
+        match self {
+            Self::DestinationMissing(path, run_id) => crate::tr!("journal-destination-missing", "Renamed file no longer exists: {path} (run {run_id})", path = path.display(), run_id = run_id),
+            Self::MovedLater(path, run_id, later_run_id) => crate::tr!("journal-moved-later", "Renamed file was renamed again: {path} (run {run_id}, again by run {later_run_id})", path = path.display(), run_id = run_id, later_run_id = later_run_id),
+            Self::ChecksumMismatch(path, run_id) => crate::tr!("journal-checksum-mismatch", "Renamed file has changed since: {path} (run {run_id})", path = path.display(), run_id = run_id),
+            Self::Unreadable(path, run_id, e) => crate::tr!("journal-unreadable", "Failed to read renamed file: {path} (run {run_id}): {error}", path = path.display(), run_id = run_id, error = e),
+        }
+    }
+}
+
+// unique enough for a single user's journal, and sorts by time
+pub fn new_run_id() -> String {
+    format!("{}-{}", now(), std::process::id())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl Journal {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            config_hash: None,
+            #[cfg(feature = "history-db")]
+            history: None,
+        }
+    }
+
+    #[cfg(feature = "history-db")]
+    pub fn with_history(mut self, history: History) -> Self {
+        self.history = Some(Rc::new(history));
+        self
+    }
+
+    // ends the transaction of the renames recorded in the history so far, see `History::record`
+    pub fn commit(&self) {
+        #[cfg(feature = "history-db")]
+        if let Some(history) = &self.history {
+            if let Err(e) = history.commit() {
+                log::warn!("Failed to record in the history: {}", e);
+            }
+        }
+    }
+
+    // records the hash of the config with the renames, and saves the config by the hash next to the journal, once for
+    // every config. the renames are still recorded when the config can't be saved
+    #[cfg(feature = "json")]
+    pub fn with_config(mut self, snapshot: &ConfigSnapshot) -> Self {
+        let hash = snapshot.hash();
+        let path = self.config_snapshot_path(&hash);
+        if !path.exists() {
+            if let Err(e) = fs::create_dir_all(self.config_snapshot_dir()).and_then(|_| fs::write(&path, snapshot.to_json())) {
+                log::warn!("Failed to save the config: {}: {}", path.display(), e);
+            }
+        }
+        self.config_hash = Some(hash);
+        self
+    }
+
+    // the config recorded with the hash, none if it isn't saved
+    #[cfg(feature = "json")]
+    pub fn config_snapshot(&self, hash: &str) -> Result<Option<ConfigSnapshot>> {
+        match fs::read_to_string(self.config_snapshot_path(hash)) {
+            Ok(json) => Ok(Some(ConfigSnapshot::from_json(&json)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // `journal.configs` for `journal.tsv`
+    #[cfg(feature = "json")]
+    fn config_snapshot_dir(&self) -> PathBuf {
+        self.path.with_extension("configs")
+    }
+
+    #[cfg(feature = "json")]
+    fn config_snapshot_path(&self, hash: &str) -> PathBuf {
+        self.config_snapshot_dir().join(format!("{}.json", hash))
+    }
+
+    // $XDG_STATE_HOME/rename-for-linux-limit/journal.tsv, ~/.local/state if not set
+    pub fn default_path() -> Option<PathBuf> {
+        let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+        };
+        Some(state_dir.join(crate_name!()).join("journal.tsv"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&self, run_id: &str, src: impl AsRef<Path>, dst: impl AsRef<Path>, checksum: Option<(ChecksumAlgorithm, String)>) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // the undo may run in another directory
+        let src = absolute_unless_uri(src.as_ref())?;
+        let dst = absolute_unless_uri(dst.as_ref())?;
+
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}{}", JOURNAL_VERSION_PREFIX, JOURNAL_VERSION)?;
+        }
+        // a single write, so that concurrent runs don't interleave within a line
+        let checksum = checksum.map(|(algorithm, checksum)| format!("{}:{}", algorithm.name(), checksum)).unwrap_or_default();
+        let config_hash = self.config_hash.as_deref().unwrap_or_default();
+        let time = now();
+        let line = format!("{}\t{}\t{}\t{}\t{}\t{}\n", run_id, time, escape_path(&src), escape_path(&dst), checksum, config_hash);
+        file.write_all(line.as_bytes())?;
+        // the journal is what undo reads, a rename missing in the history is imported from it later
+        #[cfg(feature = "history-db")]
+        if let Some(history) = &self.history {
+            if let Err(e) = history.record(run_id, time, &src, &dst) {
+                log::warn!("Failed to record in the history: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // in the recorded order, empty if nothing has been recorded yet
+    pub fn entries(&self) -> Result<Vec<JournalEntry>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if i == 0 {
+                if let Some(version) = line.strip_prefix(JOURNAL_VERSION_PREFIX) {
+                    let version = version.parse::<u32>().map_err(|_| Error::InvalidFormat(line.clone()))?;
+                    if JOURNAL_VERSION < version {
+                        return Err(Error::UnsupportedFormatVersion(version, JOURNAL_VERSION).into());
+                    }
+                    continue;
+                }
+            }
+            let fields = line.split('\t').collect::<Vec<_>>();
+            // version 1 has no checksum, 2 no config hash
+            let (run_id, time, src, dst, checksum, config_hash) = match fields[..] {
+                [run_id, time, src, dst] => (run_id, time, src, dst, "", ""),
+                [run_id, time, src, dst, checksum] => (run_id, time, src, dst, checksum, ""),
+                [run_id, time, src, dst, checksum, config_hash] => (run_id, time, src, dst, checksum, config_hash),
+                _ => return Err(Error::InvalidFormat(line.clone()).into()),
+            };
+            let config_hash = Some(config_hash.to_string()).filter(|hash| !hash.is_empty());
+            let checksum = if checksum.is_empty() {
+                None
+            } else {
+                let (algorithm, checksum) = checksum.split_once(':').ok_or_else(|| Error::InvalidFormat(line.clone()))?;
+                let algorithm = ChecksumAlgorithm::from_name(algorithm).ok_or_else(|| Error::InvalidFormat(line.clone()))?;
+                Some((algorithm, checksum.to_string()))
+            };
+            let time = time.parse().map_err(|_| Error::InvalidFormat(line.clone()))?;
+            let (Some(src), Some(dst)) = (unescape_path(src), unescape_path(dst)) else {
+                return Err(Error::InvalidFormat(line.clone()).into());
+            };
+            entries.push(JournalEntry { run_id: run_id.to_string(), time, src, dst, checksum, config_hash });
+        }
+        Ok(entries)
+    }
+
+    pub fn last_run_id(&self) -> Result<Option<String>> {
+        Ok(self.entries()?.pop().map(|entry| entry.run_id))
+    }
+}
+
+// `s3://bucket/key` is kept as it is, it would be `$PWD/s3:/bucket/key` otherwise
+fn absolute_unless_uri(path: &Path) -> io::Result<PathBuf> {
+    if path.as_os_str().as_bytes().starts_with(b"s3://") {
+        return Ok(path.to_path_buf());
+    }
+    std::path::absolute(path)
+}
+
+// the renames (from, to) which reverse the run, in the order to apply them, and what prevents reversing the rest
+pub fn plan_undo(entries: &[JournalEntry], run_id: &str) -> (Vec<(PathBuf, PathBuf)>, Vec<UndoConflict>) {
+    plan_undo_impl(entries, run_id, |p| p.symlink_metadata().is_ok())
+}
+
+// for runs on a remote store, e.g. the keys of a bucket. a failed check counts as taken, the rename fails then if it wasn't
+pub fn plan_undo_with(entries: &[JournalEntry], run_id: &str, backend: &dyn ExistenceBackend) -> (Vec<(PathBuf, PathBuf)>, Vec<UndoConflict>) {
+    plan_undo_impl(entries, run_id, |p| backend.exists(p).unwrap_or_else(|e| {
+        log::warn!("Failed to check existence: {}: {}", p.display(), e);
+        true
+    }))
+}
+
+// dependency injection for testing
+fn plan_undo_impl(entries: &[JournalEntry], run_id: &str, mut exists: impl FnMut(&Path) -> bool) -> (Vec<(PathBuf, PathBuf)>, Vec<UndoConflict>) {
+    let Some(last_index) = entries.iter().rposition(|entry| entry.run_id == run_id) else {
+        return (Vec::new(), Vec::new());
+    };
+    let later_entries = &entries[last_index + 1..];
+
+    let mut steps: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut conflicts = Vec::new();
+    // the last rename first: a directory is renamed after its contents, so it has to come back before them
+    for entry in entries[..=last_index].iter().rev().filter(|entry| entry.run_id == run_id) {
+        if let Some(later_entry) = later_entries.iter().find(|later_entry| later_entry.src == entry.dst) {
+            conflicts.push(UndoConflict::MovedLater(entry.dst.clone(), later_entry.run_id.clone()));
+            continue;
+        }
+        if !current_path(&entry.dst, &steps).is_some_and(|p| exists(&p)) {
+            conflicts.push(UndoConflict::DestinationMissing(entry.dst.clone()));
+            continue;
+        }
+        if current_path(&entry.src, &steps).is_some_and(|p| exists(&p)) {
+            conflicts.push(UndoConflict::SourceTaken(entry.src.clone()));
+            continue;
+        }
+        // the earlier steps have put the file back at the recorded destination by then
+        steps.push((entry.dst.clone(), entry.src.clone()));
+    }
+    (steps, conflicts)
+}
+
+// what can't be trusted for undo any more, for all entries, or those of a run
+pub fn verify_journal(entries: &[JournalEntry], run_id: Option<&str>) -> Vec<JournalIssue> {
+    verify_journal_impl(entries, run_id, |p| p.symlink_metadata().is_ok(), |p, algorithm| algorithm.checksum(p))
+}
+
+// dependency injection for testing
+fn verify_journal_impl(entries: &[JournalEntry], run_id: Option<&str>, mut exists: impl FnMut(&Path) -> bool, mut checksum: impl FnMut(&Path, ChecksumAlgorithm) -> io::Result<String>) -> Vec<JournalIssue> {
+    let mut issues = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if run_id.is_some_and(|run_id| run_id != entry.run_id) {
+            continue;
+        }
+        if let Some(later_entry) = entries[i + 1..].iter().find(|later_entry| later_entry.src == entry.dst) {
+            issues.push(JournalIssue::MovedLater(entry.dst.clone(), entry.run_id.clone(), later_entry.run_id.clone()));
+            continue;
+        }
+        if !exists(&entry.dst) {
+            issues.push(JournalIssue::DestinationMissing(entry.dst.clone(), entry.run_id.clone()));
+            continue;
+        }
+        if let Some((algorithm, recorded_checksum)) = &entry.checksum {
+            match checksum(&entry.dst, *algorithm) {
+                Ok(checksum) if checksum == *recorded_checksum => (),
+                Ok(_) => issues.push(JournalIssue::ChecksumMismatch(entry.dst.clone(), entry.run_id.clone())),
+                Err(e) => issues.push(JournalIssue::Unreadable(entry.dst.clone(), entry.run_id.clone(), e.to_string())),
+            }
+        }
+    }
+    issues
+}
+
+// the filesystem as it was before the entry was renamed, as far as it can be told now: the renamed file is what is at
+// the new path, so the name isn't taken
+#[derive(Debug)]
+struct ReplayBackend {
+    renamed: PathBuf,
+}
+
+impl ExistenceBackend for ReplayBackend {
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        Ok(path != self.renamed && path.symlink_metadata().is_ok())
+    }
+}
+
+// plans the rename of the entry again with the rules of the planner, for reproducing how the name was chosen. the other
+// files in the directory may have changed since, and so may the config
+pub fn replay_entry(entry: &JournalEntry, planner: Planner) -> Result<PlanEntry> {
+    let dst_dir = entry.dst.parent().filter(|dir| Some(*dir) != entry.src.parent());
+    planner.backend(ReplayBackend { renamed: entry.dst.clone() }).plan(&entry.src, dst_dir)
+}
+
+// where what will be at the path after the planned steps is now, none if the steps leave the path empty
+fn current_path(path: &Path, steps: &[(PathBuf, PathBuf)]) -> Option<PathBuf> {
+    let mut path = path.to_path_buf();
+    for (from, to) in steps.iter().rev() {
+        if let Ok(rest) = path.strip_prefix(to) {
+            path = if rest.as_os_str().is_empty() { from.clone() } else { from.join(rest) };
+        } else if path.starts_with(from) {
+            return None;
+        }
+    }
+    Some(path)
+}
+
+// paths may contain tabs, newlines and bytes which aren't utf-8
+pub(crate) fn escape_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\t' => escaped.push_str("\\t"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                c => escaped.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    escaped
+}
+
+pub(crate) fn unescape_path(escaped: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::new();
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.next()? {
+            '\\' => bytes.push(b'\\'),
+            't' => bytes.push(b'\t'),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            'x' => {
+                let hex = [chars.next()?, chars.next()?].iter().collect::<String>();
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+            },
+            _ => return None,
+        }
+    }
+    Some(PathBuf::from(OsStr::from_bytes(&bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_journal() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let journal = Journal::new(dir.join("journal.tsv"));
+        assert_eq!(journal.entries().unwrap(), vec![]);
+
+        let src = dir.join(OsStr::from_bytes(b"a\tb\\c\n\xff"));
+        journal.record("1", &src, "x", None).unwrap();
+        journal.record("2", "y", "z", Some((ChecksumAlgorithm::Crc32, "0123abcd".to_string()))).unwrap();
+        let entries = journal.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].src, src);
+        assert_eq!(entries[1].dst, std::path::absolute("z").unwrap());
+        assert_eq!(entries[1].checksum, Some((ChecksumAlgorithm::Crc32, "0123abcd".to_string())));
+        assert_eq!(journal.last_run_id().unwrap(), Some("2".to_string()));
+        assert_eq!(entries[1].config_hash, None);
+
+        #[cfg(feature = "json")]
+        let snapshot = ConfigSnapshot::load();
+        #[cfg(feature = "json")]
+        let journal = journal.with_config(&snapshot);
+        journal.record("3", "y", "z", None).unwrap();
+        #[cfg(feature = "json")]
+        {
+            assert_eq!(journal.entries().unwrap()[2].config_hash, Some(snapshot.hash()));
+            assert!(journal.config_snapshot_path(&snapshot.hash()).exists());
+            assert!(journal.config_snapshot("00000000").unwrap().is_none());
+        }
+
+        // keys of a bucket are recorded as they are, and undone against the bucket
+        let key_path = |key: &str| PathBuf::from(format!("s3://b/{}", key));
+        journal.record("4", key_path("x/long-name.txt"), key_path("x/a.txt"), None).unwrap();
+        let entries = journal.entries().unwrap();
+        assert_eq!(entries[3].src.to_str(), Some("s3://b/x/long-name.txt"));
+        assert_eq!(entries[3].dst.to_str(), Some("s3://b/x/a.txt"));
+        let listing = crate::ListingBackend::read("x/a.txt\n".as_bytes(), key_path("")).unwrap();
+        let (steps, conflicts) = plan_undo_with(&entries, "4", &listing);
+        assert_eq!(steps, vec![(key_path("x/a.txt"), key_path("x/long-name.txt"))]);
+        assert_eq!(conflicts, vec![]);
+        #[cfg(feature = "s3")]
+        assert_eq!(crate::S3Bucket::parse_uri("s3://b/").unwrap().0.key(&steps[0].1), Some("x/long-name.txt".to_string()));
+    }
+
+    #[test]
+    fn test_plan_undo() {
+        let _ = env_logger::try_init();
+
+        let entry = |run_id: &str, src: &str, dst: &str| JournalEntry { run_id: run_id.to_string(), time: 0, src: PathBuf::from(src), dst: PathBuf::from(dst), checksum: None, config_hash: None };
+        let entries = vec![
+            entry("1", "d/long-file", "d/file"),
+            entry("1", "d", "e"),
+            entry("2", "f/x", "f/y"),
+            entry("3", "f/y", "f/z"),
+        ];
+
+        // the directory first, then the file inside it at its restored path
+        let existing = ["e", "e/file", "f/z"].map(PathBuf::from);
+        let (steps, conflicts) = plan_undo_impl(&entries, "1", |p| existing.iter().any(|e| e == p));
+        assert_eq!(steps, vec![(PathBuf::from("e"), PathBuf::from("d")), (PathBuf::from("d/file"), PathBuf::from("d/long-file"))]);
+        assert_eq!(conflicts, vec![]);
+
+        let (steps, conflicts) = plan_undo_impl(&entries, "2", |p| existing.iter().any(|e| e == p));
+        assert_eq!(steps, vec![]);
+        assert_eq!(conflicts, vec![UndoConflict::MovedLater(PathBuf::from("f/y"), "3".to_string())]);
+
+        let (steps, conflicts) = plan_undo_impl(&entries, "3", |p| p == Path::new("f/y"));
+        assert_eq!(steps, vec![]);
+        assert_eq!(conflicts, vec![UndoConflict::DestinationMissing(PathBuf::from("f/z"))]);
+    }
+
+    #[test]
+    fn test_verify_journal() {
+        let _ = env_logger::try_init();
+
+        let entry = |run_id: &str, src: &str, dst: &str, checksum: Option<&str>| JournalEntry {
+            run_id: run_id.to_string(),
+            time: 0,
+            src: PathBuf::from(src),
+            dst: PathBuf::from(dst),
+            checksum: checksum.map(|c| (ChecksumAlgorithm::Crc32, c.to_string())),
+            config_hash: None,
+        };
+        let entries = vec![
+            entry("1", "a", "b", Some("1111")),
+            entry("1", "c", "d", Some("2222")),
+            entry("1", "e", "f", None),
+            entry("2", "b", "g", None),
+        ];
+        let existing = ["d", "g"].map(PathBuf::from);
+        let issues = verify_journal_impl(&entries, None, |p| existing.iter().any(|e| e == p), |_, _| Ok("3333".to_string()));
+        assert_eq!(issues, vec![
+            JournalIssue::MovedLater(PathBuf::from("b"), "1".to_string(), "2".to_string()),
+            JournalIssue::ChecksumMismatch(PathBuf::from("d"), "1".to_string()),
+            JournalIssue::DestinationMissing(PathBuf::from("f"), "1".to_string()),
+        ]);
+
+        let issues = verify_journal_impl(&entries, Some("2"), |p| existing.iter().any(|e| e == p), |_, _| Ok("3333".to_string()));
+        assert_eq!(issues, vec![]);
+    }
+
+    #[test]
+    fn test_replay_entry() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let src = dir.join("t.a1.b2.unique.txt");
+        fs::write(&src, "a").unwrap();
+        let planned = Planner::new().limit(15).plan(&src, None::<&Path>).unwrap();
+        assert_ne!(planned.dst, src);
+        fs::rename(&src, &planned.dst).unwrap();
+
+        let entry = JournalEntry { run_id: "1".to_string(), time: 0, src: src.clone(), dst: planned.dst.clone(), checksum: None, config_hash: None };
+        // the renamed file doesn't take its own name
+        assert_eq!(replay_entry(&entry, Planner::new().limit(15)).unwrap().dst, planned.dst);
+        assert_ne!(Planner::new().limit(15).plan(&src, None::<&Path>).unwrap().dst, planned.dst);
+    }
+}
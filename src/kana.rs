@@ -0,0 +1,113 @@
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+
+// which width of katakana to settle on, see `convert_kana_width`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum KanaWidth {
+    // ｶﾞ (6 bytes) to ガ (3 bytes), saves bytes
+    Full,
+    // ガ to ｶﾞ, for names which have to match half-width names elsewhere
+    Half,
+}
+
+const HALF_WIDTH_KANA: &str = "｡｢｣､･ｦｧｨｩｪｫｬｭｮｯｰｱｲｳｴｵｶｷｸｹｺｻｼｽｾｿﾀﾁﾂﾃﾄﾅﾆﾇﾈﾉﾊﾋﾌﾍﾎﾏﾐﾑﾒﾓﾔﾕﾖﾗﾘﾙﾚﾛﾜﾝ";
+const FULL_WIDTH_KANA: &str = "。「」、・ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン";
+
+const VOICEABLE_KANA: &str = "カキクケコサシスセソタチツテトハヒフヘホウ";
+const VOICED_KANA: &str = "ガギグゲゴザジズゼゾダヂヅデドバビブベボヴ";
+const SEMI_VOICEABLE_KANA: &str = "ハヒフヘホ";
+const SEMI_VOICED_KANA: &str = "パピプペポ";
+
+const HALF_WIDTH_VOICED_MARK: char = 'ﾞ';
+const HALF_WIDTH_SEMI_VOICED_MARK: char = 'ﾟ';
+// combining marks, which NFD (and so tag matching) produces
+const COMBINING_VOICED_MARK: char = '\u{3099}';
+const COMBINING_SEMI_VOICED_MARK: char = '\u{309A}';
+// spacing marks, for marks which can't be combined with the previous character
+const VOICED_MARK: char = '゛';
+const SEMI_VOICED_MARK: char = '゜';
+
+pub(crate) fn convert_kana_width(s: &str, width: KanaWidth) -> String {
+    match width {
+        KanaWidth::Full => to_full_width_kana(s),
+        KanaWidth::Half => to_half_width_kana(s),
+    }
+}
+
+fn to_full_width_kana(s: &str) -> String {
+    let mut converted = String::new();
+    for c in s.chars() {
+        let is_voiced_mark = c == HALF_WIDTH_VOICED_MARK || c == COMBINING_VOICED_MARK;
+        let is_semi_voiced_mark = c == HALF_WIDTH_SEMI_VOICED_MARK || c == COMBINING_SEMI_VOICED_MARK;
+        if is_voiced_mark || is_semi_voiced_mark {
+            let (from, to) = if is_voiced_mark { (VOICEABLE_KANA, VOICED_KANA) } else { (SEMI_VOICEABLE_KANA, SEMI_VOICED_KANA) };
+            match converted.chars().last().and_then(|last| convert_char(last, from, to)) {
+                Some(composed) => {
+                    converted.pop();
+                    converted.push(composed);
+                },
+                None if c == HALF_WIDTH_VOICED_MARK => converted.push(VOICED_MARK),
+                None if c == HALF_WIDTH_SEMI_VOICED_MARK => converted.push(SEMI_VOICED_MARK),
+                // a combining mark on something else, e.g. hiragana, is left to NFC
+                None => converted.push(c),
+            }
+            continue;
+        }
+        converted.push(convert_char(c, HALF_WIDTH_KANA, FULL_WIDTH_KANA).unwrap_or(c));
+    }
+    converted
+}
+
+fn to_half_width_kana(s: &str) -> String {
+    let mut converted = String::new();
+    for c in s.chars() {
+        if let Some(base) = convert_char(c, VOICED_KANA, VOICEABLE_KANA) {
+            converted.push(convert_char(base, FULL_WIDTH_KANA, HALF_WIDTH_KANA).unwrap_or(base));
+            converted.push(HALF_WIDTH_VOICED_MARK);
+        } else if let Some(base) = convert_char(c, SEMI_VOICED_KANA, SEMI_VOICEABLE_KANA) {
+            converted.push(convert_char(base, FULL_WIDTH_KANA, HALF_WIDTH_KANA).unwrap_or(base));
+            converted.push(HALF_WIDTH_SEMI_VOICED_MARK);
+        } else if c == VOICED_MARK || (c == COMBINING_VOICED_MARK && ends_with_half_width_kana(&converted)) {
+            converted.push(HALF_WIDTH_VOICED_MARK);
+        } else if c == SEMI_VOICED_MARK || (c == COMBINING_SEMI_VOICED_MARK && ends_with_half_width_kana(&converted)) {
+            converted.push(HALF_WIDTH_SEMI_VOICED_MARK);
+        } else {
+            converted.push(convert_char(c, FULL_WIDTH_KANA, HALF_WIDTH_KANA).unwrap_or(c));
+        }
+    }
+    converted
+}
+
+fn ends_with_half_width_kana(s: &str) -> bool {
+    s.chars().last().is_some_and(|c| HALF_WIDTH_KANA.contains(c))
+}
+
+// the character at the same position in `to`
+fn convert_char(c: char, from: &str, to: &str) -> Option<char> {
+    let i = from.chars().position(|f| f == c)?;
+    to.chars().nth(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_convert_kana_width() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(convert_kana_width("ｶﾞｲﾄﾞﾌﾞｯｸ ﾊﾟｰﾄ1", KanaWidth::Full), "ガイドブック パート1");
+        assert_eq!(convert_kana_width("ガイドブック パート1", KanaWidth::Half), "ｶﾞｲﾄﾞﾌﾞｯｸ ﾊﾟｰﾄ1");
+        assert_eq!(convert_kana_width("ｱﾞ", KanaWidth::Full), "ア゛");
+        assert_eq!(convert_kana_width("ヴ", KanaWidth::Half), "ｳﾞ");
+
+        // decomposed (NFD) input
+        assert_eq!(convert_kana_width("カ\u{3099}", KanaWidth::Full), "ガ");
+        assert_eq!(convert_kana_width("カ\u{3099}", KanaWidth::Half), "ｶﾞ");
+        assert_eq!(convert_kana_width("か\u{3099}", KanaWidth::Half), "か\u{3099}");
+    }
+}
@@ -1,49 +1,1170 @@
-use std::{path::{Path, PathBuf}, fs, collections::{HashSet, HashMap}};
+use std::{path::{Path, PathBuf}, fs, io, ffi::{OsStr, OsString}, collections::{HashSet, HashMap, BTreeMap}, rc::Rc, ops::Range, cell::RefCell};
 use clap::crate_name;
 use anyhow::Result;
 use serde::{Serialize, Deserialize};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
 use unicode_normalization::UnicodeNormalization;
 
-#[derive(Serialize, Deserialize, Debug)]
+mod walk;
+mod plan;
+mod glob;
+mod copy;
+#[cfg(feature = "archive")]
+mod archive;
+mod manifest;
+mod script;
+#[cfg(feature = "reversible")]
+mod reversible;
+mod kana;
+mod journal;
+mod text;
+mod crc32;
+mod lint;
+mod encoding;
+mod profile;
+mod git;
+mod references;
+mod backend;
+#[cfg(feature = "sftp")]
+mod sftp;
+#[cfg(feature = "s3")]
+mod s3;
+mod integration;
+mod objective;
+mod words;
+mod i18n;
+mod test_names;
+#[cfg(feature = "self-update")]
+mod update;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "history-db")]
+mod history;
+#[cfg(feature = "watch")]
+mod watch;
+#[cfg(feature = "mount")]
+mod mount;
+#[cfg(feature = "shim")]
+mod shim;
+#[cfg(feature = "json")]
+mod migrate;
+
+pub use walk::{walk, walk_with, WalkOptions, WalkOrder};
+pub use plan::{Planner, PlanEntry, PlanKind};
+pub use copy::{move_file, copy_file, check_free_space, setgid_group_mismatch, ChecksumAlgorithm, CopyOptions};
+#[cfg(feature = "archive")]
+pub use archive::shorten_archive;
+pub use manifest::{write_manifest, read_manifest, MANIFEST_VERSION};
+pub use script::{write_script, ScriptShell, ScriptOptions};
+#[cfg(feature = "reversible")]
+pub use reversible::{reversible_filename, decode_reversible_name, squeeze_filename, unsqueeze_filename};
+pub use kana::KanaWidth;
+pub use journal::{Journal, JournalEntry, UndoConflict, JournalIssue, new_run_id, plan_undo, plan_undo_with, verify_journal, replay_entry, JOURNAL_VERSION};
+pub use text::{filename_from_url, filename_from_text};
+pub use lint::{Linter, LintViolation, lint_depth};
+pub use encoding::{TargetEncoding, Unmappable, OutputEncoding};
+pub use profile::Profile;
+pub use git::{is_git_tracked, git_move_file, staged_paths, pre_commit_hook_path, write_pre_commit_hook};
+pub use references::ReferenceUpdater;
+pub use backend::{ExistenceBackend, LocalBackend, ListingBackend};
+#[cfg(feature = "sftp")]
+pub use sftp::SftpBackend;
+#[cfg(feature = "s3")]
+pub use s3::{S3Bucket, plan_s3_renames, N_MIN_SEGMENT_BYTES};
+pub use integration::FileManager;
+pub use test_names::test_names;
+#[cfg(feature = "self-update")]
+pub use update::{Release, latest_release, is_newer_version, replace_binary};
+#[cfg(feature = "tui")]
+pub use tui::review_plan;
+#[cfg(feature = "history-db")]
+pub use history::{History, HistoryQuery, HistoryEntry, parse_date, format_time};
+#[cfg(feature = "watch")]
+pub use watch::{WatchState, WatchConfig, Inotify, Debouncer, dir_files};
+#[cfg(feature = "mount")]
+pub use mount::{mount, Aliases, ShortenFs};
+#[cfg(feature = "shim")]
+pub use shim::{Shim, SHIM_LIBRARY_FILENAME, SHIM_NAMES_ENV};
+#[cfg(feature = "json")]
+pub use migrate::{migrate_config, migrate_config_file, config_path, ConfigMigration};
+pub use i18n::{localize, message_language};
+pub use objective::{PackingObjective, Packing, Objective, PackingMode, TagFrequencies, ShortestFirst, BytesKept, PriorityWeighted, Distinctiveness, Rarity};
+
+// the format of the config file, `version` of the config. an older one is migrated on load, see `migrate_config`
+pub const CONFIG_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
 struct Config {
+    // see `CONFIG_VERSION`
+    version: u32,
     ignored_tags: HashSet<String>,
     conversions: HashMap<String, String>,
+    excluded_dirs: HashSet<String>,
+    protected_paths: Vec<String>,
+    // NFKC before shortening: full-width ascii to half-width, ligatures split, circled numbers to digits and so on
+    compatibility_folding: bool,
+    // converts katakana to full-width (fewer bytes) or half-width before shortening
+    kana_width: Option<KanaWidth>,
+    // matches `ignored_tags` and the keys of `conversions` ignoring case, `SAMPLE` is ignored by `sample`
+    case_insensitive_tags: bool,
+    // follows conversions transitively (a -> b, b -> c converts a to c), otherwise a single lookup
+    chain_conversions: bool,
+    // applies conversions to substrings of the first component (the title) too, not only to whole tags
+    convert_title: bool,
+    // splits the first component into words by spaces and underscores, so that `ignored_tags` and `conversions` apply to
+    // title words, and whole words are dropped from the end before a word is cut
+    tokenize_title: bool,
+    // cuts a title which doesn't fit after its last word which fits instead of in the middle of a word, by unicode word
+    // segmentation, and by a dictionary for japanese with the `japanese` feature. a word is cut only when the first one
+    // alone doesn't fit
+    title_word_boundaries: bool,
+    // what happens to the suffixes of the conflict copies made by sync tools (`.sync-conflict-...` of Syncthing,
+    // ` (conflicted copy 2024-05-01)` of Dropbox and Nextcloud) when shortening, see `SyncConflictSuffix`
+    sync_conflict_suffix: SyncConflictSuffix,
+    // what happens to the empty tags between runs of delimiters (`a..b`) and the ones converted to nothing when
+    // shortening, see `EmptyComponents`
+    empty_components: EmptyComponents,
+    // at most this number of `.` separated components (the title included, the extension not) in the names, even when
+    // they fit in bytes. the shortest tags are kept, as when the bytes run out
+    max_components: Option<usize>,
+    // the weights of tags for `--objective priority`, a tag not listed weighs 1
+    tag_priorities: HashMap<String, usize>,
+    // filename policies checked by the `lint` subcommand
+    lint: lint::LintRules,
+    // the text files whose references to renamed files next to them are rewritten with --update-references
+    reference_extensions: HashSet<String>,
+    // files renamed together with the file of the same stem, `video.srt` and `video.en.srt` with `video.mkv` (--sidecars)
+    sidecar_extensions: HashSet<String>,
+    // named rule sets selected with --rules, for directories which need other rules than the rest
+    profiles: HashMap<String, RuleProfile>,
+    // the directories the watch subcommand watches when none is given, by any name
+    #[cfg(feature = "watch")]
+    watches: HashMap<String, WatchConfig>,
+    // keys this version doesn't know, typos or keys of newer versions, reported by `ResolvedConfig::validate`
+    #[serde(flatten, skip_serializing)]
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    unknown_keys: BTreeMap<String, serde::de::IgnoredAny>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
             ignored_tags: HashSet::new(),
             conversions: HashMap::new(),
+            // btrfs (snapper), zfs and netapp/nfs snapshots
+            excluded_dirs: [".snapshot", ".snapshots", ".zfs"].into_iter().map(|s| s.to_string()).collect(),
+            protected_paths: ["/etc/**", "~/.ssh/**", "**/.git/**"].into_iter().map(|s| s.to_string()).collect(),
+            compatibility_folding: false,
+            kana_width: None,
+            case_insensitive_tags: false,
+            chain_conversions: false,
+            convert_title: false,
+            tokenize_title: false,
+            title_word_boundaries: false,
+            sync_conflict_suffix: SyncConflictSuffix::Tag,
+            empty_components: EmptyComponents::Keep,
+            max_components: None,
+            tag_priorities: HashMap::new(),
+            lint: lint::LintRules::default(),
+            reference_extensions: ["m3u", "m3u8", "pls", "cue", "md"].into_iter().map(|s| s.to_string()).collect(),
+            // subtitles, metadata of media centers, thumbnails and photo edits
+            sidecar_extensions: ["srt", "ass", "ssa", "vtt", "sub", "idx", "nfo", "jpg", "xmp"].into_iter().map(|s| s.to_string()).collect(),
+            profiles: HashMap::new(),
+            #[cfg(feature = "watch")]
+            watches: HashMap::new(),
+            unknown_keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    // the fields set in the profile replace the ones of the top level, `conversions` and `ignored_tags` as a whole
+    fn apply_profile(&mut self, profile: &RuleProfile) {
+        if let Some(ignored_tags) = &profile.ignored_tags {
+            self.ignored_tags = ignored_tags.clone();
+        }
+        if let Some(conversions) = &profile.conversions {
+            self.conversions = conversions.clone();
+        }
+        self.compatibility_folding = profile.compatibility_folding.unwrap_or(self.compatibility_folding);
+        self.kana_width = profile.kana_width.or(self.kana_width);
+        self.case_insensitive_tags = profile.case_insensitive_tags.unwrap_or(self.case_insensitive_tags);
+        self.chain_conversions = profile.chain_conversions.unwrap_or(self.chain_conversions);
+        self.convert_title = profile.convert_title.unwrap_or(self.convert_title);
+        self.tokenize_title = profile.tokenize_title.unwrap_or(self.tokenize_title);
+        self.title_word_boundaries = profile.title_word_boundaries.unwrap_or(self.title_word_boundaries);
+        self.sync_conflict_suffix = profile.sync_conflict_suffix.unwrap_or(self.sync_conflict_suffix);
+        self.empty_components = profile.empty_components.unwrap_or(self.empty_components);
+        self.max_components = profile.max_components.or(self.max_components);
+    }
+
+    // the rules of the project the path is in, the same from any of its subdirectories. read once per project
+    fn apply_project_rules(&mut self, path: &Path) {
+        let Some(root) = project_root(path) else {
+            return;
+        };
+        let profile = PROJECT_RULES.with(|rules| rules.borrow_mut().entry(root.clone()).or_insert_with(|| read_project_rules(&root)).clone());
+        if let Some(profile) = profile {
+            self.apply_profile(&profile);
         }
     }
+
+    // the first profile by name whose `path_patterns` match the absolute path
+    fn profile_for_path(&self, path: &Path) -> Option<&str> {
+        let mut names = self.profiles.iter().filter(|(_, profile)| {
+            profile.path_patterns.iter().any(|pattern| glob::glob_match(pattern, path))
+        }).map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        names.sort();
+        names.first().copied()
+    }
+}
+
+thread_local! {
+    // read once per run, not for each file. an old config file is upgraded before
+    static CONFIG: Config = {
+        let project = jdt::project(crate_name!());
+        #[cfg(feature = "json")]
+        migrate::migrate_config_on_load(&project.config_path());
+        project.config::<Config>()
+    };
+    // the project root of each directory seen and the rules of each root, so that the ancestors of a directory are
+    // looked at once, not for each of its files
+    static PROJECT_ROOTS: RefCell<HashMap<PathBuf, Option<PathBuf>>> = RefCell::default();
+    static PROJECT_RULES: RefCell<HashMap<PathBuf, Option<RuleProfile>>> = RefCell::default();
+}
+
+pub(crate) fn load_config() -> Config {
+    CONFIG.with(Config::clone)
+}
+
+// `watches` of the config by their names, with the home directory expanded
+#[cfg(feature = "watch")]
+pub fn configured_watches() -> Vec<(String, WatchConfig)> {
+    let mut watches = CONFIG.with(|config| config.watches.clone()).into_iter()
+        .map(|(name, watch)| (name, watch.expand_home()))
+        .collect::<Vec<_>>();
+    watches.sort_by(|(a, _), (b, _)| a.cmp(b));
+    watches
+}
+
+// the nearest of the path and its ancestors with a root marker
+fn project_root(path: &Path) -> Option<PathBuf> {
+    let path = std::path::absolute(path).ok()?;
+    project_root_of_absolute(&path)
+}
+
+fn project_root_of_absolute(path: &Path) -> Option<PathBuf> {
+    if PROJECT_ROOT_MARKERS.iter().any(|marker| path.join(marker).exists()) {
+        return Some(path.to_path_buf());
+    }
+    let dir = path.parent()?;
+    if let Some(root) = PROJECT_ROOTS.with(|roots| roots.borrow().get(dir).cloned()) {
+        return root;
+    }
+    let root = project_root_of_absolute(dir);
+    PROJECT_ROOTS.with(|roots| roots.borrow_mut().insert(dir.to_path_buf(), root.clone()));
+    root
+}
+
+// a broken config file of the project is reported and ignored, like the broken conversions
+#[cfg(feature = "json")]
+fn read_project_rules(root: &Path) -> Option<RuleProfile> {
+    let config_path = root.join(PROJECT_CONFIG_FILENAME);
+    let text = match fs::read_to_string(&config_path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            log::error!("Project config not loaded: {}: {} (ignored)", config_path.display(), e);
+            return None;
+        },
+    };
+    match serde_json::from_str::<RuleProfile>(&text) {
+        Ok(profile) => {
+            log::debug!("Project config: {}", config_path.display());
+            Some(profile)
+        },
+        Err(e) => {
+            log::error!("Invalid project config: {}: {} (ignored)", config_path.display(), e);
+            None
+        },
+    }
+}
+
+#[cfg(not(feature = "json"))]
+fn read_project_rules(root: &Path) -> Option<RuleProfile> {
+    let config_path = root.join(PROJECT_CONFIG_FILENAME);
+    if config_path.exists() {
+        log::warn!("Project config not loaded, built without the json feature: {} (ignored)", config_path.display());
+    }
+    None
+}
+
+// a named set of shortening rules in `profiles` of the config, the unset fields are taken from the top level
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+struct RuleProfile {
+    ignored_tags: Option<HashSet<String>>,
+    conversions: Option<HashMap<String, String>>,
+    compatibility_folding: Option<bool>,
+    kana_width: Option<KanaWidth>,
+    case_insensitive_tags: Option<bool>,
+    chain_conversions: Option<bool>,
+    convert_title: Option<bool>,
+    tokenize_title: Option<bool>,
+    title_word_boundaries: Option<bool>,
+    sync_conflict_suffix: Option<SyncConflictSuffix>,
+    empty_components: Option<EmptyComponents>,
+    max_components: Option<usize>,
+    // the profile is used for the files matching these globs (`~/Videos/**`) when --rules isn't given
+    path_patterns: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+enum SyncConflictSuffix {
+    // split by delimiters like any other text
+    #[default]
+    Tag,
+    // kept whole, the rest of the name is cut instead, so the copy is still told from the original
+    Keep,
+    // dropped before anything else
+    Drop,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+enum EmptyComponents {
+    // packed as tags of no bytes, the shortest of all, so the delimiters stay in the shortened name
+    #[default]
+    Keep,
+    // left out, a run of delimiters becomes one
+    Collapse,
+    // the name isn't shortened, and reported
+    Error,
+}
+
+// a directory with one of these is the root of a project, whose config file holds the rules shared in it, the nearest
+// one for the nested projects of a monorepo
+const PROJECT_ROOT_MARKERS: [&str; 2] = [".renamelimit-root", ".git"];
+// fields of a rule profile, in the root of a project. they replace the ones of the config, the profiles still apply
+const PROJECT_CONFIG_FILENAME: &str = ".renamelimit.json";
+
 const N_FILENAME_BYTES: usize = 255;
 const N_MAX_EXTENSION_BYTES: usize = 5;
+// fixpoint limit of chained conversions
+const N_MAX_CONVERSION_STEPS: usize = 16;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Filename not found in path: {0}")]
     FilenameNotFound(PathBuf),
+    #[error("Failed to claim filename: {0}: {1}")]
+    ClaimFailed(PathBuf, io::Error),
+    #[error("Checksum mismatch after copy: {0} -> {1}")]
+    ChecksumMismatch(PathBuf, PathBuf),
+    #[error("Filename can't be shortened reversibly: {0}")]
+    NotReversible(String),
+    #[error("Reversibly shortened filename is already taken: {0}")]
+    ReversibleNameTaken(PathBuf),
+    #[error("Unsupported format version: {0} (up to {1} is supported)")]
+    UnsupportedFormatVersion(u32, u32),
+    #[error("Invalid format: {0}")]
+    InvalidFormat(String),
+    #[error("Not enough free space on the filesystem of {0}: {1} bytes needed, {2} bytes available")]
+    InsufficientSpace(PathBuf, u64, u64),
+    #[error("Failed to check existence: {0}: {1}")]
+    ExistenceCheckFailed(PathBuf, io::Error),
+    #[error("git failed: {0}: {1}")]
+    GitFailed(PathBuf, String),
+    #[error("aws failed: {0}")]
+    AwsFailed(String),
+    #[error("curl failed: {0}")]
+    CurlFailed(String),
+    #[error("Downloaded file doesn't match the published checksum: {0}")]
+    DownloadChecksumMismatch(String),
+    #[error("The limit of {available} bytes is too small, {needed} bytes are needed at least")]
+    BudgetImpossible { needed: usize, available: usize },
+    #[error("Empty component in filename: {0}")]
+    EmptyComponent(String),
+    #[error("Non UTF-8 name can't be shortened: {0}")]
+    NonUtf8Name(PathBuf),
+    #[error("Destination already exists: {0}")]
+    DestinationExists(PathBuf),
+}
+
+impl Error {
+    // the message in the language of the locale, see `tr!`
+    pub fn localized(&self) -> String {
+        match self {
+            Self::FilenameNotFound(path) => tr!("error-filename-not-found", "Filename not found in path: {path}", path = path.display()),
+            Self::ClaimFailed(path, e) => tr!("error-claim-failed", "Failed to claim filename: {path}: {error}", path = path.display(), error = e),
+            Self::ChecksumMismatch(src, dst) => tr!("error-checksum-mismatch", "Checksum mismatch after copy: {src} -> {dst}", src = src.display(), dst = dst.display()),
+            Self::NotReversible(name) => tr!("error-not-reversible", "Filename can't be shortened reversibly: {name}", name = name),
+            Self::ReversibleNameTaken(path) => tr!("error-reversible-name-taken", "Reversibly shortened filename is already taken: {path}", path = path.display()),
+            Self::UnsupportedFormatVersion(version, supported) => tr!("error-unsupported-format-version", "Unsupported format version: {version} (up to {supported} is supported)", version = version, supported = supported),
+            Self::InvalidFormat(line) => tr!("error-invalid-format", "Invalid format: {line}", line = line),
+            Self::InsufficientSpace(path, needed, available) => tr!("error-insufficient-space", "Not enough free space on the filesystem of {path}: {needed} bytes needed, {available} bytes available", path = path.display(), needed = needed, available = available),
+            Self::ExistenceCheckFailed(path, e) => tr!("error-existence-check-failed", "Failed to check existence: {path}: {error}", path = path.display(), error = e),
+            Self::GitFailed(path, message) => tr!("error-git-failed", "git failed: {path}: {message}", path = path.display(), message = message),
+            Self::AwsFailed(message) => tr!("error-aws-failed", "aws failed: {message}", message = message),
+            Self::CurlFailed(message) => tr!("error-curl-failed", "curl failed: {message}", message = message),
+            Self::DownloadChecksumMismatch(url) => tr!("error-download-checksum-mismatch", "Downloaded file doesn't match the published checksum: {url}", url = url),
+            Self::BudgetImpossible { needed, available } => tr!("error-budget-impossible", "The limit of {available} bytes is too small, {needed} bytes are needed at least", available = available, needed = needed),
+            Self::EmptyComponent(name) => tr!("error-empty-component", "Empty component in filename: {name}", name = name),
+            Self::NonUtf8Name(path) => tr!("error-non-utf8-name", "Non UTF-8 name can't be shortened: {path}", path = path.display()),
+            Self::DestinationExists(path) => tr!("error-destination-exists", "Destination already exists: {path}", path = path.display()),
+        }
+    }
+}
+
+// JSON Schema of the config file, for validation and completion in editors
+#[cfg(feature = "schema")]
+pub fn config_schema() -> String {
+    serde_json::to_string_pretty(&schemars::schema_for!(Config)).expect("schema is plain json")
+}
+
+// problems of the config found by `ResolvedConfig::validate`
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigIssue {
+    #[error("Conversion {0:?} -> {1:?} contains a path separator")]
+    ConversionWithSeparator(String, String),
+    #[error("Conversion {0:?} -> {1:?} contains NUL")]
+    ConversionWithNul(String, String),
+    #[error("Conversion {0:?} -> {1:?} contains a delimiter, the result would be split into other tags next time")]
+    ConversionWithDelimiter(String, String),
+    #[error("Conversion {0:?} -> {1:?} makes the tag longer")]
+    ConversionLongerThanKey(String, String),
+    #[error("Conversions form a cycle: {0}")]
+    ConversionCycle(String),
+    #[error("Conversion chain from {0:?} is longer than {} steps", N_MAX_CONVERSION_STEPS)]
+    ConversionChainTooLong(String),
+    #[error("Unknown key {0:?} (ignored)")]
+    UnknownKey(String),
+    #[error("Unknown key {0:?}, did you mean {1:?}? (ignored)")]
+    UnknownKeySimilarTo(String, String),
+}
+
+impl ConfigIssue {
+    // errors make the conversion unusable, the rest are only warnings
+    pub fn is_error(&self) -> bool {
+        !matches!(self, Self::ConversionLongerThanKey(..) | Self::UnknownKey(..) | Self::UnknownKeySimilarTo(..))
+    }
+
+    pub fn is_unknown_key(&self) -> bool {
+        matches!(self, Self::UnknownKey(..) | Self::UnknownKeySimilarTo(..))
+    }
+
+    // the message in the language of the locale, see `tr!`
+    pub fn localized(&self) -> String {
+        let quoted = |text: &String| format!("{:?}", text);
+        match self {
+            Self::ConversionWithSeparator(from, to) => tr!("config-conversion-with-separator", "Conversion {from} -> {to} contains a path separator", from = quoted(from), to = quoted(to)),
+            Self::ConversionWithNul(from, to) => tr!("config-conversion-with-nul", "Conversion {from} -> {to} contains NUL", from = quoted(from), to = quoted(to)),
+            Self::ConversionWithDelimiter(from, to) => tr!("config-conversion-with-delimiter", "Conversion {from} -> {to} contains a delimiter, the result would be split into other tags next time", from = quoted(from), to = quoted(to)),
+            Self::ConversionLongerThanKey(from, to) => tr!("config-conversion-longer-than-key", "Conversion {from} -> {to} makes the tag longer", from = quoted(from), to = quoted(to)),
+            Self::ConversionCycle(cycle) => tr!("config-conversion-cycle", "Conversions form a cycle: {cycle}", cycle = cycle),
+            Self::ConversionChainTooLong(from) => tr!("config-conversion-chain-too-long", "Conversion chain from {from} is longer than {n} steps", from = quoted(from), n = N_MAX_CONVERSION_STEPS),
+            Self::UnknownKey(key) => tr!("config-unknown-key", "Unknown key {key} (ignored)", key = quoted(key)),
+            Self::UnknownKeySimilarTo(key, similar) => tr!("config-unknown-key-similar-to", "Unknown key {key}, did you mean {similar}? (ignored)", key = quoted(key), similar = quoted(similar)),
+        }
+    }
+}
+
+// the smallest limit in which the filename can be shortened, with a counter for `n_retries` > 0.
+// the first character and the counter are kept, anything else can be cut
+pub fn min_filename_bytes(filename: impl AsRef<str>, n_retries: usize) -> usize {
+    min_candidate_bytes(filename.as_ref(), &Rules::load(), n_retries)
 }
 
 pub fn new_filename(path: impl AsRef<Path>, dst_dir: Option<impl AsRef<Path>>) -> Result<String> {
-    new_filename_impl(path, dst_dir, |p| p.exists())
+    new_filename_impl(path, dst_dir, &Rules::load(), |p| p.exists())
 }
 
-// dependency injection for testing
-fn new_filename_impl(path: impl AsRef<Path>, dst_dir: Option<impl AsRef<Path>>, mut check_file_existence: impl FnMut(&Path) -> bool) -> Result<String> {
-    let path = path.as_ref();
+// reserves the returned filename by creating an empty placeholder with O_EXCL,
+// so that other processes (even on other hosts sharing the directory) can't pick the same name.
+// the caller is expected to move the data over the placeholder.
+pub fn claim_new_filename(path: impl AsRef<Path>, dst_dir: Option<impl AsRef<Path>>) -> Result<String> {
     let dst_dir = dst_dir.map(|p| p.as_ref().to_path_buf());
+    if let Some(dst_dir) = &dst_dir {
+        fs::create_dir_all(dst_dir)?;
+    }
+
+    let mut claim_error = None;
+    let new_filename = new_filename_impl(path, dst_dir, &Rules::load(), |p| {
+        match claim_path(p) {
+            Ok(claimed) => !claimed,
+            Err(e) => {
+                // stop probing, the error is reported below
+                claim_error = Some(Error::ClaimFailed(p.to_path_buf(), e));
+                false
+            },
+        }
+    })?;
+
+    if let Some(e) = claim_error {
+        return Err(e.into());
+    }
+    Ok(new_filename)
+}
+
+// true if the placeholder is created, false if the path is already taken
+fn claim_path(path: &Path) -> io::Result<bool> {
+    match fs::OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// the length of a filename as counted against NAME_MAX, in bytes of the name on the filesystem
+pub fn filename_byte_len(filename: impl AsRef<OsStr>) -> usize {
+    filename.as_ref().as_encoded_bytes().len()
+}
+
+// whether the last component of the path is too long for the profile, or for NAME_MAX without one.
+// only the length is compared, neither the filesystem nor the config is read, so it's cheap enough to filter every path
+pub fn exceeds_limit(path: impl AsRef<Path>, profile: Option<Profile>) -> bool {
+    let Some(filename) = path.as_ref().file_name() else {
+        return false;
+    };
+    match profile {
+        Some(profile) => filename.to_string_lossy().chars().map(|c| profile.char_len(c)).sum::<usize>() > profile.n_filename_units(),
+        None => filename_byte_len(filename) > N_FILENAME_BYTES,
+    }
+}
+
+// NFS clients rename files which are still open but unlinked to `.nfsXXXX` (silly rename),
+// those are removed by the client itself once closed, so they must not be renamed.
+pub fn is_nfs_temp_file(path: impl AsRef<Path>) -> bool {
+    let Some(filename) = path.as_ref().file_name() else {
+        return false;
+    };
+    let Some(suffix) = filename.as_encoded_bytes().strip_prefix(b".nfs") else {
+        return false;
+    };
+    !suffix.is_empty() && suffix.iter().all(|b| b.is_ascii_hexdigit())
+}
+
+// whether the path matches `protected_paths` of the config, which are never renamed unless forced
+pub fn is_protected_path(path: impl AsRef<Path>) -> Result<bool> {
+    let path = resolve_for_matching(path.as_ref())?;
+    Ok(CONFIG.with(|config| config.protected_paths.iter().any(|pattern| glob::glob_match(pattern, &path))))
+}
+
+// the absolute path the glob patterns of the config are matched against. the parent is resolved instead of the path itself,
+// so that a symlink is judged by where it is, not by where it points
+fn resolve_for_matching(path: &Path) -> Result<PathBuf> {
+    let filename = path.file_name().ok_or_else(|| Error::FilenameNotFound(path.to_path_buf()))?;
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    Ok(parent.canonicalize()?.join(filename))
+}
+
+// tags and conversions of the config, normalized for matching
+#[derive(Debug, Clone)]
+struct Rules {
+    ignored_tags: HashSet<String>,
+    tag_conversion_map: HashMap<String, String>,
+    compatibility_folding: bool,
+    kana_width: Option<KanaWidth>,
+    case_insensitive_tags: bool,
+    chain_conversions: bool,
+    convert_title: bool,
+    tokenize_title: bool,
+    title_word_boundaries: bool,
+    sync_conflict_suffix: SyncConflictSuffix,
+    empty_components: EmptyComponents,
+    max_components: Option<usize>,
+    // the byte budget of a filename, less than N_FILENAME_BYTES when a run leaves headroom
+    n_filename_bytes: usize,
+    // the names are written in this encoding instead of UTF-8, and their bytes are counted in it
+    encoding: Option<OutputEncoding>,
+    // restricts the characters and the lengths further, `n_filename_bytes` is then in the units of the profile
+    profile: Option<Profile>,
+    // lowercase
+    sidecar_extensions: HashSet<String>,
+    // what the packing makes the most of when the tags don't all fit
+    objective: Objective,
+    // normalized tags
+    tag_priorities: HashMap<String, usize>,
+    packing: PackingMode,
+    // for `Objective::Rarity`, shared by the rules of all the names of a run
+    tag_frequencies: Rc<TagFrequencies>,
+    // the objective whose tag values the tags are chosen for with `PackingMode::Exact`, set for each name
+    exact_objective: Option<Rc<dyn PackingObjective>>,
+    // when the name is taken and its title was cut, the end of the title is tried before a counter
+    keep_title_end: bool,
+    // a title which doesn't fit is cut in the middle, see `cut_title_middle`. set for the retry of `keep_title_end`
+    cut_title_middle: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            ignored_tags: HashSet::new(),
+            tag_conversion_map: HashMap::new(),
+            compatibility_folding: false,
+            kana_width: None,
+            case_insensitive_tags: false,
+            chain_conversions: false,
+            convert_title: false,
+            tokenize_title: false,
+            title_word_boundaries: false,
+            sync_conflict_suffix: SyncConflictSuffix::Tag,
+            empty_components: EmptyComponents::Keep,
+            max_components: None,
+            n_filename_bytes: N_FILENAME_BYTES,
+            encoding: None,
+            profile: None,
+            sidecar_extensions: HashSet::new(),
+            objective: Objective::ShortestFirst,
+            tag_priorities: HashMap::new(),
+            packing: PackingMode::Greedy,
+            tag_frequencies: Rc::default(),
+            exact_objective: None,
+            keep_title_end: false,
+            cut_title_middle: false,
+        }
+    }
+}
+
+// the rules of a single run: a profile of the config instead of its top level, and ignored tags and conversions
+// merged over them. a conversion replaces the one of the same tag
+#[derive(Debug, Clone, Default)]
+pub struct RuleOverrides {
+    pub profile: Option<String>,
+    pub ignored_tags: Vec<String>,
+    pub conversions: Vec<(String, String)>,
+    // instead of the config file and the project rules, e.g. the one recorded by a past run
+    #[cfg(feature = "json")]
+    pub config: Option<ConfigSnapshot>,
+}
+
+impl RuleOverrides {
+    fn recorded_config(&self) -> Option<Config> {
+        #[cfg(feature = "json")]
+        return self.config.as_ref().map(|snapshot| snapshot.config.clone());
+        #[cfg(not(feature = "json"))]
+        None
+    }
+}
+
+impl Rules {
+    fn load() -> Self {
+        Self::load_with(&RuleOverrides::default())
+    }
+
+    // broken conversions are reported and left out, the overridden ones too
+    fn load_with(overrides: &RuleOverrides) -> Self {
+        Self::load_impl(overrides, None)
+    }
+
+    // with the profile whose `path_patterns` match the path, unless a profile is chosen by the overrides
+    fn load_for(path: &Path, overrides: &RuleOverrides) -> Self {
+        Self::load_impl(overrides, Some(path))
+    }
 
-    let config = jdt::project(crate_name!()).config::<Config>();
+    fn load_impl(overrides: &RuleOverrides, path: Option<&Path>) -> Self {
+        let mut config = overrides.recorded_config().unwrap_or_else(|| {
+            let mut config = load_config();
+            // of the current directory when no path is given
+            config.apply_project_rules(path.unwrap_or(Path::new(".")));
+            config
+        });
+        let profile_name = overrides.profile.clone().or_else(|| {
+            // resolving the path costs syscalls, most configs have no patterns
+            let path = path.filter(|_| config.profiles.values().any(|profile| !profile.path_patterns.is_empty()))?;
+            let path = resolve_for_matching(path).inspect_err(|e| log::debug!("Rule profile not matched: {}: {}", path.display(), e)).ok()?;
+            let name = config.profile_for_path(&path)?;
+            log::debug!("Rule profile {}: {}", name, path.display());
+            Some(name.to_string())
+        });
+        if let Some(name) = &profile_name {
+            // checked by `ResolvedConfig::has_profile` before
+            match config.profiles.get(name).cloned() {
+                Some(profile) => config.apply_profile(&profile),
+                None => log::error!("Rule profile not found in the config: {} (ignored)", name),
+            }
+        }
+        let mut rules = Self::resolve(&config);
+        rules.apply(overrides);
+        for issue in rules.validate() {
+            match &issue {
+                ConfigIssue::ConversionWithSeparator(key, _) | ConfigIssue::ConversionWithNul(key, _) | ConfigIssue::ConversionWithDelimiter(key, _) => {
+                    log::error!("{} (ignored)", issue);
+                    rules.tag_conversion_map.remove(key);
+                },
+                // the chain is cut there, see `convert_tag`
+                ConfigIssue::ConversionCycle(_) | ConfigIssue::ConversionChainTooLong(_) => log::error!("{}", issue),
+                ConfigIssue::ConversionLongerThanKey(..) => log::warn!("{}", issue),
+                // only `ResolvedConfig` knows the keys, they are reported once at startup
+                ConfigIssue::UnknownKey(..) | ConfigIssue::UnknownKeySimilarTo(..) => (),
+            }
+        }
+        rules
+    }
+
+    fn resolve(config: &Config) -> Self {
+        let mut rules = Self {
+            compatibility_folding: config.compatibility_folding,
+            kana_width: config.kana_width,
+            case_insensitive_tags: config.case_insensitive_tags,
+            chain_conversions: config.chain_conversions,
+            convert_title: config.convert_title,
+            tokenize_title: config.tokenize_title,
+            title_word_boundaries: config.title_word_boundaries,
+            sync_conflict_suffix: config.sync_conflict_suffix,
+            empty_components: config.empty_components,
+            max_components: config.max_components,
+            sidecar_extensions: config.sidecar_extensions.iter().map(|ext| ext.to_lowercase()).collect(),
+            ..Default::default()
+        };
+        rules.ignored_tags = config.ignored_tags.iter().map(|s| rules.normalize_tag(s)).collect();
+        rules.tag_conversion_map = config.conversions.iter().map(|(k, v)| {
+            (rules.normalize_tag(k), normalize_str(v))
+        }).collect();
+        rules.tag_priorities = config.tag_priorities.iter().map(|(tag, priority)| (rules.normalize_tag(tag), *priority)).collect();
+        rules
+    }
+
+    // normalized like the config, so that case_insensitive_tags applies to them too
+    fn apply(&mut self, overrides: &RuleOverrides) {
+        for tag in &overrides.ignored_tags {
+            self.ignored_tags.insert(self.normalize_tag(tag));
+        }
+        for (key, value) in &overrides.conversions {
+            self.tag_conversion_map.insert(self.normalize_tag(key), normalize_str(value));
+        }
+    }
 
-    // NFC normalization
-    let ignored_tags = config.ignored_tags.iter().map(|s| normalize_str(s)).collect();
-    let tag_conversion_map = config.conversions.iter().map(|(k, v)| {
-        (normalize_str(k), normalize_str(v))
-    }).collect();
+    fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let mut conversions = self.tag_conversion_map.iter().collect::<Vec<_>>();
+        conversions.sort();
+        for &(key, value) in &conversions {
+            let (key, value) = (key.clone(), value.clone());
+            if value.contains('/') {
+                issues.push(ConfigIssue::ConversionWithSeparator(key, value));
+            } else if value.contains('\0') {
+                issues.push(ConfigIssue::ConversionWithNul(key, value));
+            } else if value.contains(DELIMITERS) {
+                issues.push(ConfigIssue::ConversionWithDelimiter(key, value));
+            } else if key.len() < value.len() {
+                issues.push(ConfigIssue::ConversionLongerThanKey(key, value));
+            }
+        }
+
+        if self.chain_conversions {
+            let mut reported_cycles = HashSet::new();
+            for (key, _) in conversions {
+                let mut chain = vec![key.clone()];
+                while let Some(converted) = self.tag_conversion_map.get(&self.normalize_tag(chain.last().expect("not empty"))) {
+                    let converted = self.normalize_tag(converted);
+                    if chain.last() == Some(&converted) {
+                        // converted to itself, already the fixpoint
+                        break;
+                    }
+                    if let Some(i) = chain.iter().position(|tag| *tag == converted) {
+                        // the same cycle is found from each of its members
+                        let mut cycle = chain[i..].to_vec();
+                        cycle.sort();
+                        if reported_cycles.insert(cycle) {
+                            chain.push(converted);
+                            issues.push(ConfigIssue::ConversionCycle(chain[i..].join(" -> ")));
+                        }
+                        break;
+                    }
+                    chain.push(converted);
+                    if N_MAX_CONVERSION_STEPS < chain.len() - 1 {
+                        issues.push(ConfigIssue::ConversionChainTooLong(key.clone()));
+                        break;
+                    }
+                }
+            }
+        }
+        issues
+    }
+
+    // the tag after conversions, followed until nothing converts it when `chain_conversions` is set.
+    // a cycle is cut before a tag comes back, and a chain after `N_MAX_CONVERSION_STEPS`.
+    fn convert_tag<'a>(&'a self, tag: &'a str) -> &'a str {
+        let Some(mut converted) = self.tag_conversion_map.get(&self.normalize_tag(tag)) else {
+            return tag;
+        };
+        if !self.chain_conversions {
+            return converted;
+        }
+
+        let mut seen = HashSet::from([self.normalize_tag(tag), self.normalize_tag(converted)]);
+        for _ in 1..N_MAX_CONVERSION_STEPS {
+            let Some(next) = self.tag_conversion_map.get(&self.normalize_tag(converted)) else {
+                break;
+            };
+            if !seen.insert(self.normalize_tag(next)) {
+                break;
+            }
+            converted = next;
+        }
+        converted
+    }
+
+    // the form tags are matched in
+    fn normalize_tag(&self, tag: &str) -> String {
+        let tag = normalize_str(tag);
+        if self.case_insensitive_tags {
+            // full case folding isn't in std, lowercasing after NFD covers the same ground for almost all tags
+            tag.to_lowercase()
+        } else {
+            tag
+        }
+    }
+
+    // replaces substrings matching conversion keys, leftmost longest first, when `convert_title` is set
+    fn convert_title(&self, title: &str) -> String {
+        if !self.convert_title || self.tag_conversion_map.is_empty() {
+            return title.to_string();
+        }
+
+        // normalization never makes a string shorter in chars, so no match is longer than the longest key
+        let n_max_key_chars = self.tag_conversion_map.keys().map(|k| k.chars().count()).max().unwrap_or(0);
+        let boundaries = title.char_indices().map(|(i, _)| i).chain([title.len()]).collect::<Vec<_>>();
+        let mut converted = String::new();
+        let mut i = 0;
+        while i + 1 < boundaries.len() {
+            let matched = (i + 1..boundaries.len().min(i + n_max_key_chars + 1)).rev().find(|j| {
+                self.tag_conversion_map.contains_key(&self.normalize_tag(&title[boundaries[i]..boundaries[*j]]))
+            });
+            if let Some(j) = matched {
+                converted.push_str(self.convert_tag(&title[boundaries[i]..boundaries[j]]));
+                i = j;
+            } else {
+                converted.push_str(&title[boundaries[i]..boundaries[i + 1]]);
+                i += 1;
+            }
+        }
+        converted
+    }
+
+    // ignored words are removed and the rest converted as tags, when `tokenize_title` is set
+    fn convert_title_words(&self, title: &str) -> String {
+        if !self.tokenize_title {
+            return title.to_string();
+        }
+
+        let mut converted = String::new();
+        let mut start = 0;
+        for (i, delimiter) in title.match_indices(TITLE_DELIMITERS).map(|(i, d)| (i, Some(d))).chain([(title.len(), None)]) {
+            let word = &title[start..i];
+            if word.is_empty() || !self.ignored_tags.contains(&self.normalize_tag(word)) {
+                converted.push_str(self.convert_tag(word));
+                converted.push_str(delimiter.unwrap_or(""));
+            }
+            start = i + delimiter.map_or(0, |d| d.len());
+        }
+
+        // the title never disappears completely
+        let converted = converted.trim_end_matches(TITLE_DELIMITERS);
+        if converted.is_empty() {
+            title.to_string()
+        } else {
+            converted.to_string()
+        }
+    }
+
+    // transforms which keep every tag, tried before anything is dropped
+    fn fold(&self, filename: &str) -> String {
+        let filename = if self.compatibility_folding {
+            filename.nfkc().collect()
+        } else {
+            filename.to_string()
+        };
+        let filename = if let Some(kana_width) = self.kana_width {
+            kana::convert_kana_width(&filename, kana_width)
+        } else {
+            filename
+        };
+        let filename = if let Some(encoding) = self.encoding {
+            encoding.map_unmappable(&filename)
+        } else {
+            filename
+        };
+        if let Some(profile) = self.profile {
+            profile.map(&filename)
+        } else {
+            filename
+        }
+    }
+
+    // whether the name can be used without shortening
+    fn fits(&self, filename: &str) -> bool {
+        self.n_bytes(filename) <= self.n_filename_bytes && self.profile.is_none_or(|profile| profile.allows(filename)) && self.has_few_components(filename)
+    }
+
+    fn has_few_components(&self, filename: &str) -> bool {
+        self.max_components.is_none_or(|n_max_components| n_components(filename) <= n_max_components)
+    }
+
+    // the length as written on the filesystem
+    fn n_bytes(&self, s: &str) -> usize {
+        match (self.profile, self.encoding) {
+            (Some(profile), _) => s.chars().map(|c| profile.char_len(c)).sum(),
+            (None, Some(encoding)) => encoding.str_len(s),
+            (None, None) => s.len(),
+        }
+    }
+
+    fn n_char_bytes(&self, c: char) -> usize {
+        match (self.profile, self.encoding) {
+            (Some(profile), _) => profile.char_len(c),
+            (None, Some(encoding)) => encoding.str_len(c.encode_utf8(&mut [0; 4])),
+            (None, None) => c.len_utf8(),
+        }
+    }
+
+    // the filename as written on the filesystem
+    fn encode(&self, filename: &str) -> OsString {
+        match self.encoding {
+            Some(encoding) => encoding.encode(filename),
+            None => filename.into(),
+        }
+    }
+}
+
+// the config as it's used for shortening, normalized
+#[derive(Debug)]
+pub struct ResolvedConfig {
+    rules: Rules,
+    // dotted, `lint.no_space`
+    unknown_keys: Vec<String>,
+    profile_names: HashSet<String>,
+}
+
+impl ResolvedConfig {
+    pub fn load() -> Self {
+        let mut config = load_config();
+        config.apply_project_rules(Path::new("."));
+        let mut unknown_keys = config.unknown_keys.keys().cloned().collect::<Vec<_>>();
+        unknown_keys.extend(config.lint.unknown_keys().map(|key| format!("lint.{}", key)));
+        Self { rules: Rules::resolve(&config), unknown_keys, profile_names: config.profiles.keys().cloned().collect() }
+    }
+
+    pub fn has_profile(&self, name: &str) -> bool {
+        self.profile_names.contains(name)
+    }
+
+    // every problem of the config, the shortening itself only logs them and leaves broken conversions out
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = self.rules.validate();
+        let known_keys = known_config_keys();
+        for key in &self.unknown_keys {
+            issues.push(match similar_key(key, &known_keys) {
+                Some(similar_key) => ConfigIssue::UnknownKeySimilarTo(key.clone(), similar_key.to_string()),
+                None => ConfigIssue::UnknownKey(key.clone()),
+            });
+        }
+        issues
+    }
+}
+
+// the config of a run, with the rules of the project of the current directory, recorded in the journal so that a change
+// of the rules since the run is noticed
+#[cfg(feature = "json")]
+#[derive(Debug, Clone)]
+pub struct ConfigSnapshot {
+    config: Config,
+}
+
+#[cfg(feature = "json")]
+impl ConfigSnapshot {
+    pub fn load() -> Self {
+        let mut config = load_config();
+        config.apply_project_rules(Path::new("."));
+        Self { config }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(Self { config: serde_json::from_str(json)? })
+    }
+
+    // the arrays are sorted, so that the same config is the same JSON whatever the order of its sets in memory
+    pub fn to_json(&self) -> String {
+        let mut value = serde_json::to_value(&self.config).unwrap_or_default();
+        sort_arrays(&mut value);
+        value.to_string()
+    }
+
+    // of the JSON, short enough for every line of the journal
+    pub fn hash(&self) -> String {
+        let mut hasher = crate::crc32::Hasher::new();
+        hasher.update(self.to_json().as_bytes());
+        format!("{:08x}", hasher.finalize())
+    }
+}
+
+#[cfg(feature = "json")]
+fn sort_arrays(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Array(values) => {
+            values.iter_mut().for_each(sort_arrays);
+            values.sort_by_cached_key(|value| value.to_string());
+        },
+        serde_json::Value::Object(fields) => fields.values_mut().for_each(sort_arrays),
+        _ => (),
+    }
+}
+
+// dotted like the unknown keys, taken from the serialized defaults so that new fields are never missed
+fn known_config_keys() -> Vec<String> {
+    #[allow(unused_mut)]
+    let mut keys = Vec::new();
+    // none without the json feature, the unknown keys are reported without a suggestion
+    #[cfg(feature = "json")]
+    if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(Config::default()) {
+        for (key, value) in fields {
+            if let serde_json::Value::Object(sub_fields) = value {
+                keys.extend(sub_fields.keys().map(|sub_key| format!("{}.{}", key, sub_key)));
+            }
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+// the known key closest to a typo, if close enough: a third of the characters may be wrong
+fn similar_key<'a>(key: &str, known_keys: &'a [String]) -> Option<&'a str> {
+    known_keys.iter()
+        .map(|known_key| (edit_distance(key, known_key), known_key))
+        .filter(|(distance, _)| *distance <= (key.chars().count() / 3).max(1))
+        .min()
+        .map(|(_, known_key)| known_key.as_str())
+}
+
+// levenshtein distance in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev_diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = prev_diagonal + usize::from(ca != *cb);
+            prev_diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(prev_diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// shortens the filename without touching the filesystem. fails when the limit is too small for the name, or when the
+// rules refuse it (`EmptyComponents::Error`)
+fn shorten_filename(filename: &str, rules: &Rules) -> Result<String> {
+    shorten_filename_among(filename, rules, |_| false)
+}
+
+// `is_taken` tells whether a candidate is already used, then the name gets a counter
+fn shorten_filename_among(filename: &str, rules: &Rules, mut is_taken: impl FnMut(&str) -> bool) -> Result<String> {
+    if rules.fits(filename) && !is_taken(filename) {
+        return Ok(filename.to_string());
+    }
+
+    // folding alone may be enough, then no tag has to be dropped
+    let filename = &rules.fold(filename);
+    if rules.fits(filename) && !is_taken(filename) {
+        return Ok(filename.to_string());
+    }
+
+    let mut n_retries = 0;
+    loop {
+        let new_candidate_filename = new_candidate_filename(filename, rules, n_retries)?;
+        log::trace!("New candidate filename: {}", new_candidate_filename);
+
+        if !is_taken(&new_candidate_filename) {
+            return Ok(new_candidate_filename);
+        }
+
+        n_retries += 1;
+    }
+}
+
+// maps names to shortened names without looking at the filesystem or at previously mapped names,
+// so the same name is always mapped to the same result. every `/` separated component is shortened on its own.
+#[derive(Debug, Default)]
+pub struct NameMapper {
+    rules: Rules,
+}
+
+impl NameMapper {
+    pub fn new() -> Self {
+        Self::with_overrides(&RuleOverrides::default())
+    }
+
+    pub fn with_overrides(overrides: &RuleOverrides) -> Self {
+        Self { rules: Rules::load_with(overrides) }
+    }
+
+    pub fn map(&self, name: impl AsRef<str>) -> Result<String> {
+        let components = name.as_ref().split('/').map(|component| {
+            if component.is_empty() {
+                Ok(component.to_string())
+            } else {
+                shorten_filename(component, &self.rules)
+            }
+        }).collect::<Result<Vec<_>>>()?;
+        Ok(components.join("/"))
+    }
+}
+
+// options of the helpers which work on strings only, never on the filesystem
+#[derive(Debug, Clone, Default)]
+pub struct ShortenOptions {
+    // apply `ignored_tags`, `conversions` and the rest of the user's config, otherwise only the length limit
+    pub use_config: bool,
+}
+
+impl ShortenOptions {
+    fn rules(&self) -> Rules {
+        if self.use_config {
+            Rules::load()
+        } else {
+            Rules::default()
+        }
+    }
+}
+
+// shortens every component of the path to fit the limit, like NameMapper does for `/` separated names.
+// for tools generating deep trees (e.g. mirroring URLs to disk) where each directory needs to fit too.
+pub fn shorten_path(path: impl AsRef<Path>, options: &ShortenOptions) -> Result<PathBuf> {
+    shorten_path_impl(path.as_ref(), &options.rules())
+}
+
+fn shorten_path_impl(path: &Path, rules: &Rules) -> Result<PathBuf> {
+    path.components().map(|component| match component {
+        std::path::Component::Normal(name) if rules.n_filename_bytes < name.len() => {
+            Ok(std::ffi::OsString::from(shorten_filename(&name.to_string_lossy(), rules)?))
+        },
+        component => Ok(component.as_os_str().to_os_string()),
+    }).collect()
+}
+
+// dependency injection for testing
+fn new_filename_impl(path: impl AsRef<Path>, dst_dir: Option<impl AsRef<Path>>, rules: &Rules, check_file_existence: impl FnMut(&Path) -> bool) -> Result<String> {
+    new_filename_listed(path, dst_dir, rules, None, check_file_existence)
+}
+
+// `dst_names` are the names in the destination directory, when they are known: a taken name gets the counter after the
+// largest one among them instead of probing from 1, which is slow with thousands of copies. the gaps aren't filled
+fn new_filename_listed(path: impl AsRef<Path>, dst_dir: Option<impl AsRef<Path>>, rules: &Rules, dst_names: Option<&[OsString]>, mut check_file_existence: impl FnMut(&Path) -> bool) -> Result<String> {
+    let path = path.as_ref();
+    let dst_dir = dst_dir.map(|p| p.as_ref().to_path_buf());
 
     let filename = match path.file_name() {
         Some(filename) => {
@@ -54,42 +1175,349 @@ fn new_filename_impl(path: impl AsRef<Path>, dst_dir: Option<impl AsRef<Path>>,
         },
     };
 
-    let (dst_dir, to_same_dir) = if let Some(dst_dir) = dst_dir {
-        (dst_dir, false)
-    } else {
-        (path.parent().unwrap_or(Path::new(".")).to_path_buf(), true)
+    let (dst_dir, to_same_dir) = if let Some(dst_dir) = dst_dir {
+        // symlinks and bind mounts give the same directory other paths, it's still a rename in place
+        let parent = match path.parent() {
+            Some(parent) if parent != Path::new("") => parent,
+            _ => Path::new("."),
+        };
+        let to_same_dir = jdt::eq_files(&dst_dir, parent).unwrap_or(false);
+        (dst_dir, to_same_dir)
+    } else {
+        (path.parent().unwrap_or(Path::new(".")).to_path_buf(), true)
+    };
+
+    // ascii names are the same in every target encoding, others have to be transcoded. profiles restrict the characters too
+    if filename.as_encoded_bytes().len() <= rules.n_filename_bytes && (rules.encoding.is_none() || filename.as_encoded_bytes().is_ascii()) && rules.profile.is_none() && rules.has_few_components(&filename.to_string_lossy()) {
+        let filename = filename.to_string_lossy().to_string();
+        if to_same_dir {
+            return Ok(filename);
+        }
+
+        let new_path = dst_dir.join(&filename);
+        if !check_file_existence(&new_path) {
+            return Ok(filename);
+        }
+    }
+
+    // folding alone may be enough, then no tag has to be dropped
+    let src_name = filename;
+    let filename = rules.fold(&filename.to_string_lossy());
+    if rules.fits(&filename) && !check_file_existence(&dst_dir.join(rules.encode(&filename))) {
+        return Ok(filename);
+    }
+
+    // the counters are given with the same packing
+    let objective = packing_objective(rules, dst_names.into_iter().flatten().filter(|name| name.as_os_str() != src_name));
+    let best_rules = if rules.packing == PackingMode::Exact {
+        Some(Rules { exact_objective: Some(Rc::from(objective)), ..rules.clone() })
+    } else if objective.tries_alternatives() {
+        Some(best_packing_rules(&filename, rules, objective.as_ref())?)
+    } else {
+        None
+    };
+    let rules = best_rules.as_ref().unwrap_or(rules);
+
+    let mut n_retries = 0;
+    loop {
+        let new_candidate_filename = new_candidate_filename(&filename, rules, n_retries)?;
+        log::trace!("New candidate filename: {}", new_candidate_filename);
+
+        let new_path = dst_dir.join(rules.encode(&new_candidate_filename));
+
+        if !check_file_existence(&new_path) {
+            return Ok(new_candidate_filename);
+        }
+
+        // titles which begin the same usually differ at the end, `Part 1` and `Part 2`, so the name taken is likely of
+        // another file whose title was cut the same. only a cut title makes another candidate
+        if n_retries == 0 && rules.keep_title_end {
+            let title_end_rules = Rules { cut_title_middle: true, ..rules.clone() };
+            let candidate = crate::new_candidate_filename(&filename, &title_end_rules, 0)?;
+            if candidate != new_candidate_filename && rules.profile.is_none_or(|profile| profile.allows(&candidate)) && !check_file_existence(&dst_dir.join(rules.encode(&candidate))) {
+                return Ok(candidate);
+            }
+        }
+
+        n_retries += 1;
+        if n_retries == 1 {
+            if let Some(n_max_counter) = dst_names.and_then(|names| max_counter(names, &filename, rules)) {
+                n_retries = n_max_counter + 1;
+            }
+        }
+    }
+}
+
+// the title and the tags of a name without the extension, normalized for comparing
+fn split_name(name: &str, rules: &Rules) -> (String, Vec<String>) {
+    let mut components = component_spans(name, rules).into_iter().map(|(_, component)| component);
+    (components.next().unwrap_or_default(), components.collect())
+}
+
+// the title and then the tags of a name without the extension, normalized, with their ranges in the name
+fn component_spans(name: &str, rules: &Rules) -> Vec<(Range<usize>, String)> {
+    let slug = match name.rsplit_once('.') {
+        Some((slug, _)) if !slug.is_empty() => slug,
+        _ => name,
+    };
+    if slug.is_empty() {
+        return Vec::new();
+    }
+    let (title, tags) = split_into_components(slug, rules);
+    std::iter::once((0..title.len(), rules.normalize_tag(title)))
+        .chain(tags.into_iter().map(|component| (component.span, rules.normalize_tag(&component.tag))))
+        .collect()
+}
+
+fn name_components(name: &str, rules: &Rules) -> HashSet<String> {
+    let (title, tags) = split_name(name, rules);
+    std::iter::once(title).chain(tags).filter(|component| !component.is_empty()).collect()
+}
+
+// how many of the title and the tags of the name none of the siblings has, which tell the name apart from them
+fn distinctiveness_among(name: &str, sibling_components: &HashSet<String>, rules: &Rules) -> usize {
+    name_components(name, rules).iter().filter(|component| !sibling_components.contains(*component)).count()
+}
+
+// the siblings are the names in the destination directory other than the old name, for `Objective::Distinctiveness`
+fn packing_objective<'a>(rules: &Rules, siblings: impl Iterator<Item = &'a OsString>) -> Box<dyn PackingObjective> {
+    match rules.objective {
+        Objective::ShortestFirst => Box::new(ShortestFirst),
+        Objective::BytesKept => Box::new(BytesKept),
+        Objective::Priority => Box::new(PriorityWeighted::new(rules.tag_priorities.clone())),
+        Objective::Distinctiveness => {
+            Box::new(Distinctiveness::new(siblings.flat_map(|name| name_components(&name.to_string_lossy(), rules)).collect()))
+        },
+        Objective::Rarity => Box::new(Rarity::new(rules.tag_frequencies.clone())),
+    }
+}
+
+// the rules of the packing which scores the highest, see `PackingObjective`. an alternative is packed shortest-first
+// too, with one of the tags kept by the shortest-first packing ignored
+fn best_packing_rules(filename: &str, rules: &Rules, objective: &dyn PackingObjective) -> Result<Rules> {
+    let old_components = name_components(filename, rules);
+    let score = |candidate: &str| {
+        let components = name_components(candidate, rules);
+        let mut kept = components.iter().filter(|component| old_components.contains(*component)).cloned().collect::<Vec<_>>();
+        kept.sort();
+        objective.score(&Packing { name: candidate, components: &components, kept: &kept })
+    };
+    let candidate = new_candidate_filename(filename, rules, 0)?;
+    let mut best = (score(&candidate), rules.clone());
+    let (_, mut kept_tags) = split_name(&candidate, rules);
+    // the order of the alternatives is fixed for the same result every time
+    kept_tags.sort();
+    kept_tags.dedup();
+    for tag in kept_tags {
+        let mut alternative_rules = rules.clone();
+        alternative_rules.ignored_tags.insert(tag);
+        let alternative = new_candidate_filename(filename, &alternative_rules, 0)?;
+        let score = score(&alternative);
+        log::trace!("Alternative packing: {} (score {})", alternative, score);
+        if best.0 < score {
+            best = (score, alternative_rules);
+        }
+    }
+    Ok(best.1)
+}
+
+// why a name was shortened the way it was, for --explain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    // the title and the tags of the old name the new one doesn't have, normalized
+    pub dropped: Vec<String>,
+    // of the dropped ones in the old name, in bytes, for highlighting them
+    pub dropped_ranges: Vec<Range<usize>>,
+    // how many of the title and the tags of the new name no sibling has
+    pub distinctiveness: usize,
+}
+
+pub fn explain_rename(src_name: &str, dst_name: &str, siblings: &[OsString]) -> Explanation {
+    let rules = Rules::load();
+    let dst_components = name_components(dst_name, &rules);
+    let mut dropped = name_components(&rules.fold(src_name), &rules).into_iter().filter(|component| !dst_components.contains(component)).collect::<Vec<_>>();
+    dropped.sort();
+    let dropped_ranges = component_spans(src_name, &rules).into_iter()
+        .filter(|(_, component)| !dst_components.contains(&rules.normalize_tag(&rules.fold(component))))
+        .map(|(range, _)| range).collect();
+    let sibling_components = siblings.iter().filter(|name| name.as_os_str() != OsStr::new(src_name) && name.as_os_str() != OsStr::new(dst_name))
+        .flat_map(|name| name_components(&name.to_string_lossy(), &rules)).collect::<HashSet<_>>();
+    Explanation { dropped, dropped_ranges, distinctiveness: distinctiveness_among(dst_name, &sibling_components, &rules) }
+}
+
+// how much of the title and the tags of an old name the new name still has, for --loss-stats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    // of the title and the tags of the old name, normalized, without the delimiters and the extension
+    pub n_content_bytes: usize,
+    // of them, the ones in the new name. of a component cut short, the part kept
+    pub n_retained_bytes: usize,
+}
+
+impl Retention {
+    // rounded down, a name without a title or tags retains everything there is
+    pub fn percent(&self) -> usize {
+        if self.n_content_bytes == 0 {
+            return 100;
+        }
+        self.n_retained_bytes * 100 / self.n_content_bytes
+    }
+}
+
+pub fn retention(src_name: &str, dst_name: &str) -> Retention {
+    let rules = Rules::load();
+    let dst_components = name_components(dst_name, &rules);
+    let mut retention = Retention { n_content_bytes: 0, n_retained_bytes: 0 };
+    for (_, component) in component_spans(src_name, &rules) {
+        let component = rules.normalize_tag(&rules.fold(&component));
+        retention.n_content_bytes += component.len();
+        retention.n_retained_bytes += if dst_components.contains(&component) {
+            component.len()
+        } else {
+            // cut, the longest of the new components it begins with
+            dst_components.iter().filter(|dst_component| component.starts_with(dst_component.as_str())).map(String::len).max().unwrap_or(0)
+        };
+    }
+    retention
+}
+
+// what a component of a name is to the shortening, see `parse_components`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentClass {
+    // the first component, cut when nothing else is left to drop
+    Title,
+    Tag,
+    // a tag in `ignored_tags`, dropped before the others
+    IgnoredTag,
+    // the part after the last `.` when it's short enough, kept unless a counter needs the room
+    Extension,
+    // the suffix of a conflict copy made by a sync tool, with its delimiter, when `sync_conflict_suffix` isn't `tag`
+    SyncConflictSuffix,
+}
+
+// a component of a name and what shortening it would do to the component, for GUIs showing each tag before renaming
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentInfo {
+    // as in the name, without the delimiter before it
+    pub text: String,
+    // of `text` in the name, in bytes
+    pub range: Range<usize>,
+    pub class: ComponentClass,
+    // not in the shortened name as a whole, a component cut short included. nothing is dropped from a name which fits
+    pub would_be_dropped: bool,
+    // the text after `conversions`, when the name is shortened and they change it
+    pub converted_to: Option<String>,
+}
+
+// the components of the name in order, with the rules of the config and the overrides, for the limit of 255 bytes
+pub fn parse_components(filename: &str, overrides: &RuleOverrides) -> Vec<ComponentInfo> {
+    parse_components_impl(filename, &Rules::load_with(overrides))
+}
+
+fn parse_components_impl(filename: &str, rules: &Rules) -> Vec<ComponentInfo> {
+    if filename.is_empty() {
+        return Vec::new();
+    }
+    // a limit too small for any name drops everything
+    let shortened = shorten_filename_among(filename, rules, |_| false).unwrap_or_default();
+    let is_shortened = shortened != filename;
+    let kept_pieces = shortened.split(DELIMITERS).map(|piece| rules.normalize_tag(piece)).collect::<HashSet<_>>();
+
+    // the extension as `new_candidate_filename` takes it
+    let (slug_end, ext_range) = match filename.rfind(DELIMITERS) {
+        Some(i) if 0 < i && filename.len() - i - 1 <= N_MAX_EXTENSION_BYTES => (i, Some(i + 1..filename.len())),
+        _ => (filename.len(), None),
+    };
+    let (slug, sync_conflict_suffix) = match rules.sync_conflict_suffix {
+        SyncConflictSuffix::Tag => (&filename[..slug_end], None),
+        _ => split_sync_conflict_suffix(&filename[..slug_end]),
     };
 
-    if filename.as_encoded_bytes().len() <= N_FILENAME_BYTES {
-        let filename = filename.to_string_lossy().to_string();
-        if to_same_dir {
-            return Ok(filename);
-        }
-
-        let new_path = dst_dir.join(&filename);
-        if !check_file_existence(&new_path) {
-            return Ok(filename);
+    let mut components = Vec::new();
+    if !slug.is_empty() {
+        let (title, tags) = split_into_components(slug, rules);
+        let converted = rules.convert_title_words(&rules.convert_title(title));
+        components.push(ComponentInfo {
+            text: title.to_string(),
+            range: 0..title.len(),
+            class: ComponentClass::Title,
+            would_be_dropped: is_shortened && !shortened.starts_with(&rules.fold(&converted)),
+            converted_to: Some(converted).filter(|converted| is_shortened && converted != title),
+        });
+        let mut seen_tags = HashSet::new();
+        for tag in tags {
+            let text = &slug[tag.span.clone()];
+            let is_repeated = !seen_tags.insert(rules.normalize_tag(&tag.tag));
+            components.push(ComponentInfo {
+                text: text.to_string(),
+                range: tag.span,
+                class: if rules.ignored_tags.contains(&rules.normalize_tag(text)) { ComponentClass::IgnoredTag } else { ComponentClass::Tag },
+                would_be_dropped: is_shortened && (is_repeated || !kept_pieces.contains(&rules.normalize_tag(&rules.fold(&tag.tag)))),
+                converted_to: Some(tag.tag).filter(|converted| is_shortened && converted != text),
+            });
         }
     }
+    if let Some(suffix) = sync_conflict_suffix {
+        components.push(ComponentInfo {
+            text: suffix.to_string(),
+            range: slug.len()..slug_end,
+            class: ComponentClass::SyncConflictSuffix,
+            would_be_dropped: is_shortened && !shortened.contains(&rules.fold(suffix)),
+            converted_to: None,
+        });
+    }
+    if let Some(range) = ext_range {
+        let text = &filename[range.clone()];
+        components.push(ComponentInfo {
+            text: text.to_string(),
+            range,
+            class: ComponentClass::Extension,
+            would_be_dropped: is_shortened && !shortened.ends_with(&format!(".{}", rules.fold(text))),
+            converted_to: None,
+        });
+    }
+    components
+}
 
-    let filename = filename.to_string_lossy();
-    let mut n_retries = 0;
-    loop {
-        let new_candidate_filename = new_candidate_filename(&filename, &ignored_tags, &tag_conversion_map, n_retries);
-        log::trace!("New candidate filename: {}", new_candidate_filename);
-
-        fs::create_dir_all(&dst_dir)?;
-        let new_path = dst_dir.join(&new_candidate_filename);
+// the largest counter of the names which are the candidates of the filename, `a.7.txt` of `a.txt`
+fn max_counter(names: &[OsString], filename: &str, rules: &Rules) -> Option<usize> {
+    // counters are given one after another, so a larger number is a date or an id, `a.20240501.txt`
+    let n_max_digits = names.len().to_string().len();
+    (1..=n_max_digits).filter_map(|n_digits| {
+        // the candidates with counters of the same width are cut the same, and differ only where the counter goes.
+        // consecutive counters differ in the last digit, so what follows it is the common end
+        let first = 10usize.pow(n_digits as u32 - 1);
+        let candidate = |n| new_candidate_filename(filename, rules, n).ok().map(|candidate| rules.encode(&candidate));
+        let (a, b) = (candidate(first)?, candidate(first + 1)?);
+        let (a, b) = (a.as_encoded_bytes(), b.as_encoded_bytes());
+        let n_suffix_bytes = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+        let n_prefix_bytes = a.len().checked_sub(n_suffix_bytes + n_digits)?;
+        let (prefix, suffix) = (&a[..n_prefix_bytes], &a[a.len() - n_suffix_bytes..]);
+        names.iter().filter_map(|name| {
+            let digits = name.as_encoded_bytes().strip_prefix(prefix)?.strip_suffix(suffix)?;
+            if digits.len() != n_digits || digits[0] == b'0' || !digits.iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            std::str::from_utf8(digits).ok()?.parse::<usize>().ok()
+        }).filter(|&n| n <= names.len()).max()
+    }).max()
+}
 
-        if !check_file_existence(&new_path) {
-            return Ok(new_candidate_filename);
-        }
+fn counter(rules: &Rules, n_retries: usize) -> String {
+    // the counter is a part of the extension, `a.1.txt`, or joined by the delimiter of the profile, `A_1.TXT`
+    let counter_delimiter = rules.profile.map_or('.', |p| p.counter_delimiter());
+    if n_retries == 0 { String::new() } else { format!("{}{}", counter_delimiter, n_retries) }
+}
 
-        n_retries += 1;
-    }
+// the first character of the name stays in front of the counter and the extension at least,
+// a bare `.1.txt` would be hidden and tell nothing
+fn min_candidate_bytes(filename: &str, rules: &Rules, n_retries: usize) -> usize {
+    let n_min_slug_bytes = filename.chars().next().map_or(0, |c| rules.n_char_bytes(c));
+    n_min_slug_bytes + rules.n_bytes(&counter(rules, n_retries))
 }
 
-fn new_candidate_filename(filename: impl AsRef<str>, ignored_tags: &HashSet<String>, tag_conversion_map: &HashMap<String, String>, n_retries: usize) -> String {
+// fails only with very small limits, when the counter leaves no room for the name
+fn new_candidate_filename(filename: impl AsRef<str>, rules: &Rules, n_retries: usize) -> Result<String> {
     let filename = filename.as_ref();
     assert!(!filename.is_empty());
 
@@ -113,101 +1541,155 @@ fn new_candidate_filename(filename: impl AsRef<str>, ignored_tags: &HashSet<Stri
     let (ext, slug) = if let Some(ext) = ext {
         if ext.len() > N_MAX_EXTENSION_BYTES {
             (None, format!("{}.{}", slug, ext))
+        } else if let Some(n_max_extension_units) = rules.profile.and_then(|p| p.n_max_extension_units()) {
+            (Some(ext.chars().take(n_max_extension_units).collect::<String>()), slug)
         } else {
-            (Some(ext), slug)
+            (Some(ext.to_string()), slug)
         }
     } else {
         (None, slug)
     };
 
-    let ext = if let Some(ext) = ext {
-        if n_retries == 0 {
-            Some(ext.to_string())
-        } else {
-            Some(format!("{}.{}", n_retries, ext))
-        }
-    } else {
-        if n_retries == 0 {
-            None
-        } else {
-            Some(format!("{}", n_retries))
-        }
+    let budget_impossible = || Error::BudgetImpossible {
+        needed: min_candidate_bytes(filename, rules, n_retries),
+        available: rules.n_filename_bytes,
+    };
+    let counter = counter(rules, n_retries);
+    let n_min_slug_bytes = slug.chars().next().map_or(0, |c| rules.n_char_bytes(c));
+    let n_max_suffix_bytes = rules.n_filename_bytes.checked_sub(n_min_slug_bytes).ok_or_else(budget_impossible)?;
+    if n_max_suffix_bytes < rules.n_bytes(&counter) {
+        return Err(budget_impossible().into());
+    }
+    // the extension gives way to a counter too wide to go with it, and is cut as a part of the slug
+    let (ext, slug) = match ext {
+        Some(ext) if n_max_suffix_bytes < rules.n_bytes(&counter) + 1 + rules.n_bytes(&ext) => (None, format!("{}.{}", slug, ext)),
+        ext => (ext, slug),
+    };
+    let has_ext = ext.is_some();
+    let ext = match ext {
+        Some(ext) => format!("{}.{}", counter, ext),
+        None => counter,
     };
+    let n_remaining_slug_bytes = rules.n_filename_bytes - rules.n_bytes(&ext);
 
-    let (mut n_remaining_slug_bytes, slug, ext) = if let Some(ext) = &ext {
-        let ext_len = ext.len() + 1;
-        assert!(ext_len <= usize::MAX.to_string().as_bytes().len() + N_MAX_EXTENSION_BYTES + 2);
-        assert!(ext_len <= N_FILENAME_BYTES);
-        let n_remaining_slug_bytes = N_FILENAME_BYTES.checked_sub(ext_len).expect("checked");
-        (n_remaining_slug_bytes, slug, format!(".{}", ext))
-    } else {
-        (N_FILENAME_BYTES, filename.to_string(), "".to_string())
+    // 8 of 8.3 names, a counter without an extension is a part of them
+    let mut n_remaining_slug_bytes = match rules.profile.and_then(|p| p.n_max_stem_units()) {
+        Some(n_max_stem_units) if has_ext => n_remaining_slug_bytes.min(n_max_stem_units),
+        Some(n_max_stem_units) => n_max_stem_units.saturating_sub(rules.n_filename_bytes - n_remaining_slug_bytes),
+        None => n_remaining_slug_bytes,
     };
 
     log::trace!("Remaining slug bytes (subtract extention): {}", n_remaining_slug_bytes);
 
-    let (first_component, remaining_components) = split_into_components(&slug, tag_conversion_map);
+    // the suffix of a conflict copy is taken out of the slug, to be appended whole or not at all
+    let (slug, sync_conflict_suffix) = match (rules.sync_conflict_suffix, split_sync_conflict_suffix(&slug)) {
+        (SyncConflictSuffix::Keep, (rest, Some(suffix))) if rules.n_bytes(suffix) < n_remaining_slug_bytes => {
+            n_remaining_slug_bytes -= rules.n_bytes(suffix);
+            (rest, suffix)
+        },
+        (SyncConflictSuffix::Drop, (rest, Some(_))) => (rest, ""),
+        _ => (slug.as_str(), ""),
+    };
+
+    let (first_component, remaining_components) = split_into_components(&slug, rules);
+    if rules.empty_components == EmptyComponents::Error && remaining_components.iter().any(|component| component.tag.is_empty()) {
+        return Err(Error::EmptyComponent(filename.to_string()).into());
+    }
+    let first_component = &rules.convert_title_words(&rules.convert_title(first_component));
 
     let mut new_slug = String::new();
-    if first_component.as_bytes().len() > n_remaining_slug_bytes {
+    if rules.n_bytes(first_component) > n_remaining_slug_bytes {
+        let middle_cut_title = rules.cut_title_middle.then(|| cut_title_middle(first_component, n_remaining_slug_bytes, rules)).flatten();
+        let mut first_component = middle_cut_title.as_deref().unwrap_or(first_component);
+        if rules.tokenize_title {
+            // whole words from the end first, a word is cut only when the first one alone doesn't fit
+            while rules.n_bytes(first_component) > n_remaining_slug_bytes {
+                match first_component.rfind(TITLE_DELIMITERS) {
+                    Some(i) if 0 < i => first_component = first_component[..i].trim_end_matches(TITLE_DELIMITERS),
+                    _ => break,
+                }
+            }
+        }
+        if rules.title_word_boundaries {
+            if let Some(end) = words::word_ends(first_component).into_iter().rev().find(|&end| rules.n_bytes(&first_component[..end]) <= n_remaining_slug_bytes) {
+                first_component = &first_component[..end];
+            }
+        }
         for char in first_component.chars() {
-            if n_remaining_slug_bytes < char.len_utf8() {
+            if n_remaining_slug_bytes < rules.n_char_bytes(char) {
                 break;
             }
-            n_remaining_slug_bytes -= char.len_utf8();
+            n_remaining_slug_bytes -= rules.n_char_bytes(char);
             new_slug.push(char);
         }
     } else {
-        n_remaining_slug_bytes -= first_component.as_bytes().len();
+        n_remaining_slug_bytes -= rules.n_bytes(first_component);
         new_slug.push_str(first_component);
 
-        // (len, index)
-        let mut len_indecies = remaining_components.iter().enumerate().map(|(i, c)| {
-            let len = c.n_bytes();
-            (len, i)
-        }).collect::<Vec<_>>();
-
-        // shorter components prefered
-        len_indecies.sort_by(|(len1, _), (len2, _)| len1.cmp(len2));
-
-        let mut seen_tags = HashSet::new();
         let mut converted_components = vec![String::new(); remaining_components.len()];
-        for (len, i) in len_indecies {
-            let component = &remaining_components[i];
-            let delimiter = component.delimiter;
-            let raw_tag = &component.tag;
-            let normalized_tag = normalize_str(raw_tag);
-            if ignored_tags.contains(&normalized_tag) {
-                continue;
-            }
-            if seen_tags.contains(&normalized_tag) {
-                continue;
+        // the title is one of them
+        let n_max_tags = rules.max_components.map_or(usize::MAX, |n| n.saturating_sub(1));
+        let exact_indices = rules.exact_objective.as_ref().and_then(|objective| {
+            exact_tag_indices(&remaining_components, n_remaining_slug_bytes, n_max_tags, objective.as_ref(), rules)
+        });
+        if let Some(indices) = exact_indices {
+            for i in indices {
+                converted_components[i] = remaining_components[i].to_string();
             }
-            if n_remaining_slug_bytes == 0 {
-                break;
-            }
-            if n_remaining_slug_bytes < len {
-                let mut new_component = String::new();
-                if n_remaining_slug_bytes < delimiter.len_utf8() {
+        } else {
+            // (len, index)
+            let mut len_indecies = remaining_components.iter().enumerate().map(|(i, c)| {
+                let len = c.n_bytes(rules);
+                (len, i)
+            }).collect::<Vec<_>>();
+
+            // shorter components prefered
+            len_indecies.sort_by(|(len1, _), (len2, _)| len1.cmp(len2));
+
+            let mut seen_tags = HashSet::new();
+            let mut n_remaining_components = n_max_tags;
+            for (len, i) in len_indecies {
+                let component = &remaining_components[i];
+                let delimiter = component.delimiter;
+                let raw_tag = &component.tag;
+                let normalized_tag = rules.normalize_tag(raw_tag);
+                if rules.ignored_tags.contains(&normalized_tag) {
+                    continue;
+                }
+                if seen_tags.contains(&normalized_tag) {
+                    continue;
+                }
+                if n_remaining_slug_bytes == 0 || n_remaining_components == 0 {
                     break;
                 }
-                n_remaining_slug_bytes -= delimiter.len_utf8();
-                new_component.push(delimiter);
+                n_remaining_components -= 1;
+                if n_remaining_slug_bytes < len {
+                    let mut new_component = String::new();
+                    if n_remaining_slug_bytes < rules.n_char_bytes(delimiter) {
+                        break;
+                    }
+                    n_remaining_slug_bytes -= rules.n_char_bytes(delimiter);
+                    new_component.push(delimiter);
+
+                    for char in raw_tag.chars() {
+                        if n_remaining_slug_bytes < rules.n_char_bytes(char) {
+                            break;
+                        }
+                        n_remaining_slug_bytes -= rules.n_char_bytes(char);
+                        new_component.push(char);
+                    }
 
-                for char in raw_tag.chars() {
-                    if n_remaining_slug_bytes < char.len_utf8() {
+                    // nothing of the tag fits after the delimiter
+                    if new_component.len() == delimiter.len_utf8() && rules.empty_components != EmptyComponents::Keep {
                         break;
                     }
-                    n_remaining_slug_bytes -= char.len_utf8();
-                    new_component.push(char);
+                    converted_components[i] = new_component;
+                    break;
                 }
-
-                converted_components[i] = new_component;
-                break;
+                n_remaining_slug_bytes -= len;
+                converted_components[i] = delimiter.to_string() + &raw_tag;
+                seen_tags.insert(normalized_tag);
             }
-            n_remaining_slug_bytes -= len;
-            converted_components[i] = delimiter.to_string() + &raw_tag;
-            seen_tags.insert(normalized_tag);
         }
 
         for component in converted_components {
@@ -216,21 +1698,75 @@ fn new_candidate_filename(filename: impl AsRef<str>, ignored_tags: &HashSet<Stri
         }
     }
 
-    let new_filename = format!("{}{}", new_slug, ext);
+    // the stem limit of a profile can still leave no room
+    if new_slug.is_empty() {
+        return Err(budget_impossible().into());
+    }
+
+    let new_filename = format!("{}{}{}", new_slug, sync_conflict_suffix, ext);
     log::trace!("New filename: ({1}) {0}", new_filename, new_filename.as_bytes().len());
-    assert!(new_filename.as_bytes().len() <= N_FILENAME_BYTES);
-    return new_filename;
+    assert!(rules.n_bytes(&new_filename) <= rules.n_filename_bytes);
+    Ok(new_filename)
+}
+
+// the beginning and the end of a title too long for `n_bytes`, around `TITLE_ELLIPSIS`. the end takes up to half of the
+// room, from the start of a word when one fits. None when the room is too small for both
+fn cut_title_middle(title: &str, n_bytes: usize, rules: &Rules) -> Option<String> {
+    let n_half_bytes = n_bytes.checked_sub(rules.n_char_bytes(TITLE_ELLIPSIS))? / 2;
+    let mut end_start = title.len();
+    for (i, c) in title.char_indices().rev() {
+        if n_half_bytes < rules.n_bytes(&title[i..]) || i == 0 {
+            break;
+        }
+        end_start = i;
+        // the start of a word
+        if title[..i].ends_with(|c: char| !c.is_alphanumeric()) && c.is_alphanumeric() {
+            break;
+        }
+    }
+    let end = &title[end_start..];
+    let n_beginning_bytes = n_bytes - rules.n_char_bytes(TITLE_ELLIPSIS) - rules.n_bytes(end);
+    let mut beginning = String::new();
+    for c in title.chars() {
+        if n_beginning_bytes < rules.n_bytes(&beginning) + rules.n_char_bytes(c) {
+            break;
+        }
+        beginning.push(c);
+    }
+    let beginning = beginning.trim_end_matches(TITLE_DELIMITERS);
+    if end.is_empty() || beginning.is_empty() {
+        return None;
+    }
+    Some(format!("{}{}{}", beginning, TITLE_ELLIPSIS, end))
+}
+
+// the indices of the tags kept whole by `PackingMode::Exact`, None when the objective has no tag values. the ignored
+// tags and the repeated ones are left out as in the greedy packing
+fn exact_tag_indices(components: &[SlugComponent], n_bytes: usize, n_max_tags: usize, objective: &dyn PackingObjective, rules: &Rules) -> Option<Vec<usize>> {
+    let mut seen_tags = HashSet::new();
+    let mut candidates = Vec::new();
+    for (i, component) in components.iter().enumerate() {
+        let normalized_tag = rules.normalize_tag(&component.tag);
+        if rules.ignored_tags.contains(&normalized_tag) || !seen_tags.insert(normalized_tag.clone()) {
+            continue;
+        }
+        candidates.push((i, (component.n_bytes(rules), objective.tag_value(&normalized_tag)?)));
+    }
+    let items = candidates.iter().map(|&(_, item)| item).collect::<Vec<_>>();
+    Some(objective::exact_tags(&items, n_bytes, n_max_tags).into_iter().map(|j| candidates[j].0).collect())
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct SlugComponent {
     delimiter: char,
     tag: String,
+    // of the tag as it was in the slug, before conversions, without the delimiter
+    span: Range<usize>,
 }
 
 impl SlugComponent {
-    fn n_bytes(&self) -> usize {
-        self.tag.as_bytes().len() + self.delimiter.len_utf8()
+    fn n_bytes(&self, rules: &Rules) -> usize {
+        rules.n_bytes(&self.tag) + rules.n_char_bytes(self.delimiter)
     }
 }
 
@@ -241,8 +1777,12 @@ impl std::fmt::Display for SlugComponent {
 }
 
 const DELIMITERS: [char; 1] = ['.'];
+// between words of the first component, see `tokenize_title`
+const TITLE_DELIMITERS: [char; 2] = [' ', '_'];
+// in place of the middle of a title, see `keep_title_end`
+const TITLE_ELLIPSIS: char = '~';
 
-fn split_into_components<'a>(slug: &'a str, tag_conversion_map: &HashMap<String, String>) -> (&'a str, Vec<SlugComponent>) {
+fn split_into_components<'a>(slug: &'a str, rules: &Rules) -> (&'a str, Vec<SlugComponent>) {
     assert!(!slug.is_empty());
     let mut components = Vec::new();
 
@@ -265,25 +1805,61 @@ fn split_into_components<'a>(slug: &'a str, tag_conversion_map: &HashMap<String,
     while let Some((i, c)) = char_indices.next() {
         if DELIMITERS.contains(&c) {
             let tag = &slug[start + c.len_utf8() .. i];
-            components.push(SlugComponent { delimiter: c, tag: tag.to_string() });
+            components.push(SlugComponent { delimiter: c, tag: tag.to_string(), span: start + c.len_utf8()..i });
             start = i;
         }
     }
     if start < slug.len() {
         let c = slug[start..].chars().next().expect("checked");
         let tag = &slug[start + c.len_utf8()..];
-        components.push(SlugComponent { delimiter: c, tag: tag.to_string() });
+        components.push(SlugComponent { delimiter: c, tag: tag.to_string(), span: start + c.len_utf8()..slug.len() });
     }
 
     let components = components.into_iter().map(|c| {
         let delimiter = c.delimiter;
-        let tag = tag_conversion_map.get(&normalize_str(&c.tag)).unwrap_or(&c.tag);
-        SlugComponent { delimiter, tag: tag.to_string() }
-    }).collect();
+        let tag = rules.convert_tag(&c.tag);
+        SlugComponent { delimiter, tag: tag.to_string(), span: c.span }
+    }).filter(|c| rules.empty_components != EmptyComponents::Collapse || !c.tag.is_empty()).collect();
 
     (first_component, components)
 }
 
+// the `.` separated components of the name as they are packed, the extension and the suffix of a conflict copy aside.
+// a number at the end is taken for a counter and not counted either, so that names with a counter don't grow out of the limit
+fn n_components(filename: &str) -> usize {
+    // a leading dot is a part of the title
+    let (leading, rest) = filename.split_at(if filename.starts_with(DELIMITERS) { 1 } else { 0 });
+    let mut pieces = rest.split(DELIMITERS).collect::<Vec<_>>();
+    if 1 < pieces.len() && pieces.last().is_some_and(|ext| ext.len() <= N_MAX_EXTENSION_BYTES) {
+        pieces.pop();
+    }
+    if 1 < pieces.len() && pieces.last().is_some_and(|piece| !piece.is_empty() && piece.chars().all(|c| c.is_ascii_digit())) {
+        pieces.pop();
+    }
+    let slug = format!("{}{}", leading, pieces.join("."));
+    let (slug, _) = split_sync_conflict_suffix(&slug);
+    slug.trim_start_matches(DELIMITERS).split(DELIMITERS).count()
+}
+
+// (rest, suffix) of the slug of a conflict copy made by a sync tool, the rest is never empty
+fn split_sync_conflict_suffix(slug: &str) -> (&str, Option<&str>) {
+    // syncthing: `a.sync-conflict-20240501-123456-ABCDEFG`
+    if let Some(i) = slug.rfind(".sync-conflict-") {
+        if 0 < i && !slug[i + 1..].contains(DELIMITERS) {
+            return (&slug[..i], Some(&slug[i..]));
+        }
+    }
+    // dropbox: `a (Taro's conflicted copy 2024-05-01)`, nextcloud: `a (conflicted copy 2024-05-01 123456)`
+    if slug.ends_with(')') {
+        if let Some(i) = slug.rfind(" (") {
+            if 0 < i && slug[i..].to_lowercase().contains("conflicted copy") {
+                return (&slug[..i], Some(&slug[i..]));
+            }
+        }
+    }
+    (slug, None)
+}
+
 fn normalize_str(s: impl AsRef<str>) -> String {
     // NFD normalization for interportability
     s.as_ref().nfd().collect()
@@ -293,51 +1869,118 @@ fn normalize_str(s: impl AsRef<str>) -> String {
 mod tests {
     use super::*;
     use env_logger;
+    use std::ffi::OsString;
 
     #[test]
     fn test_split_into_components() {
         let _ = env_logger::try_init();
 
         let slug = "a.b.c..d";
-        let components = split_into_components(slug, &HashMap::new());
+        let components = split_into_components(slug, &Rules::default());
         assert_eq!(components, ("a", vec![
-            SlugComponent { delimiter: '.', tag: "b".to_string() },
-            SlugComponent { delimiter: '.', tag: "c".to_string() },
-            SlugComponent { delimiter: '.', tag: "".to_string() },
-            SlugComponent { delimiter: '.', tag: "d".to_string() },
+            SlugComponent { delimiter: '.', tag: "b".to_string(), span: 2..3 },
+            SlugComponent { delimiter: '.', tag: "c".to_string(), span: 4..5 },
+            SlugComponent { delimiter: '.', tag: "".to_string(), span: 6..6 },
+            SlugComponent { delimiter: '.', tag: "d".to_string(), span: 7..8 },
         ]));
 
         let slug = ".あああ.いいい.ううう";
-        let components = split_into_components(slug, &HashMap::new());
+        let components = split_into_components(slug, &Rules::default());
         assert_eq!(components, (".あああ", vec![
-            SlugComponent { delimiter: '.', tag: "いいい".to_string() },
-            SlugComponent { delimiter: '.', tag: "ううう".to_string() },
+            SlugComponent { delimiter: '.', tag: "いいい".to_string(), span: 11..20 },
+            SlugComponent { delimiter: '.', tag: "ううう".to_string(), span: 21..30 },
         ]));
+
+        // the spans are of the tags before conversions
+        let mut rules = Rules::default();
+        rules.tag_conversion_map.insert("long".to_string(), "l".to_string());
+        let (_, components) = split_into_components("a.long.b", &rules);
+        assert_eq!(components[0], SlugComponent { delimiter: '.', tag: "l".to_string(), span: 2..6 });
+    }
+
+    #[test]
+    fn test_title_word_boundaries() {
+        let _ = env_logger::try_init();
+
+        let rules = Rules { n_filename_bytes: 16, ..Default::default() };
+        assert_eq!(shorten_filename("The quick-brown fox.txt", &rules).unwrap(), "The quick-br.txt");
+        let rules = Rules { title_word_boundaries: true, ..rules };
+        assert_eq!(shorten_filename("The quick-brown fox.txt", &rules).unwrap(), "The quick.txt");
+        assert_eq!(shorten_filename("Thequickbrownfox.txt", &rules).unwrap(), "Thequickbrow.txt");
+    }
+
+    #[test]
+    fn test_keep_title_end() {
+        let _ = env_logger::try_init();
+
+        let rules = Rules { n_filename_bytes: 14, ..Default::default() };
+        assert_eq!(cut_title_middle("Long title part 12", 10, &rules).unwrap(), "Long ti~12");
+        assert_eq!(cut_title_middle("Long title part12", 10, &rules).unwrap(), "Long~rt12");
+        assert_eq!(cut_title_middle("Long", 2, &rules), None);
+
+        let taken = |path: &Path| path == Path::new("d/Long title.txt");
+        assert_eq!(new_filename_impl("Long title part 12.txt", Some("d"), &rules, taken).unwrap(), "Long tit.1.txt");
+        let rules = Rules { keep_title_end: true, ..rules };
+        assert_eq!(new_filename_impl("Long title part 12.txt", Some("d"), &rules, taken).unwrap(), "Long ti~12.txt");
+        // a whole title is the same title, the tags made the difference
+        assert_eq!(new_filename_impl("Long title.ab.txt", Some("d"), &rules, taken).unwrap(), "Long tit.1.txt");
+    }
+
+    #[test]
+    fn test_empty_components() {
+        let _ = env_logger::try_init();
+
+        let rules = Rules { n_filename_bytes: 12, ..Default::default() };
+        // the second empty tag is a repeated one
+        assert_eq!(shorten_filename("t..a1...b2.long.txt", &rules).unwrap(), "t..a1.b2.txt");
+        assert_eq!(shorten_filename("t.a1.b2.long.txt", &rules).unwrap(), "t.a1.b2..txt");
+        let rules = Rules { empty_components: EmptyComponents::Collapse, ..rules };
+        assert_eq!(shorten_filename("t..a1...b2.long.txt", &rules).unwrap(), "t.a1.b2.txt");
+        assert_eq!(split_into_components("t..a1", &rules), ("t", vec![SlugComponent { delimiter: '.', tag: "a1".to_string(), span: 3..5 }]));
+        // conversions to nothing too
+        let mut rules = Rules { n_filename_bytes: 8, ..rules };
+        rules.tag_conversion_map.insert("x".to_string(), "".to_string());
+        assert_eq!(shorten_filename("t.x.a1.long.txt", &rules).unwrap(), "t.a1.txt");
+        // fitting names are left as they are
+        let rules = Rules { empty_components: EmptyComponents::Error, ..rules };
+        assert_eq!(shorten_filename_among("a..b", &rules, |_| false).unwrap(), "a..b");
+        assert_eq!(shorten_filename_among("t..a1.long.txt", &rules, |_| false).err().unwrap().to_string(), "Empty component in filename: t..a1.long.txt");
+        // and the callers without a filesystem pass it up
+        let mapper = NameMapper { rules: rules.clone() };
+        assert_eq!(mapper.map("x/t..a1.long.txt").err().unwrap().to_string(), "Empty component in filename: t..a1.long.txt");
+        assert_eq!(shorten_path_impl(Path::new("x/t..a1.long.txt"), &rules).err().unwrap().to_string(), "Empty component in filename: t..a1.long.txt");
     }
 
     #[test]
     fn test_new_filename() {
         let _ = env_logger::try_init();
 
-        assert_eq!(new_filename_impl(PathBuf::from("."), None::<PathBuf>, |_| false).err().unwrap().to_string(), "Filename not found in path: .");
+        assert_eq!(new_filename_impl(PathBuf::from("."), None::<PathBuf>, &Rules::load(), |_| false).err().unwrap().to_string(), "Filename not found in path: .");
 
-        assert_eq!(new_filename_impl(PathBuf::from("a.b.c.txt"), None::<PathBuf>, |_| false).unwrap(), "a.b.c.txt");
-        assert_eq!(new_filename_impl(PathBuf::from("a.b.c.txt"), Some(Path::new(".")), |_| false).unwrap(), "a.b.c.txt");
+        assert_eq!(new_filename_impl(PathBuf::from("a.b.c.txt"), None::<PathBuf>, &Rules::load(), |_| false).unwrap(), "a.b.c.txt");
+        assert_eq!(new_filename_impl(PathBuf::from("a.b.c.txt"), Some(Path::new(".")), &Rules::load(), |_| false).unwrap(), "a.b.c.txt");
 
-        assert_eq!(new_filename_impl(PathBuf::from("一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十"), None::<PathBuf>, |p| {
+        assert_eq!(new_filename_impl(PathBuf::from("一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十"), None::<PathBuf>, &Rules::load(), |p| {
             log::trace!("Check file existence: {:?}", p);
             match p.file_name().unwrap().to_str() {
                 Some(p) => p == "一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五",
                 None => false
             }
         }).unwrap(), "一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四.1");
-        assert_eq!(new_filename_impl(PathBuf::from("a.b.c.txt"), Some(Path::new(".")), |p| {
+        assert_eq!(new_filename_impl(PathBuf::from("a.b.c.txt"), Some(Path::new("b")), &Rules::load(), |p| {
             match p.file_name().unwrap().to_str() {
                 Some(p) => p == "a.b.c.txt",
                 None => false
             }
         }).unwrap(), "a.b.c.1.txt");
-        assert_eq!(new_filename_impl(PathBuf::from("a.b.c.txt"), Some(Path::new(".")), |p| {
+        // the same directory, the existing file is the file itself
+        assert_eq!(new_filename_impl(PathBuf::from("a.b.c.txt"), Some(Path::new(".")), &Rules::load(), |p| {
+            match p.file_name().unwrap().to_str() {
+                Some(p) => p == "a.b.c.txt",
+                None => false
+            }
+        }).unwrap(), "a.b.c.txt");
+        assert_eq!(new_filename_impl(PathBuf::from("a.b.c.txt"), Some(Path::new("b")), &Rules::load(), |p| {
             match p.file_name().unwrap().to_str() {
                 Some(p) => p == "a.b.c.txt" || p == "a.b.c.1.txt",
                 None => false,
@@ -345,19 +1988,515 @@ mod tests {
         }).unwrap(), "a.b.c.2.txt");
     }
 
+    #[test]
+    fn test_is_nfs_temp_file() {
+        assert!(is_nfs_temp_file("/mnt/share/.nfs000000000189f5f400000001"));
+        assert!(is_nfs_temp_file(".nfsA1B2"));
+        assert!(!is_nfs_temp_file(".nfs"));
+        assert!(!is_nfs_temp_file(".nfsrc"));
+        assert!(!is_nfs_temp_file("a.nfs0001"));
+        assert!(!is_nfs_temp_file("/"));
+    }
+
+    #[test]
+    fn test_exceeds_limit() {
+        assert_eq!(filename_byte_len("あ.txt"), 7);
+        assert!(!exceeds_limit(format!("/a/{}", "あ".repeat(85)), None));
+        assert!(exceeds_limit(format!("/a/{}.txt", "あ".repeat(85)), None));
+        assert!(!exceeds_limit(format!("{}/a", "a".repeat(300)), None));
+        assert!(!exceeds_limit("/", None));
+        assert!(!exceeds_limit(format!("{}.txt", "あ".repeat(60)), Some(Profile::Joliet)));
+        assert!(exceeds_limit(format!("{}.txt", "あ".repeat(61)), Some(Profile::Joliet)));
+        assert!(exceeds_limit("abcdefghijkl.txt", Some(Profile::Iso9660Level1)));
+    }
+
+    #[test]
+    fn test_name_mapper() {
+        let _ = env_logger::try_init();
+
+        let mapper = NameMapper::default();
+        let long_name = format!("{}.a.b.txt", "あ".repeat(100));
+        let short_name = format!("{}.txt", "あ".repeat(83));
+        assert_eq!(mapper.map("a/b.txt").unwrap(), "a/b.txt");
+        assert_eq!(mapper.map(&long_name).unwrap(), short_name);
+        assert_eq!(mapper.map(format!("/x/{}/{}", long_name, long_name)).unwrap(), format!("/x/{}/{}", short_name, short_name));
+        assert_eq!(mapper.map("dir/").unwrap(), "dir/");
+
+        // a limit too small for the name is reported, not a panic
+        let mapper = NameMapper { rules: Rules { n_filename_bytes: 1, ..Default::default() } };
+        assert_eq!(mapper.map("x/éééééé").err().unwrap().to_string(), "The limit of 1 bytes is too small, 2 bytes are needed at least");
+    }
+
+    #[test]
+    fn test_parse_components() {
+        let _ = env_logger::try_init();
+
+        let info = |text: &str, range, class, would_be_dropped, converted_to: Option<&str>| ComponentInfo {
+            text: text.to_string(), range, class, would_be_dropped, converted_to: converted_to.map(|s| s.to_string()),
+        };
+        let mut rules = Rules { n_filename_bytes: 20, ..Default::default() };
+        rules.ignored_tags.insert("sample".to_string());
+        rules.tag_conversion_map.insert("1080p".to_string(), "hd".to_string());
+        assert_eq!(parse_components_impl("Title.1080p.sample.extra-long-tag.mkv", &rules), vec![
+            info("Title", 0..5, ComponentClass::Title, false, None),
+            info("1080p", 6..11, ComponentClass::Tag, false, Some("hd")),
+            info("sample", 12..18, ComponentClass::IgnoredTag, true, None),
+            info("extra-long-tag", 19..33, ComponentClass::Tag, true, None),
+            info("mkv", 34..37, ComponentClass::Extension, false, None),
+        ]);
+        assert_eq!(parse_components_impl("a.sample.txt", &rules), vec![
+            info("a", 0..1, ComponentClass::Title, false, None),
+            info("sample", 2..8, ComponentClass::IgnoredTag, false, None),
+            info("txt", 9..12, ComponentClass::Extension, false, None),
+        ]);
+        assert_eq!(parse_components_impl(".hidden", &rules), vec![info(".hidden", 0..7, ComponentClass::Title, false, None)]);
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let _ = env_logger::try_init();
+
+        let config = Config {
+            conversions: [("a", "b/c"), ("b", "x\0"), ("c", "d.e"), ("d", "long"), ("long", "l")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Default::default()
+        };
+        let issues = Rules::resolve(&config).validate();
+        assert_eq!(issues, vec![
+            ConfigIssue::ConversionWithSeparator("a".to_string(), "b/c".to_string()),
+            ConfigIssue::ConversionWithNul("b".to_string(), "x\0".to_string()),
+            ConfigIssue::ConversionWithDelimiter("c".to_string(), "d.e".to_string()),
+            ConfigIssue::ConversionLongerThanKey("d".to_string(), "long".to_string()),
+        ]);
+        assert_eq!(issues.iter().filter(|issue| issue.is_error()).count(), 3);
+    }
+
+    #[test]
+    fn test_rule_overrides() {
+        let _ = env_logger::try_init();
+
+        let config = Config {
+            ignored_tags: ["a".to_string()].into_iter().collect(),
+            conversions: [("x", "y")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            case_insensitive_tags: true,
+            ..Default::default()
+        };
+        let mut rules = Rules::resolve(&config);
+        rules.apply(&RuleOverrides {
+            ignored_tags: vec!["B".to_string()],
+            conversions: vec![("X".to_string(), "z".to_string()), ("long".to_string(), "l".to_string())],
+            ..Default::default()
+        });
+        assert!(rules.ignored_tags.contains("a"));
+        assert!(rules.ignored_tags.contains(&rules.normalize_tag("b")));
+        assert_eq!(rules.convert_tag("x"), "z");
+        assert_eq!(rules.convert_tag("LONG"), "l");
+    }
+
+    #[test]
+    fn test_rule_profile() {
+        let _ = env_logger::try_init();
+
+        let mut config = Config {
+            ignored_tags: ["a".to_string()].into_iter().collect(),
+            conversions: [("x", "y")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            chain_conversions: true,
+            ..Default::default()
+        };
+        config.apply_profile(&RuleProfile {
+            ignored_tags: Some(["b".to_string()].into_iter().collect()),
+            tokenize_title: Some(true),
+            ..Default::default()
+        });
+        assert_eq!(config.ignored_tags, ["b".to_string()].into_iter().collect());
+        assert_eq!(config.conversions.get("x").map(|s| s.as_str()), Some("y"));
+        assert!(config.chain_conversions);
+        assert!(config.tokenize_title);
+
+        config.profiles = [
+            ("media", vec!["/home/*/Videos/**"]),
+            ("anime", vec!["/home/*/Videos/anime/**"]),
+            ("none", vec![]),
+        ].into_iter().map(|(name, patterns)| {
+            (name.to_string(), RuleProfile { path_patterns: patterns.into_iter().map(|p| p.to_string()).collect(), ..Default::default() })
+        }).collect();
+        assert_eq!(config.profile_for_path(Path::new("/home/a/Videos/x.mkv")), Some("media"));
+        assert_eq!(config.profile_for_path(Path::new("/home/a/Videos/anime/x.mkv")), Some("anime"));
+        assert_eq!(config.profile_for_path(Path::new("/home/a/Music/x.mp3")), None);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_project_rules() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("repo/.git")).unwrap();
+        fs::create_dir_all(dir.join("repo/app/sub")).unwrap();
+        fs::write(dir.join("repo/.renamelimit.json"), r#"{"ignored_tags": ["a"]}"#).unwrap();
+        fs::write(dir.join("repo/app/.renamelimit-root"), "").unwrap();
+        fs::write(dir.join("repo/app/.renamelimit.json"), r#"{"ignored_tags": ["b"], "tokenize_title": true}"#).unwrap();
+
+        assert_eq!(project_root(&dir.join("repo/x.txt")), Some(dir.join("repo")));
+        assert_eq!(project_root(&dir.join("repo/app/sub/x.txt")), Some(dir.join("repo/app")));
+
+        let mut config = Config::default();
+        config.apply_project_rules(&dir.join("repo/x.txt"));
+        assert_eq!(config.ignored_tags, ["a".to_string()].into_iter().collect());
+        let mut config = Config::default();
+        config.apply_project_rules(&dir.join("repo/app/sub/x.txt"));
+        assert_eq!(config.ignored_tags, ["b".to_string()].into_iter().collect());
+        assert!(config.tokenize_title);
+
+        // broken, ignored
+        fs::write(dir.join("repo/app/.renamelimit.json"), "{").unwrap();
+        assert!(read_project_rules(&dir.join("repo/app")).is_none());
+        // but read once per run, the files of a run get the same rules
+        let mut config = Config::default();
+        config.apply_project_rules(&dir.join("repo/app/y.txt"));
+        assert!(config.tokenize_title);
+        assert_eq!(PROJECT_ROOTS.with(|roots| roots.borrow().get(&dir.join("repo/app/sub")).cloned()), Some(Some(dir.join("repo/app"))));
+    }
+
+    #[test]
+    fn test_similar_key() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        let known_keys = ["ignored_tags", "conversions", "lint.no_spaces"].map(|key| key.to_string());
+        assert_eq!(similar_key("ignore_tags", &known_keys), Some("ignored_tags"));
+        assert_eq!(similar_key("conversion", &known_keys), Some("conversions"));
+        assert_eq!(similar_key("lint.no_space", &known_keys), Some("lint.no_spaces"));
+        assert_eq!(similar_key("colors", &known_keys), None);
+
+        let config = ResolvedConfig { rules: Rules::default(), unknown_keys: vec!["colors".to_string()], profile_names: HashSet::new() };
+        let issues = config.validate();
+        assert_eq!(issues, vec![ConfigIssue::UnknownKey("colors".to_string())]);
+        assert!(!issues[0].is_error());
+    }
+
+    #[test]
+    fn test_chain_conversions() {
+        let _ = env_logger::try_init();
+
+        let mut config = Config {
+            conversions: [("a", "b"), ("b", "c"), ("x", "y"), ("y", "x"), ("s", "s")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Default::default()
+        };
+        let rules = Rules::resolve(&config);
+        assert_eq!(rules.convert_tag("a"), "b");
+        assert_eq!(rules.validate(), vec![]);
+
+        config.chain_conversions = true;
+        let rules = Rules::resolve(&config);
+        assert_eq!(rules.convert_tag("a"), "c");
+        assert_eq!(rules.convert_tag("b"), "c");
+        assert_eq!(rules.convert_tag("s"), "s");
+        assert_eq!(rules.convert_tag("x"), "y");
+        assert_eq!(rules.convert_tag("z"), "z");
+        assert_eq!(rules.validate(), vec![ConfigIssue::ConversionCycle("x -> y -> x".to_string())]);
+
+        config.conversions = (0..20).map(|i| (format!("t{}", i), format!("t{}", i + 1))).collect();
+        let rules = Rules::resolve(&config);
+        assert_eq!(rules.convert_tag("t0"), format!("t{}", N_MAX_CONVERSION_STEPS));
+        assert!(rules.validate().contains(&ConfigIssue::ConversionChainTooLong("t0".to_string())));
+    }
+
+    #[test]
+    fn test_convert_title() {
+        let _ = env_logger::try_init();
+
+        let mut config = Config {
+            conversions: [("劇場版", "劇"), ("劇場", "場"), ("第", "")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Default::default()
+        };
+        let rules = Rules::resolve(&config);
+        assert_eq!(rules.convert_title("劇場版 第1話"), "劇場版 第1話");
+
+        config.convert_title = true;
+        let rules = Rules::resolve(&config);
+        assert_eq!(rules.convert_title("劇場版 第1話 劇場"), "劇 1話 場");
+        assert_eq!(new_candidate_filename("劇場版 第1話.第.txt", &rules, 0).unwrap(), "劇 1話..txt");
+    }
+
+    #[test]
+    fn test_tokenize_title() {
+        let _ = env_logger::try_init();
+
+        let mut rules = Rules {
+            ignored_tags: ["the".to_string()].into_iter().collect(),
+            tag_conversion_map: [("episode".to_string(), "ep".to_string())].into_iter().collect(),
+            ..Default::default()
+        };
+        assert_eq!(rules.convert_title_words("the_show episode 1"), "the_show episode 1");
+
+        rules.tokenize_title = true;
+        assert_eq!(rules.convert_title_words("the_show episode 1"), "show ep 1");
+        assert_eq!(rules.convert_title_words("the"), "the");
+
+        let title = format!("{} {}", "あ".repeat(50), "い".repeat(50));
+        assert_eq!(new_candidate_filename(format!("{}.txt", title), &rules, 0).unwrap(), format!("{}.txt", "あ".repeat(50)));
+        rules.tokenize_title = false;
+        assert_eq!(new_candidate_filename(format!("{}.txt", title), &rules, 0).unwrap(), format!("{} {}.txt", "あ".repeat(50), "い".repeat(33)));
+    }
+
+    #[test]
+    fn test_shorten_path() {
+        let _ = env_logger::try_init();
+
+        let long_name = format!("{}.a.b.txt", "あ".repeat(100));
+        let short_name = format!("{}.txt", "あ".repeat(83));
+        let path = PathBuf::from(format!("/x/{}/./{}", long_name, long_name));
+        assert_eq!(shorten_path_impl(&path, &Rules::default()).unwrap(), PathBuf::from(format!("/x/{}/{}", short_name, short_name)));
+        assert_eq!(shorten_path_impl(Path::new("../a/b.txt"), &Rules::default()).unwrap(), PathBuf::from("../a/b.txt"));
+        let rules = Rules { n_filename_bytes: 1, ..Default::default() };
+        assert!(shorten_path_impl(Path::new("x/éééééé"), &rules).is_err());
+    }
+
+    #[test]
+    fn test_compatibility_folding() {
+        let _ = env_logger::try_init();
+
+        let rules = Rules { compatibility_folding: true, ..Default::default() };
+        let filename = format!("{}.ﬁ.①.txt", "Ａ".repeat(100));
+        assert_eq!(shorten_filename(&filename, &rules).unwrap(), format!("{}.fi.1.txt", "A".repeat(100)));
+        assert_eq!(shorten_filename("Ａ.txt", &rules).unwrap(), "Ａ.txt");
+
+        let rules = Rules::default();
+        assert_eq!(shorten_filename(&filename, &rules).unwrap(), format!("{}.txt", "Ａ".repeat(83)));
+
+        let rules = Rules { kana_width: Some(KanaWidth::Full), ..Default::default() };
+        let filename = format!("{}.txt", "ｶﾞ".repeat(50));
+        assert_eq!(shorten_filename(&filename, &rules).unwrap(), format!("{}.txt", "ガ".repeat(50)));
+    }
+
+    #[test]
+    fn test_new_filename_to_same_dir() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("a")).unwrap();
+        std::os::unix::fs::symlink("a", dir.join("b")).unwrap();
+        fs::write(dir.join("a/x.txt"), "").unwrap();
+
+        assert_eq!(new_filename_impl(dir.join("a/x.txt"), Some(dir.join("b")), &Rules::load(), |p| p.exists()).unwrap(), "x.txt");
+        fs::create_dir_all(dir.join("c")).unwrap();
+        fs::write(dir.join("c/x.txt"), "").unwrap();
+        assert_eq!(new_filename_impl(dir.join("a/x.txt"), Some(dir.join("c")), &Rules::load(), |p| p.exists()).unwrap(), "x.1.txt");
+    }
+
+    #[test]
+    fn test_claim_new_filename() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dst_dir = tmp.path();
+        fs::write(dst_dir.join("a.b.c.txt"), "").unwrap();
+
+        assert_eq!(claim_new_filename(PathBuf::from("a.b.c.txt"), Some(&dst_dir)).unwrap(), "a.b.c.1.txt");
+        assert!(dst_dir.join("a.b.c.1.txt").exists());
+        assert_eq!(claim_new_filename(PathBuf::from("a.b.c.txt"), Some(&dst_dir)).unwrap(), "a.b.c.2.txt");
+    }
+
     #[test]
     fn test_new_candidate_filename() {
         let _ = env_logger::try_init();
 
-        let ignored_tags = HashSet::new();
-        let tag_conversion_map = HashMap::new();
-        assert_eq!(new_candidate_filename("a.b.c..d", &ignored_tags, &tag_conversion_map, 0), "a.b.c..d");
-        assert_eq!(new_candidate_filename("a.b.c..d", &ignored_tags, &tag_conversion_map, 1), "a.b.c..1.d");
-        assert_eq!(new_candidate_filename("一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五", &ignored_tags, &tag_conversion_map, 0), "一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五");
-        assert_eq!(new_candidate_filename(".一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五", &ignored_tags, &tag_conversion_map, 0), ".一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四");
-        assert_eq!(new_candidate_filename("一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十", &ignored_tags, &tag_conversion_map, 0), "一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五");
-        assert_eq!(new_candidate_filename("一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五", &ignored_tags, &tag_conversion_map, 1), "一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四.1");
-        assert_eq!(new_candidate_filename(".一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五", &ignored_tags, &tag_conversion_map, 11), ".一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三.11");
+        let rules = Rules::default();
+        assert_eq!(new_candidate_filename("a.b.c..d", &rules, 0).unwrap(), "a.b.c..d");
+        assert_eq!(new_candidate_filename("a.b.c..d", &rules, 1).unwrap(), "a.b.c..1.d");
+        assert_eq!(new_candidate_filename("一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五", &rules, 0).unwrap(), "一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五");
+        assert_eq!(new_candidate_filename(".一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五", &rules, 0).unwrap(), ".一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四");
+        assert_eq!(new_candidate_filename("一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十", &rules, 0).unwrap(), "一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五");
+        assert_eq!(new_candidate_filename("一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五", &rules, 1).unwrap(), "一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四.1");
+        assert_eq!(new_candidate_filename(".一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五", &rules, 11).unwrap(), ".一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三四五六七八九十一二三.11");
+
+        let mut rules = Rules {
+            ignored_tags: ["sample".to_string()].into_iter().collect(),
+            tag_conversion_map: [("remastered".to_string(), "rm".to_string())].into_iter().collect(),
+            ..Default::default()
+        };
+        assert_eq!(new_candidate_filename("a.SAMPLE.Remastered.txt", &rules, 0).unwrap(), "a.SAMPLE.Remastered.txt");
+        rules.case_insensitive_tags = true;
+        assert_eq!(new_candidate_filename("a.SAMPLE.Remastered.txt", &rules, 0).unwrap(), "a.rm.txt");
+    }
+
+    #[test]
+    fn test_wide_counter() {
+        let _ = env_logger::try_init();
+
+        let rules = Rules::default();
+        let filename = new_candidate_filename("a.txt", &rules, usize::MAX).unwrap();
+        assert_eq!(filename, format!("a.{}.txt", usize::MAX));
+
+        let rules = Rules { n_filename_bytes: 8, ..Default::default() };
+        assert_eq!(new_candidate_filename("abcd.txt", &rules, 1).unwrap(), "ab.1.txt");
+        assert_eq!(new_candidate_filename("abcd.txt", &rules, 123).unwrap(), "abcd.123");
+        assert_eq!(new_candidate_filename("abcd.txt", &rules, 123456).unwrap(), "a.123456");
+        assert_eq!(new_candidate_filename("abcd.txt", &rules, 1234567).err().unwrap().to_string(), "The limit of 8 bytes is too small, 9 bytes are needed at least");
+        assert_eq!(min_candidate_bytes("abcd.txt", &rules, 1234567), 9);
+        assert_eq!(min_candidate_bytes("あ.txt", &rules, 0), 3);
+
+        let rules = Rules { n_filename_bytes: 2, ..Default::default() };
+        assert!(new_candidate_filename("あ.txt", &rules, 0).is_err());
+
+        let rules = Rules { n_filename_bytes: 3, ..Default::default() };
+        assert_eq!(shorten_filename_among("abc.txt", &rules, |name| name != "a.9").unwrap(), "a.9");
+        assert_eq!(shorten_filename_among("abc.txt", &rules, |_| true).err().unwrap().to_string(), "The limit of 3 bytes is too small, 4 bytes are needed at least");
+    }
+
+    #[test]
+    fn test_prefer_distinct() {
+        let _ = env_logger::try_init();
+
+        let names = ["t.a1.b2.other.mkv"].map(OsString::from);
+        let rules = Rules { n_filename_bytes: 11, ..Default::default() };
+        assert_eq!(new_filename_listed("t.a1.b2.unique.mkv", Some("d"), &rules, Some(&names), |_| false).unwrap(), "t.a1.b2.mkv");
+        let rules = Rules { objective: Objective::Distinctiveness, ..rules };
+        assert_eq!(new_filename_listed("t.a1.b2.unique.mkv", Some("d"), &rules, Some(&names), |_| false).unwrap(), "t.b2.un.mkv");
+        assert_eq!(new_filename_listed("t.a1.b2.unique.mkv", Some("d"), &rules, None, |_| false).unwrap(), "t.a1.b2.mkv");
+
+        assert_eq!(explain_rename("t.a1.b2.unique.mkv", "t.b2.un.mkv", &names), Explanation {
+            dropped: vec!["a1".to_string(), "unique".to_string()],
+            dropped_ranges: vec![2..4, 8..14],
+            distinctiveness: 1,
+        });
+        assert_eq!(retention("t.a1.b2.unique.mkv", "t.b2.un.mkv"), Retention { n_content_bytes: 11, n_retained_bytes: 5 });
+        assert_eq!(retention("t.a1.b2.unique.mkv", "t.b2.un.mkv").percent(), 45);
+        assert_eq!(retention("t.a1.mkv", "t.a1.mkv").percent(), 100);
+        assert_eq!(retention(".mkv", ".mkv").percent(), 100);
+    }
+
+    #[test]
+    fn test_objective() {
+        let _ = env_logger::try_init();
+
+        let rules = Rules { n_filename_bytes: 15, ..Default::default() };
+        assert_eq!(new_filename_impl("t.a1.b2.unique.mkv", None::<&Path>, &rules, |_| false).unwrap(), "t.a1.b2.uni.mkv");
+        let bytes_kept_rules = Rules { objective: Objective::BytesKept, ..rules.clone() };
+        assert_eq!(new_filename_impl("t.a1.b2.unique.mkv", None::<&Path>, &bytes_kept_rules, |_| false).unwrap(), "t.b2.unique.mkv");
+        let priority_rules = Rules { objective: Objective::Priority, tag_priorities: HashMap::from([("a1".to_string(), 3)]), ..rules.clone() };
+        assert_eq!(new_filename_impl("t.a1.b2.unique.mkv", None::<&Path>, &priority_rules, |_| false).unwrap(), "t.a1.b2.uni.mkv");
+        let priority_rules = Rules { tag_priorities: HashMap::from([("unique".to_string(), 3)]), ..priority_rules };
+        assert_eq!(new_filename_impl("t.a1.b2.unique.mkv", None::<&Path>, &priority_rules, |_| false).unwrap(), "t.b2.unique.mkv");
+
+        // the tag every name has goes first
+        let mut frequencies = TagFrequencies::default();
+        for name in ["t.ab.rare1.mkv", "u.ab.mkv", "v.ab.cd.mkv"] {
+            frequencies.add_name(split_name(name, &rules).1.into_iter().collect());
+        }
+        let rules = Rules { n_filename_bytes: 11, ..Default::default() };
+        assert_eq!(new_filename_impl("t.ab.rare1.mkv", None::<&Path>, &rules, |_| false).unwrap(), "t.ab.ra.mkv");
+        let rarity_rules = Rules { objective: Objective::Rarity, tag_frequencies: Rc::new(frequencies), ..rules };
+        assert_eq!(new_filename_impl("t.ab.rare1.mkv", None::<&Path>, &rarity_rules, |_| false).unwrap(), "t.rare1.mkv");
+
+        // leaving out one tag isn't enough room for the long one
+        let rules = Rules { n_filename_bytes: 14, objective: Objective::BytesKept, ..Default::default() };
+        assert_eq!(new_filename_impl("t.a1.b2.c3.unique12.mkv", None::<&Path>, &rules, |_| false).unwrap(), "t.a1.b2.c3.mkv");
+        let exact_rules = Rules { packing: PackingMode::Exact, ..rules };
+        assert_eq!(new_filename_impl("t.a1.b2.c3.unique12.mkv", None::<&Path>, &exact_rules, |_| false).unwrap(), "t.unique12.mkv");
+        let exact_rules = Rules { objective: Objective::ShortestFirst, ..exact_rules };
+        assert_eq!(new_filename_impl("t.a1.b2.c3.unique12.mkv", None::<&Path>, &exact_rules, |_| false).unwrap(), "t.a1.b2.c3.mkv");
+        assert_eq!(new_filename_impl("t.a1.b2.c3.unique12.mkv", None::<&Path>, &exact_rules, |path| !path.to_string_lossy().contains(".1.")).unwrap(), "t.a1.b2.1.mkv");
+    }
+
+    #[test]
+    fn test_counter_after_listed() {
+        let _ = env_logger::try_init();
+
+        let names = ["x.txt", "x.1.txt", "x.7.txt", "y.9.txt", "x.20240501.txt"].map(OsString::from);
+        let rules = Rules::default();
+        assert_eq!(max_counter(&names, "x.txt", &rules), Some(1));
+        let names = ["x.txt", "x.1.txt", "x.3.txt", "y.9.txt", "x.20240501.txt"].map(OsString::from);
+        assert_eq!(max_counter(&names, "x.txt", &rules), Some(3));
+        // counters of two widths, and a listing of names not all of the file
+        let many_names = (1..=12).map(|n| OsString::from(format!("x.{}.txt", n))).chain(["x.012.txt", "x.1.txt.bak"].map(OsString::from)).collect::<Vec<_>>();
+        assert_eq!(max_counter(&many_names, "x.txt", &rules), Some(12));
+        assert_eq!(max_counter(&many_names[9..], "x.txt", &rules), None);
+        // the counter of a shortened name goes before the extension of the cut name
+        let short_rules = Rules { n_filename_bytes: 10, ..Rules::default() };
+        let short_names = ["abcdef.txt", "abcd.1.txt", "abc.10.txt", "abcd.2.txt"].map(OsString::from);
+        assert_eq!(max_counter(&short_names, "abcdefgh.txt", &short_rules), Some(2));
+        let is_listed = |p: &Path| names.iter().any(|name| Some(name.as_os_str()) == p.file_name());
+        assert_eq!(new_filename_listed("a/x.txt", Some("b"), &rules, Some(&names), is_listed).unwrap(), "x.4.txt");
+        assert_eq!(new_filename_listed("a/x.txt", Some("b"), &rules, None, is_listed).unwrap(), "x.2.txt");
+        assert_eq!(new_filename_listed("a/z.txt", Some("b"), &rules, Some(&names), is_listed).unwrap(), "z.txt");
+    }
+
+    #[test]
+    fn test_max_components() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(n_components("a.b.c.txt"), 3);
+        assert_eq!(n_components("a.b.c.12.txt"), 3);
+        assert_eq!(n_components(".a.b.txt"), 2);
+        assert_eq!(n_components("a.b.sync-conflict-20240501-123456-ABCDEFG.txt"), 2);
+
+        let rules = Rules { max_components: Some(3), ..Default::default() };
+        assert!(!rules.fits("a.bbb.c.dd.txt"));
+        assert_eq!(shorten_filename("a.bbb.c.dd.txt", &rules).unwrap(), "a.c.dd.txt");
+        assert_eq!(shorten_filename("a.c.dd.txt", &rules).unwrap(), "a.c.dd.txt");
+        assert_eq!(shorten_filename_among("a.bbb.c.dd.txt", &rules, |name| name == "a.c.dd.txt").unwrap(), "a.c.dd.1.txt");
+        assert!(rules.fits("a.c.dd.1.txt"));
+    }
+
+    #[test]
+    fn test_sync_conflict_suffix() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(split_sync_conflict_suffix("a.b.sync-conflict-20240501-123456-ABCDEFG"), ("a.b", Some(".sync-conflict-20240501-123456-ABCDEFG")));
+        assert_eq!(split_sync_conflict_suffix("a (Taro's Conflicted Copy 2024-05-01)"), ("a", Some(" (Taro's Conflicted Copy 2024-05-01)")));
+        assert_eq!(split_sync_conflict_suffix("a (1)"), ("a (1)", None));
+        assert_eq!(split_sync_conflict_suffix(".sync-conflict-20240501-123456-ABCDEFG"), (".sync-conflict-20240501-123456-ABCDEFG", None));
+
+        let slug = format!("{}.b", "あ".repeat(100));
+        let suffix = " (conflicted copy 2024-05-01 123456)";
+        let filename = format!("{}{}.txt", slug, suffix);
+        let mut rules = Rules::default();
+        assert_eq!(new_candidate_filename(&filename, &rules, 0).unwrap(), format!("{}.txt", "あ".repeat(83)));
+        rules.sync_conflict_suffix = SyncConflictSuffix::Keep;
+        assert_eq!(new_candidate_filename(&filename, &rules, 1).unwrap(), format!("{}{}.1.txt", "あ".repeat(71), suffix));
+        rules.sync_conflict_suffix = SyncConflictSuffix::Drop;
+        let filename = format!("{}.b{}.txt", "あ".repeat(70), suffix);
+        assert_eq!(new_candidate_filename(&filename, &rules, 0).unwrap(), format!("{}.b.txt", "あ".repeat(70)));
+    }
+
+    #[cfg(feature = "shift-jis")]
+    #[test]
+    fn test_output_encoding_rules() {
+        let _ = env_logger::try_init();
+        use std::os::unix::ffi::OsStringExt;
+
+        let shift_jis = OutputEncoding { target: TargetEncoding::ShiftJis, unmappable: Unmappable::Translit };
+        let rules = Rules { encoding: Some(shift_jis), ..Rules::default() };
+
+        // 2 bytes each in Shift_JIS instead of 3
+        let filename = format!("{}.txt", "あ".repeat(120));
+        assert_eq!(shorten_filename(&filename, &rules).unwrap(), format!("{}.txt", "あ".repeat(120)));
+        let filename = format!("{}.txt", "あ".repeat(130));
+        assert_eq!(shorten_filename(&filename, &rules).unwrap(), format!("{}.txt", "あ".repeat(125)));
+
+        // the unmappable characters are transliterated when written
+        assert_eq!(rules.encode("café.txt"), "cafe.txt");
+        assert_eq!(rules.encode("あ.txt"), OsString::from_vec(b"\x82\xa0.txt".to_vec()));
+    }
+
+    #[test]
+    fn test_profile_rules() {
+        let _ = env_logger::try_init();
+
+        let rules = Rules { n_filename_bytes: Profile::Iso9660Level1.n_filename_units(), profile: Some(Profile::Iso9660Level1), ..Rules::default() };
+        assert_eq!(shorten_filename("README.TXT", &rules).unwrap(), "README.TXT");
+        assert_eq!(shorten_filename("my photo.jpeg", &rules).unwrap(), "MY_PHOTO.JPE");
+        assert_eq!(shorten_filename_among("holiday-2024.txt", &rules, |n| n == "HOLIDAY_.TXT").unwrap(), "HOLIDA_1.TXT");
+        assert_eq!(shorten_filename_among("holiday-2024", &rules, |n| n == "HOLIDAY_").unwrap(), "HOLIDA_1");
+
+        let rules = Rules { n_filename_bytes: Profile::Joliet.n_filename_units(), profile: Some(Profile::Joliet), ..Rules::default() };
+        let filename = format!("{}.txt", "あ".repeat(70));
+        assert_eq!(shorten_filename(&filename, &rules).unwrap(), format!("{}.txt", "あ".repeat(60)));
+        assert_eq!(shorten_filename("a:b.txt", &rules).unwrap(), "a_b.txt");
     }
 }
 
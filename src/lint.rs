@@ -0,0 +1,154 @@
+use std::{path::Path, collections::BTreeMap};
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use unicode_normalization::UnicodeNormalization;
+use anyhow::Result;
+
+use crate::{load_config, Rules, shorten_filename};
+
+// characters which need quoting in shells
+const SHELL_METACHARACTERS: &[char] = &['`', '$', '&', '*', '(', ')', '|', '\\', ';', '\'', '"', '<', '>', '?', '[', ']', '{', '}', '!'];
+
+// filename policies of the `lint` subcommand, independent of the length limit. all of them are off by default
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+pub(crate) struct LintRules {
+    no_spaces: bool,
+    no_uppercase: bool,
+    ascii_only: bool,
+    no_shell_metacharacters: bool,
+    // the number of components under the linted directory, `a/b.txt` is 2
+    max_depth: Option<usize>,
+    // see `Config::unknown_keys`
+    #[serde(flatten, skip_serializing)]
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    unknown_keys: BTreeMap<String, serde::de::IgnoredAny>,
+}
+
+impl LintRules {
+    pub(crate) fn unknown_keys(&self) -> impl Iterator<Item = &String> {
+        self.unknown_keys.keys()
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum LintViolation {
+    #[error("Contains spaces")]
+    Space,
+    #[error("Contains uppercase letters")]
+    Uppercase,
+    #[error("Contains non-ASCII characters")]
+    NonAscii,
+    #[error("Contains shell metacharacters: {0}")]
+    ShellMetacharacter(String),
+    #[error("Too deep: {0} (max_depth is {1})")]
+    TooDeep(usize, usize),
+}
+
+impl LintViolation {
+    // everything but the depth is fixed by renaming
+    pub fn is_fixable(&self) -> bool {
+        !matches!(self, Self::TooDeep(..))
+    }
+}
+
+// checks names against `lint` of the config
+#[derive(Debug)]
+pub struct Linter {
+    rules: LintRules,
+    shortening_rules: Rules,
+}
+
+impl Linter {
+    pub fn load() -> Self {
+        let config = load_config();
+        Self { rules: config.lint.clone(), shortening_rules: Rules::load() }
+    }
+
+    // `depth` is the number of components of the path under the linted directory
+    pub fn lint(&self, filename: &str, depth: usize) -> Vec<LintViolation> {
+        let mut violations = Vec::new();
+        if self.rules.no_spaces && filename.chars().any(char::is_whitespace) {
+            violations.push(LintViolation::Space);
+        }
+        if self.rules.no_uppercase && filename.chars().any(char::is_uppercase) {
+            violations.push(LintViolation::Uppercase);
+        }
+        if self.rules.ascii_only && !filename.is_ascii() {
+            violations.push(LintViolation::NonAscii);
+        }
+        if self.rules.no_shell_metacharacters {
+            let mut found = filename.chars().filter(|c| SHELL_METACHARACTERS.contains(c)).collect::<Vec<_>>();
+            found.sort();
+            found.dedup();
+            if !found.is_empty() {
+                violations.push(LintViolation::ShellMetacharacter(found.into_iter().collect()));
+            }
+        }
+        if let Some(max_depth) = self.rules.max_depth {
+            if max_depth < depth {
+                violations.push(LintViolation::TooDeep(depth, max_depth));
+            }
+        }
+        violations
+    }
+
+    // the name with the fixable violations fixed. non-ASCII characters are folded (NFKC) first, so that full-width
+    // letters become ASCII instead of `_`. the result is shortened as usual when the replacements make it too long
+    pub fn fix(&self, filename: &str) -> Result<String> {
+        let mut fixed = if self.rules.ascii_only { filename.nfkc().collect() } else { self.shortening_rules.fold(filename) };
+        if self.rules.no_uppercase {
+            fixed = fixed.to_lowercase();
+        }
+        fixed = fixed.chars().map(|c| {
+            if (self.rules.no_spaces && c.is_whitespace())
+                || (self.rules.ascii_only && !c.is_ascii())
+                || (self.rules.no_shell_metacharacters && SHELL_METACHARACTERS.contains(&c)) {
+                '_'
+            } else {
+                c
+            }
+        }).collect();
+        shorten_filename(&fixed, &self.shortening_rules)
+    }
+}
+
+// the number of components of the path under the root
+pub fn lint_depth(root: impl AsRef<Path>, path: impl AsRef<Path>) -> usize {
+    path.as_ref().strip_prefix(root.as_ref()).map(|p| p.components().count()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_linter() {
+        let _ = env_logger::try_init();
+
+        let linter = Linter {
+            rules: LintRules { no_spaces: true, no_uppercase: true, ascii_only: true, no_shell_metacharacters: true, max_depth: Some(2), ..Default::default() },
+            shortening_rules: Rules::default(),
+        };
+        assert_eq!(linter.lint("a_b.txt", 2), vec![]);
+        assert_eq!(linter.lint("A b(1)&.txt", 3), vec![
+            LintViolation::Space,
+            LintViolation::Uppercase,
+            LintViolation::ShellMetacharacter("&()".to_string()),
+            LintViolation::TooDeep(3, 2),
+        ]);
+        assert_eq!(linter.lint("あ.txt", 1), vec![LintViolation::NonAscii]);
+
+        assert_eq!(linter.fix("A b(1)&.txt").unwrap(), "a_b_1__.txt");
+        assert_eq!(linter.fix("Ａｂ あ.txt").unwrap(), "ab__.txt");
+
+        let linter = Linter { rules: LintRules::default(), shortening_rules: Rules::default() };
+        assert_eq!(linter.lint("A b(1)&あ.txt", 10), vec![]);
+        assert_eq!(linter.fix("A b(1)&あ.txt").unwrap(), "A b(1)&あ.txt");
+
+        assert_eq!(lint_depth("/x", "/x/a/b.txt"), 2);
+    }
+}
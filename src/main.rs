@@ -1,60 +1,2249 @@
-use std::{path::PathBuf, fs, io};
+use std::{path::{Path, PathBuf}, fs, io::{self, Write, BufRead, IsTerminal}, process::{self, Stdio}, collections::HashMap, time::{Duration, Instant}, ffi::{OsStr, OsString}, os::unix::{ffi::OsStrExt, fs::{PermissionsExt, MetadataExt}}};
 use clap::Parser;
 use anyhow::Result;
 
-use rename_for_linux_limit::new_filename;
+use rename_for_linux_limit::{tr, is_nfs_temp_file, is_protected_path, exceeds_limit, walk, walk_with, WalkOptions, WalkOrder, Planner, PlanEntry, PlanKind, move_file, copy_file, is_git_tracked, git_move_file, staged_paths, pre_commit_hook_path, write_pre_commit_hook, ReferenceUpdater, ListingBackend, check_free_space, setgid_group_mismatch, ChecksumAlgorithm, CopyOptions, NameMapper, write_script, ScriptShell, ScriptOptions, ResolvedConfig, RuleOverrides, Linter, lint_depth, TargetEncoding, Unmappable, OutputEncoding, Profile, Journal, new_run_id, plan_undo, verify_journal, replay_entry, UndoConflict, FileManager, explain_rename, retention, test_names, Objective, PackingMode};
+#[cfg(feature = "archive")]
+use rename_for_linux_limit::shorten_archive;
+#[cfg(any(feature = "archive", feature = "s3"))]
+use rename_for_linux_limit::write_manifest;
+#[cfg(feature = "schema")]
+use rename_for_linux_limit::config_schema;
+#[cfg(feature = "json")]
+use rename_for_linux_limit::{ConfigSnapshot, CONFIG_VERSION, config_path, migrate_config_file};
+#[cfg(feature = "self-update")]
+use rename_for_linux_limit::{latest_release, is_newer_version, replace_binary};
+#[cfg(feature = "tui")]
+use rename_for_linux_limit::review_plan;
+#[cfg(feature = "history-db")]
+use rename_for_linux_limit::{History, HistoryQuery, parse_date, format_time};
+#[cfg(feature = "s3")]
+use rename_for_linux_limit::{S3Bucket, plan_s3_renames, plan_undo_with, N_MIN_SEGMENT_BYTES};
+#[cfg(feature = "sftp")]
+use rename_for_linux_limit::SftpBackend;
+#[cfg(feature = "mount")]
+use rename_for_linux_limit::mount;
+#[cfg(feature = "shim")]
+use rename_for_linux_limit::{SHIM_LIBRARY_FILENAME, SHIM_NAMES_ENV};
+#[cfg(feature = "watch")]
+use rename_for_linux_limit::{WatchState, WatchConfig, Inotify, Debouncer, dir_files, configured_watches};
+
+// the mode of the created destination directories
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DirMode {
+    Mode(u32),
+    // the mode and the owner of the directory the files come from
+    Source,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DedupePolicy {
+    // leave the source as it is
+    Skip,
+    // remove the source, the identical destination stays
+    Delete,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorChoice {
+    // only when writing to a terminal and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum PrintPath {
+    // the new filename only
+    Name,
+    // the new path, relative when the given path is
+    Relative,
+    Absolute,
+}
+
+// what happens (or would happen) to each file, for --json and --exit-status
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Unchanged,
+    Renamed,
+    // renamed, but the first choice of name was taken so it got a counter
+    Conflict,
+    // the destination already has the same content, see --dedupe
+    Duplicate,
+    // a destination directory to create
+    Mkdir,
+    Failed,
+}
+
+// of the json records (--json), as the versions of the journal and the manifest: raised when a field changes its
+// meaning or goes away, not for new fields, which readers should ignore
+#[cfg(feature = "json")]
+const RECORD_VERSION: u32 = 1;
+
+#[cfg(feature = "json")]
+#[derive(serde::Serialize, Debug)]
+struct Record {
+    version: u32,
+    src: String,
+    // the final path, the same as src when unchanged
+    dst: String,
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// with --exit-status, 0 is for renamed and 1 for errors as usual
+const EXIT_UNCHANGED: i32 = 3;
+const EXIT_CONFLICT: i32 = 4;
+// with --check, some files would be renamed
+const EXIT_CHECK_FAILED: i32 = 5;
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    #[cfg(feature = "archive")]
+    #[command(about = "Write a copy of a tar or zip archive whose member names fit the limit, for archives which fail to extract.")]
+    Archive {
+        src: PathBuf,
+        dst: PathBuf,
+        #[clap(long, help = "Where to write the renamed members (tab separated). If not set, <DST>.manifest.tsv")]
+        manifest: Option<PathBuf>,
+    },
+    #[command(about = "Rename the files of a past run back, as recorded in the journal.")]
+    Undo {
+        #[clap(long, help = "The run ID to undo. If not set, the last run.")]
+        run: Option<String>,
+        #[clap(short = 'f', long, default_value = "false", help = "Undo what can be undone even if some renames conflict with later changes.")]
+        force: bool,
+        #[clap(long, help = "If not set, $XDG_STATE_HOME/rename-for-linux-limit/journal.tsv")]
+        journal: Option<PathBuf>,
+    },
+    #[cfg(feature = "s3")]
+    #[command(about = "Shorten the keys of an S3 bucket whose `/` separated segments are too long, through the aws command line. Only prints the renames unless --apply.")]
+    S3 {
+        #[clap(help = "s3://bucket/prefix")]
+        uri: String,
+        #[clap(long, default_value = "255", value_parser = parse_segment_bytes, help = "The limit of each `/` separated segment of the keys in bytes.")]
+        segment_bytes: usize,
+        #[clap(long, default_value = "false", help = "Rename the keys (copy and delete) and record them in the journal, for undo.")]
+        apply: bool,
+        #[clap(long, help = "If not set, $XDG_STATE_HOME/rename-for-linux-limit/journal.tsv")]
+        journal: Option<PathBuf>,
+        #[clap(long, help = "Where to write the planned renames of the keys (tab separated), with or without --apply.")]
+        manifest: Option<PathBuf>,
+    },
+    #[command(about = "Report journal entries which can't be trusted for undo: renamed files which are gone, renamed again or changed (see --journal-checksum).")]
+    Verify {
+        #[clap(long, help = "Only the entries of this run.")]
+        run: Option<String>,
+        #[clap(long, help = "If not set, $XDG_STATE_HOME/rename-for-linux-limit/journal.tsv")]
+        journal: Option<PathBuf>,
+    },
+    #[command(about = "Plan a recorded rename again with the config recorded for its run and print --explain for it, to reproduce and report how a name was chosen.")]
+    Replay {
+        #[clap(long, help = "The number of the rename in the journal, from 1.")]
+        entry: usize,
+        #[clap(long, help = "If not set, $XDG_STATE_HOME/rename-for-linux-limit/journal.tsv")]
+        journal: Option<PathBuf>,
+    },
+    #[cfg(feature = "history-db")]
+    #[command(about = "Find past renames by their original or new path, or by date. Prints the time (UTC), run ID, host, original and new path of each, tab separated.")]
+    History {
+        #[clap(long, help = "Only the renames whose original path contains this.")]
+        src: Option<String>,
+        #[clap(long, help = "Only the renames whose new path contains this.")]
+        dst: Option<String>,
+        #[clap(long, value_parser = parse_history_date, help = "Only the renames on or after this day (YYYY-MM-DD, UTC).")]
+        since: Option<u64>,
+        #[clap(long, value_parser = parse_history_date, help = "Only the renames on or before this day (YYYY-MM-DD, UTC).")]
+        until: Option<u64>,
+        #[clap(long, help = "If not set, $XDG_STATE_HOME/rename-for-linux-limit/journal.tsv. The history is next to it, journal.sqlite.")]
+        journal: Option<PathBuf>,
+    },
+    #[command(about = "Report names breaking the policies in `lint` of the config (spaces, uppercase, non-ASCII, shell metacharacters, depth), whatever their length.")]
+    Lint {
+        path: PathBuf,
+        #[clap(long, default_value = "false", help = "Rename the files to fix what can be fixed. Names which would collide with existing files are left as they are.")]
+        fix: bool,
+    },
+    #[command(about = "Add a \"Shorten long filenames\" action to the context menu of the file managers, running with --gui-confirm on the selected files.")]
+    InstallIntegration {
+        #[clap(long, value_enum, help = "If not set, all of them.")]
+        file_manager: Option<FileManager>,
+    },
+    #[command(about = "Manage the git hooks checking the names of the files to commit.")]
+    Hook {
+        #[command(subcommand)]
+        command: HookCommand,
+    },
+    #[command(about = "Create empty files of pathological names as long as the limit allows (combining marks, emoji, mixed delimiters, invalid UTF-8 and so on) in a directory, for testing a storage stack together with this tool.")]
+    GenTestNames {
+        dir: PathBuf,
+        #[clap(long, default_value = "255", help = "The length of the names in bytes.")]
+        n_bytes: usize,
+    },
+    #[cfg(feature = "self-update")]
+    #[command(about = "Replace this binary with the one of the latest release on GitHub, for standalone installs outside package managers.")]
+    SelfUpdate {
+        #[clap(long, default_value = "false", help = "Only print whether a newer release is available.")]
+        check: bool,
+    },
+    #[cfg(feature = "tui")]
+    #[command(about = "Review the plan of each batch in the terminal before renaming: search it, turn renames off, edit the new names, then apply what is left (y) or rename nothing (q). Takes the arguments of a run, e.g. `tui -r DIR`.")]
+    Tui {
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true, help = "The options and the path of the run.")]
+        args: Vec<OsString>,
+    },
+    #[cfg(feature = "watch")]
+    #[command(about = "Keep shortening the files written or moved into a directory as they arrive, until killed. The files looked at are remembered across restarts, so that a restarted watch doesn't rename them again.")]
+    Watch {
+        #[clap(help = "If not set, the directories in `watches` of the config, each with its own dst_dir, profile and rules.")]
+        dir: Option<PathBuf>,
+        #[clap(short = 'd', long, requires = "dir", help = "Move the files there. If not set, they are renamed in the directory.")]
+        dst_dir: Option<PathBuf>,
+        #[clap(long, value_enum, requires = "dir", help = "As --profile of a run.")]
+        profile: Option<Profile>,
+        #[clap(long, requires = "dir", help = "As --rules of a run.")]
+        rules: Option<String>,
+        #[clap(long, default_value = "false", help = "Forget the files looked at by the earlier watches, so that all the files there are looked at again.")]
+        reset_state: bool,
+        #[clap(long, help = "If not set, $XDG_STATE_HOME/rename-for-linux-limit/watch-state.tsv")]
+        state: Option<PathBuf>,
+        #[clap(long, default_value = "500", help = "Wait until no file has arrived for this long, so that the files of an extracted archive or a copied folder are planned as one batch.")]
+        debounce_ms: u64,
+    },
+    #[cfg(feature = "mount")]
+    #[command(about = "Mount a directory at MOUNTPOINT through FUSE, until unmounted (fusermount -u). Files, directories and symlinks created there with names over the limit are stored under shortened names, and are still found and listed by the long ones, so that applications writing such names don't fail with ENAMETOOLONG.")]
+    Mount {
+        src: PathBuf,
+        mountpoint: PathBuf,
+        #[clap(long, help = "If not set, $XDG_STATE_HOME/rename-for-linux-limit/journal.tsv. The shortenings are recorded there, and found again by the next mount.")]
+        journal: Option<PathBuf>,
+        #[clap(long, default_value = "false", help = "Don't record the shortenings. The long names are forgotten when unmounted.")]
+        no_journal: bool,
+    },
+    #[cfg(feature = "shim")]
+    #[command(about = "Run a command with the preload library of shim/ (LD_PRELOAD), so that the files it creates or renames to names over the limit get shortened names instead of failing with ENAMETOOLONG. For applications which can't be changed; the names are not recorded in the journal.")]
+    Shim {
+        #[clap(long, help = "The preload library. If not set, librename_for_linux_limit_shim.so next to this binary.")]
+        library: Option<PathBuf>,
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true, required = true, help = "The command and its arguments.")]
+        command: Vec<OsString>,
+    },
+    #[command(about = "Inspect the config.")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum HookCommand {
+    #[command(about = "Install a pre-commit hook in the git repository of the current directory, failing commits which add or rename files whose names would be renamed (--check --staged).")]
+    Install {
+        #[clap(short = 'f', long, default_value = "false", help = "Replace the pre-commit hook if there is one.")]
+        force: bool,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ConfigCommand {
+    #[command(about = "Report conversions which would break names (and are ignored) or make tags longer.")]
+    Validate,
+    #[cfg(feature = "schema")]
+    #[command(about = "Print the JSON Schema of the config file, for validation and completion in editors.")]
+    Schema,
+    #[cfg(feature = "json")]
+    #[command(about = "Upgrade the config file to the format of this version in place, keeping the old one as <file>.v<version>.bak. Runs do it on load too.")]
+    Migrate,
+}
 
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
     #[clap(short = 's', long, default_value = "false")]
     only_show_new_filename: bool,
-    #[clap(short = 'd', long, help = "If not set --dst-dir, the same as the given path's parent dir.")]
+    #[clap(long, value_enum, default_value = "name", help = "What -s prints for each file.")]
+    print_path: PrintPath,
+    #[clap(short = 'd', long, conflicts_with = "recursive", help = "If not set --dst-dir, the same as the given path's parent dir.")]
     dst_dir: Option<PathBuf>,
-    path: PathBuf,
+    #[clap(long, value_parser = parse_dir_mode, help = "The mode of the created destination directories in octal (e.g. 0755), or `source` to copy the mode and the owner of the directory the files come from. Otherwise the umask decides.")]
+    dir_mode: Option<DirMode>,
+    #[clap(short = 'c', long, default_value = "false", help = "Reserve the new filename with an empty placeholder file (O_EXCL) before moving the data in. Useful when several hosts rename into the same shared directory.")]
+    claim: bool,
+    #[clap(long, default_value = "false", help = "Also rename NFS silly-renamed files (.nfsXXXX), which are skipped by default.")]
+    include_nfs_temp: bool,
+    #[clap(short = 'r', long, default_value = "false", help = "Rename all files under the given directory. Directories in `excluded_dirs` of the config (snapshots by default) are skipped.")]
+    recursive: bool,
+    #[clap(long, default_value = "false", requires = "recursive", help = "Rename directories too. A directory is always renamed after everything inside it.")]
+    include_dirs: bool,
+    #[clap(long, value_enum, default_value = "depth-first", requires = "recursive")]
+    order: WalkOrder,
+    #[clap(short = 'x', long, default_value = "false", requires = "recursive", help = "Don't enter directories on other filesystems (backups, network shares) than the given directory, like `du -x`.")]
+    one_file_system: bool,
+    #[clap(long, help = "Abort before renaming anything if more than this number of files would be renamed. With - as the path (or -r --low-memory), the files of all the batches count, and the run stops before the batch which would go over, the earlier batches staying renamed.")]
+    max_changes: Option<usize>,
+    #[clap(short = 'f', long, default_value = "false", help = "Rename even paths matching `protected_paths` of the config.")]
+    force: bool,
+    #[clap(long, value_enum, help = "When the destination already exists with the same content, skip or delete the source instead of renaming it with a counter.")]
+    dedupe: Option<DedupePolicy>,
+    #[clap(long, value_enum, help = "When a file has to be copied to another filesystem, verify the copy by checksum before removing the source.")]
+    verify: Option<ChecksumAlgorithm>,
+    #[clap(long, default_value = "false", help = "When a file has to be copied to another filesystem, keep the holes of sparse files.")]
+    sparse: bool,
+    #[clap(long, default_value = "false", requires = "recursive", help = "Leave empty files (often placeholders) as they are.")]
+    skip_empty: bool,
+    #[clap(long, default_value = "false", help = "Give copies (--copy, or moves to other filesystems) the owner and the group of the source. Needs root.")]
+    preserve_owner: bool,
+    #[clap(long, default_value = "false", help = "Copy files to the new names instead of renaming them. Reflinks are used when the filesystem supports them.")]
+    copy: bool,
+    #[clap(long, default_value = "false", conflicts_with_all = ["copy", "claim"], help = "Rename the files tracked in a git work tree with git mv (in --emit-script too), keeping the index consistent. Untracked files are renamed as usual.")]
+    git: bool,
+    #[clap(long, default_value = "false", conflicts_with = "copy", help = "After renaming, rewrite the references to the renamed files in the playlists, cue sheets and notes next to them (reference_extensions of the config).")]
+    update_references: bool,
+    #[clap(long, value_enum, help = "Print a shell script doing the renames instead of renaming. The script never overwrites existing files.")]
+    emit_script: Option<ScriptShell>,
+    #[clap(long, default_value = "false", conflicts_with = "path", help = "Read names from stdin and print the shortened names to stdout line by line, without looking at the filesystem. The same name always maps to the same result.")]
+    map_name: bool,
+    #[cfg(feature = "reversible")]
+    #[clap(long, default_value = "false", conflicts_with_all = ["shrink_to", "output_encoding", "profile", "sidecars"], help = "Shorten so that the original name can be restored from the new one (the cut off part is compressed into it). Fails instead of adding a counter when the new name is taken.")]
+    reversible: bool,
+    #[cfg(feature = "reversible")]
+    #[clap(long, default_value = "false", conflicts_with_all = ["reversible", "shrink_to", "output_encoding", "profile", "sidecars"], help = "Like --reversible, but for names read by programs: the cut off part is compressed into lowercase base32, and when it doesn't fit whole, as much of it as fits is kept.")]
+    squeeze: bool,
+    #[clap(long, value_parser = parse_percent, help = "Shorten into names of at most this percentage (10% to 100%) of the limit, e.g. 80%, leaving headroom for suffixes appended by sync tools (Syncthing, Nextcloud).")]
+    shrink_to: Option<usize>,
+    #[clap(long, value_name = "NAME", help = "Shorten with the rules of this profile in `profiles` of the config instead of the top level ones. If not set, the first profile whose `path_patterns` match the file is used.")]
+    rules: Option<String>,
+    #[clap(long = "ignore-tag", value_name = "TAG", help = "Ignore this tag too in this run, as if it were in `ignored_tags` of the config. Can be repeated.")]
+    ignore_tags: Vec<String>,
+    #[clap(long = "convert", value_name = "FROM=TO", value_parser = parse_conversion, help = "Convert this tag too in this run, as if it were in `conversions` of the config (replacing the conversion of the same tag). Can be repeated.")]
+    conversions: Vec<(String, String)>,
+    #[clap(long, value_enum, help = "Write the new names in this encoding instead of UTF-8, counting the limit in its bytes, for shares mounted with a legacy iocharset.")]
+    output_encoding: Option<TargetEncoding>,
+    #[clap(long, value_enum, default_value = "replace", requires = "output_encoding", help = "What happens to the characters the --output-encoding doesn't have: dropped, replaced with _, or transliterated (é to e) when possible.")]
+    unmappable: Unmappable,
+    #[clap(long, value_enum, conflicts_with = "output_encoding", help = "Restrict the new names to the characters and the lengths of another filesystem or a cloud storage, e.g. a disc filesystem before mastering an image (ISO9660 8.3 or 31 characters, Joliet 64 UTF-16 characters), the shared storage of Android seen from Termux (127 UTF-16 characters), a Mac (APFS in NFC, HFS+ in NFD), a synced folder (OneDrive paths of 400 characters, no names the client refuses or ignores), or Google Drive, whose titles needn't be unique, so no counters are added when the destination is checked remotely (--existence-listing, --existence-sftp).")]
+    profile: Option<Profile>,
+    #[clap(long, default_value = "false", conflicts_with_all = ["output_encoding", "profile"], help = "Rename the sidecars (video.srt, video.en.srt, video.nfo, sidecar_extensions of the config) to the same new stem as the file they belong to (video.mkv).")]
+    sidecars: bool,
+    #[clap(long, requires = "dst_dir", conflicts_with = "claim", help = "Check the taken names against this listing of the destination (one path per line relative to --dst-dir, e.g. from rclone lsf -R) instead of the local filesystem, for planning the names of a remote destination with -s or --emit-script.")]
+    existence_listing: Option<PathBuf>,
+    #[cfg(feature = "sftp")]
+    #[clap(long, requires = "dst_dir", conflicts_with_all = ["claim", "existence_listing"], help = "Check the taken names on this host ([user@]host[:port]) over sftp instead of the local filesystem, --dst-dir being a path on the host. Authenticated by the ssh agent, the host has to be in ~/.ssh/known_hosts.")]
+    existence_sftp: Option<String>,
+    #[clap(long, default_value = "false", conflicts_with = "emit_script", help = "Print groups of files which would get the same name apart from the counter (their names differ only by the cut off tags, likely versions or duplicates) instead of renaming.")]
+    clusters: bool,
+    #[clap(long, help = "Print a progress line (files/s, ETA) to stderr every this number of seconds while renaming, for long batches with the output piped.")]
+    heartbeat_seconds: Option<u64>,
+    #[clap(long, default_value = "false", help = "Terminate the names printed by -s and --map-name with NUL instead of newline, for xargs -0.")]
+    print0: bool,
+    #[cfg(feature = "json")]
+    #[clap(long, default_value = "false", conflicts_with_all = ["print0", "emit_script", "clusters", "check", "dry_run", "list_over_limit", "gui_confirm"], help = "Print a JSON record (src, dst, status: unchanged, renamed, conflict, duplicate or failed) per line for every file, for -s too.")]
+    json: bool,
+    #[clap(long, default_value = "false", help = "Exit with 3 when nothing needs renaming and 4 when some names got a counter because they were taken, for -s too.")]
+    exit_status: bool,
+    #[clap(long, default_value = "false", help = "Read NUL separated names for --map-name and paths from stdin (- as the path or --list-over-limit), as from find -print0.")]
+    null: bool,
+    #[clap(long, default_value = "10000", value_parser = parse_batch_size, help = "With - as the path (or -r --low-memory), read the paths from stdin (or walk) this number at a time, renaming each batch before reading more, so that any number of paths can be piped in. -s and --clusters see one batch at a time.")]
+    batch_size: usize,
+    #[clap(long, value_enum, default_value = "shortest-first", help = "What to make the most of when tags have to be dropped: the number of tags (shortest-first), the bytes of the tags (bytes-kept), the sum of tag_priorities of the config (priority), the tags the other names in the destination directory don't have (distinctiveness), or the tags few names of the batch have (rarity).")]
+    objective: Objective,
+    #[clap(long, value_enum, default_value = "greedy", help = "How the tags to keep are chosen for --objective: greedy packs the shortest first and cuts the first one which doesn't fit, exact keeps the whole tags of the best total by dynamic programming, for names with many tags.")]
+    packing: PackingMode,
+    #[clap(long, default_value = "false", conflicts_with_all = ["objective", "prefer_distinct"], help = "Same as --objective rarity: before renaming, count how many of the names of the batch (--batch-size) each tag is in, then drop the tags most names have first and keep the rare ones, which tell the files apart.")]
+    smart_drop: bool,
+    #[clap(long, default_value = "false", conflicts_with_all = ["low_memory", "objective"], help = "Same as --objective distinctiveness: when tags have to be dropped, prefer keeping the ones the other names in the destination directory don't have, which tell the file apart from them.")]
+    prefer_distinct: bool,
+    #[clap(long, default_value = "false", help = "When the shortened name is taken and its title was cut, try keeping the end of the title after a ~ (Long ti~12.txt) before giving it a counter, since titles which begin the same usually differ at the end.")]
+    keep_title_end: bool,
+    #[clap(long, default_value = "false", help = "Print to stderr what was dropped from each renamed name, and how many of the title and the tags of the new name no other name in its directory has (distinctiveness).")]
+    explain: bool,
+    #[clap(long, default_value = "false", help = "In the previews (-s, --dry-run, --check, --emit-script, --clusters), print to stderr how much of the title and the tags of each renamed name would be retained, and how that is distributed over all of them at the end, to decide on conversions before renaming.")]
+    loss_stats: bool,
+    #[clap(long, default_value = "false", conflicts_with = "sidecars", help = "For small NAS boxes: rename the files found by -r a batch (--batch-size) at a time while walking, and don't keep the listings of the destination directories.")]
+    low_memory: bool,
+    #[clap(long, default_value = "false", conflicts_with_all = ["only_show_new_filename", "emit_script", "clusters", "map_name", "gui_confirm", "exit_status"], help = "Rename nothing, print the renames which would be done, and exit with 5 when there are any, for CI and pre-commit hooks. With --list-over-limit, exit with 5 when any path is printed.")]
+    check: bool,
+    #[clap(long, default_value = "false", conflicts_with_all = ["only_show_new_filename", "emit_script", "clusters", "map_name", "gui_confirm", "check", "list_over_limit"], help = "Rename nothing and create nothing, print the old and the new path of every file, the unchanged ones too.")]
+    dry_run: bool,
+    #[clap(long, default_value = "false", conflicts_with_all = ["only_show_new_filename", "emit_script", "clusters", "map_name", "gui_confirm"], help = "Only print the paths whose names are longer than the limit (of --profile if given), under the given directory with -r or read from stdin without a path, as a filter for other tools.")]
+    list_over_limit: bool,
+    #[clap(long, value_enum, default_value = "auto", help = "Color the preview (renamed names) and the log.")]
+    color: ColorChoice,
+    #[clap(long, default_value = "false", help = "Show the preview of -s through $PAGER (less by default) when it's longer than the terminal.")]
+    pager: bool,
+    #[clap(long, help = "Where to record the renames for undo. If not set, $XDG_STATE_HOME/rename-for-linux-limit/journal.tsv")]
+    journal: Option<PathBuf>,
+    #[clap(long, default_value = "false", conflicts_with = "journal", help = "Don't record the renames.")]
+    no_journal: bool,
+    #[clap(long, value_enum, conflicts_with = "no_journal", help = "Record a checksum of every renamed file in the journal, so that `verify` can tell whether it has changed since.")]
+    journal_checksum: Option<ChecksumAlgorithm>,
+    #[clap(long, default_value = "false", conflicts_with_all = ["only_show_new_filename", "emit_script", "clusters", "map_name"], help = "Ask for confirmation of the renames in a dialog (zenity or kdialog) and report failures in one, for running from a file manager.")]
+    gui_confirm: bool,
+    // set by the tui subcommand
+    #[cfg(feature = "tui")]
+    #[clap(skip)]
+    tui: bool,
+    #[clap(long, default_value = "false", conflicts_with_all = ["recursive", "null", "map_name", "list_over_limit"], help = "Shorten the files added or renamed in the git index (git diff --cached), of the work tree of the given directory or the current directory. Mostly with --check, as in the hook of `hook install`.")]
+    staged: bool,
+    #[clap(required_unless_present_any = ["map_name", "list_over_limit", "staged"])]
+    path: Option<PathBuf>,
 }
 
 #[derive(thiserror::Error, Debug)]
 enum Error {
     #[error("Rename error: {0} -> {1}: {2}")]
-    RenameError(PathBuf, PathBuf, io::Error),
+    RenameError(PathBuf, PathBuf, anyhow::Error),
     #[error("Filename not found in path: {0}")]
     FilenameNotFound(PathBuf),
+    #[error("Claim error: {0}: {1}")]
+    Claim(PathBuf, io::Error),
+    #[error("Existence check error: {0}: {1}")]
+    ExistenceCheck(PathBuf, io::Error),
+    #[error("Failed to rename {0} files")]
+    Batch(usize),
+    #[error("Protected path: {0} (use --force to rename it anyway)")]
+    ProtectedPath(PathBuf),
+    #[error("Too many changes: {0} files would be renamed, but --max-changes is {1}")]
+    TooManyChanges(usize, usize),
+    #[error("Invalid config: {0} errors")]
+    InvalidConfig(usize),
+    #[error("Where to install unknown, HOME isn't set")]
+    IntegrationPathUnknown,
+    #[error("Rule profile not found in the config: {0}")]
+    RuleProfileNotFound(String),
+    #[error("Journal path unknown, HOME isn't set (use --journal)")]
+    JournalPathUnknown,
+    #[error("Run not found in the journal: {0}")]
+    RunNotFound(String),
+    #[error("Entry not found in the journal: {0} ({1} entries)")]
+    EntryNotFound(usize, usize),
+    #[error("Can't undo {0} renames (use --force to undo the rest anyway)")]
+    UndoConflicts(usize),
+    #[cfg(feature = "s3")]
+    #[error("Not an S3 URI: {0} (s3://bucket/prefix)")]
+    InvalidS3Uri(String),
+    #[error("Neither zenity nor kdialog is found")]
+    DialogNotFound,
+    #[error("Found {0} problems in the journal")]
+    JournalIssues(usize),
+    #[error("Found {0} lint violations")]
+    LintViolations(usize),
+    #[error("Pre-commit hook already exists: {0} (use --force to replace it)")]
+    HookExists(PathBuf),
+    #[cfg(feature = "self-update")]
+    #[error("No binary for this machine in release {0}")]
+    ReleaseBinaryNotFound(String),
+    #[cfg(feature = "self-update")]
+    #[error("No checksum of the binary in release {0}")]
+    ReleaseChecksumNotFound(String),
+    #[error("{0} test names were rejected by the filesystem")]
+    TestNamesRejected(usize),
+    #[cfg(feature = "tui")]
+    #[error("--{0} can't be used with tui")]
+    TuiConflict(&'static str),
+    #[cfg(feature = "tui")]
+    #[error("tui takes the arguments of a run, not a subcommand")]
+    TuiSubcommand,
+    #[cfg(feature = "shim")]
+    #[error("Preload library not found: {0} (build shim/, or use --library)")]
+    ShimLibraryNotFound(PathBuf),
+    #[cfg(feature = "watch")]
+    #[error("Watch state path unknown, HOME isn't set (use --state)")]
+    WatchStatePathUnknown,
+    #[cfg(feature = "watch")]
+    #[error("No directory to watch, give one or set `watches` in the config")]
+    NothingToWatch,
+    #[cfg(feature = "watch")]
+    #[error("Directory watched twice: {0}")]
+    WatchedTwice(PathBuf),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
     #[error("Unknown error: {0}")]
     UnknownError(#[from] anyhow::Error),
 }
 
-fn main() -> Result<()> {
-    env_logger::init();
+impl Error {
+    // the message in the language of the locale, see `tr!`
+    fn localized(&self) -> String {
+        match self {
+            Self::RenameError(src, dst, e) => tr!("error-rename", "Rename error: {src} -> {dst}: {error}", src = src.display(), dst = dst.display(), error = localized_error(e)),
+            Self::FilenameNotFound(path) => tr!("error-filename-not-found", "Filename not found in path: {path}", path = path.display()),
+            Self::Claim(path, e) => tr!("error-claim", "Claim error: {path}: {error}", path = path.display(), error = e),
+            Self::ExistenceCheck(path, e) => tr!("error-existence-check", "Existence check error: {path}: {error}", path = path.display(), error = e),
+            Self::Batch(n) => tr!("error-batch", "Failed to rename {n} files", n = n),
+            Self::ProtectedPath(path) => tr!("error-protected-path", "Protected path: {path} (use --force to rename it anyway)", path = path.display()),
+            Self::TooManyChanges(n, max) => tr!("error-too-many-changes", "Too many changes: {n} files would be renamed, but --max-changes is {max}", n = n, max = max),
+            Self::InvalidConfig(n) => tr!("error-invalid-config", "Invalid config: {n} errors", n = n),
+            Self::IntegrationPathUnknown => tr!("error-integration-path-unknown", "Where to install unknown, HOME isn't set"),
+            Self::RuleProfileNotFound(name) => tr!("error-rule-profile-not-found", "Rule profile not found in the config: {name}", name = name),
+            Self::JournalPathUnknown => tr!("error-journal-path-unknown", "Journal path unknown, HOME isn't set (use --journal)"),
+            Self::RunNotFound(run_id) => tr!("error-run-not-found", "Run not found in the journal: {run_id}", run_id = run_id),
+            Self::EntryNotFound(entry, n) => tr!("error-entry-not-found", "Entry not found in the journal: {entry} ({n} entries)", entry = entry, n = n),
+            Self::UndoConflicts(n) => tr!("error-undo-conflicts", "Can't undo {n} renames (use --force to undo the rest anyway)", n = n),
+            #[cfg(feature = "s3")]
+            Self::InvalidS3Uri(uri) => tr!("error-invalid-s3-uri", "Not an S3 URI: {uri} (s3://bucket/prefix)", uri = uri),
+            Self::DialogNotFound => tr!("error-dialog-not-found", "Neither zenity nor kdialog is found"),
+            Self::JournalIssues(n) => tr!("error-journal-issues", "Found {n} problems in the journal", n = n),
+            Self::LintViolations(n) => tr!("error-lint-violations", "Found {n} lint violations", n = n),
+            Self::HookExists(path) => tr!("error-hook-exists", "Pre-commit hook already exists: {path} (use --force to replace it)", path = path.display()),
+            #[cfg(feature = "self-update")]
+            Self::ReleaseBinaryNotFound(version) => tr!("error-release-binary-not-found", "No binary for this machine in release {version}", version = version),
+            #[cfg(feature = "self-update")]
+            Self::ReleaseChecksumNotFound(version) => tr!("error-release-checksum-not-found", "No checksum of the binary in release {version}", version = version),
+            Self::TestNamesRejected(n) => tr!("error-test-names-rejected", "{n} test names were rejected by the filesystem", n = n),
+            #[cfg(feature = "tui")]
+            Self::TuiConflict(option) => tr!("error-tui-conflict", "--{option} can't be used with tui", option = option),
+            #[cfg(feature = "tui")]
+            Self::TuiSubcommand => tr!("error-tui-subcommand", "tui takes the arguments of a run, not a subcommand"),
+            #[cfg(feature = "shim")]
+            Self::ShimLibraryNotFound(path) => tr!("error-shim-library-not-found", "Preload library not found: {path} (build shim/, or use --library)", path = path.display()),
+            #[cfg(feature = "watch")]
+            Self::WatchStatePathUnknown => tr!("error-watch-state-path-unknown", "Watch state path unknown, HOME isn't set (use --state)"),
+            #[cfg(feature = "watch")]
+            Self::NothingToWatch => tr!("error-nothing-to-watch", "No directory to watch, give one or set `watches` in the config"),
+            #[cfg(feature = "watch")]
+            Self::WatchedTwice(path) => tr!("error-watched-twice", "Directory watched twice: {path}", path = path.display()),
+            Self::IoError(e) => tr!("error-io", "IO error: {error}", error = e),
+            Self::UnknownError(e) => tr!("error-unknown", "Unknown error: {error}", error = localized_error(e)),
+        }
+    }
+}
 
+// the errors of this binary and of the library in the language of the locale, the others as they are
+fn localized_error(e: &anyhow::Error) -> String {
+    if let Some(e) = e.downcast_ref::<Error>() {
+        return e.localized();
+    }
+    if let Some(e) = e.downcast_ref::<rename_for_linux_limit::Error>() {
+        return e.localized();
+    }
+    e.to_string()
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", tr!("error", "Error: {error}", error = localized_error(&e)));
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
     let args = Args::parse();
-    let path = args.path;
-    let dst_dir = args.dst_dir;
-    let only_show_new_filename = args.only_show_new_filename;
+    #[cfg(feature = "tui")]
+    let args = match &args.command {
+        Some(Command::Tui { args }) => tui_args(args)?,
+        _ => args,
+    };
+
+    env_logger::Builder::from_default_env().write_style(match args.color {
+        ColorChoice::Auto => env_logger::WriteStyle::Auto,
+        ColorChoice::Always => env_logger::WriteStyle::Always,
+        ColorChoice::Never => env_logger::WriteStyle::Never,
+    }).init();
+    let color = match args.color {
+        ColorChoice::Auto => io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+
+    // a typo in the config would silently do nothing otherwise. `config validate` reports them itself
+    if !matches!(args.command, Some(Command::Config { .. })) {
+        for issue in ResolvedConfig::load().validate().into_iter().filter(|issue| issue.is_unknown_key()) {
+            log::warn!("{}", tr!("config-issue", "Config: {issue}", issue = issue.localized()));
+        }
+    }
+
+    if let Some(command) = &args.command {
+        return run_command(command, color);
+    }
+    if let Some(name) = &args.rules {
+        if !ResolvedConfig::load().has_profile(name) {
+            return Err(Error::RuleProfileNotFound(name.clone()).into());
+        }
+    }
+    if args.map_name {
+        return map_names(&rule_overrides(&args), args.null, args.print0);
+    }
+    if args.list_over_limit {
+        return list_over_limit(&args);
+    }
+    if args.gui_confirm {
+        // nobody reads stderr when run from a file manager
+        let result = shorten(&args, color);
+        if let Err(e) = &result {
+            let _ = run_dialog(DialogKind::Error, &e.to_string());
+        }
+        return result;
+    }
+    shorten(&args, color)
+}
+
+// the path reading the paths from stdin
+const STDIN_PATH: &str = "-";
+
+// what is carried over the batches of a run
+struct Run {
+    // opened at the first rename, all the batches are recorded as one run
+    journal: Option<(Journal, String)>,
+    n_errors: usize,
+    // the files which would be renamed, for --check
+    n_would_rename: usize,
+    has_conflict: bool,
+    all_unchanged: bool,
+    // with many paths, a failed one doesn't stop the others
+    keep_going: bool,
+    retention: RetentionStats,
+    // the files renamed by the earlier batches, for --max-changes
+    n_changes: usize,
+    // (old path, new path, status) of the renamed files, collected only by a watch for its summaries
+    renamed: Option<Vec<(PathBuf, PathBuf, Status)>>,
+}
+
+impl Run {
+    fn new(keep_going: bool) -> Self {
+        Self {
+            journal: None,
+            n_errors: 0,
+            n_would_rename: 0,
+            has_conflict: false,
+            all_unchanged: true,
+            keep_going,
+            retention: RetentionStats::default(),
+            n_changes: 0,
+            renamed: None,
+        }
+    }
+
+    // counts the changes of a batch unless they would make the run go over the limit
+    fn add_changes(&mut self, n_changes: usize, max_changes: usize) -> Result<(), Error> {
+        let n_total_changes = self.n_changes + n_changes;
+        if max_changes < n_total_changes {
+            return Err(Error::TooManyChanges(n_total_changes, max_changes));
+        }
+        self.n_changes = n_total_changes;
+        Ok(())
+    }
+
+    fn add_statuses(&mut self, statuses: &[Status]) {
+        self.has_conflict |= statuses.contains(&Status::Conflict);
+        self.all_unchanged &= statuses.iter().all(|status| *status == Status::Unchanged);
+    }
+}
+
+// the retained percentages of the renamed names of all the batches, for --loss-stats
+#[derive(Debug, Default)]
+struct RetentionStats {
+    // by tens of percent, 100% alone in the last
+    histogram: [usize; 11],
+    n_names: usize,
+    sum_percent: usize,
+    min_percent: Option<usize>,
+}
+
+const N_HISTOGRAM_BAR_CHARS: usize = 40;
+
+impl RetentionStats {
+    fn add(&mut self, percent: usize) {
+        self.histogram[percent.min(100) / 10] += 1;
+        self.n_names += 1;
+        self.sum_percent += percent;
+        self.min_percent = Some(self.min_percent.map_or(percent, |min| min.min(percent)));
+    }
+
+    fn print(&self) {
+        let Some(min_percent) = self.min_percent else {
+            eprintln!("{}", tr!("retention-nothing", "Information retained: nothing would be renamed"));
+            return;
+        };
+        eprintln!("{}", tr!("retention-summary", "Information retained in {n} renamed names: mean {mean}%, min {min}%", n = self.n_names, mean = self.sum_percent / self.n_names, min = min_percent));
+        let n_max = self.histogram.iter().copied().max().unwrap_or(0).max(1);
+        for (i, n) in self.histogram.iter().enumerate() {
+            let label = if i == 10 { "100%".to_string() } else { format!("{}-{}%", i * 10, i * 10 + 9) };
+            eprintln!("{:>7} {:>6} {}", label, n, "#".repeat(n * N_HISTOGRAM_BAR_CHARS / n_max));
+        }
+    }
+}
+
+fn shorten(args: &Args, color: bool) -> Result<()> {
+    // the directory of the work tree with --staged
+    let path = args.path.clone().or_else(|| args.staged.then(|| PathBuf::from("."))).expect("required unless a subcommand is given");
+    let from_stdin = !args.staged && path == Path::new(STDIN_PATH);
+    let mut run = Run::new(args.recursive || from_stdin || args.staged);
+    if args.loss_stats && !is_preview(args) {
+        log::warn!("{}", tr!("loss-stats-previews-only", "--loss-stats is only reported in the previews (-s, --dry-run, --check, --emit-script, --clusters)"));
+    }
+
+    let mut batches = Batches {
+        planner: new_planner(args)?,
+        paths: Vec::new(),
+        applied: !is_preview(args),
+    };
+    if from_stdin {
+        let separator = if args.null { b'\0' } else { b'\n' };
+        for line in io::stdin().lock().split(separator) {
+            let line = line?;
+            let line = if args.null { &line[..] } else { line.strip_suffix(b"\r").unwrap_or(&line) };
+            if line.is_empty() {
+                continue;
+            }
+            // the writer waits while the batch is renamed
+            batches.push(PathBuf::from(OsStr::from_bytes(line)), args, color, &mut run)?;
+        }
+        batches.flush(args, color, &mut run)?;
+    } else if args.staged {
+        shorten_batch(&mut batches.planner, staged_paths(&path)?, args, color, &mut run)?;
+    } else if args.recursive && args.low_memory {
+        walk_with(&path, &walk_options(args), |path| batches.push(path, args, color, &mut run))?;
+        batches.flush(args, color, &mut run)?;
+    } else {
+        let paths = if args.recursive {
+            walk(&path, &walk_options(args))?
+        } else {
+            vec![path]
+        };
+        shorten_batch(&mut batches.planner, paths, args, color, &mut run)?;
+    }
+
+    if args.loss_stats && is_preview(args) {
+        run.retention.print();
+    }
+    // the exits below don't drop the journal
+    if let Some((journal, _)) = &run.journal {
+        journal.commit();
+    }
+    if 0 < run.n_errors {
+        return Err(Error::Batch(run.n_errors).into());
+    }
+    if args.check && 0 < run.n_would_rename {
+        log::error!("{}", tr!("would-rename", "{n} files would be renamed", n = run.n_would_rename));
+        process::exit(EXIT_CHECK_FAILED);
+    }
+    exit_with_status(args, &run)
+}
+
+// the paths renamed a batch at a time, so that no more than a batch is held
+struct Batches {
+    planner: Planner,
+    paths: Vec<PathBuf>,
+    // nothing is on the filesystem yet when only previewing, so the destinations reserved by the planner are kept for
+    // the later batches. otherwise a new planner for each batch, the destinations of the former ones are there already
+    applied: bool,
+}
+
+impl Batches {
+    fn push(&mut self, path: PathBuf, args: &Args, color: bool, run: &mut Run) -> Result<()> {
+        self.paths.push(path);
+        if args.batch_size <= self.paths.len() {
+            self.flush(args, color, run)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, args: &Args, color: bool, run: &mut Run) -> Result<()> {
+        if self.paths.is_empty() {
+            return Ok(());
+        }
+        shorten_batch(&mut self.planner, std::mem::take(&mut self.paths), args, color, run)?;
+        if self.applied {
+            self.planner = new_planner(args)?;
+        }
+        Ok(())
+    }
+}
+
+// nothing is renamed, what would be is only printed
+fn is_preview(args: &Args) -> bool {
+    args.only_show_new_filename || args.emit_script.is_some() || args.clusters || args.check || args.dry_run
+}
+
+fn new_planner(args: &Args) -> Result<Planner> {
+    // the previews never move anything, so no need to leave a placeholder
+    let claim = args.claim && !is_preview(args);
+    let mut planner = Planner::new().claim(claim).dedupe(args.dedupe.is_some()).sidecars(args.sidecars)
+        .low_memory(args.low_memory).objective(objective(args)).packing(args.packing).keep_title_end(args.keep_title_end).overrides(rule_overrides(args));
+    #[cfg(feature = "reversible")]
+    {
+        planner = planner.reversible(args.reversible).squeeze(args.squeeze);
+    }
+    if let Some(percent) = args.shrink_to {
+        planner = planner.shrink_to(percent);
+    }
+    if let Some(target) = args.output_encoding {
+        planner = planner.output_encoding(OutputEncoding { target, unmappable: args.unmappable });
+    }
+    if let Some(profile) = args.profile {
+        planner = planner.profile(profile);
+    }
+    if let (Some(listing), Some(dst_dir)) = (&args.existence_listing, &args.dst_dir) {
+        planner = planner.backend(ListingBackend::read(io::BufReader::new(fs::File::open(listing)?), dst_dir)?);
+    }
+    #[cfg(feature = "sftp")]
+    if let Some(host) = &args.existence_sftp {
+        planner = planner.backend(SftpBackend::connect(host)?);
+    }
+    Ok(planner)
+}
+
+fn objective(args: &Args) -> Objective {
+    if args.prefer_distinct {
+        Objective::Distinctiveness
+    } else if args.smart_drop {
+        Objective::Rarity
+    } else {
+        args.objective
+    }
+}
+
+fn shorten_batch(planner: &mut Planner, paths: Vec<PathBuf>, args: &Args, color: bool, run: &mut Run) -> Result<()> {
+    let paths = planner.without_sidecars(paths);
+    if objective(args) == Objective::Rarity {
+        planner.learn_tag_frequencies(&paths);
+    }
+
+    // keep going, a single broken file shouldn't stop the whole batch
+    let mut plan = Vec::new();
+    for path in paths {
+        match plan_rename(planner, &path, args) {
+            Ok(entry) => {
+                plan.extend(planner.take_dir_entries());
+                plan.push(entry);
+                plan.extend(planner.take_sidecar_entries());
+            },
+            Err(e) if run.keep_going => {
+                log::error!("{}", e.localized());
+                run.n_errors += 1;
+            },
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let mut statuses = Vec::new();
+    for entry in &plan {
+        statuses.push(status(entry)?);
+    }
+
+    if args.explain {
+        explain_plan(&plan, &statuses, color);
+    }
+
+    if args.loss_stats && is_preview(args) {
+        for (entry, status) in plan.iter().zip(&statuses) {
+            let (Some(src_name), Some(dst_name)) = (entry.src.file_name(), entry.dst.file_name()) else {
+                continue;
+            };
+            if entry.kind != PlanKind::Rename || !matches!(status, Status::Renamed | Status::Conflict) {
+                continue;
+            }
+            let retention = retention(&src_name.to_string_lossy(), &dst_name.to_string_lossy());
+            eprintln!("{}", tr!("retained", "{src} -> {dst}: {percent}% retained", src = entry.src.display(), dst = entry.dst.display(), percent = retention.percent()));
+            run.retention.add(retention.percent());
+        }
+    }
+
+    if args.clusters {
+        let mut lines = Vec::new();
+        for cluster in planner.duplicate_clusters(&plan) {
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            for entry in cluster {
+                lines.push(format!("{} -> {}", entry.src.display(), entry.dst.display()));
+            }
+        }
+        print_preview(&lines, args.pager)?;
+        return Ok(());
+    }
+
+    if args.check {
+        for entry in would_rename(&plan, &statuses) {
+            println!("{} -> {}", entry.src.display(), entry.dst.display());
+            run.n_would_rename += 1;
+        }
+        return Ok(());
+    }
+
+    if args.dry_run {
+        // the directories to create are in the new paths already
+        for entry in plan.iter().filter(|entry| entry.kind != PlanKind::CreateDir) {
+            println!("{} -> {}", entry.src.display(), entry.dst.display());
+        }
+        run.add_statuses(&statuses);
+        return Ok(());
+    }
+
+    #[cfg(feature = "json")]
+    if args.only_show_new_filename && args.json {
+        for (entry, status) in plan.iter().zip(&statuses) {
+            print_record(entry, *status, None)?;
+        }
+        run.add_statuses(&statuses);
+        return Ok(());
+    }
+
+    if args.only_show_new_filename {
+        let mut lines = Vec::new();
+        for entry in &plan {
+            if entry.kind == PlanKind::CreateDir {
+                continue;
+            }
+            let Some(printed) = printed_path(&entry.dst, args.print_path)? else {
+                continue;
+            };
+            // escape sequences would end up in the names read by the other end
+            if color && !args.print0 && entry.src != entry.dst {
+                lines.push(format!("\x1b[32m{}\x1b[0m", printed));
+            } else {
+                lines.push(printed);
+            }
+        }
+        if args.print0 {
+            let mut stdout = io::stdout().lock();
+            write_terminated(&mut stdout, &lines, "\0")?;
+            stdout.flush()?;
+        } else {
+            print_preview(&lines, args.pager)?;
+        }
+        run.add_statuses(&statuses);
+        return Ok(());
+    }
+
+    if let Some(shell) = args.emit_script {
+        let mut changes = Vec::new();
+        for entry in plan {
+            if entry.kind == PlanKind::CreateDir || entry.duplicate || !jdt::eq_files(&entry.src, &entry.dst)? {
+                changes.push(entry);
+            }
+        }
+        write_script(io::stdout().lock(), &changes, &ScriptOptions {
+            shell,
+            copy: args.copy,
+            delete_duplicates: args.dedupe == Some(DedupePolicy::Delete),
+            git: args.git,
+        })?;
+        return Ok(());
+    }
+
+    #[cfg(feature = "tui")]
+    if args.tui {
+        match review_plan(plan, planner)? {
+            Some(reviewed) => plan = reviewed,
+            None => return Ok(()),
+        }
+        statuses = plan.iter().map(status).collect::<Result<_>>()?;
+    }
+
+    if let Some(max_changes) = args.max_changes {
+        let mut n_changes = 0;
+        for entry in plan.iter().filter(|entry| entry.kind == PlanKind::Rename) {
+            let changed = if entry.duplicate {
+                args.dedupe == Some(DedupePolicy::Delete)
+            } else {
+                !jdt::eq_files(&entry.src, &entry.dst)?
+            };
+            if changed {
+                n_changes += 1;
+            }
+        }
+        if let Err(e) = run.add_changes(n_changes, max_changes) {
+            planner.release_claims();
+            return Err(e.into());
+        }
+    }
+
+    if let Err(e) = check_free_space(&plan, args.copy) {
+        planner.release_claims();
+        return Err(e);
+    }
+
+    if args.gui_confirm {
+        let mut lines = Vec::new();
+        for (entry, status) in plan.iter().zip(&statuses) {
+            if matches!(status, Status::Renamed | Status::Conflict) {
+                lines.push(format!("{} -> {}", entry.src.display(), entry.dst.display()));
+            }
+        }
+        if lines.is_empty() {
+            run_dialog(DialogKind::Info, &tr!("dialog-nothing-to-rename", "Nothing to rename."))?;
+            return Ok(());
+        }
+        let n_renames = lines.len();
+        // a dialog taller than the screen hides its buttons
+        if N_MAX_DIALOG_LINES < n_renames {
+            lines.truncate(N_MAX_DIALOG_LINES);
+            lines.push(tr!("and-more", "... and {n} more", n = n_renames - N_MAX_DIALOG_LINES));
+        }
+        if !run_dialog(DialogKind::Question, &format!("{}\n\n{}", tr!("dialog-rename", "Rename {n} files?", n = n_renames), lines.join("\n")))? {
+            planner.release_claims();
+            return Ok(());
+        }
+    }
+
+    let copy_options = CopyOptions {
+        verify: args.verify,
+        sparse: args.sparse,
+        preserve_owner: args.preserve_owner,
+        claimed: args.claim,
+    };
+    if run.journal.is_none() && !args.no_journal && !args.copy {
+        let run_id = new_run_id();
+        log::info!("{}", tr!("run-id", "Run ID: {run_id}", run_id = run_id));
+        let journal = open_journal(args.journal.as_ref())?;
+        #[cfg(feature = "json")]
+        let journal = journal.with_config(&ConfigSnapshot::load());
+        run.journal = Some((journal, run_id));
+    }
+    let mut heartbeat = args.heartbeat_seconds.map(|seconds| Heartbeat::new(Duration::from_secs(seconds), plan.len()));
+    // old and new filenames of the renames within a directory, by the directory
+    let mut renames_by_dir = HashMap::<PathBuf, Vec<(String, String)>>::new();
+    for (i, (entry, status)) in plan.into_iter().zip(statuses.iter_mut()).enumerate() {
+        #[cfg(feature = "json")]
+        let record_entry = if args.json { Some(entry.clone()) } else { None };
+        let renamed_in_dir = match (entry.src.parent(), entry.src.file_name(), entry.dst.file_name()) {
+            (Some(dir), Some(old), Some(new)) if args.update_references && entry.kind == PlanKind::Rename && !entry.duplicate && entry.dst.parent() == Some(dir) && old != new => {
+                Some((dir.to_path_buf(), old.to_string_lossy().to_string(), new.to_string_lossy().to_string()))
+            },
+            _ => None,
+        };
+        let renamed = match &run.renamed {
+            Some(_) if entry.kind == PlanKind::Rename && matches!(status, Status::Renamed | Status::Conflict) => Some((entry.src.clone(), entry.dst.clone(), *status)),
+            _ => None,
+        };
+        let result = rename(entry, args, &copy_options, run.journal.as_ref());
+        if let (Ok(()), Some((dir, old, new))) = (&result, renamed_in_dir) {
+            renames_by_dir.entry(dir).or_default().push((old, new));
+        }
+        if let (Ok(()), Some(renamed), Some(collected)) = (&result, renamed, &mut run.renamed) {
+            collected.push(renamed);
+        }
+        #[cfg(feature = "json")]
+        if let Some(entry) = record_entry {
+            match &result {
+                Ok(()) => print_record(&entry, *status, None)?,
+                Err(e) => print_record(&entry, Status::Failed, Some(e.to_string()))?,
+            }
+        }
+        if let Err(e) = result {
+            if !run.keep_going {
+                return Err(e.into());
+            }
+            log::error!("{}", e.localized());
+            run.n_errors += 1;
+            *status = Status::Failed;
+        }
+        if let Some(heartbeat) = &mut heartbeat {
+            heartbeat.beat(i + 1);
+        }
+    }
+
+    if !renames_by_dir.is_empty() {
+        let updater = ReferenceUpdater::load();
+        for (dir, renames) in &renames_by_dir {
+            // the files are renamed already, a failure here is only reported
+            let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir.as_path() };
+            match updater.update(dir, renames) {
+                Ok(updated) => for path in updated {
+                    log::info!("{}", tr!("references-updated", "Updated references: {path}", path = path.display()));
+                },
+                Err(e) => {
+                    log::error!("{}", tr!("references-update-failed", "Failed to update references in {dir}: {error}", dir = dir.display(), error = localized_error(&e)));
+                    run.n_errors += 1;
+                },
+            }
+        }
+    }
+
+    run.add_statuses(&statuses);
+    Ok(())
+}
+
+// the files --check fails for, the directories to create come with them
+fn would_rename<'a>(plan: &'a [PlanEntry], statuses: &[Status]) -> Vec<&'a PlanEntry> {
+    plan.iter().zip(statuses)
+        .filter(|(entry, status)| entry.kind != PlanKind::CreateDir && **status != Status::Unchanged)
+        .map(|(entry, _)| entry)
+        .collect()
+}
+
+// the new path as --print-path asks, none for a path without a filename with `name`
+fn printed_path(dst: &Path, print_path: PrintPath) -> io::Result<Option<String>> {
+    Ok(Some(match print_path {
+        PrintPath::Name => match dst.file_name() {
+            Some(filename) => filename.to_string_lossy().to_string(),
+            None => return Ok(None),
+        },
+        PrintPath::Relative => dst.display().to_string(),
+        PrintPath::Absolute => std::path::absolute(dst)?.display().to_string(),
+    }))
+}
+
+fn write_terminated(out: &mut impl Write, lines: &[String], terminator: &str) -> io::Result<()> {
+    for line in lines {
+        write!(out, "{}{}", line, terminator)?;
+    }
+    Ok(())
+}
+
+// to stderr, so that it goes with -s and --json too. the siblings are read before anything is renamed.
+// with colors, the dropped parts of the old name are red
+fn explain_plan(plan: &[PlanEntry], statuses: &[Status], color: bool) {
+    let mut listings = HashMap::<PathBuf, Vec<OsString>>::new();
+    for (entry, status) in plan.iter().zip(statuses) {
+        if !matches!(status, Status::Renamed | Status::Conflict) {
+            continue;
+        }
+        let (Some(src_name), Some(dst_name)) = (entry.src.file_name(), entry.dst.file_name()) else {
+            continue;
+        };
+        let dir = entry.dst.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let siblings = listings.entry(dir.to_path_buf()).or_insert_with(|| {
+            // a remote destination can't be listed here, then nothing is there to compare with
+            fs::read_dir(dir).map(|entries| entries.filter_map(|entry| entry.ok().map(|entry| entry.file_name())).collect()).unwrap_or_default()
+        });
+        let src_name = src_name.to_string_lossy();
+        let explanation = explain_rename(&src_name, &dst_name.to_string_lossy(), siblings);
+        let dropped = if explanation.dropped.is_empty() { "nothing".to_string() } else { explanation.dropped.join(", ") };
+        let src = if color {
+            entry.src.with_file_name(highlight_ranges(&src_name, &explanation.dropped_ranges)).display().to_string()
+        } else {
+            entry.src.display().to_string()
+        };
+        eprintln!("{}", tr!("explain", "{src} -> {dst}: dropped {dropped}, distinctiveness {distinctiveness}", src = src, dst = entry.dst.display(), dropped = dropped, distinctiveness = explanation.distinctiveness));
+    }
+}
+
+// the ranges are in order and don't overlap
+fn highlight_ranges(s: &str, ranges: &[std::ops::Range<usize>]) -> String {
+    let mut highlighted = String::new();
+    let mut end = 0;
+    for range in ranges {
+        highlighted.push_str(&s[end..range.start]);
+        highlighted.push_str(&format!("\x1b[31m{}\x1b[0m", &s[range.clone()]));
+        end = range.end;
+    }
+    highlighted.push_str(&s[end..]);
+    highlighted
+}
+
+const N_MAX_DIALOG_LINES: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DialogKind {
+    Question,
+    Info,
+    Error,
+}
+
+// zenity for gnome and the like, kdialog for kde. whether the question was answered with yes
+fn run_dialog(kind: DialogKind, text: &str) -> Result<bool> {
+    let title = clap::crate_name!();
+    let zenity_kind = match kind {
+        DialogKind::Question => "--question",
+        DialogKind::Info => "--info",
+        DialogKind::Error => "--error",
+    };
+    let status = match process::Command::new("zenity").arg(zenity_kind).arg("--no-markup").arg("--title").arg(title).arg("--text").arg(text).status() {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let kdialog_kind = match kind {
+                DialogKind::Question => "--yesno",
+                DialogKind::Info => "--msgbox",
+                DialogKind::Error => "--error",
+            };
+            match process::Command::new("kdialog").arg("--title").arg(title).arg(kdialog_kind).arg(text).status() {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(Error::DialogNotFound.into()),
+                status => status?,
+            }
+        },
+        status => status?,
+    };
+    // both exit with 1 for no or a closed dialog
+    Ok(status.success())
+}
+
+// a progress line at most every interval, shown whatever the log level is
+struct Heartbeat {
+    interval: Duration,
+    start: Instant,
+    last: Instant,
+    n_files: usize,
+}
+
+impl Heartbeat {
+    fn new(interval: Duration, n_files: usize) -> Self {
+        let now = Instant::now();
+        Self { interval, start: now, last: now, n_files }
+    }
+
+    fn beat(&mut self, n_done: usize) {
+        if let Some(progress) = self.beat_at(Instant::now(), n_done) {
+            eprintln!("{}", progress);
+        }
+    }
+
+    // the progress line, once an interval
+    fn beat_at(&mut self, now: Instant, n_done: usize) -> Option<String> {
+        if now.duration_since(self.last) < self.interval {
+            return None;
+        }
+        self.last = now;
+
+        let files_per_sec = n_done as f64 / now.duration_since(self.start).as_secs_f64();
+        let eta = if 0.0 < files_per_sec {
+            format!("{:.0}s", (self.n_files - n_done) as f64 / files_per_sec)
+        } else {
+            tr!("eta-unknown", "unknown")
+        };
+        Some(tr!("heartbeat", "{n_done}/{n_files} files, {files_per_sec} files/s, ETA {eta}", n_done = n_done, n_files = self.n_files, files_per_sec = format!("{:.1}", files_per_sec), eta = eta))
+    }
+}
+
+fn status(entry: &PlanEntry) -> Result<Status> {
+    Ok(if entry.kind == PlanKind::CreateDir {
+        Status::Mkdir
+    } else if entry.duplicate {
+        Status::Duplicate
+    } else if jdt::eq_files(&entry.src, &entry.dst)? {
+        Status::Unchanged
+    } else if entry.conflict {
+        Status::Conflict
+    } else {
+        Status::Renamed
+    })
+}
+
+#[cfg(feature = "json")]
+fn print_record(entry: &PlanEntry, status: Status, error: Option<String>) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", serde_json::to_string(&record(entry, status, error))?)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+fn record(entry: &PlanEntry, status: Status, error: Option<String>) -> Record {
+    Record {
+        version: RECORD_VERSION,
+        src: entry.src.to_string_lossy().to_string(),
+        dst: if status == Status::Failed { entry.src.to_string_lossy().to_string() } else { entry.dst.to_string_lossy().to_string() },
+        status,
+        error,
+    }
+}
+
+fn exit_with_status(args: &Args, run: &Run) -> Result<()> {
+    if !args.exit_status {
+        return Ok(());
+    }
+    if run.has_conflict {
+        process::exit(EXIT_CONFLICT);
+    }
+    if run.all_unchanged {
+        process::exit(EXIT_UNCHANGED);
+    }
+    Ok(())
+}
+
+// pages the lines with $PAGER when asked and they don't fit in the terminal
+fn print_preview(lines: &[String], pager: bool) -> Result<()> {
+    if pager && io::stdout().is_terminal() && terminal_rows().is_some_and(|n_rows| n_rows < lines.len()) {
+        let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -R".to_string());
+        let mut child = process::Command::new("sh").arg("-c").arg(&pager).stdin(Stdio::piped()).spawn()?;
+        let mut stdin = child.stdin.take().expect("piped");
+        for line in lines {
+            match writeln!(stdin, "{}", line) {
+                Ok(()) => (),
+                // quit before reading everything
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        drop(stdin);
+        child.wait()?;
+        return Ok(());
+    }
+
+    let mut stdout = io::stdout().lock();
+    for line in lines {
+        writeln!(stdout, "{}", line)?;
+    }
+    Ok(())
+}
 
-    let new_filename = new_filename(&path, dst_dir.as_ref()).map_err(|e| match e.downcast::<rename_for_linux_limit::Error>() {
+fn terminal_rows() -> Option<usize> {
+    let mut size = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } < 0 || size.ws_row == 0 {
+        return None;
+    }
+    Some(size.ws_row as usize)
+}
+
+fn walk_options(args: &Args) -> WalkOptions {
+    WalkOptions {
+        include_nfs_temp: args.include_nfs_temp,
+        include_dirs: args.include_dirs,
+        order: args.order,
+        one_file_system: args.one_file_system,
+    }
+}
+
+// nothing is planned, only the lengths are compared, so it's fast enough for millions of paths
+fn list_over_limit(args: &Args) -> Result<()> {
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    let terminator: &[u8] = if args.print0 { b"\0" } else { b"\n" };
+    let mut n_over_limit = 0;
+    let mut print = |path: &Path| -> io::Result<()> {
+        if print_over_limit(&mut stdout, path, args.profile, terminator)? {
+            n_over_limit += 1;
+        }
+        Ok(())
+    };
+    match &args.path {
+        Some(path) if args.recursive => walk_with(path, &walk_options(args), |path| Ok(print(&path)?))?,
+        Some(path) => print(path)?,
+        None => {
+            let separator = if args.null { b'\0' } else { b'\n' };
+            for path in io::stdin().lock().split(separator) {
+                let path = path?;
+                let path = if args.null { &path[..] } else { path.strip_suffix(b"\r").unwrap_or(&path) };
+                if !path.is_empty() {
+                    print(Path::new(OsStr::from_bytes(path)))?;
+                }
+            }
+        },
+    }
+    stdout.flush()?;
+    if args.check && 0 < n_over_limit {
+        process::exit(EXIT_CHECK_FAILED);
+    }
+    Ok(())
+}
+
+// whether the path is over the limit, printed if so
+fn print_over_limit(out: &mut impl Write, path: &Path, profile: Option<Profile>, terminator: &[u8]) -> io::Result<bool> {
+    if !exceeds_limit(path, profile) {
+        return Ok(false);
+    }
+    out.write_all(path.as_os_str().as_bytes())?;
+    out.write_all(terminator)?;
+    Ok(true)
+}
+
+fn map_names(overrides: &RuleOverrides, null: bool, print0: bool) -> Result<()> {
+    let mapper = NameMapper::with_overrides(overrides);
+    let mut stdout = io::stdout().lock();
+    let separator = if null { b'\0' } else { b'\n' };
+    let terminator = if print0 { "\0" } else { "\n" };
+    for name in io::stdin().lock().split(separator) {
+        let name = name?;
+        let name = String::from_utf8_lossy(&name);
+        // lines() drops \r\n too
+        let name = if null { &name } else { name.strip_suffix('\r').unwrap_or(&name) };
+        write!(stdout, "{}{}", mapper.map(name)?, terminator)?;
+        // the reader may wait for each name
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+// the arguments of a run with the review turned on
+#[cfg(feature = "tui")]
+fn tui_args(tui_args: &[OsString]) -> Result<Args, Error> {
+    let mut args = Args::parse_from(std::iter::once(OsString::from(clap::crate_name!())).chain(tui_args.iter().cloned()));
+    if args.command.is_some() {
+        return Err(Error::TuiSubcommand);
+    }
+    // the outputs which replace the renames, and the renames without the review
+    let conflicts = [
+        ("only-show-new-filename", args.only_show_new_filename),
+        ("emit-script", args.emit_script.is_some()),
+        ("clusters", args.clusters),
+        #[cfg(feature = "json")]
+        ("json", args.json),
+        ("map-name", args.map_name),
+        ("gui-confirm", args.gui_confirm),
+        ("check", args.check),
+        ("dry-run", args.dry_run),
+        ("list-over-limit", args.list_over_limit),
+        ("claim", args.claim),
+    ];
+    if let Some((name, _)) = conflicts.into_iter().find(|(_, set)| *set) {
+        return Err(Error::TuiConflict(name));
+    }
+    args.tui = true;
+    Ok(args)
+}
+
+fn run_command(command: &Command, color: bool) -> Result<()> {
+    match command {
+        #[cfg(feature = "watch")]
+        Command::Watch { dir, dst_dir, profile, rules, reset_state, state, debounce_ms } => {
+            let watches = match dir {
+                Some(dir) => vec![WatchConfig { dir: dir.clone(), dst_dir: dst_dir.clone(), profile: *profile, rules: rules.clone() }],
+                None => configured_watches().into_iter().map(|(_, watch)| watch).collect(),
+            };
+            let config = ResolvedConfig::load();
+            let mut dirs = std::collections::HashSet::new();
+            for watch in &watches {
+                if let Some(name) = watch.rules.as_ref().filter(|name| !config.has_profile(name)) {
+                    return Err(Error::RuleProfileNotFound(name.clone()).into());
+                }
+                if !dirs.insert(&watch.dir) {
+                    return Err(Error::WatchedTwice(watch.dir.clone()).into());
+                }
+            }
+            if watches.is_empty() {
+                return Err(Error::NothingToWatch.into());
+            }
+            let debouncer = Debouncer::new(Duration::from_millis(*debounce_ms), MAX_BATCH_WAIT);
+            watch(&watches.iter().map(watch_args).collect::<Vec<_>>(), state.as_ref(), *reset_state, debouncer, color)?;
+        },
+        #[cfg(feature = "mount")]
+        Command::Mount { src, mountpoint, journal, no_journal } => {
+            let journal = if *no_journal { None } else { Some(open_journal(journal.as_ref())?) };
+            log::info!("{}", tr!("mounting", "Mounting {src} at {mountpoint}", src = src.display(), mountpoint = mountpoint.display()));
+            mount(src, mountpoint, journal)?;
+        },
+        #[cfg(feature = "shim")]
+        Command::Shim { library, command } => {
+            let library = match library {
+                Some(library) => library.clone(),
+                None => std::env::current_exe()?.with_file_name(SHIM_LIBRARY_FILENAME),
+            };
+            if !library.is_file() {
+                return Err(Error::ShimLibraryNotFound(library).into());
+            }
+            // the ones preloaded already stay
+            let mut preload = std::path::absolute(library)?.into_os_string();
+            if let Some(others) = std::env::var_os("LD_PRELOAD").filter(|others| !others.is_empty()) {
+                preload.push(" ");
+                preload.push(others);
+            }
+            let mut shimmed = process::Command::new(&command[0]);
+            shimmed.args(&command[1..]).env("LD_PRELOAD", preload);
+            // a shim run within another one shares its names
+            let names_path = match std::env::var_os(SHIM_NAMES_ENV).filter(|path| !path.is_empty()) {
+                Some(_) => None,
+                None => {
+                    let path = std::env::temp_dir().join(format!("{}-shim-{}.tsv", clap::crate_name!(), process::id()));
+                    shimmed.env(SHIM_NAMES_ENV, &path);
+                    Some(path)
+                },
+            };
+            let status = shimmed.status();
+            if let Some(path) = names_path {
+                let _ = fs::remove_file(path);
+            }
+            process::exit(status?.code().unwrap_or(1));
+        },
+        // replaced by the arguments of the run in main
+        #[cfg(feature = "tui")]
+        Command::Tui { .. } => unreachable!("tui is run as a run"),
+        #[cfg(feature = "archive")]
+        Command::Archive { src, dst, manifest } => {
+            let renamed_members = shorten_archive(src, dst)?;
+
+            let manifest = manifest.clone().unwrap_or_else(|| {
+                let mut manifest = dst.clone().into_os_string();
+                manifest.push(".manifest.tsv");
+                PathBuf::from(manifest)
+            });
+            write_manifest(io::BufWriter::new(fs::File::create(&manifest)?), &renamed_members)?;
+
+            log::info!("{}", tr!("archive-renamed", "Renamed {n} members: {dst} (manifest: {manifest})", n = renamed_members.len(), dst = dst.display(), manifest = manifest.display()));
+        },
+        Command::Undo { run, force, journal } => {
+            let journal = open_journal(journal.as_ref())?;
+            let run_id = match run {
+                Some(run_id) => run_id.clone(),
+                None => journal.last_run_id()?.ok_or_else(|| Error::RunNotFound("(last)".to_string()))?,
+            };
+            let entries = journal.entries()?;
+            #[cfg_attr(not(feature = "json"), allow(unused_variables))]
+            let Some(first_entry) = entries.iter().find(|entry| entry.run_id == run_id) else {
+                return Err(Error::RunNotFound(run_id).into());
+            };
+            // the names are put back all the same, but shortening them again wouldn't give the same names
+            #[cfg(feature = "json")]
+            if first_entry.config_hash.as_ref().is_some_and(|hash| *hash != ConfigSnapshot::load().hash()) {
+                log::warn!("{}", tr!("config-changed-since-run", "The config has changed since run {run_id}", run_id = run_id));
+            }
+
+            // a run of the s3 subcommand renamed keys of a bucket
+            #[cfg(feature = "s3")]
+            if let Some((bucket, _)) = S3Bucket::parse_uri(&first_entry.src.to_string_lossy()) {
+                let (steps, conflicts) = plan_undo_with(&entries, &run_id, &bucket);
+                return undo(&journal, &run_id, steps, conflicts, *force, |from, to| {
+                    let (Some(from_key), Some(to_key)) = (bucket.key(from), bucket.key(to)) else {
+                        return Err(Error::InvalidS3Uri(from.display().to_string()).into());
+                    };
+                    bucket.move_key(&from_key, &to_key)
+                });
+            }
+            let (steps, conflicts) = plan_undo(&entries, &run_id);
+            undo(&journal, &run_id, steps, conflicts, *force, |from, to| {
+                if let Some(dir) = to.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                Ok(jdt::rename_file(from, to)?)
+            })?;
+        },
+        #[cfg(feature = "s3")]
+        Command::S3 { uri, segment_bytes, apply, journal, manifest } => {
+            let (bucket, prefix) = S3Bucket::parse_uri(uri).ok_or_else(|| Error::InvalidS3Uri(uri.clone()))?;
+            let keys = bucket.list_keys(&prefix)?;
+            let renames = plan_s3_renames(&bucket, &keys, *segment_bytes)?;
+            if let Some(manifest) = manifest {
+                let renamed_keys = renames.iter().map(|(old, new)| (bucket.path(old), bucket.path(new))).collect::<Vec<_>>();
+                write_manifest(io::BufWriter::new(fs::File::create(manifest)?), &renamed_keys)?;
+            }
+            if !apply {
+                for (old, new) in &renames {
+                    println!("{} -> {}", old, new);
+                }
+                return Ok(());
+            }
+
+            let journal = open_journal(journal.as_ref())?.with_config(&ConfigSnapshot::load());
+            let run_id = new_run_id();
+            log::info!("{}", tr!("s3-renaming", "Renaming {n} keys (run ID: {run_id})", n = renames.len(), run_id = run_id));
+            for (old, new) in &renames {
+                bucket.move_key(old, new).map_err(|e| Error::RenameError(bucket.path(old), bucket.path(new), e))?;
+                log::info!("{}", tr!("renamed", "Renamed: {src} -> {dst}", src = old, dst = new));
+                if let Err(e) = journal.record(&run_id, bucket.path(old), bucket.path(new), None) {
+                    log::warn!("{}", tr!("journal-record-failed", "Failed to record in the journal: {path}: {error}", path = journal.path().display(), error = localized_error(&e)));
+                }
+            }
+        },
+        Command::InstallIntegration { file_manager } => {
+            let exe = std::env::current_exe()?;
+            let file_managers = match file_manager {
+                Some(file_manager) => std::slice::from_ref(file_manager),
+                None => FileManager::all(),
+            };
+            for file_manager in file_managers {
+                let path = file_manager.integration_path().ok_or(Error::IntegrationPathUnknown)?;
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                let mut file = fs::File::create(&path)?;
+                file_manager.write_integration(&mut file, &exe)?;
+                file.set_permissions(fs::Permissions::from_mode(0o755))?;
+                log::info!("{}", tr!("installed", "Installed: {path}", path = path.display()));
+            }
+        },
+        Command::Hook { command: HookCommand::Install { force } } => {
+            let path = pre_commit_hook_path(".")?;
+            // an existing hook is someone's, not ours to lose
+            if path.exists() && !force {
+                return Err(Error::HookExists(path).into());
+            }
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut file = fs::File::create(&path)?;
+            write_pre_commit_hook(&mut file, &std::env::current_exe()?)?;
+            file.set_permissions(fs::Permissions::from_mode(0o755))?;
+            log::info!("{}", tr!("installed", "Installed: {path}", path = path.display()));
+        },
+        Command::Verify { run, journal } => {
+            let journal = open_journal(journal.as_ref())?;
+            let issues = verify_journal(&journal.entries()?, run.as_deref());
+            for issue in &issues {
+                println!("{}", issue.localized());
+            }
+            if !issues.is_empty() {
+                return Err(Error::JournalIssues(issues.len()).into());
+            }
+        },
+        Command::Replay { entry, journal } => {
+            let journal = open_journal(journal.as_ref())?;
+            let entries = journal.entries()?;
+            let recorded = entry.checked_sub(1).and_then(|i| entries.get(i)).ok_or(Error::EntryNotFound(*entry, entries.len()))?;
+            // the config of the run, the rules may have changed since
+            #[cfg(feature = "json")]
+            let snapshot = match &recorded.config_hash {
+                Some(hash) => {
+                    let snapshot = journal.config_snapshot(hash)?;
+                    match &snapshot {
+                        Some(_) if *hash != ConfigSnapshot::load().hash() => log::warn!("{}", tr!("replay-config-changed", "The config has changed since the run, replaying with the recorded one")),
+                        Some(_) => (),
+                        None => log::warn!("{}", tr!("replay-config-not-saved", "The config of the run isn't saved, replaying with the current one")),
+                    }
+                    snapshot
+                },
+                None => None,
+            };
+            #[cfg(feature = "json")]
+            let overrides = RuleOverrides { config: snapshot, ..Default::default() };
+            #[cfg(not(feature = "json"))]
+            let overrides = {
+                if recorded.config_hash.is_some() {
+                    log::warn!("{}", tr!("replay-config-not-read", "The recorded config isn't read without the json feature, replaying with the current one"));
+                }
+                RuleOverrides::default()
+            };
+            let replayed = replay_entry(recorded, Planner::new().overrides(overrides))?;
+            println!("{}", tr!("replay-recorded", "Recorded: {src} -> {dst} (run {run_id})", src = recorded.src.display(), dst = recorded.dst.display(), run_id = recorded.run_id));
+            println!("{}", tr!("replay-replayed", "Replayed: {src} -> {dst}", src = replayed.src.display(), dst = replayed.dst.display()));
+            if replayed.dst != recorded.dst {
+                // the options of the run aren't recorded, the defaults are replayed
+                log::warn!("{}", tr!("replay-differs", "The name differs from the recorded one, the options of the run, the config or the directory differ"));
+            }
+            explain_plan(&[replayed], &[Status::Renamed], color);
+        },
+        #[cfg(feature = "history-db")]
+        Command::History { src, dst, since, until, journal } => {
+            let journal = open_journal(journal.as_ref())?;
+            let mut history = History::open(History::path_for(journal.path()))?;
+            // the renames recorded without the history
+            let n_imported = history.import(&journal.entries()?)?;
+            if 0 < n_imported {
+                log::info!("{}", tr!("history-imported", "Imported {n} renames from the journal", n = n_imported));
+            }
+            let query = HistoryQuery { src: src.clone(), dst: dst.clone(), since: *since, until: until.map(|day| day + 24 * 60 * 60) };
+            for entry in history.query(&query)? {
+                println!("{}\t{}\t{}\t{}\t{}", format_time(entry.time), entry.run_id, entry.hostname.as_deref().unwrap_or("-"), entry.src.display(), entry.dst.display());
+            }
+        },
+        Command::Lint { path, fix } => {
+            let linter = Linter::load();
+            let paths = if path.is_dir() {
+                walk(path, &WalkOptions { include_dirs: true, ..Default::default() })?
+            } else {
+                vec![path.clone()]
+            };
+
+            // a directory comes after everything inside it, so renaming it never invalidates the paths not yet seen
+            let mut n_violations = 0;
+            for path_to_lint in paths {
+                let Some(filename) = path_to_lint.file_name() else {
+                    continue;
+                };
+                let filename = filename.to_string_lossy();
+                let mut violations = linter.lint(&filename, lint_depth(path, &path_to_lint).max(1));
+                if *fix && violations.iter().any(|violation| violation.is_fixable()) {
+                    match linter.fix(&filename).map(|fixed| path_to_lint.with_file_name(fixed)) {
+                        Err(e) => log::error!("{}", tr!("lint-fix-failed", "Can't fix: {path}: {error}", path = path_to_lint.display(), error = localized_error(&e))),
+                        Ok(new_path) if new_path.symlink_metadata().is_ok() => {
+                            log::error!("{}", tr!("lint-fix-taken", "Can't fix, the name is taken: {src} -> {dst}", src = path_to_lint.display(), dst = new_path.display()));
+                        },
+                        Ok(new_path) => {
+                            jdt::rename_file(&path_to_lint, &new_path).map_err(|e| Error::RenameError(path_to_lint.clone(), new_path.clone(), e.into()))?;
+                            log::info!("{}", tr!("renamed", "Renamed: {src} -> {dst}", src = path_to_lint.display(), dst = new_path.display()));
+                            violations.retain(|violation| !violation.is_fixable());
+                        },
+                    }
+                }
+                for violation in &violations {
+                    println!("{}: {}", path_to_lint.display(), violation);
+                }
+                n_violations += violations.len();
+            }
+            if 0 < n_violations {
+                return Err(Error::LintViolations(n_violations).into());
+            }
+        },
+        Command::GenTestNames { dir, n_bytes } => {
+            fs::create_dir_all(dir)?;
+            // a rejected name is what the test is for, the rest are still created
+            let mut n_rejected = 0;
+            for name in test_names(*n_bytes) {
+                let path = dir.join(&name);
+                match fs::File::create(&path) {
+                    Ok(_) => println!("{}", path.display()),
+                    Err(e) => {
+                        log::error!("{}", tr!("test-name-rejected", "Rejected: {path}: {error}", path = path.display(), error = e));
+                        n_rejected += 1;
+                    },
+                }
+            }
+            if 0 < n_rejected {
+                return Err(Error::TestNamesRejected(n_rejected).into());
+            }
+        },
+        #[cfg(feature = "self-update")]
+        Command::SelfUpdate { check } => {
+            let release = latest_release()?;
+            let current_version = clap::crate_version!();
+            if !is_newer_version(&release.version, current_version) {
+                log::info!("{}", tr!("update-up-to-date", "Up to date: {version}", version = current_version));
+                return Ok(());
+            }
+            if *check {
+                println!("{}", tr!("update-available", "{version} is available (current: {current})", version = release.version, current = current_version));
+                return Ok(());
+            }
+            let url = release.binary_url.as_ref().ok_or_else(|| Error::ReleaseBinaryNotFound(release.version.clone()))?;
+            let checksum_url = release.checksum_url.as_ref().ok_or_else(|| Error::ReleaseChecksumNotFound(release.version.clone()))?;
+            let exe = std::env::current_exe()?;
+            replace_binary(url, checksum_url, &exe)?;
+            log::info!("{}", tr!("updated", "Updated: {current} -> {version}: {path}", current = current_version, version = release.version, path = exe.display()));
+        },
+        #[cfg(feature = "schema")]
+        Command::Config { command: ConfigCommand::Schema } => {
+            println!("{}", config_schema());
+        },
+        #[cfg(feature = "json")]
+        Command::Config { command: ConfigCommand::Migrate } => {
+            let path = config_path();
+            if !path.exists() {
+                println!("{}", tr!("config-not-found", "No config file: {path}", path = path.display()));
+                return Ok(());
+            }
+            match migrate_config_file(&path)? {
+                Some(migration) => println!("{}", tr!("config-migrated", "Config migrated from version {from_version} to {version}: {path} (the old one is kept as {backup_path})",
+                    from_version = migration.from_version, version = CONFIG_VERSION, path = migration.path.display(), backup_path = migration.backup_path.display())),
+                None => println!("{}", tr!("config-up-to-date", "Config is of this version already: {path} (version {version})", path = path.display(), version = CONFIG_VERSION)),
+            }
+        },
+        Command::Config { command: ConfigCommand::Validate } => {
+            let issues = ResolvedConfig::load().validate();
+            let mut n_errors = 0;
+            for issue in &issues {
+                if issue.is_error() {
+                    println!("{}", tr!("config-validate-error", "error: {issue}", issue = issue.localized()));
+                    n_errors += 1;
+                } else {
+                    println!("{}", tr!("config-validate-warning", "warning: {issue}", issue = issue.localized()));
+                }
+            }
+            if 0 < n_errors {
+                return Err(Error::InvalidConfig(n_errors).into());
+            }
+        },
+    }
+    Ok(())
+}
+
+// `0755`, `755` or `source`
+fn parse_dir_mode(s: &str) -> Result<DirMode, String> {
+    if s == "source" {
+        return Ok(DirMode::Source);
+    }
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(DirMode::Mode(mode)),
+        _ => Err(format!("{}: must be an octal mode like 0755, or `source`", s)),
+    }
+}
+
+// the recorded config of the overrides is there only with the json feature
+#[cfg_attr(not(feature = "json"), allow(clippy::needless_update))]
+fn rule_overrides(args: &Args) -> RuleOverrides {
+    RuleOverrides {
+        profile: args.rules.clone(),
+        ignored_tags: args.ignore_tags.clone(),
+        conversions: args.conversions.clone(),
+        ..Default::default()
+    }
+}
+
+// `FROM=TO`, the first `=` splits them
+fn parse_conversion(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok((from.to_string(), to.to_string())),
+        _ => Err(format!("{}: must be FROM=TO", s)),
+    }
+}
+
+// `80%` or `80`
+fn parse_percent(s: &str) -> Result<usize, String> {
+    let percent = s.strip_suffix('%').unwrap_or(s).parse::<usize>().map_err(|e| format!("{}: {}", s, e))?;
+    if !(10..=100).contains(&percent) {
+        return Err(format!("{}: must be between 10% and 100%", s));
+    }
+    Ok(percent)
+}
+
+fn parse_batch_size(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err(format!("{}: must be at least 1", s)),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("{}: {}", s, e)),
+    }
+}
+
+#[cfg(feature = "s3")]
+fn parse_segment_bytes(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n < N_MIN_SEGMENT_BYTES => Err(format!("{}: must be at least {}", s, N_MIN_SEGMENT_BYTES)),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("{}: {}", s, e)),
+    }
+}
+
+#[cfg(feature = "history-db")]
+fn parse_history_date(s: &str) -> Result<u64, String> {
+    parse_date(s).ok_or_else(|| format!("{}: must be YYYY-MM-DD", s))
+}
+
+fn plan_rename(planner: &mut Planner, path: &Path, args: &Args) -> Result<PlanEntry, Error> {
+    if is_nfs_temp_file(path) && !args.include_nfs_temp {
+        log::info!("{}", tr!("skipped-nfs-temp-file", "Skipped NFS temporary file: {path}", path = path.display()));
+        return Ok(PlanEntry::unchanged(path));
+    }
+
+    if args.skip_empty && path.symlink_metadata().is_ok_and(|m| m.is_file() && m.len() == 0) {
+        log::info!("{}", tr!("skipped-empty-file", "Skipped empty file: {path}", path = path.display()));
+        return Ok(PlanEntry::unchanged(path));
+    }
+
+    if !args.force && is_protected_path(path)? {
+        if args.recursive {
+            log::info!("{}", tr!("skipped-protected-path", "Skipped protected path: {path}", path = path.display()));
+            return Ok(PlanEntry::unchanged(path));
+        }
+        return Err(Error::ProtectedPath(path.to_path_buf()));
+    }
+
+    planner.plan(path, args.dst_dir.as_ref()).map_err(|e| match e.downcast::<rename_for_linux_limit::Error>() {
         Ok(rename_for_linux_limit::Error::FilenameNotFound(path)) => Error::FilenameNotFound(path),
+        Ok(rename_for_linux_limit::Error::ClaimFailed(path, e)) => Error::Claim(path, e),
+        Ok(rename_for_linux_limit::Error::ExistenceCheckFailed(path, e)) => Error::ExistenceCheck(path, e),
+        Ok(e) => Error::UnknownError(e.into()),
         Err(e) => Error::UnknownError(e),
-    })?;
+    })
+}
+
+// moves the files, or the keys, of the steps back and records that as a run of its own, so it can be undone again
+fn undo(journal: &Journal, run_id: &str, steps: Vec<(PathBuf, PathBuf)>, conflicts: Vec<UndoConflict>, force: bool, mut move_back: impl FnMut(&Path, &Path) -> Result<()>) -> Result<()> {
+    for conflict in &conflicts {
+        log::error!("{}", conflict.localized());
+    }
+    if !conflicts.is_empty() && !force {
+        return Err(Error::UndoConflicts(conflicts.len()).into());
+    }
 
-    if only_show_new_filename {
-        println!("{}", new_filename);
+    let undo_run_id = new_run_id();
+    log::info!("{}", tr!("undoing", "Undoing run {run_id} (run ID: {undo_run_id})", run_id = run_id, undo_run_id = undo_run_id));
+    for (from, to) in steps {
+        move_back(&from, &to).map_err(|e| Error::RenameError(from.clone(), to.clone(), e))?;
+        log::info!("{}", tr!("renamed", "Renamed: {src} -> {dst}", src = from.display(), dst = to.display()));
+        if let Err(e) = journal.record(&undo_run_id, &from, &to, None) {
+            log::warn!("{}", tr!("journal-record-failed", "Failed to record in the journal: {path}: {error}", path = journal.path().display(), error = localized_error(&e)));
+        }
+    }
+    Ok(())
+}
+
+// the arguments of the runs of a watch
+#[cfg(feature = "watch")]
+fn watch_args(watch: &WatchConfig) -> Args {
+    let mut args = Args::parse_from([OsStr::new(clap::crate_name!()), watch.dir.as_os_str()]);
+    args.dst_dir = watch.dst_dir.clone();
+    args.profile = watch.profile;
+    args.rules = watch.rules.clone();
+    args
+}
+
+// a batch of files arriving without a pause is still cut after this, so that the first ones don't wait forever
+#[cfg(feature = "watch")]
+const MAX_BATCH_WAIT: Duration = Duration::from_secs(30);
+
+// the runs of each watch, of the directory in their path
+#[cfg(feature = "watch")]
+fn watch(watches: &[Args], state_path: Option<&PathBuf>, reset_state: bool, mut debouncer: Debouncer, color: bool) -> Result<()> {
+    let state_path = state_path.cloned().or_else(WatchState::default_path).ok_or(Error::WatchStatePathUnknown)?;
+    let mut state = WatchState::load(&state_path)?;
+    if reset_state {
+        state.clear();
+        log::info!("{}", tr!("watch-state-reset", "Watch state reset: {path}", path = state_path.display()));
+    }
+    let dir = |args: &Args| args.path.clone().expect("set by watch_args");
+    // before looking at the files there, so that none written meanwhile is missed
+    let mut inotify = Inotify::new()?;
+    for args in watches {
+        inotify.add_dir(dir(args))?;
+    }
+    let files = watches.iter().map(|args| dir_files(dir(args))).collect::<io::Result<Vec<_>>>()?;
+    state.retain(files.iter().flatten().map(|(_, metadata)| metadata));
+
+    // one run for the whole watch, undone at once
+    let mut run = Run::new(true);
+    run.renamed = Some(Vec::new());
+    for (args, files) in watches.iter().zip(files) {
+        shorten_arrived(args, color, &mut run, &mut state, files.into_iter().map(|(path, _)| path).collect())?;
+        log::info!("{}", tr!("watching", "Watching {dir}", dir = dir(args).display()));
+    }
+    loop {
+        let paths = inotify.read(debouncer.timeout(Instant::now()))?;
+        debouncer.push(paths, Instant::now());
+        let Some(paths) = debouncer.take_ready(Instant::now()) else {
+            continue;
+        };
+        for args in watches {
+            let dir = dir(args);
+            let arrived = paths.iter().filter(|path| path.parent() == Some(&dir)).cloned().collect();
+            shorten_arrived(args, color, &mut run, &mut state, arrived)?;
+        }
+    }
+}
+
+// the files not looked at yet, in a batch. the renamed ones arrive again by their new names, and are skipped then
+#[cfg(feature = "watch")]
+fn shorten_arrived(args: &Args, color: bool, run: &mut Run, state: &mut WatchState, paths: Vec<PathBuf>) -> Result<()> {
+    let mut seen = std::collections::HashSet::new();
+    let mut arrived = Vec::new();
+    for path in paths {
+        // gone already, or not a regular file
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if metadata.is_file() && !state.is_processed(&metadata) && seen.insert(path.clone()) {
+            arrived.push((path, metadata));
+        }
+    }
+    if arrived.is_empty() {
         return Ok(());
     }
+    let metadata = arrived.iter().map(|(_, metadata)| metadata.clone()).collect::<Vec<_>>();
+    let n_errors = run.n_errors;
+    // one planner for the batch, so that two arrived files shortened to the same name get a counter
+    shorten_batch(&mut new_planner(args)?, arrived.into_iter().map(|(path, _)| path).collect(), args, color, run)?;
+    if let Some((journal, _)) = &run.journal {
+        journal.commit();
+    }
+    let renamed = run.renamed.as_mut().map(std::mem::take).unwrap_or_default();
+    let dir = args.path.as_deref().unwrap_or(Path::new("."));
+    log::info!("{}", batch_summary(dir, metadata.len(), &renamed, run.n_errors - n_errors));
+    #[cfg(feature = "desktop")]
+    notify_renames(&renamed);
+    // the failed ones too, they would fail again. a file changed since is looked at again
+    for metadata in &metadata {
+        state.insert(metadata);
+    }
+    if let Err(e) = state.save() {
+        log::warn!("{}", tr!("watch-state-save-failed", "Failed to save the watch state: {path}: {error}", path = state.path().display(), error = e));
+    }
+    Ok(())
+}
 
-    let new_path = if let Some(dst_dir) = dst_dir {
-        fs::create_dir_all(&dst_dir)?;
-        dst_dir.join(&new_filename)
-    } else {
-        path.with_file_name(&new_filename)
+// one line for a batch of a watch, instead of one for each file of a burst
+#[cfg(feature = "watch")]
+fn batch_summary(dir: &Path, n_arrived: usize, renamed: &[(PathBuf, PathBuf, Status)], n_failed: usize) -> String {
+    let mut summary = tr!("batch-summary", "{dir}: {n_arrived} files arrived, {n_renamed} renamed", dir = dir.display(), n_arrived = n_arrived, n_renamed = renamed.len());
+    let n_conflicts = renamed.iter().filter(|(_, _, status)| *status == Status::Conflict).count();
+    if 0 < n_conflicts {
+        summary.push_str(&tr!("batch-summary-conflicts", " ({n} with a counter)", n = n_conflicts));
+    }
+    if 0 < n_failed {
+        summary.push_str(&tr!("batch-summary-failed", ", {n} failed", n = n_failed));
+    }
+    summary
+}
+
+// the renames listed in a notification, which a notification server shows only a few lines of anyway
+#[cfg(feature = "desktop")]
+const N_MAX_NOTIFICATION_LINES: usize = 5;
+
+// so that a file just downloaded isn't looked for by its old name. a notification failing doesn't stop the watch
+#[cfg(feature = "desktop")]
+fn notify_renames(renamed: &[(PathBuf, PathBuf, Status)]) {
+    let Some((summary, body)) = renames_notification(renamed) else {
+        return;
     };
+    let result = process::Command::new("notify-send").arg("--app-name").arg(clap::crate_name!()).arg(&summary).arg(&body).status();
+    match result {
+        Ok(status) if status.success() => (),
+        Ok(status) => log::warn!("{}", tr!("notify-send-failed", "notify-send failed: {error}", error = status)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => log::warn!("{}", tr!("notify-send-not-found", "notify-send not found, install libnotify for the notifications")),
+        Err(e) => log::warn!("{}", tr!("notify-send-failed", "notify-send failed: {error}", error = e)),
+    }
+}
+
+// (summary, body), by the filenames, as the directory is the watched one
+#[cfg(feature = "desktop")]
+fn renames_notification(renamed: &[(PathBuf, PathBuf, Status)]) -> Option<(String, String)> {
+    if renamed.is_empty() {
+        return None;
+    }
+    let filename = |path: &Path| path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().to_string();
+    // notification servers may read the body as markup
+    let escape = |text: String| text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let mut lines = Vec::new();
+    for (src, dst, _) in renamed.iter().take(N_MAX_NOTIFICATION_LINES) {
+        lines.push(escape(format!("{} → {}", filename(src), filename(dst))));
+    }
+    if N_MAX_NOTIFICATION_LINES < renamed.len() {
+        lines.push(tr!("and-more", "... and {n} more", n = renamed.len() - N_MAX_NOTIFICATION_LINES));
+    }
+    let summary = match renamed.len() {
+        1 => tr!("notification-renamed-one", "Renamed a file"),
+        n => tr!("notification-renamed", "Renamed {n} files", n = n),
+    };
+    Some((summary, lines.join("\n")))
+}
+
+fn open_journal(path: Option<&PathBuf>) -> Result<Journal, Error> {
+    let path = path.cloned().or_else(Journal::default_path).ok_or(Error::JournalPathUnknown)?;
+    let journal = Journal::new(path);
+    // the renames are still journaled without the history
+    #[cfg(feature = "history-db")]
+    let journal = match History::open(History::path_for(journal.path())) {
+        Ok(history) => journal.with_history(history),
+        Err(e) => {
+            log::warn!("{}", tr!("history-open-failed", "Failed to open the history: {error}", error = localized_error(&e)));
+            journal
+        },
+    };
+    Ok(journal)
+}
+
+// copies aren't recorded, there is nothing to undo for them
+// of a created destination directory, `src` is the directory the files come from
+fn set_dir_mode(dst: &Path, src: &Path, dir_mode: DirMode) -> io::Result<()> {
+    match dir_mode {
+        DirMode::Mode(mode) => fs::set_permissions(dst, fs::Permissions::from_mode(mode)),
+        DirMode::Source => {
+            let metadata = fs::metadata(src)?;
+            // only root can give the directory away. before the mode, since chown clears the setgid bit
+            if let Err(e) = std::os::unix::fs::chown(dst, Some(metadata.uid()), Some(metadata.gid())) {
+                log::warn!("{}", tr!("copy-owner-failed", "Failed to copy the owner of {src}: {dst}: {error}", src = src.display(), dst = dst.display(), error = e));
+            }
+            fs::set_permissions(dst, metadata.permissions())
+        },
+    }
+}
+
+fn rename(entry: PlanEntry, args: &Args, copy_options: &CopyOptions, journal: Option<&(Journal, String)>) -> Result<(), Error> {
+    let PlanEntry { kind, src, dst, duplicate, .. } = entry;
+
+    if kind == PlanKind::CreateDir {
+        // already there with --claim
+        if !dst.is_dir() {
+            fs::create_dir(&dst)?;
+            log::info!("{}", tr!("created-directory", "Created directory: {path}", path = dst.display()));
+        }
+        if let Some(dir_mode) = args.dir_mode {
+            set_dir_mode(&dst, &src, dir_mode)?;
+        }
+        return Ok(());
+    }
+
+    if duplicate {
+        if args.dedupe == Some(DedupePolicy::Delete) {
+            log::info!("{}", tr!("deleted-duplicate", "Deleted duplicate: {src} (same as {dst})", src = src.display(), dst = dst.display()));
+            fs::remove_file(&src)?;
+        } else {
+            log::info!("{}", tr!("skipped-duplicate", "Skipped duplicate: {src} (same as {dst})", src = src.display(), dst = dst.display()));
+        }
+        return Ok(());
+    }
 
-    if jdt::eq_files(&path, &new_path)? {
-        log::info!("Filename is already short enough: {}", new_path.display());
+    if jdt::eq_files(&src, &dst)? {
+        log::info!("{}", tr!("already-short", "Filename is already short enough: {path}", path = dst.display()));
     } else {
-        log::info!("Renamed: {} -> {}", path.display(), new_path.display());
-        jdt::rename_file(&path, &new_path).map_err(|e| Error::RenameError(path, new_path, e))?;
+        if !args.copy && src.parent() != dst.parent() {
+            if let Some(dst_dir) = dst.parent().filter(|p| !p.as_os_str().is_empty()) {
+                if let Ok(Some((gid, dir_gid))) = setgid_group_mismatch(&src, dst_dir) {
+                    log::warn!("{}", tr!("setgid-group-mismatch", "Moved into a setgid directory of group {dir_gid} keeping group {gid}, members of the group may lose access: {path}", dir_gid = dir_gid, gid = gid, path = dst.display()));
+                }
+            }
+        }
+        let result = if args.copy {
+            log::info!("{}", tr!("copied", "Copied: {src} -> {dst}", src = src.display(), dst = dst.display()));
+            copy_file(&src, &dst, copy_options)
+        } else if args.git && is_git_tracked(&src) {
+            log::info!("{}", tr!("renamed-git", "Renamed with git mv: {src} -> {dst}", src = src.display(), dst = dst.display()));
+            git_move_file(&src, &dst).map(|_| None)
+        } else if !copy_options.is_plain() {
+            log::info!("{}", tr!("renamed", "Renamed: {src} -> {dst}", src = src.display(), dst = dst.display()));
+            move_file(&src, &dst, copy_options)
+        } else {
+            log::info!("{}", tr!("renamed", "Renamed: {src} -> {dst}", src = src.display(), dst = dst.display()));
+            jdt::rename_file(&src, &dst).map(|_| None).map_err(anyhow::Error::from)
+        };
+        let verified_checksum = match result {
+            Ok(checksum) => checksum,
+            Err(e) => {
+                if args.claim {
+                    // release the placeholder, it's no use for anyone
+                    let _ = fs::remove_file(&dst);
+                }
+                return Err(Error::RenameError(src, dst, e));
+            },
+        };
+        if let Some((journal, run_id)) = journal {
+            // the checksum of --verify is recorded as it is, unless --journal-checksum asks for another algorithm
+            let checksum = match args.journal_checksum {
+                Some(algorithm) if verified_checksum.as_ref().is_none_or(|(verified, _)| *verified != algorithm) => match algorithm.checksum(&dst) {
+                    Ok(checksum) => Some((algorithm, checksum)),
+                    Err(e) => {
+                        log::warn!("{}", tr!("checksum-failed", "Failed to compute checksum: {path}: {error}", path = dst.display(), error = e));
+                        None
+                    },
+                },
+                _ => verified_checksum,
+            };
+            if let Err(e) = journal.record(run_id, &src, &dst, checksum) {
+                log::warn!("{}", tr!("journal-record-failed", "Failed to record in the journal: {path}: {error}", path = journal.path().display(), error = localized_error(&e)));
+            }
+        }
     }
 
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_max_changes() {
+        let _ = env_logger::try_init();
+
+        // each batch of -r --low-memory is under the limit, the run is not
+        let mut run = Run::new(true);
+        run.add_changes(3, 5).unwrap();
+        assert_eq!(run.add_changes(3, 5).err().unwrap().to_string(), "Too many changes: 6 files would be renamed, but --max-changes is 5");
+        assert_eq!(run.n_changes, 3);
+        run.add_changes(2, 5).unwrap();
+        assert_eq!(run.n_changes, 5);
+    }
+
+    #[test]
+    fn test_print_path() {
+        let _ = env_logger::try_init();
+
+        let dst = Path::new("dir/l.txt");
+        assert_eq!(printed_path(dst, PrintPath::Name).unwrap(), Some("l.txt".to_string()));
+        assert_eq!(printed_path(dst, PrintPath::Relative).unwrap(), Some("dir/l.txt".to_string()));
+        assert_eq!(printed_path(dst, PrintPath::Absolute).unwrap(), Some(std::env::current_dir().unwrap().join(dst).display().to_string()));
+        assert_eq!(printed_path(Path::new("/"), PrintPath::Name).unwrap(), None);
+    }
+
+    #[test]
+    fn test_print0() {
+        let _ = env_logger::try_init();
+
+        // newlines in names stay in them
+        let mut out = Vec::new();
+        write_terminated(&mut out, &["a b".to_string(), "c\nd".to_string()], "\0").unwrap();
+        assert_eq!(out, b"a b\0c\nd\0");
+    }
+
+    #[test]
+    fn test_shrink_to() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(parse_percent("80%"), Ok(80));
+        assert_eq!(parse_percent("100"), Ok(100));
+        assert!(parse_percent("5%").is_err());
+        assert!(parse_percent("101%").is_err());
+        assert!(parse_percent("half").is_err());
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join(format!("{}.txt", "あ".repeat(80)));
+        let mut planner = Planner::new().shrink_to(parse_percent("50%").unwrap());
+        let entry = planner.plan(&path, None::<PathBuf>).unwrap();
+        assert!(entry.dst.file_name().unwrap().len() <= 255 / 2);
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let _ = env_logger::try_init();
+
+        let mut heartbeat = Heartbeat::new(Duration::from_secs(10), 100);
+        let start = heartbeat.start;
+        assert_eq!(heartbeat.beat_at(start + Duration::from_secs(5), 5), None);
+        assert_eq!(heartbeat.beat_at(start + Duration::from_secs(10), 10), Some("10/100 files, 1.0 files/s, ETA 90s".to_string()));
+        // the interval counts from the last line
+        assert_eq!(heartbeat.beat_at(start + Duration::from_secs(15), 15), None);
+        assert_eq!(heartbeat.beat_at(start + Duration::from_secs(20), 100), Some("100/100 files, 5.0 files/s, ETA 0s".to_string()));
+    }
+
+    #[test]
+    fn test_dir_mode() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(parse_dir_mode("0750"), Ok(DirMode::Mode(0o750)));
+        assert_eq!(parse_dir_mode("source"), Ok(DirMode::Source));
+        assert!(parse_dir_mode("0789").is_err());
+        assert!(parse_dir_mode("17777").is_err());
+
+        let tmp = tempfile::tempdir().unwrap();
+        let (src, dst) = (tmp.path().join("src"), tmp.path().join("dst"));
+        fs::create_dir(&src).unwrap();
+        fs::create_dir(&dst).unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        set_dir_mode(&dst, &src, DirMode::Mode(0o700)).unwrap();
+        assert_eq!(mode(&dst), 0o700);
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o751)).unwrap();
+        set_dir_mode(&dst, &src, DirMode::Source).unwrap();
+        assert_eq!(mode(&dst), 0o751);
+    }
+
+    #[test]
+    fn test_list_over_limit() {
+        let _ = env_logger::try_init();
+
+        let long = PathBuf::from(format!("dir/{}.txt", "a".repeat(300)));
+        let mut out = Vec::new();
+        assert!(print_over_limit(&mut out, &long, None, b"\0").unwrap());
+        assert!(!print_over_limit(&mut out, Path::new("dir/short.txt"), None, b"\0").unwrap());
+        // only the filename counts, not the directories
+        assert!(!print_over_limit(&mut out, &Path::new(&"d".repeat(300)).join("short.txt"), None, b"\0").unwrap());
+        assert_eq!(out, [long.as_os_str().as_bytes(), b"\0"].concat());
+
+        // by the rules of the profile
+        let mut out = Vec::new();
+        assert!(print_over_limit(&mut out, Path::new("LONGNAME1.TXT"), Some(Profile::Iso9660Level1), b"\n").unwrap());
+        assert_eq!(out, b"LONGNAME1.TXT\n");
+    }
+
+    #[test]
+    fn test_check() {
+        let _ = env_logger::try_init();
+
+        let rename = |src: &str, dst: &str| PlanEntry { kind: PlanKind::Rename, src: PathBuf::from(src), dst: PathBuf::from(dst), duplicate: false, conflict: false };
+        let plan = [PlanEntry::create_dir("d", "."), rename("long.txt", "d/l.txt"), rename("short.txt", "short.txt")];
+        let statuses = [Status::Mkdir, Status::Renamed, Status::Unchanged];
+        assert_eq!(would_rename(&plan, &statuses), vec![&plan[1]]);
+        assert_eq!(would_rename(&plan[2..], &statuses[2..]), Vec::<&PlanEntry>::new());
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn test_batch_summary() {
+        let _ = env_logger::try_init();
+
+        let dir = Path::new("Downloads");
+        assert_eq!(batch_summary(dir, 3, &[], 0), "Downloads: 3 files arrived, 0 renamed");
+        let renamed = [
+            (PathBuf::from("Downloads/long-a.txt"), PathBuf::from("Downloads/l.txt"), Status::Renamed),
+            (PathBuf::from("Downloads/long-b.txt"), PathBuf::from("Downloads/l (1).txt"), Status::Conflict),
+        ];
+        assert_eq!(batch_summary(dir, 5, &renamed, 1), "Downloads: 5 files arrived, 2 renamed (1 with a counter), 1 failed");
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn test_renames_notification() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(renames_notification(&[]), None);
+        let renamed = |i: usize| (PathBuf::from(format!("Downloads/<long> & {}.txt", i)), PathBuf::from(format!("Downloads/l{}.txt", i)), Status::Renamed);
+        assert_eq!(renames_notification(&[renamed(0)]), Some(("Renamed a file".to_string(), "&lt;long&gt; &amp; 0.txt → l0.txt".to_string())));
+        let (summary, body) = renames_notification(&(0..7).map(renamed).collect::<Vec<_>>()).unwrap();
+        assert_eq!(summary, "Renamed 7 files");
+        assert_eq!(body.lines().count(), N_MAX_NOTIFICATION_LINES + 1);
+        assert!(body.ends_with("... and 2 more"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_record() {
+        let _ = env_logger::try_init();
+
+        let entry = PlanEntry { kind: PlanKind::Rename, src: PathBuf::from("long.txt"), dst: PathBuf::from("l.txt"), duplicate: false, conflict: false };
+        let json = serde_json::to_string(&record(&entry, Status::Renamed, None)).unwrap();
+        assert!(json.starts_with(&format!("{{\"version\":{},", RECORD_VERSION)));
+        assert!(json.contains("\"dst\":\"l.txt\""));
+        // a failed rename leaves the file where it was
+        let json = serde_json::to_string(&record(&entry, Status::Failed, Some("denied".to_string()))).unwrap();
+        assert!(json.contains("\"dst\":\"long.txt\"") && json.contains("\"error\":\"denied\""));
+    }
+}
@@ -0,0 +1,75 @@
+use std::{path::PathBuf, io::{self, Write, BufRead}};
+use anyhow::Result;
+
+use crate::{Error, journal::{escape_path, unescape_path}};
+
+// bumped when the manifest changes incompatibly. readers accept this and older versions (unversioned is 0),
+// newer ones are refused instead of being misread. the paths are escaped as in the journal since version 2
+pub const MANIFEST_VERSION: u32 = 2;
+const MANIFEST_VERSION_PREFIX: &str = "# manifest version ";
+
+// a version line, then a tab separated (original, new) line per renamed member or key
+pub fn write_manifest(mut writer: impl Write, renamed_members: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    writeln!(writer, "{}{}", MANIFEST_VERSION_PREFIX, MANIFEST_VERSION)?;
+    for (path, new_path) in renamed_members {
+        writeln!(writer, "{}\t{}", escape_path(path), escape_path(new_path))?;
+    }
+    writer.flush()
+}
+
+pub fn read_manifest(reader: impl BufRead) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut renamed_members = Vec::new();
+    let mut version = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if i == 0 {
+            if let Some(version_str) = line.strip_prefix(MANIFEST_VERSION_PREFIX) {
+                version = version_str.parse::<u32>().map_err(|_| Error::InvalidFormat(line.clone()))?;
+                if MANIFEST_VERSION < version {
+                    return Err(Error::UnsupportedFormatVersion(version, MANIFEST_VERSION).into());
+                }
+                continue;
+            }
+        }
+        let (path, new_path) = line.split_once('\t').ok_or_else(|| Error::InvalidFormat(line.clone()))?;
+        let (path, new_path) = if version < 2 {
+            (PathBuf::from(path), PathBuf::from(new_path))
+        } else {
+            match (unescape_path(path), unescape_path(new_path)) {
+                (Some(path), Some(new_path)) => (path, new_path),
+                _ => return Err(Error::InvalidFormat(line.clone()).into()),
+            }
+        };
+        renamed_members.push((path, new_path));
+    }
+    Ok(renamed_members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    use env_logger;
+
+    #[test]
+    fn test_manifest() {
+        let _ = env_logger::try_init();
+
+        let renamed_members = vec![(PathBuf::from("a/b c"), PathBuf::from("a/b"))];
+        let mut buf = Vec::new();
+        write_manifest(&mut buf, &renamed_members).unwrap();
+        assert_eq!(read_manifest(&buf[..]).unwrap(), renamed_members);
+
+        // tabs, newlines and non utf-8 bytes survive the round trip
+        let escaped_members = vec![(PathBuf::from(OsStr::from_bytes(b"a/b\tc\nd\xff")), PathBuf::from("a/b"))];
+        let mut buf = Vec::new();
+        write_manifest(&mut buf, &escaped_members).unwrap();
+        assert_eq!(read_manifest(&buf[..]).unwrap(), escaped_members);
+
+        // written before the version line
+        assert_eq!(read_manifest(&b"a/b c\ta/b\n"[..]).unwrap(), renamed_members);
+
+        let newer = format!("{}{}\n", MANIFEST_VERSION_PREFIX, MANIFEST_VERSION + 1);
+        assert!(read_manifest(newer.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use std::{path::{Path, PathBuf}, fs, io};
+use anyhow::Result;
+use clap::crate_name;
+use serde_json::{Map, Value};
+
+use crate::{Error, CONFIG_VERSION};
+
+// from each version of the config to the next, the first one from the configs without `version`. a key renamed or
+// dropped by a new version gets a step here and `CONFIG_VERSION` goes up, so that its old configs keep working
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[
+    // the keys of the configs before `version` are the ones of version 1, only the version is written
+    |_| (),
+];
+
+const _: () = assert!(MIGRATIONS.len() == CONFIG_VERSION as usize);
+
+// the config file read by the runs
+pub fn config_path() -> PathBuf {
+    jdt::project(crate_name!()).config_path()
+}
+
+// a config file upgraded by `migrate_config_file`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigMigration {
+    pub path: PathBuf,
+    // the file as it was
+    pub backup_path: PathBuf,
+    pub from_version: u32,
+}
+
+// upgrades the config to `CONFIG_VERSION`, returning the version it was of. none when it already is, an error when it is
+// of a newer version, which this one can't read as it was meant
+pub fn migrate_config(config: &mut Map<String, Value>) -> Result<Option<u32>> {
+    let from_version = match config.get("version") {
+        None => 0,
+        Some(version) => version.as_u64().and_then(|version| u32::try_from(version).ok()).ok_or_else(|| Error::InvalidFormat(format!("version: {}", version)))?,
+    };
+    if CONFIG_VERSION < from_version {
+        return Err(Error::UnsupportedFormatVersion(from_version, CONFIG_VERSION).into());
+    }
+    if from_version == CONFIG_VERSION {
+        return Ok(None);
+    }
+    for migration in &MIGRATIONS[from_version as usize..] {
+        migration(config);
+    }
+    config.insert("version".to_string(), Value::from(CONFIG_VERSION));
+    Ok(Some(from_version))
+}
+
+// upgrades the config file in place, the old one is kept as `<file>.v<version>.bak`. none when there is no config file or
+// it is of this version already. the keys are written in order, the layout of the old one isn't kept
+pub fn migrate_config_file(path: &Path) -> Result<Option<ConfigMigration>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let Value::Object(mut config) = serde_json::from_str(&text)? else {
+        return Err(Error::InvalidFormat(path.display().to_string()).into());
+    };
+    let Some(from_version) = migrate_config(&mut config)? else {
+        return Ok(None);
+    };
+    let filename = path.file_name().ok_or_else(|| Error::FilenameNotFound(path.to_path_buf()))?.to_string_lossy();
+    let backup_path = path.with_file_name(format!("{}.v{}.bak", filename, from_version));
+    fs::copy(path, &backup_path)?;
+    // written next to it and renamed over it, the old one stays as it was when the writing fails
+    let tmp_path = path.with_file_name(format!("{}.tmp", filename));
+    fs::write(&tmp_path, serde_json::to_string_pretty(&Value::Object(config))? + "\n")?;
+    fs::rename(&tmp_path, path)?;
+    Ok(Some(ConfigMigration { path: path.to_path_buf(), backup_path, from_version }))
+}
+
+// before the config is read for a run: an old one is upgraded with a notice, a broken one or one of a newer version is
+// left as it is to the reading and `config validate`
+pub(crate) fn migrate_config_on_load(path: &Path) {
+    match migrate_config_file(path) {
+        Ok(Some(migration)) => log::warn!("{}", crate::tr!("config-migrated", "Config migrated from version {from_version} to {version}: {path} (the old one is kept as {backup_path})",
+            from_version = migration.from_version, version = CONFIG_VERSION, path = migration.path.display(), backup_path = migration.backup_path.display())),
+        Ok(None) => (),
+        Err(e) => log::error!("{}", crate::tr!("config-not-migrated", "Config not migrated: {path}: {error}", path = path.display(),
+            error = e.downcast_ref::<Error>().map_or_else(|| e.to_string(), Error::localized))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_migrate_config() {
+        let _ = env_logger::try_init();
+
+        let object = |text: &str| match serde_json::from_str(text).unwrap() {
+            Value::Object(config) => config,
+            _ => unreachable!(),
+        };
+
+        let mut config = object(r#"{"ignored_tags": ["sample"]}"#);
+        assert_eq!(migrate_config(&mut config).unwrap(), Some(0));
+        assert_eq!(config, object(&format!(r#"{{"ignored_tags": ["sample"], "version": {}}}"#, CONFIG_VERSION)));
+        assert_eq!(migrate_config(&mut config).unwrap(), None);
+
+        let mut config = object(&format!(r#"{{"version": {}}}"#, CONFIG_VERSION + 1));
+        let e = migrate_config(&mut config).unwrap_err();
+        assert!(matches!(e.downcast_ref::<Error>(), Some(Error::UnsupportedFormatVersion(version, CONFIG_VERSION)) if *version == CONFIG_VERSION + 1));
+
+        let mut config = object(r#"{"version": "1"}"#);
+        assert!(matches!(migrate_config(&mut config).unwrap_err().downcast_ref::<Error>(), Some(Error::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_migrate_config_file() {
+        let _ = env_logger::try_init();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        assert_eq!(migrate_config_file(&path).unwrap(), None);
+
+        let old = r#"{"conversions": {"sample": "s"}}"#;
+        fs::write(&path, old).unwrap();
+        let migration = migrate_config_file(&path).unwrap().unwrap();
+        assert_eq!(migration, ConfigMigration { path: path.clone(), backup_path: dir.path().join("config.json.v0.bak"), from_version: 0 });
+        assert_eq!(fs::read_to_string(&migration.backup_path).unwrap(), old);
+        let migrated = serde_json::from_str::<Value>(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(migrated["version"], Value::from(CONFIG_VERSION));
+        assert_eq!(migrated["conversions"]["sample"], Value::from("s"));
+        assert!(!dir.path().join("config.json.tmp").exists());
+
+        // already migrated
+        assert_eq!(migrate_config_file(&path).unwrap(), None);
+
+        fs::write(&path, "[]").unwrap();
+        assert!(migrate_config_file(&path).is_err());
+    }
+}
@@ -0,0 +1,634 @@
+use std::{path::{Path, PathBuf}, fs, io, collections::HashMap, ffi::{OsStr, OsString, CString}, time::{Duration, SystemTime, UNIX_EPOCH}, os::unix::{ffi::OsStrExt, fs::{FileExt, MetadataExt, OpenOptionsExt, PermissionsExt, DirBuilderExt}}};
+use anyhow::Result;
+use fuser::{Filesystem, Request, ReplyEntry, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyOpen, ReplyWrite, ReplyCreate, ReplyStatfs, FileAttr, FileType, MountOption, TimeOrNow, FUSE_ROOT_ID};
+
+use crate::{exceeds_limit, new_filename, Journal, JournalEntry};
+
+// the kernel caches the attributes and the entries this long. short, as the source directory may change under the mount
+const TTL: Duration = Duration::from_secs(1);
+
+// the longest name the kernel passes to a FUSE filesystem, longer than NAME_MAX of the filesystems underneath
+const FUSE_NAME_MAX: u32 = 1024;
+
+// the long names given through the mount, by the shortened names they are stored under. the directories are the
+// relative paths in the source directory, as stored
+#[derive(Debug, Default)]
+pub struct Aliases {
+    short_names: HashMap<(PathBuf, OsString), OsString>,
+    long_names: HashMap<(PathBuf, OsString), OsString>,
+}
+
+impl Aliases {
+    // the shortenings recorded by the earlier mounts of the source directory, whose files are still there
+    pub fn from_journal(entries: &[JournalEntry], src: &Path) -> Self {
+        let mut aliases = Self::default();
+        for entry in entries {
+            let (Some(dir), Some(long), Some(short)) = (entry.src.parent(), entry.src.file_name(), entry.dst.file_name()) else {
+                continue;
+            };
+            let Ok(rel_dir) = dir.strip_prefix(src) else {
+                continue;
+            };
+            if entry.dst.parent() == Some(dir) && entry.dst.symlink_metadata().is_ok() {
+                aliases.insert(rel_dir, long, short);
+            }
+        }
+        aliases
+    }
+
+    pub fn insert(&mut self, dir: &Path, long: &OsStr, short: &OsStr) {
+        // the name was given to another file since
+        if let Some(old_long) = self.long_names.remove(&(dir.to_path_buf(), short.to_os_string())) {
+            self.short_names.remove(&(dir.to_path_buf(), old_long));
+        }
+        self.short_names.insert((dir.to_path_buf(), long.to_os_string()), short.to_os_string());
+        self.long_names.insert((dir.to_path_buf(), short.to_os_string()), long.to_os_string());
+    }
+
+    pub fn short_name(&self, dir: &Path, long: &OsStr) -> Option<&OsStr> {
+        self.short_names.get(&(dir.to_path_buf(), long.to_os_string())).map(OsString::as_os_str)
+    }
+
+    pub fn long_name(&self, dir: &Path, short: &OsStr) -> Option<&OsStr> {
+        self.long_names.get(&(dir.to_path_buf(), short.to_os_string())).map(OsString::as_os_str)
+    }
+
+    // when the stored file is removed or renamed
+    pub fn remove(&mut self, dir: &Path, short: &OsStr) {
+        if let Some(long) = self.long_names.remove(&(dir.to_path_buf(), short.to_os_string())) {
+            self.short_names.remove(&(dir.to_path_buf(), long));
+        }
+    }
+
+    // the aliases in a renamed directory and the directories in it go with it
+    pub fn move_dir(&mut self, from: &Path, to: &Path) {
+        let moved = |map: &mut HashMap<(PathBuf, OsString), OsString>| {
+            let keys = map.keys().filter(|(dir, _)| dir.starts_with(from)).cloned().collect::<Vec<_>>();
+            for (dir, name) in keys {
+                if let Some(value) = map.remove(&(dir.clone(), name.clone())) {
+                    let dir = to.join(dir.strip_prefix(from).expect("filtered by the prefix"));
+                    map.insert((dir, name), value);
+                }
+            }
+        };
+        moved(&mut self.short_names);
+        moved(&mut self.long_names);
+    }
+}
+
+// the inode numbers given to the stored paths, relative to the source directory, the root being empty. the numbers
+// aren't reused nor forgotten while mounted
+#[derive(Debug)]
+struct Inodes {
+    paths: HashMap<u64, PathBuf>,
+    inos: HashMap<PathBuf, u64>,
+    next_ino: u64,
+}
+
+impl Inodes {
+    fn new() -> Self {
+        let mut inodes = Self { paths: HashMap::new(), inos: HashMap::new(), next_ino: FUSE_ROOT_ID + 1 };
+        inodes.paths.insert(FUSE_ROOT_ID, PathBuf::new());
+        inodes.inos.insert(PathBuf::new(), FUSE_ROOT_ID);
+        inodes
+    }
+
+    fn ino(&mut self, path: &Path) -> u64 {
+        if let Some(ino) = self.inos.get(path) {
+            return *ino;
+        }
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.paths.insert(ino, path.to_path_buf());
+        self.inos.insert(path.to_path_buf(), ino);
+        ino
+    }
+
+    fn path(&self, ino: u64) -> Option<PathBuf> {
+        self.paths.get(&ino).cloned()
+    }
+
+    // the paths in a renamed directory keep their numbers
+    fn rename(&mut self, from: &Path, to: &Path) {
+        self.remove(to);
+        let moved = self.inos.keys().filter(|path| path.starts_with(from)).cloned().collect::<Vec<_>>();
+        for path in moved {
+            let ino = self.inos.remove(&path).expect("listed from the keys");
+            let new_path = to.join(path.strip_prefix(from).expect("filtered by the prefix"));
+            self.paths.insert(ino, new_path.clone());
+            self.inos.insert(new_path, ino);
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(ino) = self.inos.remove(path) {
+            self.paths.remove(&ino);
+        }
+    }
+}
+
+// a passthrough of the source directory where a file, directory or symlink created or renamed to a name over the limit
+// is stored under the name this crate shortens it into. the long name still finds it, and is what the directory
+// lists, so an application writing giant names never sees ENAMETOOLONG
+pub struct ShortenFs {
+    src: PathBuf,
+    inodes: Inodes,
+    aliases: Aliases,
+    files: HashMap<u64, fs::File>,
+    next_fh: u64,
+    // (journal, run id), the shortenings of the mount are recorded as one run
+    journal: Option<(Journal, String)>,
+}
+
+impl ShortenFs {
+    pub fn new(src: impl AsRef<Path>, aliases: Aliases, journal: Option<(Journal, String)>) -> Self {
+        Self { src: src.as_ref().to_path_buf(), inodes: Inodes::new(), aliases, files: HashMap::new(), next_fh: 1, journal }
+    }
+
+    // the stored path of a name in a directory, none for a long name which isn't an alias
+    fn stored_path(&self, parent: u64, name: &OsStr) -> Result<PathBuf, i32> {
+        let dir = self.inodes.path(parent).ok_or(libc::ENOENT)?;
+        match self.aliases.short_name(&dir, name) {
+            Some(short) => Ok(dir.join(short)),
+            None if exceeds_limit(Path::new(name), None) => Err(libc::ENOENT),
+            None => Ok(dir.join(name)),
+        }
+    }
+
+    // the stored path for a name to create, and the long name when it's shortened. a name already given keeps its alias
+    fn path_to_create(&self, parent: u64, name: &OsStr) -> Result<(PathBuf, Option<OsString>), i32> {
+        let dir = self.inodes.path(parent).ok_or(libc::ENOENT)?;
+        if let Some(short) = self.aliases.short_name(&dir, name) {
+            return Ok((dir.join(short), None));
+        }
+        if !exceeds_limit(Path::new(name), None) {
+            return Ok((dir.join(name), None));
+        }
+        let short = new_filename(self.src.join(&dir).join(name), None::<&Path>).map_err(|e| {
+            log::error!("Failed to shorten {}: {}", dir.join(name).display(), e);
+            libc::ENAMETOOLONG
+        })?;
+        Ok((dir.join(short), Some(name.to_os_string())))
+    }
+
+    // once the shortened path is created
+    fn add_alias(&mut self, path: &Path, long: &OsStr) {
+        let (Some(dir), Some(short)) = (path.parent(), path.file_name()) else {
+            return;
+        };
+        log::info!("Shortened: {} -> {}", dir.join(long).display(), path.display());
+        if let Some((journal, run_id)) = &self.journal {
+            if let Err(e) = journal.record(run_id, self.src.join(dir).join(long), self.src.join(path), None) {
+                log::warn!("Failed to record in the journal: {}", e);
+            }
+            journal.commit();
+        }
+        self.aliases.insert(dir, long, short);
+    }
+
+    fn remove_alias(&mut self, path: &Path) {
+        if let (Some(dir), Some(short)) = (path.parent(), path.file_name()) {
+            self.aliases.remove(dir, short);
+        }
+    }
+
+    fn attr(&mut self, path: &Path) -> io::Result<FileAttr> {
+        let metadata = fs::symlink_metadata(self.src.join(path))?;
+        Ok(file_attr(self.inodes.ino(path), &metadata))
+    }
+
+    fn entry(&mut self, path: &Path, reply: ReplyEntry) {
+        match self.attr(path) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+}
+
+impl Filesystem for ShortenFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.stored_path(parent, name) {
+            Ok(path) => self.entry(&path, reply),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        let Some(path) = self.inodes.path(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        match self.attr(&path) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn setattr(&mut self, _req: &Request<'_>, ino: u64, mode: Option<u32>, uid: Option<u32>, gid: Option<u32>, size: Option<u64>, atime: Option<TimeOrNow>, mtime: Option<TimeOrNow>, _ctime: Option<SystemTime>, fh: Option<u64>, _crtime: Option<SystemTime>, _chgtime: Option<SystemTime>, _bkuptime: Option<SystemTime>, _flags: Option<u32>, reply: ReplyAttr) {
+        let Some(path) = self.inodes.path(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        let full_path = self.src.join(&path);
+        let result = (|| {
+            if let Some(mode) = mode {
+                fs::set_permissions(&full_path, fs::Permissions::from_mode(mode))?;
+            }
+            if uid.is_some() || gid.is_some() {
+                std::os::unix::fs::lchown(&full_path, uid, gid)?;
+            }
+            if let Some(size) = size {
+                match fh.and_then(|fh| self.files.get(&fh)) {
+                    Some(file) => file.set_len(size)?,
+                    None => fs::OpenOptions::new().write(true).open(&full_path)?.set_len(size)?,
+                }
+            }
+            if atime.is_some() || mtime.is_some() {
+                let time = |time: TimeOrNow| match time {
+                    TimeOrNow::SpecificTime(time) => time,
+                    TimeOrNow::Now => SystemTime::now(),
+                };
+                let mut times = fs::FileTimes::new();
+                if let Some(atime) = atime {
+                    times = times.set_accessed(time(atime));
+                }
+                if let Some(mtime) = mtime {
+                    times = times.set_modified(time(mtime));
+                }
+                fs::File::open(&full_path)?.set_times(times)?;
+            }
+            self.attr(&path)
+        })();
+        match result {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        let Some(path) = self.inodes.path(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        match fs::read_link(self.src.join(path)) {
+            Ok(target) => reply.data(target.as_os_str().as_bytes()),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn mkdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, mode: u32, umask: u32, reply: ReplyEntry) {
+        let (path, long) = match self.path_to_create(parent, name) {
+            Ok(created) => created,
+            Err(errno) => return reply.error(errno),
+        };
+        if let Err(e) = fs::DirBuilder::new().mode(mode & !umask).create(self.src.join(&path)) {
+            return reply.error(errno(&e));
+        }
+        if let Some(long) = long {
+            self.add_alias(&path, &long);
+        }
+        self.entry(&path, reply);
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let path = match self.stored_path(parent, name) {
+            Ok(path) => path,
+            Err(errno) => return reply.error(errno),
+        };
+        match fs::remove_file(self.src.join(&path)) {
+            Ok(()) => {
+                self.remove_alias(&path);
+                self.inodes.remove(&path);
+                reply.ok();
+            },
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let path = match self.stored_path(parent, name) {
+            Ok(path) => path,
+            Err(errno) => return reply.error(errno),
+        };
+        match fs::remove_dir(self.src.join(&path)) {
+            Ok(()) => {
+                self.remove_alias(&path);
+                self.inodes.remove(&path);
+                reply.ok();
+            },
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn symlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, link: &Path, reply: ReplyEntry) {
+        let (path, long) = match self.path_to_create(parent, name) {
+            Ok(created) => created,
+            Err(errno) => return reply.error(errno),
+        };
+        if let Err(e) = std::os::unix::fs::symlink(link, self.src.join(&path)) {
+            return reply.error(errno(&e));
+        }
+        if let Some(long) = long {
+            self.add_alias(&path, &long);
+        }
+        self.entry(&path, reply);
+    }
+
+    fn rename(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr, flags: u32, reply: ReplyEmpty) {
+        // RENAME_NOREPLACE and RENAME_EXCHANGE aren't passed through
+        if flags != 0 {
+            return reply.error(libc::EINVAL);
+        }
+        let from = match self.stored_path(parent, name) {
+            Ok(path) => path,
+            Err(errno) => return reply.error(errno),
+        };
+        let (to, long) = match self.path_to_create(newparent, newname) {
+            Ok(created) => created,
+            Err(errno) => return reply.error(errno),
+        };
+        if let Err(e) = fs::rename(self.src.join(&from), self.src.join(&to)) {
+            return reply.error(errno(&e));
+        }
+        self.remove_alias(&from);
+        match long {
+            Some(long) => self.add_alias(&to, &long),
+            // the file replaced had been stored under that name for a long one
+            None if !exceeds_limit(Path::new(newname), None) => self.remove_alias(&to),
+            None => (),
+        }
+        self.aliases.move_dir(&from, &to);
+        self.inodes.rename(&from, &to);
+        reply.ok();
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        let Some(path) = self.inodes.path(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        match open_options(flags).open(self.src.join(path)) {
+            Ok(file) => {
+                let fh = self.next_fh;
+                self.next_fh += 1;
+                self.files.insert(fh, file);
+                reply.opened(fh, 0);
+            },
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn read(&mut self, _req: &Request<'_>, _ino: u64, fh: u64, offset: i64, size: u32, _flags: i32, _lock_owner: Option<u64>, reply: ReplyData) {
+        let Some(file) = self.files.get(&fh) else {
+            return reply.error(libc::EBADF);
+        };
+        // short only at the end of the file, the kernel takes a short read for it
+        let mut buf = vec![0; size as usize];
+        let mut n_read = 0;
+        while n_read < buf.len() {
+            match file.read_at(&mut buf[n_read..], offset as u64 + n_read as u64) {
+                Ok(0) => break,
+                Ok(n) => n_read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return reply.error(errno(&e)),
+            }
+        }
+        reply.data(&buf[..n_read]);
+    }
+
+    fn write(&mut self, _req: &Request<'_>, _ino: u64, fh: u64, offset: i64, data: &[u8], _write_flags: u32, _flags: i32, _lock_owner: Option<u64>, reply: ReplyWrite) {
+        let Some(file) = self.files.get(&fh) else {
+            return reply.error(libc::EBADF);
+        };
+        match file.write_all_at(data, offset as u64) {
+            Ok(()) => reply.written(data.len() as u32),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn flush(&mut self, _req: &Request<'_>, _ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        reply.ok();
+    }
+
+    fn release(&mut self, _req: &Request<'_>, _ino: u64, fh: u64, _flags: i32, _lock_owner: Option<u64>, _flush: bool, reply: ReplyEmpty) {
+        self.files.remove(&fh);
+        reply.ok();
+    }
+
+    fn fsync(&mut self, _req: &Request<'_>, _ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        let Some(file) = self.files.get(&fh) else {
+            return reply.error(libc::EBADF);
+        };
+        let result = if datasync { file.sync_data() } else { file.sync_all() };
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let Some(dir) = self.inodes.path(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        let read_dir = match fs::read_dir(self.src.join(&dir)) {
+            Ok(read_dir) => read_dir,
+            Err(e) => return reply.error(errno(&e)),
+        };
+        let parent_ino = match dir.parent() {
+            Some(parent) => self.inodes.ino(parent),
+            None => FUSE_ROOT_ID,
+        };
+        let mut entries = vec![(ino, FileType::Directory, OsString::from(".")), (parent_ino, FileType::Directory, OsString::from(".."))];
+        // sorted, so that the offsets stay the same between the calls
+        let mut dir_entries = read_dir.collect::<io::Result<Vec<_>>>().unwrap_or_default();
+        dir_entries.sort_by_key(|entry| entry.file_name());
+        for entry in dir_entries {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let short = entry.file_name();
+            let name = self.aliases.long_name(&dir, &short).map(OsStr::to_os_string).unwrap_or_else(|| short.clone());
+            entries.push((self.inodes.ino(&dir.join(&short)), file_kind(file_type), name));
+        }
+        for (i, (ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            // the buffer is full, the rest comes with the next call
+            if reply.add(ino, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    #[allow(clippy::unnecessary_cast)] // the field types differ by platform
+    fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+        let Ok(c_path) = CString::new(self.src.as_os_str().as_bytes()) else {
+            return reply.error(libc::EINVAL);
+        };
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } < 0 {
+            return reply.error(errno(&io::Error::last_os_error()));
+        }
+        let stat = unsafe { stat.assume_init() };
+        // the long names are taken, so they're not cut by an application asking for the limit first
+        reply.statfs(stat.f_blocks as u64, stat.f_bfree as u64, stat.f_bavail as u64, stat.f_files as u64, stat.f_ffree as u64, stat.f_bsize as u32, FUSE_NAME_MAX, stat.f_frsize as u32);
+    }
+
+    fn create(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, mode: u32, umask: u32, flags: i32, reply: ReplyCreate) {
+        let (path, long) = match self.path_to_create(parent, name) {
+            Ok(created) => created,
+            Err(errno) => return reply.error(errno),
+        };
+        let file = match open_options(flags).create(true).mode(mode & !umask).open(self.src.join(&path)) {
+            Ok(file) => file,
+            Err(e) => return reply.error(errno(&e)),
+        };
+        if let Some(long) = long {
+            self.add_alias(&path, &long);
+        }
+        match self.attr(&path) {
+            Ok(attr) => {
+                let fh = self.next_fh;
+                self.next_fh += 1;
+                self.files.insert(fh, file);
+                reply.created(&TTL, &attr, 0, fh, 0);
+            },
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+}
+
+// until unmounted (fusermount -u), in this thread
+pub fn mount(src: impl AsRef<Path>, mountpoint: impl AsRef<Path>, journal: Option<Journal>) -> Result<()> {
+    let src = std::path::absolute(src.as_ref())?;
+    let aliases = match &journal {
+        Some(journal) => Aliases::from_journal(&journal.entries()?, &src),
+        None => Aliases::default(),
+    };
+    let journal = journal.map(|journal| (journal, crate::new_run_id()));
+    let options = [MountOption::FSName(src.display().to_string()), MountOption::DefaultPermissions];
+    fuser::mount2(ShortenFs::new(&src, aliases, journal), mountpoint, &options)?;
+    Ok(())
+}
+
+fn errno(e: &io::Error) -> i32 {
+    e.raw_os_error().unwrap_or(libc::EIO)
+}
+
+// the access mode and the flags of open(2), O_CREAT and O_EXCL of create included
+fn open_options(flags: i32) -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    match flags & libc::O_ACCMODE {
+        libc::O_WRONLY => options.write(true),
+        libc::O_RDWR => options.read(true).write(true),
+        _ => options.read(true),
+    };
+    options.custom_flags(flags & !libc::O_ACCMODE);
+    options
+}
+
+fn file_kind(file_type: fs::FileType) -> FileType {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_dir() {
+        FileType::Directory
+    } else if file_type.is_symlink() {
+        FileType::Symlink
+    } else if file_type.is_block_device() {
+        FileType::BlockDevice
+    } else if file_type.is_char_device() {
+        FileType::CharDevice
+    } else if file_type.is_fifo() {
+        FileType::NamedPipe
+    } else if file_type.is_socket() {
+        FileType::Socket
+    } else {
+        FileType::RegularFile
+    }
+}
+
+fn file_attr(ino: u64, metadata: &fs::Metadata) -> FileAttr {
+    let time = |secs: i64, nsecs: i64| match u64::try_from(secs) {
+        Ok(secs) => UNIX_EPOCH + Duration::new(secs, nsecs as u32),
+        Err(_) => UNIX_EPOCH,
+    };
+    FileAttr {
+        ino,
+        size: metadata.size(),
+        blocks: metadata.blocks(),
+        atime: time(metadata.atime(), metadata.atime_nsec()),
+        mtime: time(metadata.mtime(), metadata.mtime_nsec()),
+        ctime: time(metadata.ctime(), metadata.ctime_nsec()),
+        crtime: metadata.created().unwrap_or(UNIX_EPOCH),
+        kind: file_kind(metadata.file_type()),
+        perm: (metadata.mode() & 0o7777) as u16,
+        nlink: metadata.nlink() as u32,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        rdev: metadata.rdev() as u32,
+        blksize: metadata.blksize() as u32,
+        flags: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_aliases() {
+        let _ = env_logger::try_init();
+
+        let mut aliases = Aliases::default();
+        let long = OsString::from("a".repeat(300));
+        aliases.insert(Path::new("dir"), &long, OsStr::new("a.txt"));
+        assert_eq!(aliases.short_name(Path::new("dir"), &long), Some(OsStr::new("a.txt")));
+        assert_eq!(aliases.long_name(Path::new("dir"), OsStr::new("a.txt")), Some(long.as_os_str()));
+        assert_eq!(aliases.short_name(Path::new(""), &long), None);
+
+        aliases.move_dir(Path::new("dir"), Path::new("moved/dir"));
+        assert_eq!(aliases.short_name(Path::new("dir"), &long), None);
+        assert_eq!(aliases.short_name(Path::new("moved/dir"), &long), Some(OsStr::new("a.txt")));
+
+        // the short name given to another long name
+        let other = OsString::from("b".repeat(300));
+        aliases.insert(Path::new("moved/dir"), &other, OsStr::new("a.txt"));
+        assert_eq!(aliases.short_name(Path::new("moved/dir"), &long), None);
+        aliases.remove(Path::new("moved/dir"), OsStr::new("a.txt"));
+        assert_eq!(aliases.short_name(Path::new("moved/dir"), &other), None);
+    }
+
+    #[test]
+    fn test_aliases_from_journal() {
+        let _ = env_logger::try_init();
+
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path();
+        fs::create_dir(src.join("sub")).unwrap();
+        fs::write(src.join("sub/short.txt"), "").unwrap();
+        let long = "l".repeat(300);
+        let entry = |src: PathBuf, dst: PathBuf| JournalEntry { run_id: "1-1".to_string(), time: 1, src, dst, checksum: None, config_hash: None };
+        let entries = [
+            entry(src.join("sub").join(&long), src.join("sub/short.txt")),
+            // gone since
+            entry(src.join(&long), src.join("gone.txt")),
+            // of another directory
+            entry(PathBuf::from("/elsewhere").join(&long), PathBuf::from("/elsewhere/short.txt")),
+        ];
+        let aliases = Aliases::from_journal(&entries, src);
+        assert_eq!(aliases.short_name(Path::new("sub"), OsStr::new(&long)), Some(OsStr::new("short.txt")));
+        assert_eq!(aliases.short_name(Path::new(""), OsStr::new(&long)), None);
+        assert_eq!(aliases.long_names.len(), 1);
+    }
+
+    #[test]
+    fn test_inodes() {
+        let _ = env_logger::try_init();
+
+        let mut inodes = Inodes::new();
+        assert_eq!(inodes.path(FUSE_ROOT_ID), Some(PathBuf::new()));
+        let dir = inodes.ino(Path::new("dir"));
+        let file = inodes.ino(Path::new("dir/file"));
+        assert_eq!(inodes.ino(Path::new("dir")), dir);
+        inodes.rename(Path::new("dir"), Path::new("renamed"));
+        assert_eq!(inodes.path(dir), Some(PathBuf::from("renamed")));
+        assert_eq!(inodes.path(file), Some(PathBuf::from("renamed/file")));
+        inodes.remove(Path::new("renamed/file"));
+        assert_eq!(inodes.path(file), None);
+    }
+}
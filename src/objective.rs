@@ -0,0 +1,248 @@
+use std::{collections::{HashMap, HashSet}, rc::Rc};
+
+// a packing of a name whose tags don't all fit, as it's scored
+#[derive(Debug, Clone)]
+pub struct Packing<'a> {
+    // the candidate name, with the extension
+    pub name: &'a str,
+    // the title and the tags of the candidate name, normalized. a tag cut short is one of them
+    pub components: &'a HashSet<String>,
+    // the ones of `components` which are whole components of the old name
+    pub kept: &'a [String],
+}
+
+// what the packer makes the most of when the tags of a name don't all fit. the shortest-first packing is scored first,
+// then the alternatives leaving out one of the tags it keeps, which makes room for the ones it drops. the highest score
+// wins, a tie keeps the earlier packing
+pub trait PackingObjective: std::fmt::Debug {
+    fn score(&self, packing: &Packing) -> usize;
+
+    // false when no alternative can score higher than the shortest-first packing, then they aren't packed at all
+    fn tries_alternatives(&self) -> bool {
+        true
+    }
+
+    // what keeping the normalized tag whole adds to the score, when the score is such a sum. `PackingMode::Exact`
+    // maximizes the sum, an objective without the values is packed greedily
+    fn tag_value(&self, _tag: &str) -> Option<usize> {
+        None
+    }
+}
+
+// as many whole tags as possible, which the shortest-first packing keeps already
+#[derive(Debug, Default)]
+pub struct ShortestFirst;
+
+impl PackingObjective for ShortestFirst {
+    fn score(&self, packing: &Packing) -> usize {
+        packing.kept.len()
+    }
+
+    fn tries_alternatives(&self) -> bool {
+        false
+    }
+
+    fn tag_value(&self, _tag: &str) -> Option<usize> {
+        Some(1)
+    }
+}
+
+// as many bytes of the whole tags as possible, one long tag over two short ones
+#[derive(Debug, Default)]
+pub struct BytesKept;
+
+impl PackingObjective for BytesKept {
+    fn score(&self, packing: &Packing) -> usize {
+        packing.kept.iter().map(|component| component.len()).sum()
+    }
+
+    fn tag_value(&self, tag: &str) -> Option<usize> {
+        Some(tag.len())
+    }
+}
+
+// the sum of the priorities of the whole tags kept, a tag without a priority counts 1
+#[derive(Debug, Default)]
+pub struct PriorityWeighted {
+    // normalized tags
+    priorities: HashMap<String, usize>,
+}
+
+impl PriorityWeighted {
+    pub fn new(priorities: HashMap<String, usize>) -> Self {
+        Self { priorities }
+    }
+}
+
+impl PackingObjective for PriorityWeighted {
+    fn score(&self, packing: &Packing) -> usize {
+        packing.kept.iter().map(|component| self.priorities.get(component).copied().unwrap_or(1)).sum()
+    }
+
+    fn tag_value(&self, tag: &str) -> Option<usize> {
+        Some(self.priorities.get(tag).copied().unwrap_or(1))
+    }
+}
+
+// how many of the components of the name none of the siblings in the destination directory has, which tell the name
+// apart from them. nothing is compared when the directory isn't listed, then every packing is as distinct
+#[derive(Debug, Default)]
+pub struct Distinctiveness {
+    // normalized
+    sibling_components: HashSet<String>,
+}
+
+impl Distinctiveness {
+    pub fn new(sibling_components: HashSet<String>) -> Self {
+        Self { sibling_components }
+    }
+}
+
+impl PackingObjective for Distinctiveness {
+    fn score(&self, packing: &Packing) -> usize {
+        packing.components.iter().filter(|component| !self.sibling_components.contains(*component)).count()
+    }
+
+    fn tag_value(&self, tag: &str) -> Option<usize> {
+        Some(if self.sibling_components.contains(tag) { 0 } else { 1 })
+    }
+}
+
+// the number of names each tag is in, among the names learned so far
+#[derive(Debug, Clone, Default)]
+pub struct TagFrequencies {
+    // normalized tags
+    frequencies: HashMap<String, usize>,
+    n_names: usize,
+}
+
+impl TagFrequencies {
+    // the normalized tags of a name, each counted once
+    pub fn add_name(&mut self, tags: HashSet<String>) {
+        for tag in tags {
+            *self.frequencies.entry(tag).or_default() += 1;
+        }
+        self.n_names += 1;
+    }
+
+    pub fn n_names_without(&self, tag: &str) -> usize {
+        self.n_names - self.frequencies.get(tag).copied().unwrap_or(0)
+    }
+}
+
+// the sum of the numbers of the learned names without each whole tag kept, so that the tags most names have are dropped
+// first and the rare ones, which tell the file apart, are kept (--smart-drop)
+#[derive(Debug, Default)]
+pub struct Rarity {
+    frequencies: Rc<TagFrequencies>,
+}
+
+impl Rarity {
+    pub fn new(frequencies: Rc<TagFrequencies>) -> Self {
+        Self { frequencies }
+    }
+}
+
+impl PackingObjective for Rarity {
+    fn score(&self, packing: &Packing) -> usize {
+        packing.kept.iter().map(|component| self.frequencies.n_names_without(component)).sum()
+    }
+
+    fn tag_value(&self, tag: &str) -> Option<usize> {
+        Some(self.frequencies.n_names_without(tag))
+    }
+}
+
+// the objectives selected with --objective
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Objective {
+    #[default]
+    ShortestFirst,
+    BytesKept,
+    // by `tag_priorities` of the config
+    Priority,
+    Distinctiveness,
+    // by the tags of the other names of the batch, see --smart-drop
+    Rarity,
+}
+
+// how the tags to keep are chosen, selected with --packing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PackingMode {
+    // shortest first, and the alternatives the objective tries. the first tag which doesn't fit whole is cut
+    #[default]
+    Greedy,
+    // the whole tags of the largest sum of the values of the objective, by dynamic programming. no tag is cut, the
+    // bytes they leave are left
+    Exact,
+}
+
+// the indices of the (bytes, value) items of the largest sum of values in `n_bytes`, at most `n_max_items` of them.
+// of the same sum, the most items. a 0-1 knapsack, the budgets of filenames are small enough for the whole table
+pub(crate) fn exact_tags(items: &[(usize, usize)], n_bytes: usize, n_max_items: usize) -> Vec<usize> {
+    let n_max_items = n_max_items.min(items.len());
+    // (sum of values, number of items) of at most k items in at most b bytes
+    let mut best = vec![vec![(0, 0); n_bytes + 1]; n_max_items + 1];
+    let mut taken = vec![vec![vec![false; n_bytes + 1]; n_max_items + 1]; items.len()];
+    for (i, &(n_item_bytes, value)) in items.iter().enumerate() {
+        for k in (1..=n_max_items).rev() {
+            for b in (n_item_bytes..=n_bytes).rev() {
+                let (sum, n_items) = best[k - 1][b - n_item_bytes];
+                let with_item = (sum + value, n_items + 1);
+                if best[k][b] < with_item {
+                    best[k][b] = with_item;
+                    taken[i][k][b] = true;
+                }
+            }
+        }
+    }
+    let (mut k, mut b) = (n_max_items, n_bytes);
+    let mut indices = Vec::new();
+    for i in (0..items.len()).rev() {
+        if taken[i][k][b] {
+            indices.push(i);
+            k -= 1;
+            b -= items[i].0;
+        }
+    }
+    indices.reverse();
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_scores() {
+        let _ = env_logger::try_init();
+
+        let components = ["t", "a1", "long", "un"].map(String::from).into_iter().collect::<HashSet<_>>();
+        let kept = ["t", "a1", "long"].map(String::from);
+        let packing = Packing { name: "t.a1.long.un.mkv", components: &components, kept: &kept };
+        assert_eq!(ShortestFirst.score(&packing), 3);
+        assert_eq!(BytesKept.score(&packing), 7);
+        assert_eq!(PriorityWeighted::new(HashMap::from([("long".to_string(), 5)])).score(&packing), 7);
+        assert_eq!(Distinctiveness::new(["t", "a1"].map(String::from).into_iter().collect()).score(&packing), 2);
+
+        let mut frequencies = TagFrequencies::default();
+        frequencies.add_name(["a1", "long"].map(String::from).into_iter().collect());
+        frequencies.add_name(["a1"].map(String::from).into_iter().collect());
+        frequencies.add_name(HashSet::new());
+        assert_eq!(frequencies.n_names_without("a1"), 1);
+        assert_eq!(Rarity::new(Rc::new(frequencies)).score(&packing), 3 + 1 + 2);
+    }
+
+    #[test]
+    fn test_exact_tags() {
+        let _ = env_logger::try_init();
+
+        // greedy by bytes would take 0 and 1
+        assert_eq!(exact_tags(&[(2, 2), (3, 3), (5, 5)], 7, usize::MAX), vec![0, 2]);
+        assert_eq!(exact_tags(&[(2, 1), (3, 1), (5, 4)], 7, usize::MAX), vec![0, 2]);
+        assert_eq!(exact_tags(&[(1, 1), (1, 1), (1, 1)], 7, 2), vec![0, 1]);
+        assert_eq!(exact_tags(&[(8, 1)], 7, usize::MAX), Vec::<usize>::new());
+        assert_eq!(exact_tags(&[], 7, usize::MAX), Vec::<usize>::new());
+    }
+}
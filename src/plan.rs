@@ -0,0 +1,1050 @@
+use std::{path::{Path, PathBuf}, fs, io::{self, Read, BufReader}, ffi::OsString, collections::{HashSet, HashMap}, rc::Rc};
+use anyhow::Result;
+
+use crate::{Error, new_filename_impl, new_filename_listed, claim_path, OutputEncoding, Profile, Rules, RuleOverrides, ExistenceBackend, Objective, PackingMode, TagFrequencies, split_name, N_FILENAME_BYTES};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PlanKind {
+    #[default]
+    Rename,
+    // creates the directory dst, planned before the entries moving into it.
+    // src is the directory the first of them comes from, whose mode and owner may be copied
+    CreateDir,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanEntry {
+    pub kind: PlanKind,
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    // dst already exists with the same content as src
+    pub duplicate: bool,
+    // the first choice of name was taken, so dst got a counter
+    pub conflict: bool,
+}
+
+impl PlanEntry {
+    // the path stays as it is
+    pub fn unchanged(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        Self { kind: PlanKind::Rename, src: path.to_path_buf(), dst: path.to_path_buf(), duplicate: false, conflict: false }
+    }
+
+    pub fn create_dir(path: impl AsRef<Path>, src_dir: impl AsRef<Path>) -> Self {
+        Self { kind: PlanKind::CreateDir, src: src_dir.as_ref().to_path_buf(), dst: path.as_ref().to_path_buf(), duplicate: false, conflict: false }
+    }
+}
+
+// the rest of a sidecar name after the stem, `.en.forced.srt`, longer ones are files of their own
+const N_MAX_SIDECAR_EXTENSION_BYTES: usize = 32;
+
+// sidecars or parts of a set, with the rest of their names after the stem they share
+type StemGroup = Vec<(PathBuf, String)>;
+
+// the names of the parts in a directory by their stem and their kind of set, see `part_stem`
+type PartGroups = HashMap<(String, String), Vec<String>>;
+
+// plans the renames of many files before applying any of them.
+// the destinations planned so far are reserved, so files which are shortened into the same name
+// in one batch get distinct names, even though none of them exists yet.
+#[derive(Debug, Default)]
+pub struct Planner {
+    claim: bool,
+    dedupe: bool,
+    reversible: bool,
+    squeeze: bool,
+    limit: Option<usize>,
+    shrink_to: Option<usize>,
+    encoding: Option<OutputEncoding>,
+    profile: Option<Profile>,
+    overrides: RuleOverrides,
+    reserved: HashSet<PathBuf>,
+    claimed: Vec<PathBuf>,
+    // the first choices of the destinations which got a counter
+    first_choices: HashMap<PathBuf, PathBuf>,
+    // the missing destination directories planned so far, and the ones not taken by `take_dir_entries` yet
+    planned_dirs: HashSet<PathBuf>,
+    dir_entries: Vec<PlanEntry>,
+    sidecars: bool,
+    // the renames of the sidecars of the entries planned since the last `take_sidecar_entries`, and all the sidecars planned so far.
+    // the parts of a multi-part set after the first one are planned as its sidecars
+    sidecar_entries: Vec<PlanEntry>,
+    planned_sidecars: HashSet<PathBuf>,
+    backend: Option<Rc<dyn ExistenceBackend>>,
+    // the names in the destination directories, read once for the counters. the names planned since are in `reserved`
+    dst_listings: HashMap<PathBuf, Rc<Vec<OsString>>>,
+    // the parts of multi-part sets in the source directories, read once for all the parts
+    part_listings: HashMap<PathBuf, Rc<PartGroups>>,
+    low_memory: bool,
+    objective: Objective,
+    packing: PackingMode,
+    tag_frequencies: Rc<TagFrequencies>,
+    keep_title_end: bool,
+}
+
+impl Planner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // reserves the planned destinations on the filesystem too, see `claim_new_filename`
+    pub fn claim(mut self, claim: bool) -> Self {
+        self.claim = claim;
+        self
+    }
+
+    // when a destination candidate is an existing file with the same content as the source,
+    // plans it as a duplicate instead of looking for another name
+    pub fn dedupe(mut self, dedupe: bool) -> Self {
+        self.dedupe = dedupe;
+        self
+    }
+
+    // shortens with `reversible_filename` instead, a taken destination is an error since a counter would break the decoding
+    #[cfg(feature = "reversible")]
+    pub fn reversible(mut self, reversible: bool) -> Self {
+        self.reversible = reversible;
+        self
+    }
+
+    // shortens with `squeeze_filename` instead, taken destinations are errors as with `reversible`
+    #[cfg(feature = "reversible")]
+    pub fn squeeze(mut self, squeeze: bool) -> Self {
+        self.squeeze = squeeze;
+        self
+    }
+
+    // shortens into names of at most the given percentage of the limit, leaving headroom for suffixes
+    // which sync tools append later (e.g. `.sync-conflict-...`). reversible and squeezed names always use the whole limit
+    pub fn shrink_to(mut self, percent: usize) -> Self {
+        self.shrink_to = Some(percent.min(100));
+        self
+    }
+
+    // shortens into names of at most the given bytes instead of the limit of Linux, e.g. for object stores with their own limits
+    pub fn limit(mut self, n_filename_bytes: usize) -> Self {
+        self.limit = Some(n_filename_bytes);
+        self
+    }
+
+    // writes the shortened names in a legacy encoding, counting the limit in its bytes
+    pub fn output_encoding(mut self, encoding: OutputEncoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    // restricts the names to the characters and the lengths of the profile, e.g. ISO9660 for burning the tree to a disc
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    // ignored tags and conversions merged over the config for this run
+    pub fn overrides(mut self, overrides: RuleOverrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    // renames the sidecars (`video.srt`, `video.en.srt`) with the file of the same stem (`video.mkv`), to the same new stem.
+    // the name is chosen so that none of the sidecars conflicts, and leaves room for their longer extensions
+    pub fn sidecars(mut self, sidecars: bool) -> Self {
+        self.sidecars = sidecars;
+        self
+    }
+
+    // what the packing of the tags makes the most of when they don't all fit, the number of tags by default.
+    // `Objective::Distinctiveness` needs the listing of the directory, see `low_memory`
+    pub fn objective(mut self, objective: Objective) -> Self {
+        self.objective = objective;
+        self
+    }
+
+    // counts the names of the paths each tag is in, for `Objective::Rarity`. the counts of the batches add up, so that
+    // later batches are planned with what was learned from the earlier ones too
+    pub fn learn_tag_frequencies(&mut self, paths: &[PathBuf]) {
+        let rules = Rules::load_with(&self.overrides);
+        let frequencies = Rc::make_mut(&mut self.tag_frequencies);
+        for path in paths {
+            if let Some(name) = path.file_name() {
+                let (_, tags) = split_name(&rules.fold(&name.to_string_lossy()), &rules);
+                frequencies.add_name(tags.into_iter().collect());
+            }
+        }
+    }
+
+    // a name taken by a file whose title begins the same gets the end of its title instead of a counter, when it fits
+    pub fn keep_title_end(mut self, keep_title_end: bool) -> Self {
+        self.keep_title_end = keep_title_end;
+        self
+    }
+
+    // how the tags are chosen for the objective, see `PackingMode`
+    pub fn packing(mut self, packing: PackingMode) -> Self {
+        self.packing = packing;
+        self
+    }
+
+    // no listing of the destination directories is kept, the counters are probed one by one instead
+    pub fn low_memory(mut self, low_memory: bool) -> Self {
+        self.low_memory = low_memory;
+        self
+    }
+
+    // checks the existence of the destinations on the backend instead of the local filesystem, for planning the names
+    // of a remote destination. `claim` and `dedupe` still work on the local filesystem only
+    pub fn backend(mut self, backend: impl ExistenceBackend + 'static) -> Self {
+        self.backend = Some(Rc::new(backend));
+        self
+    }
+
+    pub fn plan(&mut self, path: impl AsRef<Path>, dst_dir: Option<impl AsRef<Path>>) -> Result<PlanEntry> {
+        let claim = self.claim;
+        let backend = self.backend.clone();
+        self.plan_impl(path, dst_dir, |p| match &backend {
+            Some(backend) => backend.exists(p).map(|exists| !exists),
+            None if claim => claim_path(p),
+            None => Ok(!p.exists()),
+        }, |src, dst| {
+            match is_duplicate(src, dst) {
+                Ok(is_duplicate) => is_duplicate,
+                Err(e) => {
+                    log::warn!("Failed to compare files: {} and {}: {}", src.display(), dst.display(), e);
+                    false
+                },
+            }
+        })
+    }
+
+    // the directories to create for the entries planned since the last call, outermost first.
+    // they have to be applied before those entries, nothing is created while planning (except with `claim`)
+    pub fn take_dir_entries(&mut self) -> Vec<PlanEntry> {
+        std::mem::take(&mut self.dir_entries)
+    }
+
+    // the renames of the sidecars of the entries planned since the last call, to be applied after those entries
+    pub fn take_sidecar_entries(&mut self) -> Vec<PlanEntry> {
+        std::mem::take(&mut self.sidecar_entries)
+    }
+
+    // leaves out the sidecars whose file of the same stem is in the paths too, they are planned with that file.
+    // so are the parts of a multi-part set but the first one
+    pub fn without_sidecars(&self, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        let mut part_stems = HashSet::new();
+        let paths = paths.into_iter().filter(|path| {
+            let filename = path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+            match part_stem(&filename) {
+                Some((stem, set)) => part_stems.insert((path.with_file_name(stem), set.to_ascii_lowercase())),
+                None => true,
+            }
+        }).collect::<Vec<_>>();
+        if !self.sidecars {
+            return paths;
+        }
+        let extensions = Rules::load().sidecar_extensions;
+        let stems = paths.iter().filter_map(|path| {
+            let stem = sidecar_stem(&path.file_name()?.to_string_lossy(), &extensions)?.to_string();
+            Some(path.with_file_name(stem))
+        }).collect::<HashSet<_>>();
+        paths.into_iter().filter(|path| {
+            let Some(filename) = path.file_name().map(|f| f.to_string_lossy().to_string()) else {
+                return true;
+            };
+            // `video.en.srt` is of `video.en` or `video`
+            let is_sidecar = filename.rsplit_once('.').is_some_and(|(_, ext)| extensions.contains(&ext.to_lowercase()))
+                && filename.match_indices('.').any(|(i, _)| {
+                    0 < i && filename.len() - i <= N_MAX_SIDECAR_EXTENSION_BYTES && stems.contains(&path.with_file_name(&filename[..i]))
+                });
+            !is_sidecar
+        }).collect()
+    }
+
+    // whether the name of the destination fits what the names are shortened into: the limit, the profile and the
+    // percentage of --shrink-to. for names given by hand
+    pub fn fits(&self, dst: &Path) -> bool {
+        let Some(filename) = dst.file_name() else {
+            return true;
+        };
+        let rules = self.rules(dst, dst.parent());
+        match filename.to_str() {
+            Some(filename) => rules.fits(filename),
+            None => filename.len() <= rules.n_filename_bytes,
+        }
+    }
+
+    // the destinations planned so far, for an application creating files itself in the same directories
+    // to stay clear of the planned renames
+    pub fn reserved_names(&self) -> &HashSet<PathBuf> {
+        &self.reserved
+    }
+
+    // groups of planned files which got the same name apart from the counter, which means their names differed only by
+    // the tags cut off by the shortening. they are likely versions or duplicates of each other, worth a look before
+    // the distinguishing tags are gone. groups are in the order of their first entries.
+    pub fn duplicate_clusters(&self, plan: &[PlanEntry]) -> Vec<Vec<PlanEntry>> {
+        let mut keys = Vec::new();
+        let mut clusters: HashMap<&Path, Vec<PlanEntry>> = HashMap::new();
+        for entry in plan {
+            let key = self.first_choices.get(&entry.dst).unwrap_or(&entry.dst).as_path();
+            if !clusters.contains_key(key) {
+                keys.push(key);
+            }
+            clusters.entry(key).or_default().push(entry.clone());
+        }
+        keys.into_iter().filter_map(|key| clusters.remove(key)).filter(|cluster| {
+            1 < cluster.len() && cluster.iter().any(|entry| entry.conflict)
+        }).collect()
+    }
+
+    // removes the placeholders of the claimed destinations, for when the plan is abandoned
+    pub fn release_claims(&mut self) {
+        release_paths(&self.claimed);
+        self.claimed.clear();
+    }
+
+    // dependency injection for testing, `take_path` returns whether the path is available (and now taken)
+    fn plan_impl(&mut self, path: impl AsRef<Path>, dst_dir: Option<impl AsRef<Path>>, take_path: impl FnMut(&Path) -> io::Result<bool>, is_duplicate: impl FnMut(&Path, &Path) -> bool) -> Result<PlanEntry> {
+        let path = path.as_ref();
+        let dst_dir = dst_dir.map(|p| p.as_ref().to_path_buf());
+        let missing_dirs = match &dst_dir {
+            Some(dst_dir) => self.missing_dirs(dst_dir),
+            None => Vec::new(),
+        };
+        if self.claim {
+            if let Some(dst_dir) = &dst_dir {
+                fs::create_dir_all(dst_dir)?;
+            }
+        }
+
+        // never set without the reversible feature
+        let parts = if self.reversible || self.squeeze { None } else { self.find_parts(path) };
+        let entry = match parts {
+            #[cfg(feature = "reversible")]
+            _ if self.reversible || self.squeeze => self.plan_reversible(path, dst_dir, take_path)?,
+            Some((stem, parts)) => self.plan_parts(path, dst_dir, &stem, parts, take_path, is_duplicate)?,
+            None => self.plan_shortened(path, dst_dir, take_path, is_duplicate)?,
+        };
+
+        let src_dir = match path.parent() {
+            Some(parent) if parent != Path::new("") => parent,
+            _ => Path::new("."),
+        };
+        for dir in missing_dirs {
+            self.planned_dirs.insert(dir.clone());
+            self.dir_entries.push(PlanEntry::create_dir(dir, src_dir));
+        }
+        Ok(entry)
+    }
+
+    // the directories from the outermost one which neither exist nor are planned yet
+    fn missing_dirs(&self, dir: &Path) -> Vec<PathBuf> {
+        let mut missing_dirs = Vec::new();
+        let mut dir = dir;
+        while !dir.as_os_str().is_empty() && !self.planned_dirs.contains(dir) && !path_exists(&self.backend, dir).unwrap_or(false) {
+            missing_dirs.push(dir.to_path_buf());
+            match dir.parent() {
+                Some(parent) => dir = parent,
+                None => break,
+            }
+        }
+        missing_dirs.reverse();
+        missing_dirs
+    }
+
+    // the limit of the names in the destination directory, before --shrink-to
+    fn n_max_filename_bytes(&self, path: &Path, dst_dir: Option<&Path>) -> usize {
+        let n_filename_bytes = self.limit.or(self.profile.map(|p| p.n_filename_units())).unwrap_or(N_FILENAME_BYTES);
+        // what the directory leaves of the limit of the whole path, with the separator
+        match self.profile.and_then(|p| Some(p).zip(p.n_max_path_units())) {
+            Some((profile, n_max_path_units)) => {
+                let dir = dst_dir.or(path.parent()).unwrap_or(Path::new(""));
+                let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+                let n_dir_units = dir.to_string_lossy().chars().map(|c| profile.char_len(c)).sum::<usize>() + 1;
+                n_filename_bytes.min(n_max_path_units.saturating_sub(n_dir_units))
+            },
+            None => n_filename_bytes,
+        }
+    }
+
+    fn rules(&self, path: &Path, dst_dir: Option<&Path>) -> Rules {
+        Rules {
+            n_filename_bytes: self.n_max_filename_bytes(path, dst_dir) * self.shrink_to.unwrap_or(100) / 100,
+            encoding: self.encoding,
+            profile: self.profile,
+            objective: self.objective,
+            packing: self.packing,
+            tag_frequencies: self.tag_frequencies.clone(),
+            keep_title_end: self.keep_title_end,
+            ..Rules::load_for(path, &self.overrides)
+        }
+    }
+
+    fn dst_names(&mut self, dir: &Path) -> Option<Rc<Vec<OsString>>> {
+        // every candidate has to be probed for duplicates, and a remote destination isn't listed here
+        if self.dedupe || self.backend.is_some() || self.low_memory {
+            return None;
+        }
+        if let Some(names) = self.dst_listings.get(dir) {
+            return Some(names.clone());
+        }
+        let names = Rc::new(fs::read_dir(dir).ok()?.filter_map(|entry| entry.ok().map(|entry| entry.file_name())).collect::<Vec<_>>());
+        self.dst_listings.insert(dir.to_path_buf(), names.clone());
+        Some(names)
+    }
+
+    // the stem and all the parts of the set the file is a part of, with the rest of their names (`.part1.rar`).
+    // none when there's no other part
+    fn find_parts(&mut self, path: &Path) -> Option<(String, StemGroup)> {
+        let filename = path.file_name()?.to_str()?;
+        let (stem, set) = part_stem(filename)?;
+        let dir = match path.parent() {
+            Some(parent) if parent != Path::new("") => parent,
+            _ => Path::new("."),
+        };
+        let groups = match self.part_listings.get(dir) {
+            Some(groups) => groups.clone(),
+            None => {
+                let groups = Rc::new(read_part_groups(dir));
+                if !self.low_memory {
+                    self.part_listings.insert(dir.to_path_buf(), groups.clone());
+                }
+                groups
+            },
+        };
+        let names = groups.get(&(stem.to_string(), set.to_ascii_lowercase()))?;
+        if names.len() < 2 {
+            return None;
+        }
+        let parts = names.iter().map(|name| (path.with_file_name(name), name[stem.len()..].to_string())).collect();
+        Some((stem.to_string(), parts))
+    }
+
+    fn plan_shortened(&mut self, path: &Path, dst_dir: Option<PathBuf>, mut take_path: impl FnMut(&Path) -> io::Result<bool>, mut is_duplicate: impl FnMut(&Path, &Path) -> bool) -> Result<PlanEntry> {
+        // a remote destination which doesn't need unique names (google drive) gets the names without counters.
+        // a local one still does, whatever is synced from it
+        if self.backend.is_some() && self.profile.is_some_and(|p| p.allows_duplicate_names()) {
+            let new_filename = new_filename_impl(path, dst_dir.as_ref(), &self.rules(path, dst_dir.as_deref()), |_| false)?;
+            let dst = match dst_dir {
+                Some(dst_dir) => dst_dir.join(new_filename),
+                None => path.with_file_name(new_filename),
+            };
+            return Ok(PlanEntry { kind: PlanKind::Rename, src: path.to_path_buf(), dst, duplicate: false, conflict: false });
+        }
+
+        let dst_names = match &dst_dir {
+            Some(dst_dir) => self.dst_names(dst_dir),
+            None => self.dst_names(path.parent().filter(|parent| *parent != Path::new("")).unwrap_or(Path::new("."))),
+        };
+        let reserved = &self.reserved;
+        let dedupe = self.dedupe;
+        let mut taken = None;
+        let mut take_error = None;
+        let mut duplicate = false;
+        let mut conflict = false;
+        let mut first_choice = None;
+        let mut rules = self.rules(path, dst_dir.as_deref());
+        let (ext, mut sidecars) = if self.sidecars { find_sidecars(path, &rules.sidecar_extensions)? } else { (String::new(), Vec::new()) };
+        // `video.srt` goes with `video.mkv` or `video.mp4`, whichever is planned first
+        sidecars.retain(|(sidecar, _)| !self.planned_sidecars.contains(sidecar));
+        // the longest extension of the sidecars has to fit too
+        let n_extra_bytes = sidecars.iter().map(|(_, sidecar_ext)| rules.n_bytes(sidecar_ext).saturating_sub(rules.n_bytes(&ext))).max().unwrap_or(0);
+        rules.n_filename_bytes = rules.n_filename_bytes.saturating_sub(n_extra_bytes);
+        let sidecar_src_set = sidecars.iter().map(|(sidecar, _)| sidecar.clone()).collect::<HashSet<_>>();
+        let backend = &self.backend;
+        let claim = self.claim;
+        let new_filename = new_filename_listed(path, dst_dir.as_ref(), &rules, dst_names.as_ref().map(|names| names.as_slice()), |p| {
+            if first_choice.is_none() {
+                first_choice = Some(p.to_path_buf());
+            }
+            if reserved.contains(p) {
+                conflict = true;
+                return true;
+            }
+            // the group is renamed as a whole, so a sidecar which can't take the stem rules out the name
+            let sidecar_taken = sidecars.iter().any(|(_, sidecar_ext)| {
+                let sidecar_dst = sidecar_path(p, &ext, sidecar_ext);
+                reserved.contains(&sidecar_dst) || (!sidecar_src_set.contains(&sidecar_dst) && match path_exists(backend, &sidecar_dst) {
+                    Ok(exists) => exists,
+                    Err(e) => {
+                        take_error.get_or_insert(Error::ExistenceCheckFailed(sidecar_dst, e));
+                        false
+                    },
+                })
+            });
+            if sidecar_taken {
+                conflict = true;
+                return true;
+            }
+            match take_path(p) {
+                Ok(true) => {
+                    taken = Some(p.to_path_buf());
+                    false
+                },
+                Ok(false) if dedupe && is_duplicate(path, p) => {
+                    duplicate = true;
+                    false
+                },
+                Ok(false) => {
+                    conflict = true;
+                    true
+                },
+                Err(e) => {
+                    // stop probing, the error is reported below
+                    take_error = Some(if claim { Error::ClaimFailed(p.to_path_buf(), e) } else { Error::ExistenceCheckFailed(p.to_path_buf(), e) });
+                    false
+                },
+            }
+        })?;
+
+        if let Some(e) = take_error {
+            return Err(e.into());
+        }
+        if self.claim {
+            self.claimed.extend(taken);
+        }
+
+        let new_filename = match self.encoding {
+            Some(encoding) => encoding.encode(&new_filename),
+            None => new_filename.into(),
+        };
+        let dst = if let Some(dst_dir) = dst_dir {
+            dst_dir.join(&new_filename)
+        } else {
+            path.with_file_name(&new_filename)
+        };
+        self.reserved.insert(dst.clone());
+        if let (true, Some(first_choice)) = (conflict, first_choice) {
+            self.first_choices.insert(dst.clone(), first_choice);
+        }
+        // the sidecars of a duplicate stay, the file they belong to is already there
+        if !duplicate {
+            for (sidecar, sidecar_ext) in sidecars {
+                let sidecar_dst = sidecar_path(&dst, &ext, &sidecar_ext);
+                self.reserved.insert(sidecar_dst.clone());
+                self.planned_sidecars.insert(sidecar.clone());
+                self.sidecar_entries.push(PlanEntry { kind: PlanKind::Rename, src: sidecar, dst: sidecar_dst, duplicate: false, conflict });
+            }
+        }
+
+        Ok(PlanEntry { kind: PlanKind::Rename, src: path.to_path_buf(), dst, duplicate, conflict })
+    }
+
+    // all the parts get the same new stem, otherwise extracting tools wouldn't find the rest of the set.
+    // the stem is shortened on its own, leaving room for the longest part suffix. a stem is taken when any of the parts
+    // is, and the set is a duplicate only when every part is one
+    fn plan_parts(&mut self, path: &Path, dst_dir: Option<PathBuf>, stem: &str, parts: StemGroup, mut take_path: impl FnMut(&Path) -> io::Result<bool>, mut is_duplicate: impl FnMut(&Path, &Path) -> bool) -> Result<PlanEntry> {
+        let mut rules = self.rules(path, dst_dir.as_deref());
+        let n_suffix_bytes = parts.iter().map(|(_, suffix)| rules.n_bytes(suffix)).max().unwrap_or(0);
+        rules.n_filename_bytes = rules.n_filename_bytes.saturating_sub(n_suffix_bytes);
+
+        let reserved = &self.reserved;
+        let part_set = parts.iter().map(|(part, _)| part.clone()).collect::<HashSet<_>>();
+        let dedupe = self.dedupe;
+        let claim = self.claim;
+        let mut taken = Vec::new();
+        let mut conflict = false;
+        let mut duplicate = false;
+        let mut take_error = None;
+        let new_stem = new_filename_impl(path.with_file_name(stem), dst_dir.as_ref(), &rules, |p| {
+            let part_dsts = parts.iter().map(|(part, suffix)| (part, with_suffix(p, suffix))).collect::<Vec<_>>();
+            if part_dsts.iter().any(|(_, part_dst)| reserved.contains(part_dst)) {
+                conflict = true;
+                return true;
+            }
+            let mut taken_for_stem = Vec::new();
+            let mut n_probed = 0;
+            let mut n_duplicates = 0;
+            let mut is_taken = false;
+            for (part, part_dst) in part_dsts {
+                // a part keeping its name
+                if part_set.contains(&part_dst) {
+                    continue;
+                }
+                n_probed += 1;
+                match take_path(&part_dst) {
+                    Ok(true) => taken_for_stem.push(part_dst),
+                    Ok(false) if dedupe && is_duplicate(part, &part_dst) => n_duplicates += 1,
+                    Ok(false) => {
+                        is_taken = true;
+                        break;
+                    },
+                    Err(e) => {
+                        // stop probing, the error is reported below
+                        take_error = Some(if claim { Error::ClaimFailed(part_dst, e) } else { Error::ExistenceCheckFailed(part_dst, e) });
+                        break;
+                    },
+                }
+            }
+            // some parts being there already and the others not is a different set
+            if is_taken || take_error.is_some() || (0 < n_duplicates && n_duplicates < n_probed) {
+                if claim {
+                    release_paths(&taken_for_stem);
+                }
+                conflict |= take_error.is_none();
+                return take_error.is_none();
+            }
+            duplicate = 0 < n_duplicates;
+            taken = taken_for_stem;
+            false
+        })?;
+        if let Some(e) = take_error {
+            return Err(e.into());
+        }
+        if self.claim {
+            self.claimed.extend(taken);
+        }
+        let new_stem = match self.encoding {
+            Some(encoding) => encoding.encode(&new_stem),
+            None => new_stem.into(),
+        };
+        let stem_dst = match dst_dir {
+            Some(dst_dir) => dst_dir.join(&new_stem),
+            None => path.with_file_name(&new_stem),
+        };
+
+        let mut entry = None;
+        for (part, suffix) in parts {
+            let part_dst = with_suffix(&stem_dst, &suffix);
+            self.reserved.insert(part_dst.clone());
+            let part_entry = PlanEntry { kind: PlanKind::Rename, src: part, dst: part_dst, duplicate, conflict };
+            if part_entry.src == path {
+                entry = Some(part_entry);
+            } else {
+                self.planned_sidecars.insert(part_entry.src.clone());
+                self.sidecar_entries.push(part_entry);
+            }
+        }
+        Ok(entry.expect("the path is one of the parts"))
+    }
+
+    #[cfg(feature = "reversible")]
+    fn plan_reversible(&mut self, path: &Path, dst_dir: Option<PathBuf>, mut take_path: impl FnMut(&Path) -> io::Result<bool>) -> Result<PlanEntry> {
+        let Some(filename) = path.file_name() else {
+            return Err(Error::FilenameNotFound(path.to_path_buf()).into());
+        };
+        // a lossy name would decode into another name
+        let filename = filename.to_str().ok_or_else(|| Error::NonUtf8Name(path.to_path_buf()))?;
+        // the whole limit, whatever --shrink-to says. the encoded names are utf-8, not in the output encoding
+        let rules = Rules { n_filename_bytes: self.n_max_filename_bytes(path, dst_dir.as_deref()), encoding: None, ..self.rules(path, dst_dir.as_deref()) };
+        let n_bytes = |s: &str| rules.n_bytes(s);
+        let new_filename = if self.squeeze {
+            crate::reversible::squeeze_filename_within(filename, rules.n_filename_bytes, n_bytes)
+        } else {
+            crate::reversible::reversible_filename_within(filename, rules.n_filename_bytes, n_bytes)?
+        };
+        // the encoded rest may be made of characters the profile doesn't allow
+        if new_filename != filename && !rules.fits(&new_filename) {
+            return Err(Error::NotReversible(filename.to_string()).into());
+        }
+        let dst = if let Some(dst_dir) = dst_dir {
+            dst_dir.join(&new_filename)
+        } else {
+            path.with_file_name(&new_filename)
+        };
+
+        // another path of the same file, when dst_dir is the same directory seen through a symlink
+        if dst != path && !jdt::eq_files(&dst, path).unwrap_or(false) {
+            if self.reserved.contains(&dst) {
+                return Err(Error::ReversibleNameTaken(dst).into());
+            }
+            match take_path(&dst) {
+                Ok(true) => (),
+                Ok(false) => return Err(Error::ReversibleNameTaken(dst).into()),
+                Err(e) => return Err(Error::ClaimFailed(dst, e).into()),
+            }
+            if self.claim {
+                self.claimed.push(dst.clone());
+            }
+        }
+        self.reserved.insert(dst.clone());
+
+        Ok(PlanEntry { kind: PlanKind::Rename, src: path.to_path_buf(), dst, duplicate: false, conflict: false })
+    }
+}
+
+// different files with the same content, the same file seen through two paths is not a duplicate
+fn is_duplicate(src: &Path, dst: &Path) -> io::Result<bool> {
+    let src_metadata = fs::metadata(src)?;
+    let dst_metadata = fs::metadata(dst)?;
+    if !src_metadata.is_file() || !dst_metadata.is_file() || src_metadata.len() != dst_metadata.len() {
+        return Ok(false);
+    }
+    if jdt::eq_files(src, dst)? {
+        return Ok(false);
+    }
+
+    let mut src_reader = BufReader::new(fs::File::open(src)?);
+    let mut dst_reader = BufReader::new(fs::File::open(dst)?);
+    let mut src_buf = [0; 8192];
+    let mut dst_buf = [0; 8192];
+    loop {
+        let n = src_reader.read(&mut src_buf)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        dst_reader.read_exact(&mut dst_buf[..n])?;
+        if src_buf[..n] != dst_buf[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+// `video` of `video.mkv`, none for names without an extension and for the sidecars themselves
+fn sidecar_stem<'a>(filename: &'a str, extensions: &HashSet<String>) -> Option<&'a str> {
+    match filename.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && !extensions.contains(&ext.to_lowercase()) => Some(stem),
+        _ => None,
+    }
+}
+
+// the extension of the file (`.mkv`) and its sidecars next to it with theirs (`.en.srt` of `video.en.srt`)
+fn find_sidecars(path: &Path, extensions: &HashSet<String>) -> io::Result<(String, StemGroup)> {
+    let filename = match path.file_name() {
+        Some(filename) => filename.to_string_lossy(),
+        None => return Ok((String::new(), Vec::new())),
+    };
+    let Some(stem) = sidecar_stem(&filename, extensions) else {
+        return Ok((String::new(), Vec::new()));
+    };
+    let dir = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    let mut sidecars = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let Some(sidecar_ext) = name.strip_prefix(stem).filter(|rest| rest.starts_with('.') && rest.len() <= N_MAX_SIDECAR_EXTENSION_BYTES) else {
+            continue;
+        };
+        let is_sidecar = sidecar_ext.rsplit_once('.').is_some_and(|(_, ext)| extensions.contains(&ext.to_lowercase()));
+        if is_sidecar && entry.file_type()?.is_file() {
+            sidecars.push((path.with_file_name(&name), sidecar_ext.to_string()));
+        }
+    }
+    sidecars.sort();
+    Ok((filename[stem.len()..].to_string(), sidecars))
+}
+
+// on the backend when there's one
+fn path_exists(backend: &Option<Rc<dyn ExistenceBackend>>, path: &Path) -> io::Result<bool> {
+    match backend {
+        Some(backend) => backend.exists(path),
+        None => Ok(path.exists()),
+    }
+}
+
+// `name` of a part of a multi-part set: `name.part1.rar`, `name.7z.001`, `name.z01` and `name.r00`, with the kind of the
+// set, `rar`, `zip` or the extension before the number of a split file (`7z`), in the case of the name.
+// `name.zip` and `name.rar` are the first parts of the sets of `.z01` and `.r00`
+fn part_stem(filename: &str) -> Option<(&str, &str)> {
+    let (rest, ext) = filename.rsplit_once('.')?;
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let (stem, set) = match ext.to_ascii_lowercase().as_str() {
+        "rar" => match rest.rsplit_once('.') {
+            Some((stem, part)) if part.get(..4).is_some_and(|p| p.eq_ignore_ascii_case("part")) && is_number(&part[4..]) => (stem, "rar"),
+            _ => (rest, "rar"),
+        },
+        "zip" => (rest, "zip"),
+        ext if ext.len() == 3 && is_number(ext) => rest.rsplit_once('.').unwrap_or((rest, "")),
+        ext if ext.len() == 3 && ext.starts_with('z') && is_number(&ext[1..]) => (rest, "zip"),
+        ext if ext.len() == 3 && ext.starts_with('r') && is_number(&ext[1..]) => (rest, "rar"),
+        _ => return None,
+    };
+    if stem.is_empty() { None } else { Some((stem, set)) }
+}
+
+// the names of the parts in the directory, sorted. empty when it can't be read: the sources may not be local, e.g. the
+// keys of an object store
+fn read_part_groups(dir: &Path) -> PartGroups {
+    let mut groups = PartGroups::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return groups;
+    };
+    for entry in entries.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let Some((stem, set)) = part_stem(&name) else {
+            continue;
+        };
+        if entry.file_type().is_ok_and(|file_type| file_type.is_file()) {
+            let key = (stem.to_string(), set.to_ascii_lowercase());
+            groups.entry(key).or_default().push(name);
+        }
+    }
+    for names in groups.values_mut() {
+        names.sort();
+    }
+    groups
+}
+
+// removes the placeholders of claimed destinations
+fn release_paths(paths: &[PathBuf]) {
+    for path in paths {
+        if let Err(e) = fs::remove_file(path) {
+            log::warn!("Failed to remove placeholder: {}: {}", path.display(), e);
+        }
+    }
+}
+
+// the path with the suffix appended to the filename, the filename may not be UTF-8 with --output-encoding
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut filename = path.file_name().unwrap_or_default().to_os_string();
+    filename.push(suffix);
+    path.with_file_name(filename)
+}
+
+// the new path of a sidecar, the new stem of the file with the extension of the sidecar
+fn sidecar_path(dst: &Path, ext: &str, sidecar_ext: &str) -> PathBuf {
+    let filename = dst.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+    let stem = filename.strip_suffix(ext).unwrap_or(&filename);
+    dst.with_file_name(format!("{}{}", stem, sidecar_ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_plan() {
+        let _ = env_logger::try_init();
+
+        let long_slug = "あ".repeat(100);
+        let mut planner = Planner::new();
+        let entry1 = planner.plan_impl(format!("{}.a.txt", long_slug), None::<PathBuf>, |_| Ok(true), |_, _| false).unwrap();
+        let entry2 = planner.plan_impl(format!("{}.b.txt", long_slug), None::<PathBuf>, |_| Ok(true), |_, _| false).unwrap();
+        let entry3 = planner.plan_impl("c.txt", Some("d"), |_| Ok(true), |_, _| false).unwrap();
+        let entry4 = planner.plan_impl("e/c.txt", Some("d"), |_| Ok(true), |_, _| false).unwrap();
+
+        assert_eq!(entry1.dst, PathBuf::from(format!("{}.txt", "あ".repeat(83))));
+        assert_eq!(entry2.dst, PathBuf::from(format!("{}.1.txt", "あ".repeat(83))));
+        assert_eq!(entry3.dst, PathBuf::from("d/c.txt"));
+        assert_eq!(entry4.dst, PathBuf::from("d/c.1.txt"));
+        assert!(!entry1.conflict && entry2.conflict && !entry3.conflict && entry4.conflict);
+        assert_eq!(planner.reserved_names(), &HashSet::from([entry1.dst, entry2.dst, entry3.dst, entry4.dst]));
+
+        let mut planner = Planner::new().dedupe(true);
+        let entry = planner.plan_impl("a/x.txt", Some("b"), |p| Ok(p != Path::new("b/x.txt")), |_, dst| dst == Path::new("b/x.txt")).unwrap();
+        assert_eq!(entry, PlanEntry { kind: PlanKind::Rename, src: PathBuf::from("a/x.txt"), dst: PathBuf::from("b/x.txt"), duplicate: true, conflict: false });
+        let entry = planner.plan_impl("a/y.txt", Some("b"), |p| Ok(p != Path::new("b/y.txt")), |_, _| false).unwrap();
+        assert_eq!(entry, PlanEntry { kind: PlanKind::Rename, src: PathBuf::from("a/y.txt"), dst: PathBuf::from("b/y.1.txt"), duplicate: false, conflict: true });
+
+        // nothing is created while planning
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("plan");
+        let mut planner = Planner::new();
+        planner.plan_impl("a.txt", Some(dir.join("p/q")), |_| Ok(true), |_, _| false).unwrap();
+        assert_eq!(planner.take_dir_entries(), vec![
+            PlanEntry::create_dir(&dir, "."),
+            PlanEntry::create_dir(dir.join("p"), "."),
+            PlanEntry::create_dir(dir.join("p/q"), "."),
+        ]);
+        planner.plan_impl("s/b.txt", Some(dir.join("p/r")), |_| Ok(true), |_, _| false).unwrap();
+        assert_eq!(planner.take_dir_entries(), vec![PlanEntry::create_dir(dir.join("p/r"), "s")]);
+        assert!(!dir.exists());
+
+        let mut planner = Planner::new().shrink_to(80);
+        let entry = planner.plan_impl(format!("{}.txt", "あ".repeat(80)), None::<PathBuf>, |_| Ok(true), |_, _| false).unwrap();
+        assert_eq!(entry.dst, PathBuf::from(format!("{}.txt", "あ".repeat(66))));
+    }
+
+    #[cfg(feature = "reversible")]
+    #[test]
+    fn test_plan_reversible() {
+        let _ = env_logger::try_init();
+
+        let mut planner = Planner::new().reversible(true);
+        let filename = format!("{}.txt", "a".repeat(400));
+        let entry = planner.plan_impl(&filename, None::<PathBuf>, |_| Ok(true), |_, _| false).unwrap();
+        assert_eq!(crate::decode_reversible_name(entry.dst.to_str().unwrap()).unwrap(), filename);
+        assert!(planner.plan_impl(&filename, None::<PathBuf>, |_| Ok(true), |_, _| false).is_err());
+
+        // within the configured limit, and not below it for --shrink-to
+        let mut planner = Planner::new().reversible(true).limit(100).shrink_to(50);
+        let entry = planner.plan_impl(&filename, None::<PathBuf>, |_| Ok(true), |_, _| false).unwrap();
+        assert!(entry.dst.as_os_str().len() <= 100 && 50 < entry.dst.as_os_str().len());
+        assert_eq!(crate::decode_reversible_name(entry.dst.to_str().unwrap()).unwrap(), filename);
+
+        let mut planner = Planner::new().reversible(true).squeeze(true).limit(100);
+        let entry = planner.plan_impl(&filename, None::<PathBuf>, |_| Ok(true), |_, _| false).unwrap();
+        assert!(entry.dst.as_os_str().len() <= 100);
+
+        // a lossy name would decode into another one
+        use std::os::unix::ffi::OsStrExt;
+        let filename = PathBuf::from(std::ffi::OsStr::from_bytes(&[b'a'; 300].iter().copied().chain(*b"\xff.txt").collect::<Vec<_>>()));
+        let mut planner = Planner::new().reversible(true);
+        let e = planner.plan_impl(&filename, None::<PathBuf>, |_| Ok(true), |_, _| false).unwrap_err();
+        assert!(matches!(e.downcast_ref::<Error>(), Some(Error::NonUtf8Name(_))));
+    }
+
+    #[test]
+    fn test_duplicate_clusters() {
+        let _ = env_logger::try_init();
+
+        let long_slug = "あ".repeat(100);
+        let mut planner = Planner::new();
+        let plan = [
+            format!("{}.v1.txt", long_slug),
+            "b.txt".to_string(),
+            format!("{}.v2.txt", long_slug),
+            format!("{}.version3", long_slug),
+            format!("{}.version4", long_slug),
+        ].iter().map(|path| planner.plan_impl(path, None::<PathBuf>, |_| Ok(true), |_, _| false).unwrap()).collect::<Vec<_>>();
+
+        let clusters = planner.duplicate_clusters(&plan);
+        assert_eq!(clusters, vec![
+            vec![plan[0].clone(), plan[2].clone()],
+            vec![plan[3].clone(), plan[4].clone()],
+        ]);
+    }
+
+    #[test]
+    fn test_sidecars() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let stem = "あ".repeat(82);
+        for ext in ["mkv", "srt", "en.srt", "txt"] {
+            fs::write(dir.join(format!("{}.{}", stem, ext)), ext).unwrap();
+        }
+        let paths = ["mkv", "srt", "en.srt", "txt"].iter().map(|ext| dir.join(format!("{}.{}", stem, ext))).collect::<Vec<_>>();
+
+        let mut planner = Planner::new().sidecars(true).shrink_to(80);
+        assert_eq!(planner.without_sidecars(paths.clone()), vec![paths[0].clone(), paths[3].clone()]);
+
+        // 3 bytes more for `.en.srt` than `.mkv`, one あ less than 66
+        let entry = planner.plan_impl(&paths[0], None::<PathBuf>, |_| Ok(true), |_, _| false).unwrap();
+        let new_stem = "あ".repeat(65);
+        assert_eq!(entry.dst, dir.join(format!("{}.mkv", new_stem)));
+        assert_eq!(planner.take_sidecar_entries(), vec![
+            PlanEntry { kind: PlanKind::Rename, src: paths[2].clone(), dst: dir.join(format!("{}.en.srt", new_stem)), duplicate: false, conflict: false },
+            PlanEntry { kind: PlanKind::Rename, src: paths[1].clone(), dst: dir.join(format!("{}.srt", new_stem)), duplicate: false, conflict: false },
+        ]);
+
+        // the sidecars went with the video already
+        let entry = planner.plan_impl(&paths[3], None::<PathBuf>, |_| Ok(true), |_, _| false).unwrap();
+        assert_eq!(entry.dst, dir.join(format!("{}.txt", "あ".repeat(66))));
+        assert!(planner.take_sidecar_entries().is_empty());
+    }
+
+    #[test]
+    fn test_backend() {
+        let _ = env_logger::try_init();
+
+        let backend = crate::ListingBackend::read("c.txt\nc.1.txt\np/\n".as_bytes(), "d").unwrap();
+        let mut planner = Planner::new().backend(backend);
+        assert_eq!(planner.plan("c.txt", Some("d")).unwrap().dst, PathBuf::from("d/c.2.txt"));
+        planner.plan("c.txt", Some("d/p")).unwrap();
+        planner.plan("c.txt", Some("d/q")).unwrap();
+        assert_eq!(planner.take_dir_entries(), vec![PlanEntry::create_dir("d/q", ".")]);
+    }
+
+    #[test]
+    fn test_path_limit() {
+        let _ = env_logger::try_init();
+
+        let dst_dir = PathBuf::from("/").join("d".repeat(350));
+        let backend = crate::ListingBackend::read("".as_bytes(), &dst_dir).unwrap();
+        let mut planner = Planner::new().backend(backend).profile(Profile::OneDrive);
+        let dst = planner.plan(format!("{}.txt", "a".repeat(100)), Some(&dst_dir)).unwrap().dst;
+        // 400 - 351 - 1
+        assert_eq!(dst, dst_dir.join(format!("{}.txt", "a".repeat(44))));
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let _ = env_logger::try_init();
+
+        let name = format!("{}.txt", "a".repeat(300));
+        let backend = crate::ListingBackend::read(format!("{}.txt\n", "a".repeat(251)).as_bytes(), "d").unwrap();
+        let mut planner = Planner::new().backend(backend).profile(Profile::GoogleDrive);
+        let dst = planner.plan(&name, Some("d")).unwrap().dst;
+        assert_eq!(dst, Path::new("d").join(format!("{}.txt", "a".repeat(251))));
+        assert_eq!(planner.plan(&name, Some("d")).unwrap().dst, dst);
+    }
+
+    #[test]
+    fn test_parts() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(part_stem("a.b.part01.rar"), Some(("a.b", "rar")));
+        assert_eq!(part_stem("a.7z.001"), Some(("a", "7z")));
+        assert_eq!(part_stem("a.z01"), Some(("a", "zip")));
+        assert_eq!(part_stem("a.zip"), Some(("a", "zip")));
+        assert_eq!(part_stem("a.r00"), Some(("a", "rar")));
+        assert_eq!(part_stem("a.txt"), None);
+        assert_eq!(part_stem(".z01"), None);
+        // different sets of the same stem
+        assert_ne!(part_stem("a.zip"), part_stem("a.rar"));
+        assert_ne!(part_stem("a.zip"), part_stem("a.txt.001"));
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let stem = format!("{}.{}", "あ".repeat(60), "い".repeat(20));
+        let paths = ["part1.rar", "part2.rar", "part10.rar"].iter().map(|suffix| dir.join(format!("{}.{}", stem, suffix))).collect::<Vec<_>>();
+        for path in &paths {
+            fs::write(path, "").unwrap();
+        }
+
+        let mut planner = Planner::new().shrink_to(80);
+        assert_eq!(planner.without_sidecars(paths.clone()), vec![paths[0].clone()]);
+
+        // cut by the longest suffix `.part10.rar` for all the parts, not only for the ones it doesn't fit in
+        let entry = planner.plan_impl(&paths[0], None::<PathBuf>, |_| Ok(true), |_, _| false).unwrap();
+        let new_stem = format!("{}.{}", "あ".repeat(60), "い".repeat(4));
+        assert_eq!(entry.dst, dir.join(format!("{}.part1.rar", new_stem)));
+        assert_eq!(planner.take_sidecar_entries().into_iter().map(|entry| entry.dst).collect::<Vec<_>>(), vec![
+            dir.join(format!("{}.part10.rar", new_stem)),
+            dir.join(format!("{}.part2.rar", new_stem)),
+        ]);
+        let new_paths = ["part1.rar", "part2.rar", "part10.rar"].map(|suffix| dir.join(format!("{}.{}", new_stem, suffix)));
+
+        // the destinations of all the parts are claimed, and released with the plan
+        let mut planner = Planner::new().shrink_to(80).claim(true);
+        assert_eq!(planner.plan(&paths[0], None::<PathBuf>).unwrap().dst, new_paths[0]);
+        assert!(new_paths.iter().all(|path| path.exists()));
+        planner.release_claims();
+        assert!(new_paths.iter().all(|path| !path.exists()));
+
+        // a set is a duplicate when all of its parts are
+        for path in &new_paths {
+            fs::write(path, "").unwrap();
+        }
+        let mut planner = Planner::new().shrink_to(80).dedupe(true);
+        let entry = planner.plan(&paths[0], None::<PathBuf>).unwrap();
+        assert!(entry.duplicate && !entry.conflict);
+        assert!(planner.take_sidecar_entries().iter().all(|entry| entry.duplicate));
+        fs::write(&new_paths[1], "x").unwrap();
+        let mut planner = Planner::new().shrink_to(80).dedupe(true);
+        let entry = planner.plan(&paths[0], None::<PathBuf>).unwrap();
+        assert!(!entry.duplicate && entry.conflict);
+        assert_eq!(entry.dst, dir.join(format!("{}.{}.1.part1.rar", "あ".repeat(60), "い".repeat(3))));
+
+        // parts of different sets of the same stem are files of their own, and the directory is read once
+        for name in ["b.zip", "b.rar", "b.txt.001"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let mut planner = Planner::new();
+        assert_eq!(planner.find_parts(&dir.join("b.zip")), None);
+        assert_eq!(planner.find_parts(&dir.join("b.txt.001")), None);
+        assert_eq!(planner.find_parts(&paths[1]).map(|(_, parts)| parts.len()), Some(3));
+        assert_eq!(planner.part_listings.len(), 1);
+    }
+
+    #[test]
+    fn test_is_duplicate() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("a"), "abc").unwrap();
+        fs::write(dir.join("b"), "abc").unwrap();
+        fs::write(dir.join("c"), "abd").unwrap();
+
+        assert!(is_duplicate(&dir.join("a"), &dir.join("b")).unwrap());
+        assert!(!is_duplicate(&dir.join("a"), &dir.join("c")).unwrap());
+        assert!(!is_duplicate(&dir.join("a"), &dir.join("a")).unwrap());
+    }
+}
@@ -0,0 +1,224 @@
+use unicode_normalization::UnicodeNormalization;
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+
+// constraints of the names on the filesystems of optical discs, for preparing a tree before mastering an image,
+// and of other filesystems stricter than the one the names are checked on.
+// the names stay UTF-8 on the disk the tree is prepared on, only the characters and the lengths are restricted
+// by the names of --profile in the config too
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    // 8.3 names of d-characters (uppercase letters, digits and `_`)
+    Iso9660Level1,
+    // 31 d-characters including the dot and the extension, level 3 has the same limit
+    Iso9660Level2,
+    // 64 UTF-16 code units
+    Joliet,
+    // the shared storage of android (/sdcard, /storage/emulated) as seen from termux, emulated by FUSE over the rules of FAT.
+    // some devices fail on names longer than 127 UTF-16 code units, far before NAME_MAX
+    AndroidStorage,
+    // 255 bytes of UTF-8, names are compared in NFC whatever form they are written in, so they're written in NFC
+    Apfs,
+    // 255 UTF-16 code units, names are stored decomposed (NFD) by the filesystem itself
+    HfsPlus,
+    // names synced by the clients of windows too: the characters of FAT, no device names and 400 UTF-16 code units
+    // of the whole path, counted on the local path which is never shorter than the path in the cloud
+    #[value(name = "onedrive")]
+    OneDrive,
+    // the characters of FAT, and no names the client ignores (desktop.ini) as they would silently stay local
+    Dropbox,
+    // the names of the mac (APFS)
+    #[value(name = "icloud")]
+    ICloud,
+    // titles of google drive, which needn't be unique in a folder. 255 UTF-16 code units, which the sync clients can
+    // write to the disks of windows and macs too
+    GoogleDrive,
+}
+
+// not allowed by Joliet, in addition to the control characters
+const JOLIET_RESERVED_CHARACTERS: &[char] = &['*', '/', ':', ';', '?', '\\'];
+// not allowed by FAT, in addition to the control characters
+const FAT_RESERVED_CHARACTERS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+// refused by OneDrive in any case, the device names of windows with any extension too (`con.txt`)
+const ONEDRIVE_RESERVED_NAMES: &[&str] = &[
+    ".lock", "desktop.ini", "con", "prn", "aux", "nul",
+    "com0", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
+    "lpt0", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+// never synced by Dropbox
+const DROPBOX_IGNORED_NAMES: &[&str] = &["desktop.ini", "thumbs.db", ".ds_store", ".dropbox", ".dropbox.attr"];
+
+impl Profile {
+    // the limit of a filename, in the units of `char_len`
+    pub(crate) fn n_filename_units(&self) -> usize {
+        match self {
+            Self::Iso9660Level1 => 12,
+            Self::Iso9660Level2 => 31,
+            Self::Joliet => 64,
+            Self::AndroidStorage => 127,
+            Self::Apfs | Self::HfsPlus | Self::OneDrive | Self::Dropbox | Self::ICloud | Self::GoogleDrive => 255,
+        }
+    }
+
+    // the limit of the whole path, in the units of `char_len`
+    pub(crate) fn n_max_path_units(&self) -> Option<usize> {
+        match self {
+            Self::OneDrive => Some(400),
+            _ => None,
+        }
+    }
+
+    // the limit of the name before the extension, when there's one apart from `n_filename_units`
+    pub(crate) fn n_max_stem_units(&self) -> Option<usize> {
+        match self {
+            Self::Iso9660Level1 => Some(8),
+            _ => None,
+        }
+    }
+
+    // longer extensions are cut, as `JPEG` to `JPE`
+    pub(crate) fn n_max_extension_units(&self) -> Option<usize> {
+        match self {
+            Self::Iso9660Level1 => Some(3),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn char_len(&self, c: char) -> usize {
+        match self {
+            Self::Iso9660Level1 | Self::Iso9660Level2 => 1,
+            Self::Joliet | Self::AndroidStorage | Self::HfsPlus | Self::OneDrive | Self::Dropbox | Self::GoogleDrive => c.len_utf16(),
+            Self::Apfs | Self::ICloud => c.len_utf8(),
+        }
+    }
+
+    // the names of the destination can be the same as others, no counter is needed for them
+    pub(crate) fn allows_duplicate_names(&self) -> bool {
+        *self == Self::GoogleDrive
+    }
+
+    // ISO9660 allows a single dot, so `a.1.txt` would be invalid
+    pub(crate) fn counter_delimiter(&self) -> char {
+        match self {
+            Self::Iso9660Level1 | Self::Iso9660Level2 => '_',
+            _ => '.',
+        }
+    }
+
+    fn is_iso9660(&self) -> bool {
+        matches!(self, Self::Iso9660Level1 | Self::Iso9660Level2)
+    }
+
+    // replaces the characters the profile doesn't allow with `_`, ISO9660 names are uppercased
+    // and only the last dot (of the extension) is kept
+    pub(crate) fn map(&self, filename: &str) -> String {
+        // the names are written in the form the mac reads them back in, so they survive a round trip unchanged.
+        // `:` is the path separator of the Finder, shown as `/`
+        match self {
+            Self::Apfs | Self::ICloud => return filename.nfc().map(|c| if c == ':' { '_' } else { c }).collect(),
+            Self::HfsPlus => return filename.nfd().map(|c| if c == ':' { '_' } else { c }).collect(),
+            Self::AndroidStorage => return map_fat(filename),
+            // office lock files, `~$report.docx`, aren't synced either
+            Self::OneDrive => return escape_reserved_name(&map_fat(filename).replace("_vti_", "_vti-"), ONEDRIVE_RESERVED_NAMES, "~$"),
+            Self::Dropbox => return escape_reserved_name(&map_fat(filename), DROPBOX_IGNORED_NAMES, ""),
+            Self::GoogleDrive => return filename.chars().map(|c| if c.is_control() { '_' } else { c }).collect(),
+            _ => {},
+        }
+        if !self.is_iso9660() {
+            return filename.chars().map(|c| if c.is_control() || JOLIET_RESERVED_CHARACTERS.contains(&c) { '_' } else { c }).collect();
+        }
+        let ext_dot = filename.rfind('.').filter(|&i| 0 < i);
+        filename.char_indices().map(|(i, c)| {
+            let c = c.to_ascii_uppercase();
+            if c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_' || Some(i) == ext_dot {
+                c
+            } else {
+                '_'
+            }
+        }).collect()
+    }
+
+    // whether the name can be used as it is, the total length is checked by the caller
+    pub(crate) fn allows(&self, filename: &str) -> bool {
+        if self.map(filename) != filename {
+            return false;
+        }
+        let (stem, ext) = match filename.rfind('.') {
+            Some(i) => (&filename[..i], Some(&filename[i + 1..])),
+            None => (filename, None),
+        };
+        let len = |s: &str| s.chars().map(|c| self.char_len(c)).sum::<usize>();
+        let stem_fits = self.n_max_stem_units().is_none_or(|n| len(stem) <= n);
+        let ext_fits = ext.zip(self.n_max_extension_units()).is_none_or(|(ext, n)| len(ext) <= n);
+        stem_fits && ext_fits
+    }
+}
+
+fn map_fat(filename: &str) -> String {
+    let mapped = filename.chars().map(|c| if c.is_control() || FAT_RESERVED_CHARACTERS.contains(&c) { '_' } else { c }).collect::<String>();
+    // FAT drops the trailing dots and spaces silently, so `a.` would be created as `a`
+    let trimmed = mapped.trim_end_matches(['.', ' ']);
+    format!("{}{}", trimmed, "_".repeat(mapped.len() - trimmed.len()))
+}
+
+// `_` in front of the names in `names` (ignoring case, or their part before the first dot) and the ones starting with
+// `prefix` if any
+fn escape_reserved_name(filename: &str, names: &[&str], prefix: &str) -> String {
+    let lowercase = filename.to_lowercase();
+    let stem = lowercase.split('.').next().unwrap_or("");
+    if names.contains(&lowercase.as_str()) || names.contains(&stem) || (!prefix.is_empty() && lowercase.starts_with(prefix)) {
+        format!("_{}", filename)
+    } else {
+        filename.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_profile() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(Profile::Iso9660Level1.map("My photo.v2.jpg"), "MY_PHOTO_V2.JPG");
+        assert_eq!(Profile::Iso9660Level2.map(".bashrc"), "_BASHRC");
+        assert_eq!(Profile::Joliet.map("a:b?.txt"), "a_b_.txt");
+
+        assert!(Profile::Iso9660Level1.allows("README.TXT"));
+        assert!(!Profile::Iso9660Level1.allows("README.MD.TXT"));
+        assert!(!Profile::Iso9660Level1.allows("LONGREADME.TXT"));
+        assert!(!Profile::Iso9660Level1.allows("PHOTO.JPEG"));
+        assert!(!Profile::Iso9660Level2.allows("readme.txt"));
+        assert!(Profile::Joliet.allows("readme.txt"));
+
+        assert_eq!(Profile::Joliet.char_len('あ'), 1);
+        assert_eq!(Profile::Joliet.char_len('😀'), 2);
+
+        assert_eq!(Profile::AndroidStorage.map("a|b\"c?.txt"), "a_b_c_.txt");
+        assert_eq!(Profile::AndroidStorage.map("etc. "), "etc__");
+        assert!(Profile::AndroidStorage.allows("Ｑ&A; 1.txt"));
+        assert!(!Profile::AndroidStorage.allows("what?.txt"));
+
+        assert_eq!(Profile::Apfs.map("Cafe\u{301}: menu.txt"), "Café_ menu.txt");
+        assert_eq!(Profile::HfsPlus.map("Café.txt"), "Cafe\u{301}.txt");
+        assert!(Profile::HfsPlus.allows("Cafe\u{301}.txt"));
+        assert!(!Profile::HfsPlus.allows("Café.txt"));
+        assert_eq!(Profile::HfsPlus.char_len('é'), 1);
+        assert_eq!(Profile::Apfs.char_len('é'), 2);
+
+        assert_eq!(Profile::OneDrive.map("Desktop.ini"), "_Desktop.ini");
+        assert_eq!(Profile::OneDrive.map("con.txt"), "_con.txt");
+        assert_eq!(Profile::OneDrive.map("~$report.docx"), "_~$report.docx");
+        assert_eq!(Profile::OneDrive.map("a_vti_b?.txt"), "a_vti-b_.txt");
+        assert!(Profile::OneDrive.allows("console.txt"));
+        assert_eq!(Profile::Dropbox.map("Thumbs.db"), "_Thumbs.db");
+        assert_eq!(Profile::Dropbox.map("draft."), "draft_");
+        assert!(Profile::Dropbox.allows("~$report.docx"));
+        assert!(Profile::GoogleDrive.allows("a:b?.txt"));
+    }
+}
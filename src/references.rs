@@ -0,0 +1,126 @@
+use std::{path::{Path, PathBuf}, fs, collections::HashSet, os::unix::fs::MetadataExt};
+use anyhow::Result;
+
+use crate::load_config;
+
+// characters around a filename in playlists (`dir/a.mp3`), cue sheets (`FILE "a.wav" WAVE`) and markdown (`[a](a.md)`)
+const REFERENCE_PREFIXES: &[char] = &['/', '\\', '"', '\'', '(', '[', '<', '='];
+const REFERENCE_SUFFIXES: &[char] = &['"', '\'', ')', ']', '>', '#', '?'];
+
+// rewrites references to renamed files in the text files next to them, e.g. playlists, so that they keep working
+#[derive(Debug)]
+pub struct ReferenceUpdater {
+    extensions: HashSet<String>,
+}
+
+impl ReferenceUpdater {
+    pub fn load() -> Self {
+        let config = load_config();
+        Self { extensions: config.reference_extensions.iter().map(|ext| ext.to_lowercase()).collect() }
+    }
+
+    // rewrites the files of the configured extensions in the directory, `renames` are pairs of the old and the new
+    // filename of the files renamed in it. returns the rewritten files
+    pub fn update(&self, dir: impl AsRef<Path>, renames: &[(String, String)]) -> Result<Vec<PathBuf>> {
+        let mut updated = Vec::new();
+        for entry in fs::read_dir(dir.as_ref())? {
+            let path = entry?.path();
+            let is_target = path.extension().is_some_and(|ext| self.extensions.contains(&ext.to_string_lossy().to_lowercase()));
+            if !is_target || !path.is_file() {
+                continue;
+            }
+            // binary or legacy encoded files are left alone
+            let Ok(text) = fs::read_to_string(&path) else {
+                log::debug!("Not a UTF-8 text file, references not updated: {}", path.display());
+                continue;
+            };
+            let mut new_text = text.clone();
+            for (old, new) in renames {
+                new_text = replace_references(&new_text, old, new);
+            }
+            if new_text != text {
+                write_replacing(&path, &new_text)?;
+                updated.push(path);
+            }
+        }
+        Ok(updated)
+    }
+}
+
+// through a temporary file, a half written playlist is worse than an outdated one. the file a symlink points to is
+// replaced, not the symlink, and the replacement gets the mode and the owner of the old one
+fn write_replacing(path: &Path, text: &str) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let metadata = fs::metadata(&path)?;
+    let tmp_path = path.with_file_name(format!(".{}.tmp", path.file_name().expect("canonical path of a file").to_string_lossy()));
+    fs::write(&tmp_path, text)?;
+    // only root can give the file away. before the mode, since chown clears the setuid and setgid bits
+    if let Err(e) = std::os::unix::fs::chown(&tmp_path, Some(metadata.uid()), Some(metadata.gid())) {
+        log::warn!("Failed to keep the owner of {}: {}", path.display(), e);
+    }
+    if let Err(e) = fs::set_permissions(&tmp_path, metadata.permissions()).and_then(|_| fs::rename(&tmp_path, &path)) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+// replaces the old filename only where it stands as a whole name, `a.mp3` isn't a reference in `aa.mp3`
+fn replace_references(text: &str, old: &str, new: &str) -> String {
+    let mut replaced = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find(old) {
+        let (before, after) = (&rest[..i], &rest[i + old.len()..]);
+        let starts = before.chars().next_back().or_else(|| replaced.chars().next_back()).is_none_or(|c| c.is_whitespace() || REFERENCE_PREFIXES.contains(&c));
+        let ends = after.chars().next().is_none_or(|c| c.is_whitespace() || REFERENCE_SUFFIXES.contains(&c));
+        replaced.push_str(before);
+        replaced.push_str(if starts && ends { new } else { old });
+        rest = after;
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_replace_references() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(replace_references("a.mp3\nmusic/a.mp3\naa.mp3\na.mp3.bak\n", "a.mp3", "b.mp3"), "b.mp3\nmusic/b.mp3\naa.mp3\na.mp3.bak\n");
+        assert_eq!(replace_references("FILE \"a.wav\" WAVE", "a.wav", "b.wav"), "FILE \"b.wav\" WAVE");
+        assert_eq!(replace_references("see [a](a.md#x) and a.md", "a.md", "b.md"), "see [a](b.md#x) and b.md");
+    }
+
+    #[test]
+    fn test_update_references() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("list.m3u"), "a.mp3\nc.mp3\n").unwrap();
+        fs::write(dir.join("notes.txt"), "a.mp3\n").unwrap();
+
+        let updater = ReferenceUpdater { extensions: ["m3u".to_string()].into_iter().collect() };
+        let updated = updater.update(dir, &[("a.mp3".to_string(), "b.mp3".to_string())]).unwrap();
+        assert_eq!(updated, vec![dir.join("list.m3u")]);
+        assert_eq!(fs::read_to_string(dir.join("list.m3u")).unwrap(), "b.mp3\nc.mp3\n");
+        assert_eq!(fs::read_to_string(dir.join("notes.txt")).unwrap(), "a.mp3\n");
+
+        // the mode is kept, and a symlinked playlist stays a symlink
+        use std::os::unix::fs::PermissionsExt;
+        fs::create_dir(dir.join("lists")).unwrap();
+        fs::write(dir.join("lists/all.m3u"), "b.mp3\n").unwrap();
+        fs::set_permissions(dir.join("lists/all.m3u"), fs::Permissions::from_mode(0o600)).unwrap();
+        std::os::unix::fs::symlink("lists/all.m3u", dir.join("all.m3u")).unwrap();
+        let updated = updater.update(dir, &[("b.mp3".to_string(), "c.mp3".to_string())]).unwrap();
+        assert_eq!(updated.len(), 2);
+        assert!(fs::symlink_metadata(dir.join("all.m3u")).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(dir.join("lists/all.m3u")).unwrap(), "c.mp3\n");
+        assert_eq!(fs::metadata(dir.join("lists/all.m3u")).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(!dir.join("lists/.all.m3u.tmp").exists());
+    }
+}
@@ -0,0 +1,398 @@
+use std::io::{Read, Write};
+use anyhow::Result;
+use flate2::{Compression, write::DeflateEncoder, read::DeflateDecoder};
+
+use crate::{Error, N_FILENAME_BYTES, N_MAX_EXTENSION_BYTES};
+
+// separates the kept prefix from the encoded rest, `~` is not in the base64 alphabet
+const MARKER: &str = "~z";
+
+// the same for squeezed names, whose encoded part may be only the beginning of the rest. `~y` for the deflated ones
+// and `~x` for the ones coded by the dictionary, of the same length
+const SQUEEZE_MARKER: &str = "~y";
+const DICTIONARY_MARKER: &str = "~x";
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+// lowercase only, squeezed names survive case insensitive filesystems and tools
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+// fragments common in filenames, a byte of the dictionary coding stands for one of them. the order is the format,
+// new ones only go to the end
+const CODEBOOK: &[&str] = &[
+    " ", "_", "-", ".", "e", "t", "a", "o", "i", "n", "s", "r", "h", "l", "d", "c", "u", "m", "f", "p", "g", "w", "y", "b", "v", "k", "x", "j", "q", "z",
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "(", ")", "[", "]", ",", "'", "&", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "R", "S", "T", "U", "V", "W",
+    "th", "he", "in", "er", "an", "re", "on", "at", "en", "nd", "ti", "es", "or", "te", "of", "ed", "is", "it", "al", "ar", "st", "to", "nt", "ng", "se",
+    "ha", "as", "ou", "io", "le", "ve", "co", "me", "de", "hi", "ri", "ro", "ic", "ne", "ea", "ra", "ce", "li", "ch", "ll", "be", "ma", "si", "om", "ur",
+    "the", "and", "ing", "ion", "tion", "ent", "for", "ver", "ter", "ment", "ation", "with", "from", "that", "this", "you", "are", "all", "est", "ers", "ould", "ight", "The",
+    "20", "19", "00", "01", "02", "03", "04", "05", "06", "07", "08", "09", "10", "11", "12", "2020", "2021", "2022", "2023", "2024", "2025", "2026",
+    "final", "copy", "draft", "version", "backup", "image", "photo", "video", "audio", "music", "document", "report", "screenshot", "Screenshot", "untitled", "new", "old",
+    "IMG_", "DSC", "file", "data", "test", "page", "part", "track", "episode", "season", "chapter", "volume", "official", "remix", "live", "feat.", "edition", "original", "download",
+    "project", "archive", "invoice", "scan", "meeting", "notes", "README", "www.", "http", "com", "org", "html", "pdf", "jpg", "jpeg", "png", "mp3", "mp4",
+    "txt", "__", "--", "..", "1080p", "720p",
+    " the ", "of the", "in the", " - ", "_-_", "  ", " (", ") ", " [", "] ", ", ",
+];
+// the codes after the codebook: one byte as it is, and a run of up to 256 bytes as they are, after its length - 1
+const VERBATIM_BYTE: u8 = 254;
+const VERBATIM_RUN: u8 = 255;
+
+// shortens the filename so that `decode_reversible_name` can restore the original:
+// the beginning is kept as is, the rest is deflated and base64 (url safe) encoded, and the extension is kept.
+// the longest readable prefix which fits is chosen. fails when even the compressed rest doesn't fit.
+pub fn reversible_filename(filename: &str) -> Result<String> {
+    reversible_filename_within(filename, N_FILENAME_BYTES, str::len)
+}
+
+// within the limit of the destination, the length counted as there, e.g. in the units of a profile
+pub(crate) fn reversible_filename_within(filename: &str, n_max_bytes: usize, n_bytes: impl Fn(&str) -> usize) -> Result<String> {
+    if n_bytes(filename) <= n_max_bytes {
+        return Ok(filename.to_string());
+    }
+
+    let (slug, ext) = split_extension(filename);
+    let n_available_bytes = n_max_bytes.saturating_sub(n_bytes(MARKER) + n_bytes(ext));
+    let prefix_lens = slug.char_indices().map(|(i, _)| i).take_while(|i| n_bytes(&slug[..*i]) <= n_available_bytes).collect::<Vec<_>>();
+    for prefix_len in prefix_lens.into_iter().rev() {
+        let encoded = base64_encode(&deflate(&slug.as_bytes()[prefix_len..]));
+        if n_bytes(&slug[..prefix_len]) + n_bytes(&encoded) <= n_available_bytes {
+            return Ok(format!("{}{}{}{}", &slug[..prefix_len], MARKER, encoded, ext));
+        }
+    }
+    Err(Error::NotReversible(filename.to_string()).into())
+}
+
+// restores the original of a name made by `reversible_filename`, names which weren't shortened are returned as they are
+pub fn decode_reversible_name(filename: impl AsRef<str>) -> Result<String> {
+    let filename = filename.as_ref();
+    if let Some(decoded) = decode_impl(filename, BASE64_ALPHABET, &[(MARKER, |encoded| inflate(&base64_decode(encoded)?))]) {
+        return Ok(decoded);
+    }
+
+    if filename.len() <= N_FILENAME_BYTES {
+        Ok(filename.to_string())
+    } else {
+        Err(Error::NotReversible(filename.to_string()).into())
+    }
+}
+
+// for names read by programs rather than people: like `reversible_filename`, but base32 encoded, and compressed by
+// the dictionary of filename fragments when that is shorter than deflate, as it usually is for the short rests.
+// when even the compressed rest doesn't fit, as much of it as fits is compressed instead of cutting it off plainly.
+// so it never fails, but `unsqueeze_filename` may restore only the beginning of the original.
+pub fn squeeze_filename(filename: &str) -> String {
+    squeeze_filename_within(filename, N_FILENAME_BYTES, str::len)
+}
+
+// within the limit of the destination, as `reversible_filename_within`
+pub(crate) fn squeeze_filename_within(filename: &str, n_max_bytes: usize, n_bytes: impl Fn(&str) -> usize) -> String {
+    if n_bytes(filename) <= n_max_bytes {
+        return filename.to_string();
+    }
+
+    let (slug, ext) = split_extension(filename);
+    let n_available_bytes = n_max_bytes.saturating_sub(n_bytes(SQUEEZE_MARKER) + n_bytes(ext));
+    // the marker and the encoded tail, whichever compression is shorter
+    let squeeze = |prefix_len: usize, tail_len: usize| {
+        let tail = &slug.as_bytes()[prefix_len..(prefix_len + tail_len)];
+        let by_dictionary = base32_encode(&dictionary_compress(tail));
+        let by_deflate = base32_encode(&deflate(tail));
+        if by_dictionary.len() <= by_deflate.len() { (DICTIONARY_MARKER, by_dictionary) } else { (SQUEEZE_MARKER, by_deflate) }
+    };
+
+    let prefix_bytes = |prefix_len: usize| n_bytes(&slug[..prefix_len]);
+    let prefix_lens = slug.char_indices().map(|(i, _)| i).take_while(|i| prefix_bytes(*i) <= n_available_bytes).collect::<Vec<_>>();
+    for prefix_len in prefix_lens.iter().copied().rev() {
+        let (marker, encoded) = squeeze(prefix_len, slug.len() - prefix_len);
+        if prefix_bytes(prefix_len) + n_bytes(&encoded) <= n_available_bytes {
+            return format!("{}{}{}{}", &slug[..prefix_len], marker, encoded, ext);
+        }
+    }
+
+    // the rest doesn't fit whole, keep half of the budget readable and squeeze as much as possible into the other half
+    let prefix_len = prefix_lens.iter().copied().take_while(|i| prefix_bytes(*i) <= n_available_bytes / 2).last().unwrap_or(0);
+    let n_squeezed_bytes = n_available_bytes - prefix_bytes(prefix_len);
+    let tail_lens = slug[prefix_len..].char_indices().map(|(i, _)| i).skip(1).collect::<Vec<_>>();
+    // the compressed size mostly grows with the input, so binary search is good enough
+    let n_fitting_tails = tail_lens.partition_point(|tail_len| n_bytes(&squeeze(prefix_len, *tail_len).1) <= n_squeezed_bytes);
+    let tail_len = if n_fitting_tails == 0 { 0 } else { tail_lens[n_fitting_tails - 1] };
+    let (marker, encoded) = squeeze(prefix_len, tail_len);
+    format!("{}{}{}{}", &slug[..prefix_len], marker, encoded, ext)
+}
+
+// restores what survived in a name made by `squeeze_filename`, names which weren't squeezed are returned as they are
+pub fn unsqueeze_filename(filename: impl AsRef<str>) -> Result<String> {
+    let filename = filename.as_ref();
+    let codecs: &[(&str, Decode)] = &[
+        (SQUEEZE_MARKER, |encoded| inflate(&base32_decode(encoded)?)),
+        (DICTIONARY_MARKER, |encoded| dictionary_decompress(&base32_decode(encoded)?)),
+    ];
+    if let Some(decoded) = decode_impl(filename, BASE32_ALPHABET, codecs) {
+        return Ok(decoded);
+    }
+
+    if filename.len() <= N_FILENAME_BYTES {
+        Ok(filename.to_string())
+    } else {
+        Err(Error::NotReversible(filename.to_string()).into())
+    }
+}
+
+// the decompressed bytes of the encoded part after a marker
+type Decode = fn(&str) -> Option<Vec<u8>>;
+
+fn decode_impl(filename: &str, alphabet: &[u8], codecs: &[(&str, Decode)]) -> Option<String> {
+    // the original extension may contain a marker too, so the last one isn't always the right one
+    let mut markers = codecs.iter().flat_map(|(marker, decode)| filename.match_indices(marker).map(move |(i, _)| (i, *marker, decode))).collect::<Vec<_>>();
+    markers.sort_by_key(|(i, _, _)| *i);
+    for (i, marker, decode) in markers.into_iter().rev() {
+        let prefix = &filename[..i];
+        let rest = &filename[i + marker.len()..];
+        let encoded_len = rest.find(|c: char| !c.is_ascii() || !alphabet.contains(&(c as u8))).unwrap_or(rest.len());
+        let (encoded, ext) = rest.split_at(encoded_len);
+        if encoded.is_empty() || (!ext.is_empty() && !ext.starts_with('.')) {
+            continue;
+        }
+        let Some(decoded) = decode(encoded) else {
+            continue;
+        };
+        let Ok(decoded) = String::from_utf8(decoded) else {
+            continue;
+        };
+        return Some(format!("{}{}{}", prefix, decoded, ext));
+    }
+    None
+}
+
+// the same extension rule as the usual shortening
+fn split_extension(filename: &str) -> (&str, &str) {
+    match filename.rfind('.') {
+        Some(i) if 0 < i && filename.len() - i - 1 <= N_MAX_EXTENSION_BYTES => filename.split_at(i),
+        _ => (filename, ""),
+    }
+}
+
+fn deflate(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(bytes).expect("writing to vec never fails");
+    encoder.finish().expect("writing to vec never fails")
+}
+
+fn inflate(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    DeflateDecoder::new(bytes).read_to_end(&mut decoded).ok()?;
+    Some(decoded)
+}
+
+// smaz-like: the longest fragment of the codebook at each place, the bytes no fragment matches as they are
+fn dictionary_compress(bytes: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    let mut verbatim = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let fragment = CODEBOOK.iter().enumerate().filter(|(_, fragment)| bytes[i..].starts_with(fragment.as_bytes())).max_by_key(|(_, fragment)| fragment.len());
+        match fragment {
+            Some((code, fragment)) => {
+                push_verbatim(&mut compressed, &mut verbatim);
+                compressed.push(code as u8);
+                i += fragment.len();
+            },
+            None => {
+                verbatim.push(bytes[i]);
+                i += 1;
+            },
+        }
+    }
+    push_verbatim(&mut compressed, &mut verbatim);
+    compressed
+}
+
+fn push_verbatim(compressed: &mut Vec<u8>, verbatim: &mut Vec<u8>) {
+    for chunk in verbatim.chunks(256) {
+        if let [byte] = chunk {
+            compressed.extend([VERBATIM_BYTE, *byte]);
+        } else {
+            compressed.extend([VERBATIM_RUN, (chunk.len() - 1) as u8]);
+            compressed.extend(chunk);
+        }
+    }
+    verbatim.clear();
+}
+
+fn dictionary_decompress(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    let mut bytes = bytes.iter();
+    while let Some(code) = bytes.next() {
+        match *code {
+            VERBATIM_BYTE => decompressed.push(*bytes.next()?),
+            VERBATIM_RUN => {
+                let n = *bytes.next()? as usize + 1;
+                let run = bytes.as_slice().get(..n)?;
+                decompressed.extend(run);
+                bytes = bytes.as_slice()[n..].iter();
+            },
+            code => decompressed.extend(CODEBOOK.get(code as usize)?.as_bytes()),
+        }
+    }
+    Some(decompressed)
+}
+
+// without padding
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..(chunk.len() + 1) {
+            encoded.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for chunk in encoded.as_bytes().chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut n = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|a| a == c)? as u32;
+            n |= value << (18 - 6 * i);
+        }
+        for i in 0..(chunk.len() - 1) {
+            bytes.push((n >> (16 - 8 * i) & 0xff) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+// without padding
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(5) {
+        let n = chunk.iter().enumerate().fold(0u64, |n, (i, b)| n | (*b as u64) << (32 - 8 * i));
+        for i in 0..((chunk.len() * 8).div_ceil(5)) {
+            encoded.push(BASE32_ALPHABET[(n >> (35 - 5 * i) & 0x1f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for chunk in encoded.as_bytes().chunks(8) {
+        // 1, 3 and 6 characters never come out of `base32_encode`
+        if [1, 3, 6].contains(&chunk.len()) {
+            return None;
+        }
+        let mut n = 0u64;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = BASE32_ALPHABET.iter().position(|a| a == c)? as u64;
+            n |= value << (35 - 5 * i);
+        }
+        for i in 0..(chunk.len() * 5 / 8) {
+            bytes.push((n >> (32 - 8 * i) & 0xff) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_base64() {
+        let _ = env_logger::try_init();
+
+        for bytes in [&b""[..], b"a", b"ab", b"abc", b"abcd", b"\xff\xfe\x00"] {
+            assert_eq!(base64_decode(&base64_encode(bytes)).unwrap(), bytes);
+        }
+        assert_eq!(base64_encode(b"abc"), "YWJj");
+        assert_eq!(base64_encode(b"\xfb\xff"), "-_8");
+        assert!(base64_decode("a~").is_none());
+    }
+
+    #[test]
+    fn test_base32() {
+        let _ = env_logger::try_init();
+
+        for bytes in [&b""[..], b"a", b"ab", b"abc", b"abcd", b"abcde", b"abcdef", b"\xff\xfe\x00"] {
+            assert_eq!(base32_decode(&base32_encode(bytes)).unwrap(), bytes);
+        }
+        assert_eq!(base32_encode(b"foobar"), "mzxw6ytboi");
+        assert!(base32_decode("abc").is_none());
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let _ = env_logger::try_init();
+
+        assert!(CODEBOOK.len() <= VERBATIM_BYTE as usize);
+        assert!(CODEBOOK.iter().enumerate().all(|(i, fragment)| !fragment.is_empty() && !CODEBOOK[..i].contains(fragment)));
+        let long_run = "あ".repeat(100);
+        for text in ["", "a", "the_final_report_2024.pdf", "~", "~~", "Zebra Quiz", &long_run] {
+            assert_eq!(dictionary_decompress(&dictionary_compress(text.as_bytes())).unwrap(), text.as_bytes());
+        }
+        assert!(dictionary_compress(b"the_final_report").len() < 6);
+        assert_eq!(dictionary_compress(b"~"), vec![VERBATIM_BYTE, b'~']);
+        assert!(dictionary_decompress(&[VERBATIM_RUN, 3, b'a']).is_none());
+        assert!(dictionary_decompress(&[VERBATIM_BYTE]).is_none());
+    }
+
+    #[test]
+    fn test_reversible_filename() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(reversible_filename("a.txt").unwrap(), "a.txt");
+        assert_eq!(decode_reversible_name("a.txt").unwrap(), "a.txt");
+
+        let filename = format!("{}.txt", "a".repeat(400));
+        let new_filename = reversible_filename(&filename).unwrap();
+        assert!(new_filename.len() <= N_FILENAME_BYTES);
+        assert!(new_filename.starts_with("aaaa"));
+        assert!(new_filename.ends_with(".txt"));
+        assert_eq!(decode_reversible_name(&new_filename).unwrap(), filename);
+
+        let filename = format!("{}.~z", "x".repeat(300));
+        let new_filename = reversible_filename(&filename).unwrap();
+        assert_eq!(decode_reversible_name(&new_filename).unwrap(), filename);
+    }
+
+    #[test]
+    fn test_squeeze_filename() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(squeeze_filename("a.txt"), "a.txt");
+        assert_eq!(unsqueeze_filename("a.txt").unwrap(), "a.txt");
+
+        let filename = format!("{}.txt", "a".repeat(400));
+        let new_filename = squeeze_filename(&filename);
+        assert!(new_filename.len() <= N_FILENAME_BYTES);
+        assert!(new_filename.ends_with(".txt"));
+        assert_eq!(unsqueeze_filename(&new_filename).unwrap(), filename);
+
+        // hardly compressible, only the beginning survives
+        let slug = (0..1000).map(|i| char::from_u32(0x4e00 + (i * 7919) % 20000).unwrap()).collect::<String>();
+        let filename = format!("{}.txt", slug);
+        let new_filename = squeeze_filename(&filename);
+        assert!(new_filename.len() <= N_FILENAME_BYTES);
+        assert!(new_filename.ends_with(".txt"));
+        let restored = unsqueeze_filename(&new_filename).unwrap();
+        let restored_slug = restored.strip_suffix(".txt").unwrap();
+        assert!(slug.starts_with(restored_slug));
+        assert!(N_FILENAME_BYTES / 2 < restored_slug.len());
+
+        // the words of a name are shorter by the dictionary than deflated
+        let filename = "The_final_report_of_the_meeting_with_the_project_team_about_the_new_version_of_the_document_archive_\
+            and_the_invoice_scan_from_2024_which_was_sent_to_everyone_in_the_office_after_the_backup_of_the_old_data_\
+            was_restored_by_the_original_author_of_the_chapter.pdf";
+        let new_filename = squeeze_filename(filename);
+        assert!(new_filename.contains(DICTIONARY_MARKER));
+        assert_eq!(unsqueeze_filename(&new_filename).unwrap(), filename);
+
+        // the deflated names of before are still restored
+        let filename = format!("{}~y{}.txt", "a".repeat(10), base32_encode(&deflate(b"bbbb")));
+        assert_eq!(unsqueeze_filename(&filename).unwrap(), "aaaaaaaaaabbbb.txt");
+    }
+}
@@ -0,0 +1,196 @@
+use std::{path::{Path, PathBuf}, io, process::{Command, Stdio}, collections::{HashMap, HashSet}};
+use anyhow::Result;
+
+use crate::{Error, Planner, ExistenceBackend, ListingBackend, Rules, shorten_filename_among};
+
+// a bucket accessed through the aws command line, which takes the credentials and the region from its own config.
+// keys are handled as `s3://bucket/key` paths, as they are recorded in the journal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Bucket {
+    bucket: String,
+}
+
+impl S3Bucket {
+    // the bucket and the prefix of `s3://bucket/prefix`
+    pub fn parse_uri(uri: &str) -> Option<(Self, String)> {
+        let rest = uri.strip_prefix("s3://")?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return None;
+        }
+        Some((Self { bucket: bucket.to_string() }, prefix.to_string()))
+    }
+
+    pub fn path(&self, key: &str) -> PathBuf {
+        PathBuf::from(format!("s3://{}/{}", self.bucket, key))
+    }
+
+    // the key of an `s3://` path of this bucket
+    pub fn key(&self, path: &Path) -> Option<String> {
+        let key = path.to_str()?.strip_prefix("s3://")?.strip_prefix(self.bucket.as_str())?.strip_prefix('/')?;
+        Some(key.to_string())
+    }
+
+    pub fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        // the command line follows the continuation tokens itself
+        let output = aws(&["s3api", "list-objects-v2", "--bucket", &self.bucket, "--prefix", prefix, "--output", "json"])?;
+        if output.iter().all(|b| b.is_ascii_whitespace()) {
+            return Ok(Vec::new());
+        }
+        let listing: serde_json::Value = serde_json::from_slice(&output)?;
+        // no `Contents` when nothing is under the prefix
+        let keys = listing["Contents"].as_array().map(|objects| {
+            objects.iter().filter_map(|object| object["Key"].as_str().map(|key| key.to_string())).collect()
+        }).unwrap_or_default();
+        Ok(keys)
+    }
+
+    // copy and delete, s3 has no rename. `aws s3 mv` would overwrite an existing destination, which may have been
+    // uploaded since the plan, so it's checked first
+    pub fn move_key(&self, src: &str, dst: &str) -> Result<()> {
+        let (src, dst) = (self.path(src), self.path(dst));
+        if self.exists(&dst).map_err(|e| Error::ExistenceCheckFailed(dst.clone(), e))? {
+            return Err(Error::DestinationExists(dst).into());
+        }
+        aws(&["s3", "mv", "--only-show-errors", &src.to_string_lossy(), &dst.to_string_lossy()])?;
+        Ok(())
+    }
+}
+
+impl ExistenceBackend for S3Bucket {
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        let Some(key) = self.key(path) else {
+            return Ok(false);
+        };
+        let output = Command::new("aws").args(["s3api", "head-object", "--bucket", &self.bucket, "--key", &key])
+            .stdin(Stdio::null()).output()?;
+        if output.status.success() {
+            return Ok(true);
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("Not Found") || stderr.contains("(404)") {
+            Ok(false)
+        } else {
+            Err(io::Error::other(stderr.trim().to_string()))
+        }
+    }
+}
+
+// the longest utf-8 character, a segment keeps its first character at least
+pub const N_MIN_SEGMENT_BYTES: usize = 4;
+
+fn aws(args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("aws").args(args).stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(Error::AwsFailed(String::from_utf8_lossy(&output.stderr).trim().to_string()).into());
+    }
+    Ok(output.stdout)
+}
+
+// the renames (old key, new key) which make every `/` separated segment of the keys fit in `n_segment_bytes`.
+// a prefix is shortened the same way for all the keys under it, and the last segments get counters when taken
+pub fn plan_s3_renames(bucket: &S3Bucket, keys: &[String], n_segment_bytes: usize) -> Result<Vec<(String, String)>> {
+    if n_segment_bytes < N_MIN_SEGMENT_BYTES {
+        return Err(Error::BudgetImpossible { needed: N_MIN_SEGMENT_BYTES, available: n_segment_bytes }.into());
+    }
+    let rules = Rules { n_filename_bytes: n_segment_bytes, ..Rules::load() };
+    let listing = ListingBackend::read(keys.join("\n").as_bytes(), bucket.path(""))?;
+    let mut planner = Planner::new().limit(n_segment_bytes).backend(listing);
+    // a folder marker of the console (`a/`) is a prefix of its own
+    let dirs = keys.iter().filter_map(|key| key.strip_suffix('/').or(key.rsplit_once('/').map(|(dir, _)| dir))).collect::<Vec<_>>();
+    let prefix_map = shorten_prefixes(&dirs, &rules)?;
+    let mut renames = Vec::new();
+    for key in keys {
+        if key.split('/').all(|segment| segment.len() <= n_segment_bytes) {
+            continue;
+        }
+        if let Some(dir) = key.strip_suffix('/') {
+            renames.push((key.clone(), format!("{}/", prefix_map[dir])));
+            continue;
+        }
+        let dir = key.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+        let dst_dir = prefix_map.get(dir).filter(|new_dir| *new_dir != dir).map(|new_dir| bucket.path(new_dir));
+        let entry = planner.plan(bucket.path(key), dst_dir)?;
+        // prefixes aren't created, they are there as long as the keys under them are
+        planner.take_dir_entries();
+        let new_key = bucket.key(&entry.dst).ok_or_else(|| Error::InvalidFormat(entry.dst.display().to_string()))?;
+        renames.push((key.clone(), new_key));
+    }
+    Ok(renames)
+}
+
+// the new prefixes of the directories of the keys. a segment is shortened once for all the prefixes under it, and
+// clear of the prefixes in the bucket and the ones shortened before, so that two long prefixes never merge into one
+fn shorten_prefixes(dirs: &[&str], rules: &Rules) -> Result<HashMap<String, String>> {
+    let mut taken = HashSet::new();
+    for dir in dirs {
+        for (i, _) in dir.match_indices('/') {
+            taken.insert(dir[..i].to_string());
+        }
+        taken.insert(dir.to_string());
+    }
+
+    let mut prefix_map: HashMap<String, String> = HashMap::new();
+    for dir in dirs {
+        let mut prefix = String::new();
+        let mut new_prefix = String::new();
+        for (i, segment) in dir.split('/').enumerate() {
+            let separator = if i == 0 { "" } else { "/" };
+            prefix = format!("{}{}{}", prefix, separator, segment);
+            if let Some(mapped_prefix) = prefix_map.get(&prefix) {
+                new_prefix = mapped_prefix.clone();
+                continue;
+            }
+            let new_segment = if segment.len() <= rules.n_filename_bytes {
+                segment.to_string()
+            } else {
+                shorten_filename_among(segment, rules, |candidate| taken.contains(&format!("{}{}{}", new_prefix, separator, candidate)))?
+            };
+            new_prefix = format!("{}{}{}", new_prefix, separator, new_segment);
+            taken.insert(new_prefix.clone());
+            prefix_map.insert(prefix.clone(), new_prefix.clone());
+        }
+    }
+    Ok(prefix_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_plan_s3_renames() {
+        let _ = env_logger::try_init();
+
+        let (bucket, prefix) = S3Bucket::parse_uri("s3://b/x/").unwrap();
+        assert_eq!(prefix, "x/");
+        assert_eq!(bucket.key(&bucket.path("x/a.txt")), Some("x/a.txt".to_string()));
+        assert!(S3Bucket::parse_uri("s3:///x").is_none());
+
+        let keys = [
+            "x/short.txt",
+            "x/long.nam.a.txt",
+            "x/long.name.a.txt",
+            "x/long.name.b.txt",
+            "x/long.directory.name/a.txt",
+        ].iter().map(|key| key.to_string()).collect::<Vec<_>>();
+        assert_eq!(plan_s3_renames(&bucket, &keys, 14).unwrap(), vec![
+            ("x/long.name.a.txt".to_string(), "x/long.n.a.1.txt".to_string()),
+            ("x/long.name.b.txt".to_string(), "x/long.nam.b.txt".to_string()),
+            ("x/long.directory.name/a.txt".to_string(), "x/long.dire.name/a.txt".to_string()),
+        ]);
+
+        // two long prefixes shortened into the same one get apart, rather than merging the keys under them
+        let keys = [
+            "x/long.directory.name.a/a.txt",
+            "x/long.directory.name.b/a.txt",
+        ].iter().map(|key| key.to_string()).collect::<Vec<_>>();
+        let renames = plan_s3_renames(&bucket, &keys, 14).unwrap();
+        let new_dirs = renames.iter().map(|(_, new_key)| new_key.rsplit_once('/').unwrap().0).collect::<HashSet<_>>();
+        assert_eq!(new_dirs.len(), 2);
+
+        // too small for a segment to keep its first character, an error rather than a panic
+        assert!(plan_s3_renames(&bucket, &["x/éééééé/a.txt".to_string()], 1).is_err());
+    }
+}
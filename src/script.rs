@@ -0,0 +1,251 @@
+use std::{path::Path, io::{self, Write}, os::unix::ffi::OsStrExt};
+
+use crate::{PlanEntry, PlanKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ScriptShell {
+    Bash,
+    Powershell,
+    Cmd,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptOptions {
+    pub shell: ScriptShell,
+    // copy instead of move
+    pub copy: bool,
+    // remove the sources of duplicates, otherwise they are left as they are
+    pub delete_duplicates: bool,
+    // move with `git mv` the files tracked in a git work tree, the others are moved as usual
+    pub git: bool,
+}
+
+// writes the plan as a shell script, so that the renames can be reviewed and run separately.
+// the script never overwrites existing files, and creates only the directories planned by `PlanKind::CreateDir` entries.
+// powershell and cmd scripts are for running the plan on a windows file server, `/` in paths are written as `\`, and a
+// non UTF-8 path fails the whole script.
+pub fn write_script(writer: impl Write, plan: &[PlanEntry], options: &ScriptOptions) -> io::Result<()> {
+    match options.shell {
+        ScriptShell::Bash => write_bash_script(writer, plan, options),
+        ScriptShell::Powershell => write_powershell_script(writer, plan, options),
+        ScriptShell::Cmd => write_cmd_script(writer, plan, options),
+    }
+}
+
+fn write_bash_script(mut writer: impl Write, plan: &[PlanEntry], options: &ScriptOptions) -> io::Result<()> {
+    writeln!(writer, "#!/usr/bin/env bash")?;
+    writeln!(writer, "# generated by {} {}", clap::crate_name!(), clap::crate_version!())?;
+    writeln!(writer, "set -eu")?;
+    if options.git {
+        writeln!(writer, "git_mv() {{ if git ls-files --error-unmatch -- \"$1\" >/dev/null 2>&1; then git mv -- \"$1\" \"$2\"; else mv -n -- \"$1\" \"$2\"; fi; }}")?;
+    }
+    for entry in plan {
+        let src = bash_quote(&entry.src);
+        let dst = bash_quote(&entry.dst);
+        if entry.kind == PlanKind::CreateDir {
+            writeln!(writer, "mkdir -p -- {}", dst)?;
+            continue;
+        }
+        if entry.duplicate {
+            if options.delete_duplicates {
+                writeln!(writer, "rm -- {}  # same as {}", src, dst)?;
+            } else {
+                writeln!(writer, "# duplicate of {}: {}", dst, src)?;
+            }
+            continue;
+        }
+        if options.copy {
+            writeln!(writer, "cp -n --reflink=auto -- {} {}", src, dst)?;
+        } else if options.git {
+            writeln!(writer, "git_mv {} {}", src, dst)?;
+        } else {
+            writeln!(writer, "mv -n -- {} {}", src, dst)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_powershell_script(mut writer: impl Write, plan: &[PlanEntry], options: &ScriptOptions) -> io::Result<()> {
+    // without BOM, windows powershell reads the script in the ANSI code page
+    write!(writer, "\u{feff}")?;
+    writeln!(writer, "# generated by {} {}", clap::crate_name!(), clap::crate_version!())?;
+    writeln!(writer, "$ErrorActionPreference = 'Stop'")?;
+    if options.git {
+        writeln!(writer, "function Move-GitItem($src, $dst) {{ git ls-files --error-unmatch -- $src *> $null; if ($LASTEXITCODE -eq 0) {{ git mv -- $src $dst }} else {{ Move-Item -LiteralPath $src -Destination $dst }} }}")?;
+    }
+    for entry in plan {
+        let src = powershell_quote(&entry.src)?;
+        let dst = powershell_quote(&entry.dst)?;
+        if entry.kind == PlanKind::CreateDir {
+            writeln!(writer, "New-Item -ItemType Directory -Force -Path {} | Out-Null", dst)?;
+            continue;
+        }
+        if entry.duplicate {
+            if options.delete_duplicates {
+                writeln!(writer, "Remove-Item -LiteralPath {}  # same as {}", src, dst)?;
+            } else {
+                writeln!(writer, "# duplicate of {}: {}", dst, src)?;
+            }
+            continue;
+        }
+        if options.git && !options.copy {
+            writeln!(writer, "if (-not (Test-Path -LiteralPath {1})) {{ Move-GitItem {0} {1} }}", src, dst)?;
+            continue;
+        }
+        let command = if options.copy { "Copy-Item" } else { "Move-Item" };
+        writeln!(writer, "if (-not (Test-Path -LiteralPath {1})) {{ {2} -LiteralPath {0} -Destination {1} }}", src, dst, command)?;
+    }
+    Ok(())
+}
+
+fn write_cmd_script(mut writer: impl Write, plan: &[PlanEntry], options: &ScriptOptions) -> io::Result<()> {
+    writeln!(writer, "@echo off\r")?;
+    writeln!(writer, "rem generated by {} {}\r", clap::crate_name!(), clap::crate_version!())?;
+    // the script is written in UTF-8
+    writeln!(writer, "chcp 65001 >nul\r")?;
+    for entry in plan {
+        let src = cmd_quote(&entry.src)?;
+        let dst = cmd_quote(&entry.dst)?;
+        if entry.kind == PlanKind::CreateDir {
+            writeln!(writer, "if not exist {0} mkdir {0}\r", dst)?;
+            continue;
+        }
+        if entry.duplicate {
+            if options.delete_duplicates {
+                writeln!(writer, "del {}\r", src)?;
+            } else {
+                writeln!(writer, "rem duplicate of {}: {}\r", dst, src)?;
+            }
+            continue;
+        }
+        if options.git && !options.copy {
+            writeln!(writer, "if not exist {1} (git ls-files --error-unmatch -- {0} >nul 2>&1 && git mv -- {0} {1} || move {0} {1})\r", src, dst)?;
+            continue;
+        }
+        let command = if options.copy { "copy" } else { "move" };
+        writeln!(writer, "if not exist {1} {2} {0} {1}\r", src, dst, command)?;
+    }
+    Ok(())
+}
+
+// powershell takes the curly single quotes (U+2018 to U+201B) as `'` too, they are doubled the same way
+fn powershell_quote(path: &Path) -> io::Result<String> {
+    let mut quoted = String::from("'");
+    for c in windows_path(path)?.chars() {
+        if matches!(c, '\'' | '\u{2018}'..='\u{201b}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    Ok(quoted)
+}
+
+// `%` has to be doubled in batch files, `"` is rejected by `windows_path`
+fn cmd_quote(path: &Path) -> io::Result<String> {
+    Ok(format!("\"{}\"", windows_path(path)?.replace('%', "%%")))
+}
+
+// windows names are unicode, a non UTF-8 name can't be written as the same name there. neither can the characters
+// windows doesn't allow in names, which linux does, and `"` in a name would end the quoting of cmd
+fn windows_path(path: &Path) -> io::Result<String> {
+    let path = path.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Non UTF-8 path: {}", path.display())))?;
+    if let Some(c) = path.chars().find(|c| matches!(c, '"' | '<' | '>' | '|' | ':' | '*' | '?') || c.is_control()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Character not allowed on windows: {:?}: {}", c, path)));
+    }
+    Ok(path.replace('/', "\\"))
+}
+
+// single quotes when possible, ANSI-C quoting ($'...') for control characters and non UTF-8 bytes
+pub(crate) fn bash_quote(path: &Path) -> String {
+    let bytes = path.as_os_str().as_bytes();
+    match std::str::from_utf8(bytes) {
+        Ok(s) if !s.chars().any(|c| c.is_control()) => {
+            format!("'{}'", s.replace('\'', r"'\''"))
+        },
+        _ => {
+            let mut quoted = String::from("$'");
+            for chunk in bytes.utf8_chunks() {
+                for c in chunk.valid().chars() {
+                    match c {
+                        '\'' | '\\' => {
+                            quoted.push('\\');
+                            quoted.push(c);
+                        },
+                        c if c.is_control() => {
+                            let mut buf = [0; 4];
+                            for b in c.encode_utf8(&mut buf).bytes() {
+                                quoted.push_str(&format!("\\x{:02x}", b));
+                            }
+                        },
+                        c => quoted.push(c),
+                    }
+                }
+                for b in chunk.invalid() {
+                    quoted.push_str(&format!("\\x{:02x}", b));
+                }
+            }
+            quoted.push('\'');
+            quoted
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{path::PathBuf, ffi::OsStr};
+
+    #[test]
+    fn test_bash_quote() {
+        assert_eq!(bash_quote(Path::new("a b.txt")), "'a b.txt'");
+        assert_eq!(bash_quote(Path::new("it's.txt")), r"'it'\''s.txt'");
+        assert_eq!(bash_quote(Path::new("a\nb")), r"$'a\x0ab'");
+        assert_eq!(bash_quote(Path::new(OsStr::from_bytes(b"\xff'\\"))), r"$'\xff\'\\'");
+    }
+
+    #[test]
+    fn test_windows_quote() {
+        assert_eq!(powershell_quote(Path::new("a/it's.txt")).unwrap(), "'a\\it''s.txt'");
+        assert_eq!(powershell_quote(Path::new("\u{2018}a\u{2019}.txt")).unwrap(), "'\u{2018}\u{2018}a\u{2019}\u{2019}.txt'");
+        assert_eq!(cmd_quote(Path::new("a/100%.txt")).unwrap(), "\"a\\100%%.txt\"");
+        assert!(powershell_quote(Path::new(OsStr::from_bytes(b"\xff.txt"))).is_err());
+        assert!(cmd_quote(Path::new(OsStr::from_bytes(b"\xff.txt"))).is_err());
+        assert!(cmd_quote(Path::new("x\" & del /q * & \".txt")).is_err());
+        assert!(powershell_quote(Path::new("a?.txt")).is_err());
+        assert!(cmd_quote(Path::new("a\tb.txt")).is_err());
+    }
+
+    #[test]
+    fn test_write_script() {
+        let plan = vec![
+            PlanEntry::create_dir("b", "a"),
+            PlanEntry { kind: PlanKind::Rename, src: PathBuf::from("a/x.txt"), dst: PathBuf::from("b/y.txt"), duplicate: false, conflict: false },
+            PlanEntry { kind: PlanKind::Rename, src: PathBuf::from("z.txt"), dst: PathBuf::from("b/z.txt"), duplicate: true, conflict: false },
+        ];
+        let mut script = Vec::new();
+        write_script(&mut script, &plan, &ScriptOptions { shell: ScriptShell::Bash, copy: false, delete_duplicates: true, git: false }).unwrap();
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.ends_with("mkdir -p -- 'b'\nmv -n -- 'a/x.txt' 'b/y.txt'\nrm -- 'z.txt'  # same as 'b/z.txt'\n"));
+
+        let mut script = Vec::new();
+        write_script(&mut script, &plan, &ScriptOptions { shell: ScriptShell::Powershell, copy: false, delete_duplicates: false, git: false }).unwrap();
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.starts_with('\u{feff}'));
+        assert!(script.ends_with("New-Item -ItemType Directory -Force -Path 'b' | Out-Null\nif (-not (Test-Path -LiteralPath 'b\\y.txt')) { Move-Item -LiteralPath 'a\\x.txt' -Destination 'b\\y.txt' }\n# duplicate of 'b\\z.txt': 'z.txt'\n"));
+
+        let plan = vec![
+            PlanEntry { kind: PlanKind::Rename, src: PathBuf::from("a/100%.txt"), dst: PathBuf::from("a/100%.1.txt"), duplicate: false, conflict: false },
+        ];
+        let mut script = Vec::new();
+        write_script(&mut script, &plan, &ScriptOptions { shell: ScriptShell::Cmd, copy: true, delete_duplicates: false, git: false }).unwrap();
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.ends_with("chcp 65001 >nul\r\nif not exist \"a\\100%%.1.txt\" copy \"a\\100%%.txt\" \"a\\100%%.1.txt\"\r\n"));
+
+        let mut script = Vec::new();
+        write_script(&mut script, &plan, &ScriptOptions { shell: ScriptShell::Bash, copy: false, delete_duplicates: false, git: true }).unwrap();
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("git_mv() {"));
+        assert!(script.ends_with("git_mv 'a/100%.txt' 'a/100%.1.txt'\n"));
+    }
+}
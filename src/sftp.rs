@@ -0,0 +1,100 @@
+use std::{path::{Path, PathBuf}, io, net::TcpStream};
+use ssh2::{Session, Sftp, ErrorCode, KnownHostFileKind, CheckResult};
+
+use crate::ExistenceBackend;
+
+// LIBSSH2_FX_NO_SUCH_FILE
+const FX_NO_SUCH_FILE: i32 = 2;
+
+const DEFAULT_SSH_PORT: u16 = 22;
+
+// checks on a host over one sftp session, authenticated by the ssh agent. the host key has to be in
+// ~/.ssh/known_hosts, as ssh asks for it interactively, which a check in the middle of a plan can't
+pub struct SftpBackend {
+    host: String,
+    // the session has to live as long as the sftp channel
+    _session: Session,
+    sftp: Sftp,
+}
+
+impl std::fmt::Debug for SftpBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SftpBackend").field("host", &self.host).finish()
+    }
+}
+
+impl SftpBackend {
+    // `[user@]host[:port]`, the user of this machine by default
+    pub fn connect(spec: &str) -> io::Result<Self> {
+        let (user, host, port) = parse_host(spec).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Not [user@]host[:port]: {}", spec)))?;
+        let user = match user {
+            Some(user) => user.to_string(),
+            None => std::env::var("USER").map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("No user for {}", spec)))?,
+        };
+        let mut session = Session::new()?;
+        session.set_tcp_stream(TcpStream::connect((host, port))?);
+        session.handshake()?;
+        check_host_key(&session, host, port)?;
+        session.userauth_agent(&user)?;
+        let sftp = session.sftp()?;
+        Ok(Self { host: spec.to_string(), _session: session, sftp })
+    }
+}
+
+impl ExistenceBackend for SftpBackend {
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        // lstat, a dangling symlink is there too. the path goes as its bytes, not UTF-8 only
+        match self.sftp.lstat(path) {
+            Ok(_) => Ok(true),
+            Err(e) if e.code() == ErrorCode::SFTP(FX_NO_SUCH_FILE) => Ok(false),
+            Err(e) => Err(io::Error::other(format!("sftp {} failed: {}", self.host, e))),
+        }
+    }
+}
+
+fn check_host_key(session: &Session, host: &str, port: u16) -> io::Result<()> {
+    let known_hosts_path = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".ssh/known_hosts"))
+        .ok_or_else(|| io::Error::other("No home directory for ~/.ssh/known_hosts"))?;
+    let mut known_hosts = session.known_hosts()?;
+    known_hosts.read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)?;
+    let (key, _) = session.host_key().ok_or_else(|| io::Error::other(format!("No host key from {}", host)))?;
+    match known_hosts.check_port(host, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(io::Error::other(format!("Host key of {} doesn't match {}", host, known_hosts_path.display()))),
+        CheckResult::NotFound | CheckResult::Failure => Err(io::Error::other(format!("Host key of {} not in {}, connect with ssh once first", host, known_hosts_path.display()))),
+    }
+}
+
+// (user, host, port) of `[user@]host[:port]`
+fn parse_host(spec: &str) -> Option<(Option<&str>, &str, u16)> {
+    let (user, rest) = match spec.split_once('@') {
+        Some((user, rest)) if !user.is_empty() => (Some(user), rest),
+        Some(_) => return None,
+        None => (None, spec),
+    };
+    let (host, port) = match rest.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (rest, DEFAULT_SSH_PORT),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((user, host, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_parse_host() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(parse_host("example.com"), Some((None, "example.com", 22)));
+        assert_eq!(parse_host("me@example.com:2222"), Some((Some("me"), "example.com", 2222)));
+        assert_eq!(parse_host("@example.com"), None);
+        assert_eq!(parse_host("example.com:ssh"), None);
+        assert_eq!(parse_host(":22"), None);
+    }
+}
@@ -0,0 +1,120 @@
+use std::{path::{Path, PathBuf}, fs, io::{self, Write}, collections::HashMap};
+
+use crate::{exceeds_limit, new_filename};
+use crate::journal::{escape_path, unescape_path};
+
+// the filename of the preload library built from shim/, looked for next to the binary
+pub const SHIM_LIBRARY_FILENAME: &str = "librename_for_linux_limit_shim.so";
+
+// the file of the names shortened by the processes of a shim run, set by the shim subcommand. a process finds there
+// the files created by the others by their long names, as `sh -c 'echo > LONG; cat LONG'` does
+pub const SHIM_NAMES_ENV: &str = "RENAME_FOR_LINUX_LIMIT_SHIM_NAMES";
+
+// what the preload library does with the paths a process opens and renames to: a file created under a name over the
+// limit gets the name this crate shortens it into instead, and the process finds it by the long name later too
+#[derive(Debug, Default)]
+pub struct Shim {
+    // the shortened paths by the long ones, absolute
+    shortened: HashMap<PathBuf, PathBuf>,
+    // `long\tshort` lines, escaped as in the journal
+    names_path: Option<PathBuf>,
+}
+
+impl Shim {
+    // with the names file of $RENAME_FOR_LINUX_LIMIT_SHIM_NAMES, if set
+    pub fn new() -> Self {
+        Self { shortened: HashMap::new(), names_path: std::env::var_os(SHIM_NAMES_ENV).filter(|path| !path.is_empty()).map(PathBuf::from) }
+    }
+
+    pub fn with_names_path(path: impl AsRef<Path>) -> Self {
+        Self { shortened: HashMap::new(), names_path: Some(path.as_ref().to_path_buf()) }
+    }
+
+    // the path to use instead, none to use the given one. only a file to create is given a new name, a long name
+    // which isn't one of those fails as it would
+    pub fn path(&mut self, path: &Path, create: bool) -> Option<PathBuf> {
+        let path = std::path::absolute(path).ok()?;
+        if !exceeds_limit(&path, None) {
+            return None;
+        }
+        if !self.shortened.contains_key(&path) {
+            self.read_names();
+        }
+        if let Some(shortened) = self.shortened.get(&path) {
+            return Some(shortened.clone());
+        }
+        if !create {
+            return None;
+        }
+        let filename = match new_filename(&path, None::<&Path>) {
+            Ok(filename) => filename,
+            Err(e) => {
+                log::warn!("Failed to shorten {}: {}", path.display(), e);
+                return None;
+            },
+        };
+        let shortened = path.with_file_name(filename);
+        log::info!("Shortened: {} -> {}", path.display(), shortened.display());
+        if let Err(e) = self.write_name(&path, &shortened) {
+            log::warn!("Failed to write the shortened name: {}", e);
+        }
+        self.shortened.insert(path, shortened.clone());
+        Some(shortened)
+    }
+
+    // the names shortened by the other processes since
+    fn read_names(&mut self) {
+        let Some(names) = self.names_path.as_ref().and_then(|path| fs::read_to_string(path).ok()) else {
+            return;
+        };
+        for line in names.lines() {
+            let Some((long, short)) = line.split_once('\t') else {
+                continue;
+            };
+            if let (Some(long), Some(short)) = (unescape_path(long), unescape_path(short)) {
+                self.shortened.insert(long, short);
+            }
+        }
+    }
+
+    fn write_name(&self, long: &Path, short: &Path) -> io::Result<()> {
+        let Some(path) = &self.names_path else {
+            return Ok(());
+        };
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        // a single write, so that the lines of the processes don't interleave
+        file.write_all(format!("{}\t{}\n", escape_path(long), escape_path(short)).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_shim() {
+        let _ = env_logger::try_init();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut shim = Shim::new();
+        let short = dir.path().join("short.txt");
+        assert_eq!(shim.path(&short, true), None);
+
+        let long = dir.path().join(format!("{}.txt", "long ".repeat(60)));
+        assert_eq!(shim.path(&long, false), None);
+        let shortened = shim.path(&long, true).unwrap();
+        assert_eq!(shortened.parent(), Some(dir.path()));
+        assert!(!exceeds_limit(&shortened, None));
+        // opened again by the long name
+        assert_eq!(shim.path(&long, false), Some(shortened.clone()));
+
+        // by another process
+        let names_path = dir.path().join("names.tsv");
+        let mut shim = Shim::with_names_path(&names_path);
+        let other_long = dir.path().join(format!("{}.txt", "other ".repeat(60)));
+        let other_shortened = shim.path(&other_long, true).unwrap();
+        assert_eq!(Shim::with_names_path(&names_path).path(&other_long, false), Some(other_shortened));
+        assert_eq!(Shim::with_names_path(&names_path).path(&long, false), None);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+// names as long as the limit allows which tend to break storage stacks and shortening alike, for gen-test-names.
+// each is exactly `n_bytes` long unless noted, the unit is repeated as long as it fits and the rest is padded with `x`
+pub fn test_names(n_bytes: usize) -> Vec<OsString> {
+    let mut names = vec![
+        // plain ascii, the baseline
+        fill("a", ".txt", n_bytes),
+        // 3 bytes a character, the limit falls in the middle of one unless cut right
+        fill("あ", ".txt", n_bytes),
+        // decomposed (NFD) accents, a cut between the base and its mark changes the letter
+        fill("e\u{301}", ".txt", n_bytes),
+        // stacked combining marks, a single grapheme of many code points
+        fill("a\u{300}\u{301}\u{302}\u{303}", ".txt", n_bytes),
+        // a family, seven code points joined by zero width joiners into one emoji
+        fill("\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{200d}\u{1f466}", ".jpg", n_bytes),
+        // regional indicators, a cut between the pair turns a flag into two letters
+        fill("\u{1f1ef}\u{1f1f5}", ".png", n_bytes),
+        // skin tone modifiers
+        fill("\u{1f44d}\u{1f3fd}", ".png", n_bytes),
+        // every delimiter the tags are split by
+        fill("tag.tag_tag-tag tag[tag](tag)", ".mkv", n_bytes),
+        // empty components between the delimiters
+        fill("a..b__c--", ".mkv", n_bytes),
+        // full-width ascii and half-width katakana, changed by compatibility_folding and kana_width
+        fill("ＡＢＣｱｲｳ", ".txt", n_bytes),
+        // hidden, no extension
+        fill(".hidden", "", n_bytes),
+        // an extension as long as the rest
+        fill("a", &format!(".{}", "e".repeat(n_bytes / 2)), n_bytes),
+        // trailing dots and spaces, which some filesystems and protocols strip
+        fill("a", ". .", n_bytes),
+        // right-to-left text and a direction override
+        fill("\u{5e9}\u{5dc}\u{5d5}\u{5dd}\u{202e}", ".txt", n_bytes),
+    ];
+    // invalid utf-8: latin-1 bytes, a lone continuation byte and a truncated sequence
+    for unit in [&b"caf\xe9"[..], b"\x80a", b"\xe3\x81"] {
+        names.push(OsString::from_vec(fill_bytes(unit, b".txt", n_bytes)));
+    }
+    names
+}
+
+fn fill(unit: &str, suffix: &str, n_bytes: usize) -> OsString {
+    OsString::from_vec(fill_bytes(unit.as_bytes(), suffix.as_bytes(), n_bytes))
+}
+
+fn fill_bytes(unit: &[u8], suffix: &[u8], n_bytes: usize) -> Vec<u8> {
+    let n_units = n_bytes.saturating_sub(suffix.len()) / unit.len().max(1);
+    let mut name = unit.repeat(n_units.max(1));
+    name.resize(n_bytes.saturating_sub(suffix.len()).max(name.len()), b'x');
+    name.extend_from_slice(suffix);
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_test_names() {
+        let _ = env_logger::try_init();
+
+        let names = test_names(255);
+        assert!(names.iter().all(|name| name.len() <= 255));
+        assert!(names.iter().filter(|name| name.len() == 255).count() >= 14);
+        assert!(names.iter().any(|name| name.to_str().is_none()));
+        assert!(names.iter().all(|name| !name.as_encoded_bytes().contains(&b'/') && !name.as_encoded_bytes().contains(&0)));
+        let mut unique = names.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), names.len());
+
+        assert_eq!(fill("あ", ".txt", 12), "ああxx.txt");
+        assert_eq!(fill("ab", "", 5), "ababx");
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::Result;
+
+use crate::{ShortenOptions, Rules, shorten_filename};
+
+// converts a URL into a filename: the host and the path segments joined by `_`, with the query
+// (which is usually long and meaningless) replaced by its hash. the fragment is dropped.
+// e.g. `https://example.com/a/b.html?x=1` -> `example.com_a_b.<hash>.html`
+pub fn filename_from_url(url: &str, options: &ShortenOptions) -> Result<String> {
+    filename_from_url_impl(url, &options.rules())
+}
+
+fn filename_from_url_impl(url: &str, rules: &Rules) -> Result<String> {
+    let url = url.split_once('#').map_or(url, |(url, _)| url);
+    let (url, query) = match url.split_once('?') {
+        Some((url, query)) => (url, Some(query)),
+        None => (url, None),
+    };
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+
+    let segments: Vec<String> = url.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| sanitize(&percent_decode(segment)))
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    let mut filename = segments.join("_");
+
+    if let Some(query) = query.filter(|query| !query.is_empty()) {
+        let mut hasher = crate::crc32::Hasher::new();
+        hasher.update(query.as_bytes());
+        let hash = format!("{:08x}", hasher.finalize());
+
+        // the hash goes before the extension of the last segment, so the type of the file is still seen
+        let last_segment = segments.last().map(String::as_str).unwrap_or("");
+        match last_segment.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => {
+                filename.truncate(filename.len() - extension.len() - 1);
+                filename = format!("{}.{}.{}", filename, hash, extension);
+            },
+            _ => {
+                filename = format!("{}.{}", filename, hash);
+            },
+        }
+    }
+
+    if filename.is_empty() {
+        filename = "_".to_string();
+    }
+    shorten_filename(&filename, rules)
+}
+
+// converts arbitrary text (mail subjects, chat messages, note titles) into a filename.
+// whitespace including newlines is collapsed into single spaces, and the rest goes as `sanitize` does.
+pub fn filename_from_text(text: &str, options: &ShortenOptions) -> Result<String> {
+    filename_from_text_impl(text, &options.rules())
+}
+
+fn filename_from_text_impl(text: &str, rules: &Rules) -> Result<String> {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut filename = sanitize(&text).trim_start().to_string();
+    if filename.is_empty() {
+        filename = "_".to_string();
+    }
+    shorten_filename(&filename, rules)
+}
+
+// makes the text usable as a single path component
+fn sanitize(s: &str) -> String {
+    let s: String = s.chars().map(|c| if c == '/' { '_' } else { c }).filter(|c| !c.is_control()).collect();
+    // `.` and `..` are not filenames, and a leading `.` hides the file
+    s.trim_start_matches('.').to_string()
+}
+
+// invalid escapes are left as they are, bytes which are not UTF-8 are replaced
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(byte) = std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_filename_from_url() {
+        let _ = env_logger::try_init();
+
+        let rules = Rules::default();
+        assert_eq!(filename_from_url_impl("https://example.com/a/b.html", &rules).unwrap(), "example.com_a_b.html");
+        assert_eq!(filename_from_url_impl("https://example.com/a//b/#top", &rules).unwrap(), "example.com_a_b");
+        assert_eq!(filename_from_url_impl("http://example.com/%E3%81%82%2F%zz", &rules).unwrap(), "example.com_あ_%zz");
+        assert_eq!(filename_from_url_impl("https://example.com/..", &rules).unwrap(), "example.com");
+
+        let with_query = filename_from_url_impl("https://example.com/a/b.html?x=1", &rules).unwrap();
+        assert!(with_query.starts_with("example.com_a_b.") && with_query.ends_with(".html"));
+        assert_ne!(with_query, filename_from_url_impl("https://example.com/a/b.html?x=2", &rules).unwrap());
+        assert_eq!(with_query, filename_from_url_impl("https://example.com/a/b.html?x=1#top", &rules).unwrap());
+
+        let long_url = format!("https://example.com/{}.html?{}", "あ".repeat(100), "x".repeat(1000));
+        assert!(filename_from_url_impl(&long_url, &rules).unwrap().len() <= crate::N_FILENAME_BYTES);
+    }
+
+    #[test]
+    fn test_filename_from_text() {
+        let _ = env_logger::try_init();
+
+        let rules = Rules::default();
+        assert_eq!(filename_from_text_impl("Re: report\n\t2024/05\u{0}", &rules).unwrap(), "Re: report 2024_05");
+        assert_eq!(filename_from_text_impl("  ... hidden", &rules).unwrap(), "hidden");
+        assert_eq!(filename_from_text_impl("..", &rules).unwrap(), "_");
+        assert_eq!(filename_from_text_impl(" \n ", &rules).unwrap(), "_");
+
+        let long_text = "あ".repeat(100);
+        assert_eq!(filename_from_text_impl(&long_text, &rules).unwrap(), "あ".repeat(85));
+    }
+}
@@ -0,0 +1,335 @@
+use std::{io, path::Path, collections::HashSet};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+
+use crate::{PlanEntry, PlanKind, Planner};
+
+const HELP: &str = "space: toggle  a: toggle shown  /: search  e: edit name  y: apply  q: quit";
+
+// reviews the plan in the terminal, the renames can be searched, turned off and given other names, which have to fit
+// what the planner shortens into. the plan to apply, or none when the review is quit. the directories to create are
+// kept for the renames left only
+pub fn review_plan(plan: Vec<PlanEntry>, planner: &Planner) -> io::Result<Option<Vec<PlanEntry>>> {
+    let mut review = Review::new(plan, planner);
+    let mut terminal = ratatui::try_init()?;
+    let result = (|| -> io::Result<bool> {
+        loop {
+            terminal.draw(|frame| review.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(apply) = review.handle_key(key.code, key.modifiers) {
+                    return Ok(apply);
+                }
+            }
+        }
+    })();
+    ratatui::try_restore()?;
+    Ok(result?.then(|| review.into_plan()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mode {
+    Browse,
+    Search,
+    // the new name being typed
+    Edit(String),
+}
+
+#[derive(Debug)]
+struct ReviewEntry {
+    entry: PlanEntry,
+    enabled: bool,
+    edited: bool,
+}
+
+#[derive(Debug)]
+struct Review<'a> {
+    planner: &'a Planner,
+    // the renames which change something, the directories to create are held apart
+    entries: Vec<ReviewEntry>,
+    dir_entries: Vec<PlanEntry>,
+    // the other entries of the plan, renamed to themselves
+    unchanged_entries: Vec<PlanEntry>,
+    filter: String,
+    mode: Mode,
+    // the indices of `entries` matching the filter, and the selected one of them
+    visible: Vec<usize>,
+    list_state: ListState,
+    message: Option<String>,
+}
+
+impl<'a> Review<'a> {
+    fn new(plan: Vec<PlanEntry>, planner: &'a Planner) -> Self {
+        let mut entries = Vec::new();
+        let mut dir_entries = Vec::new();
+        let mut unchanged_entries = Vec::new();
+        for entry in plan {
+            if entry.kind == PlanKind::CreateDir {
+                dir_entries.push(entry);
+            } else if entry.src == entry.dst && !entry.duplicate {
+                unchanged_entries.push(entry);
+            } else {
+                entries.push(ReviewEntry { entry, enabled: true, edited: false });
+            }
+        }
+        let mut review = Self {
+            planner,
+            entries,
+            dir_entries,
+            unchanged_entries,
+            filter: String::new(),
+            mode: Mode::Browse,
+            visible: Vec::new(),
+            list_state: ListState::default(),
+            message: None,
+        };
+        review.update_visible();
+        review
+    }
+
+    fn update_visible(&mut self) {
+        let filter = self.filter.to_lowercase();
+        self.visible = (0..self.entries.len()).filter(|&i| {
+            let entry = &self.entries[i].entry;
+            filter.is_empty()
+                || entry.src.to_string_lossy().to_lowercase().contains(&filter)
+                || entry.dst.to_string_lossy().to_lowercase().contains(&filter)
+        }).collect();
+        let selected = self.list_state.selected().unwrap_or(0).min(self.visible.len().saturating_sub(1));
+        self.list_state.select(if self.visible.is_empty() { None } else { Some(selected) });
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.list_state.selected().and_then(|i| self.visible.get(i)).copied()
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let selected = self.list_state.selected().unwrap_or(0).saturating_add_signed(delta).min(self.visible.len() - 1);
+        self.list_state.select(Some(selected));
+    }
+
+    // some(true) to apply, some(false) to quit, none to go on
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> Option<bool> {
+        if modifiers.contains(KeyModifiers::CONTROL) && code == KeyCode::Char('c') {
+            return Some(false);
+        }
+        match self.mode.clone() {
+            Mode::Browse => return self.handle_browse_key(code),
+            Mode::Search => match code {
+                KeyCode::Enter => self.mode = Mode::Browse,
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.mode = Mode::Browse;
+                },
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                },
+                KeyCode::Char(c) => self.filter.push(c),
+                _ => (),
+            },
+            Mode::Edit(mut name) => match code {
+                KeyCode::Enter => {
+                    self.mode = Mode::Browse;
+                    if let Err(message) = self.rename_selected(&name) {
+                        self.message = Some(message);
+                    }
+                },
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Backspace => {
+                    name.pop();
+                    self.mode = Mode::Edit(name);
+                },
+                KeyCode::Char(c) => {
+                    name.push(c);
+                    self.mode = Mode::Edit(name);
+                },
+                _ => (),
+            },
+        }
+        self.update_visible();
+        None
+    }
+
+    fn handle_browse_key(&mut self, code: KeyCode) -> Option<bool> {
+        self.message = None;
+        match code {
+            KeyCode::Char('y') => return Some(true),
+            KeyCode::Char('q') | KeyCode::Esc => return Some(false),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-20),
+            KeyCode::PageDown => self.move_selection(20),
+            KeyCode::Home => self.move_selection(isize::MIN),
+            KeyCode::End => self.move_selection(isize::MAX),
+            KeyCode::Char(' ') => {
+                if let Some(i) = self.selected() {
+                    self.entries[i].enabled = !self.entries[i].enabled;
+                    self.move_selection(1);
+                }
+            },
+            // all the shown ones on, or off when they are all on already
+            KeyCode::Char('a') => {
+                let enabled = !self.visible.iter().all(|&i| self.entries[i].enabled);
+                for &i in &self.visible {
+                    self.entries[i].enabled = enabled;
+                }
+            },
+            KeyCode::Char('/') => self.mode = Mode::Search,
+            KeyCode::Char('e') => match self.selected().map(|i| &self.entries[i].entry) {
+                // a duplicate is deleted or left, it has no name of its own
+                Some(entry) if entry.duplicate => self.message = Some("A duplicate keeps the name of the file it duplicates".to_string()),
+                Some(entry) => self.mode = Mode::Edit(entry.dst.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default()),
+                None => (),
+            },
+            _ => (),
+        }
+        None
+    }
+
+    // the new name of the selected entry, which has to fit and must not be taken
+    fn rename_selected(&mut self, name: &str) -> Result<(), String> {
+        let Some(i) = self.selected() else {
+            return Ok(());
+        };
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+            return Err(format!("Not a filename: {}", name));
+        }
+        let dst = self.entries[i].entry.dst.with_file_name(name);
+        if dst == self.entries[i].entry.dst {
+            return Ok(());
+        }
+        if !self.planner.fits(&dst) {
+            return Err(format!("Too long: {}", name));
+        }
+        if self.entries.iter().any(|entry| entry.enabled && entry.entry.dst == dst) || (dst != self.entries[i].entry.src && dst.symlink_metadata().is_ok()) {
+            return Err(format!("Taken: {}", dst.display()));
+        }
+        self.entries[i].entry.dst = dst;
+        self.entries[i].edited = true;
+        Ok(())
+    }
+
+    // the directories to create first, for the destinations left
+    fn into_plan(self) -> Vec<PlanEntry> {
+        let entries = self.entries.into_iter().filter(|entry| entry.enabled).map(|entry| entry.entry).collect::<Vec<_>>();
+        let dsts = entries.iter().filter_map(|entry| entry.dst.parent()).flat_map(Path::ancestors).collect::<HashSet<_>>();
+        let mut plan = self.dir_entries.into_iter().filter(|entry| dsts.contains(entry.dst.as_path())).collect::<Vec<_>>();
+        plan.extend(self.unchanged_entries);
+        plan.extend(entries);
+        plan
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [list_area, status_area] = Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+        let items = self.visible.iter().map(|&i| {
+            let entry = &self.entries[i];
+            let (mark, style) = if entry.enabled { ("[x] ", Style::new()) } else { ("[ ] ", Style::new().add_modifier(Modifier::DIM)) };
+            let dst_style = if entry.edited { style.fg(Color::Yellow) } else { style.fg(Color::Green) };
+            let dst = if entry.entry.duplicate { format!("(duplicate of {})", entry.entry.dst.display()) } else { entry.entry.dst.display().to_string() };
+            ListItem::new(Line::from(vec![
+                Span::styled(mark, style),
+                Span::styled(entry.entry.src.display().to_string(), style),
+                Span::styled(" -> ", style),
+                Span::styled(dst, dst_style),
+            ]))
+        }).collect::<Vec<_>>();
+        let n_enabled = self.entries.iter().filter(|entry| entry.enabled).count();
+        let title = format!(" {} of {} renames, {} shown ", n_enabled, self.entries.len(), self.visible.len());
+        let list = List::new(items).block(Block::bordered().title(title)).highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        let status = match &self.mode {
+            Mode::Browse => match &self.message {
+                Some(message) => Line::from(Span::styled(message.clone(), Style::new().fg(Color::Red))),
+                None if self.filter.is_empty() => Line::from(HELP),
+                None => Line::from(format!("/{}  {}", self.filter, HELP)),
+            },
+            Mode::Search => Line::from(format!("/{}", self.filter)),
+            Mode::Edit(name) => Line::from(format!("name: {}", name)),
+        };
+        let n_status_chars = status.width() as u16;
+        frame.render_widget(Paragraph::new(status), status_area);
+        if self.mode != Mode::Browse {
+            frame.set_cursor_position((status_area.x + n_status_chars.min(status_area.width.saturating_sub(1)), status_area.y));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use env_logger;
+
+    #[test]
+    fn test_review() {
+        let _ = env_logger::try_init();
+
+        let rename = |src: &str, dst: &str| PlanEntry { kind: PlanKind::Rename, src: PathBuf::from(src), dst: PathBuf::from(dst), duplicate: false, conflict: false };
+        // nothing of these is on the filesystem
+        let plan = vec![
+            PlanEntry::create_dir("review/d", "review"),
+            rename("review/a-long.txt", "review/d/a.txt"),
+            rename("review/b-long.txt", "review/b.txt"),
+            rename("review/c.txt", "review/c.txt"),
+        ];
+        let key = |review: &mut Review, code| review.handle_key(code, KeyModifiers::NONE);
+        let type_text = |review: &mut Review, text: &str| for c in text.chars() {
+            review.handle_key(KeyCode::Char(c), KeyModifiers::NONE);
+        };
+
+        let planner = Planner::new().limit(10);
+        let mut review = Review::new(plan.clone(), &planner);
+        assert_eq!(review.visible, vec![0, 1]);
+        // search, then turn the only match off
+        key(&mut review, KeyCode::Char('/'));
+        type_text(&mut review, "A-LONG");
+        key(&mut review, KeyCode::Enter);
+        assert_eq!(review.visible, vec![0]);
+        key(&mut review, KeyCode::Char(' '));
+        assert!(!review.entries[0].enabled);
+        key(&mut review, KeyCode::Char('/'));
+        key(&mut review, KeyCode::Esc);
+        assert_eq!(review.visible, vec![0, 1]);
+
+        // another name for the second, a taken or a broken one is refused
+        key(&mut review, KeyCode::Down);
+        key(&mut review, KeyCode::Char('e'));
+        for _ in 0.."b.txt".len() {
+            key(&mut review, KeyCode::Backspace);
+        }
+        type_text(&mut review, "x/y");
+        key(&mut review, KeyCode::Enter);
+        assert!(review.message.is_some());
+        key(&mut review, KeyCode::Char('e'));
+        type_text(&mut review, ".bak");
+        key(&mut review, KeyCode::Enter);
+        assert_eq!(review.entries[1].entry.dst, PathBuf::from("review/b.txt.bak"));
+        // over the limit of the planner
+        key(&mut review, KeyCode::Char('e'));
+        type_text(&mut review, "up");
+        key(&mut review, KeyCode::Enter);
+        assert!(review.message.is_some());
+        assert_eq!(review.entries[1].entry.dst, PathBuf::from("review/b.txt.bak"));
+
+        // the directory is left out with the rename into it
+        assert_eq!(key(&mut review, KeyCode::Char('y')), Some(true));
+        assert_eq!(review.into_plan(), vec![rename("review/c.txt", "review/c.txt"), rename("review/b-long.txt", "review/b.txt.bak")]);
+
+        let mut review = Review::new(plan.clone(), &planner);
+        assert_eq!(review.into_plan().len(), plan.len());
+        review = Review::new(plan, &planner);
+        assert_eq!(key(&mut review, KeyCode::Char('q')), Some(false));
+    }
+}
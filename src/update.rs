@@ -0,0 +1,106 @@
+use std::{path::Path, process::{Command, Stdio}, fs, os::unix::fs::PermissionsExt};
+use anyhow::Result;
+use clap::crate_name;
+
+use crate::{Error, ChecksumAlgorithm};
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/amachang/rename-for-linux-limit/releases/latest";
+
+// the latest release on github, and its standalone binary for this machine if it has one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    // without the `v` of the tag
+    pub version: String,
+    pub binary_url: Option<String>,
+    // the published sha256 of the binary, `<binary>.sha256`
+    pub checksum_url: Option<String>,
+}
+
+// through the curl command line, which takes the proxies from the environment
+pub fn latest_release() -> Result<Release> {
+    let output = curl(&["-fsSL", "-H", "Accept: application/vnd.github+json", LATEST_RELEASE_URL])?;
+    let release: serde_json::Value = serde_json::from_slice(&output)?;
+    let tag = release["tag_name"].as_str().ok_or_else(|| Error::InvalidFormat("release without tag_name".to_string()))?;
+    let asset_url = |name: &str| release["assets"].as_array().into_iter().flatten()
+        .find(|asset| asset["name"].as_str() == Some(name))
+        .and_then(|asset| asset["browser_download_url"].as_str())
+        .map(str::to_string);
+    let binary_name = binary_asset_name(std::env::consts::ARCH);
+    Ok(Release {
+        version: tag.trim_start_matches('v').to_string(),
+        binary_url: asset_url(&binary_name),
+        checksum_url: asset_url(&format!("{}.sha256", binary_name)),
+    })
+}
+
+// `rename-for-linux-limit-x86_64-unknown-linux-musl`, only the standalone binary, not the archives of the same target
+fn binary_asset_name(arch: &str) -> String {
+    format!("{}-{}-unknown-linux-musl", crate_name!(), arch)
+}
+
+// the checksum of `sha256sum` output, `<hex>  <name>`
+fn published_checksum(text: &str) -> Option<String> {
+    let checksum = text.split_whitespace().next()?;
+    (checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit())).then(|| checksum.to_ascii_lowercase())
+}
+
+// by the numbers of the versions, `0.10.0` is newer than `0.9.1`. a pre-release is as new as its release
+pub fn is_newer_version(version: &str, current: &str) -> bool {
+    let numbers = |version: &str| -> Vec<u64> {
+        version.split(['-', '+']).next().unwrap_or("").split('.').map(|n| n.parse().unwrap_or(0)).collect()
+    };
+    numbers(version) > numbers(current)
+}
+
+// downloads the binary next to the one to replace and moves it over, so that the replacement is atomic and a failed
+// download leaves the old one as it is. the binary has to match the published checksum
+pub fn replace_binary(url: &str, checksum_url: &str, exe: &Path) -> Result<()> {
+    let published = curl(&["-fsSL", checksum_url])?;
+    let expected = published_checksum(&String::from_utf8_lossy(&published)).ok_or_else(|| Error::InvalidFormat(format!("checksum: {}", checksum_url)))?;
+    let tmp = exe.with_file_name(format!(".{}.update-{}", crate_name!(), std::process::id()));
+    let result = (|| -> Result<()> {
+        curl(&["-fsSL", "-o", &tmp.to_string_lossy(), url])?;
+        if ChecksumAlgorithm::Sha256.checksum(&tmp)? != expected {
+            return Err(Error::DownloadChecksumMismatch(url.to_string()).into());
+        }
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755))?;
+        fs::rename(&tmp, exe)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn curl(args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("curl").args(args).stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(Error::CurlFailed(String::from_utf8_lossy(&output.stderr).trim().to_string()).into());
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_versions() {
+        let _ = env_logger::try_init();
+
+        assert!(is_newer_version("0.10.0", "0.9.1"));
+        assert!(is_newer_version("1.0.0", "0.1.0"));
+        assert!(!is_newer_version("0.1.0", "0.1.0"));
+        assert!(!is_newer_version("0.1.0-rc.1", "0.1.0"));
+        assert!(!is_newer_version("0.0.9", "0.1.0"));
+
+        assert_eq!(binary_asset_name("x86_64"), "rename-for-linux-limit-x86_64-unknown-linux-musl");
+
+        let checksum = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        assert_eq!(published_checksum(&format!("{}  rename-for-linux-limit-x86_64-unknown-linux-musl\n", checksum.to_uppercase())), Some(checksum.to_string()));
+        assert_eq!(published_checksum("Not Found"), None);
+        assert_eq!(published_checksum(""), None);
+    }
+}
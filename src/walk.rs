@@ -0,0 +1,262 @@
+use std::{path::{Path, PathBuf}, fs, collections::HashSet, os::unix::fs::MetadataExt};
+use anyhow::Result;
+
+use crate::{load_config, is_nfs_temp_file};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum WalkOrder {
+    #[default]
+    DepthFirst,
+    BreadthFirst,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    pub include_nfs_temp: bool,
+    pub include_dirs: bool,
+    pub order: WalkOrder,
+    // directories on other filesystems than the root (backups, network shares) are not entered, like `du -x`
+    pub one_file_system: bool,
+}
+
+// collects files under the given directory recursively, symlinks are not followed.
+// directories listed in `excluded_dirs` of the config (snapshots by default) are not entered,
+// because everything inside them is read-only.
+//
+// whatever the order is, a directory always comes after everything inside it,
+// so renaming the paths one by one never invalidates the paths not yet renamed.
+//   depth-first:   a/x, a/y, a, b/z, b, c
+//   breadth-first: c, a/x, a/y, b/z, (then directories, deepest first) a, b
+// entries in the same directory are sorted by name.
+pub fn walk(root: impl AsRef<Path>, options: &WalkOptions) -> Result<Vec<PathBuf>> {
+    let config = load_config();
+    walk_impl(root, options, &config.excluded_dirs)
+}
+
+// the same as `walk`, but each path is given to `f` as soon as it's found, so that the paths aren't held all at once
+// in the depth-first order. the directories entered are read whole before `f` gets their entries, so `f` may rename them.
+// the breadth-first order has to collect the directories of each level anyway
+pub fn walk_with(root: impl AsRef<Path>, options: &WalkOptions, mut f: impl FnMut(PathBuf) -> Result<()>) -> Result<()> {
+    let config = load_config();
+    walk_with_impl(root, options, &config.excluded_dirs, &mut f)
+}
+
+// dependency injection for testing
+fn walk_impl(root: impl AsRef<Path>, options: &WalkOptions, excluded_dirs: &HashSet<String>) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    walk_with_impl(root, options, excluded_dirs, &mut |path| {
+        paths.push(path);
+        Ok(())
+    })?;
+    Ok(paths)
+}
+
+fn walk_with_impl(root: impl AsRef<Path>, options: &WalkOptions, excluded_dirs: &HashSet<String>, f: &mut dyn FnMut(PathBuf) -> Result<()>) -> Result<()> {
+    let root = root.as_ref();
+    let root_dev = if options.one_file_system { Some(fs::metadata(root)?.dev()) } else { None };
+    match options.order {
+        WalkOrder::DepthFirst => {
+            walk_dir_depth_first(root, options, excluded_dirs, root_dev, f)?;
+        },
+        WalkOrder::BreadthFirst => {
+            let mut dirs = Vec::new();
+            let mut current_level = vec![root.to_path_buf()];
+            while !current_level.is_empty() {
+                let mut next_level = Vec::new();
+                for dir in current_level {
+                    let (files, subdirs) = match read_dir(&dir, options, excluded_dirs, root_dev) {
+                        Ok(entries) => entries,
+                        // an unreadable subdirectory shouldn't stop the whole walk
+                        Err(e) if dir != root => {
+                            log::warn!("Failed to read directory: {}: {}", dir.display(), e);
+                            continue;
+                        },
+                        Err(e) => return Err(e),
+                    };
+                    for file in files {
+                        f(file)?;
+                    }
+                    next_level.extend(subdirs);
+                }
+                dirs.push(next_level.clone());
+                current_level = next_level;
+            }
+            if options.include_dirs {
+                for dir in dirs.into_iter().rev().flatten() {
+                    f(dir)?;
+                }
+            }
+        },
+    }
+    Ok(())
+}
+
+fn walk_dir_depth_first(dir: &Path, options: &WalkOptions, excluded_dirs: &HashSet<String>, root_dev: Option<u64>, f: &mut dyn FnMut(PathBuf) -> Result<()>) -> Result<()> {
+    let (files, subdirs) = read_dir(dir, options, excluded_dirs, root_dev)?;
+    walk_entries_depth_first(files, subdirs, options, excluded_dirs, root_dev, f)
+}
+
+fn walk_entries_depth_first(files: Vec<PathBuf>, subdirs: Vec<PathBuf>, options: &WalkOptions, excluded_dirs: &HashSet<String>, root_dev: Option<u64>, f: &mut dyn FnMut(PathBuf) -> Result<()>) -> Result<()> {
+    // subdirectories and files are visited in name order together
+    let mut entries = files.into_iter().map(|p| (p, false)).chain(subdirs.into_iter().map(|p| (p, true))).collect::<Vec<_>>();
+    entries.sort();
+
+    for (path, is_dir) in entries {
+        if !is_dir {
+            f(path)?;
+            continue;
+        }
+        match read_dir(&path, options, excluded_dirs, root_dev) {
+            Ok((files, subdirs)) => walk_entries_depth_first(files, subdirs, options, excluded_dirs, root_dev, f)?,
+            // an unreadable subdirectory shouldn't stop the whole walk, unlike a failure of `f`
+            Err(e) => log::warn!("Failed to read directory: {}: {}", path.display(), e),
+        }
+        if options.include_dirs {
+            f(path)?;
+        }
+    }
+    Ok(())
+}
+
+// returns (files, subdirectories), both sorted by name. subdirectories on another device than `root_dev` are skipped
+fn read_dir(dir: &Path, options: &WalkOptions, excluded_dirs: &HashSet<String>, root_dev: Option<u64>) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if excluded_dirs.contains(entry.file_name().to_string_lossy().as_ref()) {
+                log::debug!("Skipped excluded directory: {}", path.display());
+                continue;
+            }
+            if let Some(root_dev) = root_dev {
+                if entry.metadata()?.dev() != root_dev {
+                    log::debug!("Skipped directory on another filesystem: {}", path.display());
+                    continue;
+                }
+            }
+            subdirs.push(path);
+        } else {
+            if !options.include_nfs_temp && is_nfs_temp_file(&path) {
+                log::debug!("Skipped NFS temporary file: {}", path.display());
+                continue;
+            }
+            files.push(path);
+        }
+    }
+    Ok((files, subdirs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_walk() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("b/.zfs/snapshot")).unwrap();
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("b/.zfs/snapshot/x.txt"), "").unwrap();
+        fs::write(root.join("b/y.txt"), "").unwrap();
+        fs::write(root.join("a/.nfs0000000000000001"), "").unwrap();
+        fs::write(root.join("z.txt"), "").unwrap();
+
+        let excluded_dirs = HashSet::from([".zfs".to_string()]);
+        assert_eq!(walk_impl(root, &WalkOptions::default(), &excluded_dirs).unwrap(), vec![
+            root.join("b/y.txt"),
+            root.join("z.txt"),
+        ]);
+        assert_eq!(walk_impl(root, &WalkOptions { include_nfs_temp: true, ..Default::default() }, &HashSet::new()).unwrap(), vec![
+            root.join("a/.nfs0000000000000001"),
+            root.join("b/.zfs/snapshot/x.txt"),
+            root.join("b/y.txt"),
+            root.join("z.txt"),
+        ]);
+        // everything is on the same filesystem as the root
+        assert_eq!(walk_impl(root, &WalkOptions { one_file_system: true, ..Default::default() }, &excluded_dirs).unwrap(), vec![
+            root.join("b/y.txt"),
+            root.join("z.txt"),
+        ]);
+    }
+
+    #[test]
+    fn test_walk_with() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/x.txt"), "").unwrap();
+        fs::write(root.join("a/y.txt"), "").unwrap();
+        fs::write(root.join("z.txt"), "").unwrap();
+
+        // the entries found are renamed on the way
+        let mut paths = Vec::new();
+        walk_with_impl(root, &WalkOptions { include_dirs: true, ..Default::default() }, &HashSet::new(), &mut |path| {
+            fs::rename(&path, path.with_extension("1"))?;
+            paths.push(path);
+            Ok(())
+        }).unwrap();
+        assert_eq!(paths, vec![root.join("a/x.txt"), root.join("a/y.txt"), root.join("a"), root.join("z.txt")]);
+        assert!(root.join("a.1/y.1").exists());
+
+        // a failure of the callback stops the walk
+        let mut n_paths = 0;
+        assert!(walk_with_impl(root, &WalkOptions::default(), &HashSet::new(), &mut |_| {
+            n_paths += 1;
+            Err(anyhow::anyhow!("stop"))
+        }).is_err());
+        assert_eq!(n_paths, 1);
+    }
+
+    #[test]
+    fn test_walk_order() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir_all(root.join("c")).unwrap();
+        fs::write(root.join("a/b/x.txt"), "").unwrap();
+        fs::write(root.join("a/y.txt"), "").unwrap();
+        fs::write(root.join("b.txt"), "").unwrap();
+        fs::write(root.join("c/z.txt"), "").unwrap();
+
+        let options = WalkOptions { include_dirs: true, ..Default::default() };
+        assert_eq!(walk_impl(root, &options, &HashSet::new()).unwrap(), vec![
+            root.join("a/b/x.txt"),
+            root.join("a/b"),
+            root.join("a/y.txt"),
+            root.join("a"),
+            root.join("b.txt"),
+            root.join("c/z.txt"),
+            root.join("c"),
+        ]);
+
+        let options = WalkOptions { include_dirs: true, order: WalkOrder::BreadthFirst, ..Default::default() };
+        assert_eq!(walk_impl(root, &options, &HashSet::new()).unwrap(), vec![
+            root.join("b.txt"),
+            root.join("a/y.txt"),
+            root.join("c/z.txt"),
+            root.join("a/b/x.txt"),
+            root.join("a/b"),
+            root.join("a"),
+            root.join("c"),
+        ]);
+
+        let options = WalkOptions { order: WalkOrder::BreadthFirst, ..Default::default() };
+        assert_eq!(walk_impl(root, &options, &HashSet::new()).unwrap(), vec![
+            root.join("b.txt"),
+            root.join("a/y.txt"),
+            root.join("c/z.txt"),
+            root.join("a/b/x.txt"),
+        ]);
+    }
+}
@@ -0,0 +1,381 @@
+use std::{path::{Path, PathBuf}, fs, io::{self, BufRead, BufReader, Write}, ffi::{OsStr, CString}, collections::{HashMap, HashSet}, os::{fd::{AsRawFd, FromRawFd, OwnedFd}, unix::{ffi::OsStrExt, fs::MetadataExt}}, time::{Duration, Instant}};
+use clap::crate_name;
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+
+use crate::Profile;
+
+// a directory watched by the watch subcommand when none is given, in `watches` of the config by any name. the fields
+// are the options of the subcommand
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+pub struct WatchConfig {
+    pub dir: PathBuf,
+    pub dst_dir: Option<PathBuf>,
+    pub profile: Option<Profile>,
+    // a named rule set in `profiles` of the config
+    pub rules: Option<String>,
+}
+
+impl WatchConfig {
+    // `~/Downloads` is in the home directory
+    pub fn expand_home(mut self) -> Self {
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        self.dir = expand_home(self.dir, home.as_deref());
+        self.dst_dir = self.dst_dir.map(|dst_dir| expand_home(dst_dir, home.as_deref()));
+        self
+    }
+}
+
+fn expand_home(path: PathBuf, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path,
+    }
+}
+
+// a file by its device, inode and modification time. a new file reusing the inode of a deleted one has another time
+type FileKey = (u64, u64, i64, i64);
+
+fn file_key(metadata: &fs::Metadata) -> FileKey {
+    (metadata.dev(), metadata.ino(), metadata.mtime(), metadata.mtime_nsec())
+}
+
+// the files of the watched directories looked at already, kept across restarts so that a restarted watch doesn't
+// shorten them again, which would give a counter to the names taken by themselves
+#[derive(Debug)]
+pub struct WatchState {
+    path: PathBuf,
+    processed: HashSet<FileKey>,
+}
+
+impl WatchState {
+    // `dev ino mtime mtime_nsec` lines, empty when there is no file yet
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut processed = HashSet::new();
+        match fs::File::open(&path) {
+            Ok(file) => for line in BufReader::new(file).lines() {
+                let line = line?;
+                let fields = line.split(' ').map(str::parse::<i64>).collect::<Result<Vec<_>, _>>();
+                match fields.as_deref() {
+                    Ok(&[dev, ino, mtime, mtime_nsec]) => processed.insert((dev as u64, ino as u64, mtime, mtime_nsec)),
+                    _ => {
+                        log::warn!("Broken line in the watch state: {}: {} (ignored)", path.display(), line);
+                        continue;
+                    },
+                };
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Self { path, processed })
+    }
+
+    // $XDG_STATE_HOME/rename-for-linux-limit/watch-state.tsv, ~/.local/state if not set
+    pub fn default_path() -> Option<PathBuf> {
+        let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+        };
+        Some(state_dir.join(crate_name!()).join("watch-state.tsv"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_processed(&self, metadata: &fs::Metadata) -> bool {
+        self.processed.contains(&file_key(metadata))
+    }
+
+    pub fn insert(&mut self, metadata: &fs::Metadata) {
+        self.processed.insert(file_key(metadata));
+    }
+
+    // forgets everything, the files are looked at again
+    pub fn clear(&mut self) {
+        self.processed.clear();
+    }
+
+    // forgets the files which are gone, of the metadata of the ones still there
+    pub fn retain<'a>(&mut self, existing: impl IntoIterator<Item = &'a fs::Metadata>) {
+        let existing = existing.into_iter().map(file_key).collect::<HashSet<_>>();
+        self.processed.retain(|key| existing.contains(key));
+    }
+
+    // through a temporary file, a watch killed while saving keeps the former state
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut file = io::BufWriter::new(fs::File::create(&tmp_path)?);
+        for (dev, ino, mtime, mtime_nsec) in &self.processed {
+            writeln!(file, "{} {} {} {}", dev, ino, mtime, mtime_nsec)?;
+        }
+        file.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+// the files closed after writing and moved into the watched directories. a download or a copy is complete by then
+#[derive(Debug)]
+pub struct Inotify {
+    fd: OwnedFd,
+    dirs: HashMap<i32, PathBuf>,
+}
+
+const INOTIFY_EVENT_HEADER_BYTES: usize = 16;
+
+impl Inotify {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { fd: unsafe { OwnedFd::from_raw_fd(fd) }, dirs: HashMap::new() })
+    }
+
+    pub fn add_dir(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        let c_dir = CString::new(dir.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c_dir.as_ptr(), libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_ONLYDIR) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.dirs.insert(wd, dir.to_path_buf());
+        Ok(())
+    }
+
+    // the paths of the events there are, waiting for them up to the timeout, or until there are some without one.
+    // when events were lost, all the files of the directories
+    pub fn read(&self, timeout: Option<Duration>) -> io::Result<Vec<PathBuf>> {
+        let timeout_ms = timeout.map_or(-1, |timeout| timeout.as_millis().min(i32::MAX as u128) as i32);
+        let mut pollfd = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        loop {
+            match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+                0 => return Ok(Vec::new()),
+                n if 0 < n => break,
+                _ => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                },
+            }
+        }
+
+        let mut paths = Vec::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    break;
+                }
+                return Err(e);
+            }
+            for (wd, mask, name) in parse_events(&buf[..n as usize]) {
+                if mask & libc::IN_Q_OVERFLOW != 0 {
+                    log::warn!("Inotify events were lost, looking at all the files again");
+                    return self.dirs.values().map(|dir| dir_files(dir).map(|files| files.into_iter().map(|(path, _)| path))).collect::<io::Result<Vec<_>>>()
+                        .map(|files| files.into_iter().flatten().collect());
+                }
+                if mask & libc::IN_ISDIR != 0 || name.is_empty() {
+                    continue;
+                }
+                if let Some(dir) = self.dirs.get(&wd) {
+                    paths.push(dir.join(OsStr::from_bytes(name)));
+                }
+            }
+        }
+        Ok(paths)
+    }
+}
+
+// the paths of a burst of events (an archive extracted, a folder copied) together, once no more have come for a while,
+// so that they are planned as one batch. a burst going on and on is still cut after `max_wait`
+#[derive(Debug)]
+pub struct Debouncer {
+    quiet: Duration,
+    max_wait: Duration,
+    pending: Vec<PathBuf>,
+    pending_set: HashSet<PathBuf>,
+    // of the first and the last event of the pending ones
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Debouncer {
+    pub fn new(quiet: Duration, max_wait: Duration) -> Self {
+        Self { quiet, max_wait, pending: Vec::new(), pending_set: HashSet::new(), first: None, last: None }
+    }
+
+    // a path written again is there once, where it came first
+    pub fn push(&mut self, paths: Vec<PathBuf>, now: Instant) {
+        if paths.is_empty() {
+            return;
+        }
+        for path in paths {
+            if self.pending_set.insert(path.clone()) {
+                self.pending.push(path);
+            }
+        }
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+    }
+
+    // how long to wait for more events, forever when nothing is pending
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        let (first, last) = (self.first?, self.last?);
+        let quiet_end = last + self.quiet;
+        let max_end = first + self.max_wait;
+        Some(quiet_end.min(max_end).saturating_duration_since(now))
+    }
+
+    // the pending paths when the burst is over
+    pub fn take_ready(&mut self, now: Instant) -> Option<Vec<PathBuf>> {
+        if self.timeout(now)? != Duration::ZERO {
+            return None;
+        }
+        self.first = None;
+        self.last = None;
+        self.pending_set.clear();
+        Some(std::mem::take(&mut self.pending))
+    }
+}
+
+// (wd, mask, name) of the `struct inotify_event`s, the name without its padding
+fn parse_events(buf: &[u8]) -> Vec<(i32, u32, &[u8])> {
+    let mut events = Vec::new();
+    let mut rest = buf;
+    while INOTIFY_EVENT_HEADER_BYTES <= rest.len() {
+        let field = |i: usize| [rest[i], rest[i + 1], rest[i + 2], rest[i + 3]];
+        let wd = i32::from_ne_bytes(field(0));
+        let mask = u32::from_ne_bytes(field(4));
+        let len = u32::from_ne_bytes(field(12)) as usize;
+        let Some(name) = rest.get(INOTIFY_EVENT_HEADER_BYTES..INOTIFY_EVENT_HEADER_BYTES + len) else {
+            break;
+        };
+        let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+        events.push((wd, mask, name));
+        rest = &rest[INOTIFY_EVENT_HEADER_BYTES + len..];
+    }
+    events
+}
+
+// the regular files directly in the directory, not following symlinks
+pub fn dir_files(dir: impl AsRef<Path>) -> io::Result<Vec<(PathBuf, fs::Metadata)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            files.push((entry.path(), metadata));
+        }
+    }
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_watch_state() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("b.txt"), "b").unwrap();
+        let a = fs::metadata(dir.join("a.txt")).unwrap();
+        let b = fs::metadata(dir.join("b.txt")).unwrap();
+
+        let state_path = dir.join("state/watch-state.tsv");
+        let mut state = WatchState::load(&state_path).unwrap();
+        assert!(!state.is_processed(&a));
+        state.insert(&a);
+        state.insert(&b);
+        state.save().unwrap();
+
+        // a renamed file is the same file
+        fs::rename(dir.join("a.txt"), dir.join("c.txt")).unwrap();
+        let mut state = WatchState::load(&state_path).unwrap();
+        assert!(state.is_processed(&fs::metadata(dir.join("c.txt")).unwrap()));
+        assert!(state.is_processed(&b));
+
+        // the gone ones are forgotten
+        fs::remove_file(dir.join("b.txt")).unwrap();
+        state.retain(dir_files(dir).unwrap().iter().map(|(_, metadata)| metadata));
+        assert!(!state.is_processed(&b));
+        assert!(state.is_processed(&a));
+        state.clear();
+        assert!(!state.is_processed(&a));
+    }
+
+    #[test]
+    fn test_watch_config() {
+        let _ = env_logger::try_init();
+
+        let Some(home) = std::env::var_os("HOME").map(PathBuf::from) else {
+            return;
+        };
+        let config = WatchConfig { dir: PathBuf::from("~/Downloads"), dst_dir: Some(PathBuf::from("/mnt/~")), ..Default::default() }.expand_home();
+        assert_eq!(config.dir, home.join("Downloads"));
+        assert_eq!(config.dst_dir, Some(PathBuf::from("/mnt/~")));
+    }
+
+    #[test]
+    fn test_debouncer() {
+        let _ = env_logger::try_init();
+
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut debouncer = Debouncer::new(Duration::from_millis(100), Duration::from_millis(1000));
+        assert_eq!(debouncer.timeout(start), None);
+        assert_eq!(debouncer.take_ready(start), None);
+
+        debouncer.push(vec![PathBuf::from("a"), PathBuf::from("b")], at(0));
+        debouncer.push(vec![PathBuf::from("a"), PathBuf::from("c")], at(50));
+        assert_eq!(debouncer.timeout(at(60)), Some(Duration::from_millis(90)));
+        assert_eq!(debouncer.take_ready(at(60)), None);
+        assert_eq!(debouncer.take_ready(at(150)), Some(vec![PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("c")]));
+        assert_eq!(debouncer.timeout(at(150)), None);
+
+        // a burst which doesn't stop
+        for ms in (200..1300).step_by(50) {
+            debouncer.push(vec![PathBuf::from(ms.to_string())], at(ms));
+            if let Some(paths) = debouncer.take_ready(at(ms)) {
+                assert_eq!(ms, 1200);
+                assert_eq!(paths.len(), 21);
+            }
+        }
+    }
+
+    #[test]
+    fn test_inotify() {
+        let _ = env_logger::try_init();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let mut inotify = Inotify::new().unwrap();
+        inotify.add_dir(dir).unwrap();
+        assert_eq!(inotify.read(Some(Duration::ZERO)).unwrap(), Vec::<PathBuf>::new());
+
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("b.part"), "b").unwrap();
+        fs::rename(dir.join("b.part"), dir.join("b.txt")).unwrap();
+        fs::create_dir(dir.join("sub")).unwrap();
+        let paths = inotify.read(Some(Duration::from_secs(1))).unwrap();
+        // the rename ends the download, the close of `b.part` comes before it
+        assert_eq!(paths, vec![dir.join("a.txt"), dir.join("b.part"), dir.join("b.txt")]);
+    }
+}
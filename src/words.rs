@@ -0,0 +1,83 @@
+#[cfg(feature = "unicode-words")]
+use unicode_segmentation::UnicodeSegmentation;
+
+// the byte offsets in the text where its words end, in order. unicode word segmentation (UAX #29) has no dictionary,
+// so every kanji and hiragana is a word of its own there. with the `japanese` feature, japanese text is split into
+// its words by the dictionary of lindera instead
+pub(crate) fn word_ends(text: &str) -> Vec<usize> {
+    #[cfg(feature = "japanese")]
+    if text.chars().any(japanese::is_japanese) {
+        if let Some(ends) = japanese::word_ends(text) {
+            return ends;
+        }
+    }
+    segment_word_ends(text)
+}
+
+#[cfg(feature = "unicode-words")]
+fn segment_word_ends(text: &str) -> Vec<usize> {
+    text.split_word_bound_indices()
+        // spaces and punctuation are segments too, a cut after them would leave them at the end
+        .filter(|(_, segment)| segment.chars().any(char::is_alphanumeric))
+        .map(|(i, segment)| i + segment.len())
+        .collect()
+}
+
+// without the tables of `unicode-words`: the ends of the runs of letters and digits, and of every ideograph and kana,
+// which are words of their own in UAX #29 too
+#[cfg(not(feature = "unicode-words"))]
+fn segment_word_ends(text: &str) -> Vec<usize> {
+    let is_single = |c: char| matches!(c, '\u{3040}'..='\u{30ff}' | '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}');
+    let mut ends = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        let ends_word = c.is_alphanumeric() && (is_single(c) || next.is_none_or(|next| !next.is_alphanumeric() || is_single(next)));
+        if ends_word {
+            ends.push(i + c.len_utf8());
+        }
+    }
+    ends
+}
+
+#[cfg(feature = "japanese")]
+mod japanese {
+    use lindera::{DictionaryConfig, DictionaryKind, Mode, Tokenizer, TokenizerConfig};
+
+    thread_local! {
+        // loading the dictionary takes a while, once per thread
+        static TOKENIZER: Option<Tokenizer> = {
+            let dictionary = DictionaryConfig { kind: Some(DictionaryKind::IPADIC), path: None };
+            let config = TokenizerConfig { dictionary, user_dictionary: None, mode: Mode::Normal };
+            Tokenizer::from_config(config).inspect_err(|e| log::warn!("Japanese tokenizer not loaded: {}", e)).ok()
+        };
+    }
+
+    pub(super) fn is_japanese(c: char) -> bool {
+        // hiragana, katakana, the half-width ones and the cjk ideographs
+        matches!(c, '\u{3040}'..='\u{30ff}' | '\u{ff66}'..='\u{ff9f}' | '\u{4e00}'..='\u{9fff}' | '\u{3400}'..='\u{4dbf}')
+    }
+
+    pub(super) fn word_ends(text: &str) -> Option<Vec<usize>> {
+        TOKENIZER.with(|tokenizer| {
+            let tokens = tokenizer.as_ref()?.tokenize(text).inspect_err(|e| log::warn!("Japanese tokenizer failed: {}", e)).ok()?;
+            Some(tokens.iter().filter(|token| token.text.chars().any(char::is_alphanumeric)).map(|token| token.byte_end).collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env_logger;
+
+    #[test]
+    fn test_word_ends() {
+        let _ = env_logger::try_init();
+
+        assert_eq!(word_ends("Hello, big world"), vec![5, 10, 16]);
+        assert_eq!(word_ends("a-b"), vec![1, 3]);
+        assert_eq!(word_ends("ab漢字"), vec![2, 5, 8]);
+        assert_eq!(word_ends(""), Vec::<usize>::new());
+    }
+}